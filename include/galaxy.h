@@ -0,0 +1,46 @@
+/* C ABI of the galaxy interpreter. Build with `cargo build --release --features ffi`.
+ * Kept in the layout cbindgen generates with cbindgen.toml, so that
+ * `cbindgen --config cbindgen.toml --output include/galaxy.h` can replace it. */
+#ifndef GALAXY_H
+#define GALAXY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Galaxy Galaxy;
+typedef struct InteractResult InteractResult;
+
+/* Message of the last failure on this thread. Valid until the next call. */
+const char *galaxy_last_error(void);
+
+Galaxy *galaxy_new(void);
+void galaxy_free(Galaxy *g);
+
+/* Evaluates e.g. "ap ap add 1 2". Returns NULL on failure. Free with galaxy_string_free. */
+char *galaxy_eval(const Galaxy *g, const char *expr);
+
+/* Clicks (x, y) on galaxy in state. Returns NULL on failure. Free with galaxy_result_free. */
+InteractResult *galaxy_click(const Galaxy *g, const char *state, int32_t x, int32_t y);
+
+/* Clicks (x, y) on galaxy in state and returns {"state": ..., "images": [[[x, y], ...], ...]}.
+ * Returns NULL on failure. Free with galaxy_string_free. */
+char *galaxy_interact(const Galaxy *g, const char *state, int32_t x, int32_t y);
+
+/* The next state. Free with galaxy_string_free. */
+char *galaxy_result_state(const InteractResult *res);
+size_t galaxy_result_image_count(const InteractResult *res);
+/* Copies up to cap points of image i into out as x, y pairs; returns the image's point count. */
+size_t galaxy_result_image(const InteractResult *res, size_t i, int32_t *out, size_t cap);
+void galaxy_result_free(InteractResult *res);
+
+void galaxy_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GALAXY_H */
@@ -26,7 +26,7 @@ const inc = ap(add, 1n);
 const mul = (x) => (y) => eval(x) * eval(y);
 const div = (x) => (y) => eval(x) / eval(y);
 const eq = (x) => (y) => eval(x) == eval(y) ? t : f;
-const lt = (x) => (y) => eval(x) <= eval(y) ? t : f;
+const lt = (x) => (y) => eval(x) < eval(y) ? t : f;
 const neg = (x) => -eval(x);
 
 const s = (x) => (y) => (z) => ap(ap(x, z), ap(y, z));
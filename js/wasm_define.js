@@ -13,6 +13,22 @@ export function request(url, req) {
     return xhr.responseText;
 }
 
+export async function fetch_request(url, req) {
+    const resp = await fetch(url, {
+        method: 'POST',
+        headers: { 'Content-Type': 'text/plain', 'accept': '*/*' },
+        body: req,
+    });
+    if (!resp.ok) {
+        throw new Error(`HTTP ${resp.status}`);
+    }
+    return await resp.text();
+}
+
+export function now_ms() {
+    return performance.now();
+}
+
 export function js_eval_js(prog) {
     return Function(prog)();
 }
@@ -0,0 +1,227 @@
+//! Reverse-engineering notes on galaxy: what symbols like :1029 do, and bookmarks of
+//! interesting states. Traces, violations, Expr::pretty_annotated and Env::describe label
+//! symbols with their annotated names.
+
+use crate::ast::Node;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    // a short human name, e.g. "draw_frame".
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Annotation {
+    pub fn new(name: &str) -> Self {
+        Annotation {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.into();
+        self
+    }
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+}
+
+/// Annotations of symbols and of states, saved as JSON:
+///
+/// ```json
+/// {"symbols": {":1029": {"name": "draw_frame", "tags": ["render"]}},
+///  "bookmarks": {"ap ap cons 1 nil": {"name": "stage 1"}}}
+/// ```
+///
+/// or as TOML, which is easier to edit by hand:
+///
+/// ```toml
+/// [symbols.":1029"]
+/// name = "draw_frame"
+/// tags = ["render"]
+///
+/// [bookmarks."ap ap cons 1 nil"]
+/// name = "stage 1"
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default)]
+    symbols: BTreeMap<String, Annotation>,
+    // keyed by the state in the "ap ap cons 1 nil" form.
+    #[serde(default)]
+    bookmarks: BTreeMap<String, Annotation>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Loads a file saved by save, TOML if its extension is .toml and JSON otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let s =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        if is_toml(path) {
+            Self::from_toml(&s)
+        } else {
+            Self::from_json(&s)
+        }
+        .with_context(|| format!("parsing {}", path.display()))
+    }
+    // Saves self as TOML if the extension of path is .toml and as JSON otherwise.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let s = if is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()
+        };
+        std::fs::write(path, s).with_context(|| format!("writing {}", path.display()))
+    }
+    pub fn from_json(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    // Annotates a symbol, returning the previous annotation.
+    pub fn annotate(&mut self, symbol: &str, a: Annotation) -> Option<Annotation> {
+        self.symbols.insert(symbol.into(), a)
+    }
+    pub fn symbol(&self, symbol: &str) -> Option<&Annotation> {
+        self.symbols.get(symbol)
+    }
+    pub fn bookmark(&mut self, state: &Node, a: Annotation) -> Option<Annotation> {
+        self.bookmarks.insert(state.to_string(), a)
+    }
+    pub fn bookmark_of(&self, state: &Node) -> Option<&Annotation> {
+        self.bookmarks.get(&state.to_string())
+    }
+    pub fn bookmarks(&self) -> impl Iterator<Item = (Node, &Annotation)> {
+        self.bookmarks
+            .iter()
+            .filter_map(|(s, a)| Some((s.parse().ok()?, a)))
+    }
+
+    // Symbols tagged with tag.
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = (&'a str, &'a Annotation)> {
+        self.symbols
+            .iter()
+            .filter(move |(_, a)| a.tags.iter().any(|t| t == tag))
+            .map(|(s, a)| (s.as_str(), a))
+    }
+    // Symbols whose name, description or tags contain query.
+    pub fn search<'a>(&'a self, query: &'a str) -> impl Iterator<Item = (&'a str, &'a Annotation)> {
+        self.symbols
+            .iter()
+            .filter(move |(_, a)| {
+                a.name.contains(query)
+                    || a.description.contains(query)
+                    || a.tags.iter().any(|t| t.contains(query))
+            })
+            .map(|(s, a)| (s.as_str(), a))
+    }
+
+    // symbol followed by its annotated name, e.g. ":1029 (draw_frame)".
+    pub fn label(&self, symbol: &str) -> String {
+        match self.symbol(symbol) {
+            Some(a) => format!("{} ({})", symbol, a.name),
+            None => symbol.into(),
+        }
+    }
+
+    // Adds the annotations of other, replacing ones for the same symbol or state.
+    pub fn merge(&mut self, other: Annotations) {
+        self.symbols.extend(other.symbols);
+        self.bookmarks.extend(other.bookmarks);
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotations() {
+        let mut a = Annotations::new();
+        a.annotate(
+            ":1029",
+            Annotation::new("draw_frame")
+                .description("draws the images of a stage")
+                .tag("render"),
+        );
+        a.annotate(":1141", Annotation::new("stage_counter").tag("state"));
+        let state: Node = "ap ap cons 1 nil".parse().unwrap();
+        a.bookmark(&state, Annotation::new("stage 1"));
+
+        assert_eq!(a.label(":1029"), ":1029 (draw_frame)");
+        assert_eq!(a.label(":1"), ":1");
+        assert_eq!(a.bookmark_of(&state).unwrap().name, "stage 1");
+        assert_eq!(
+            a.tagged("render").map(|(s, _)| s).collect::<Vec<_>>(),
+            [":1029"]
+        );
+        assert_eq!(
+            a.search("stage").map(|(s, _)| s).collect::<Vec<_>>(),
+            [":1029", ":1141"]
+        );
+
+        let json = a.to_json();
+        assert_eq!(Annotations::from_json(&json).unwrap(), a);
+        let b = Annotations::from_json(r#"{"symbols": {":1": {"name": "one"}}}"#).unwrap();
+        assert_eq!(b.symbol(":1"), Some(&Annotation::new("one")));
+        assert!(Annotations::from_json(r#"{"symbols": {":1": {}}}"#).is_err());
+
+        let toml = a.to_toml().unwrap();
+        assert_eq!(Annotations::from_toml(&toml).unwrap(), a);
+        let b = Annotations::from_toml(
+            r#"
+            [symbols.":1029"]
+            name = "draw_frame"
+            tags = ["render"]
+
+            [bookmarks."ap ap cons 1 nil"]
+            name = "stage 1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            b.symbol(":1029"),
+            Some(&Annotation::new("draw_frame").tag("render"))
+        );
+        assert_eq!(b.bookmark_of(&state).unwrap().name, "stage 1");
+        assert!(Annotations::from_toml("[symbols.\":1\"]").is_err());
+
+        for ext in ["toml", "json"] {
+            let path = std::env::temp_dir().join(format!(
+                "annotations_test_{}.{}",
+                std::process::id(),
+                ext
+            ));
+            a.save(&path).unwrap();
+            let s = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(s.starts_with('{'), ext == "json", "{}", s);
+            assert_eq!(Annotations::load(&path).unwrap(), a);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,856 @@
+//! Values exchanged with protocols and the aliens.
+//!
+//! Node is a fully evaluated value: a number, nil, or a cons cell, written in the
+//! "ap ap cons 1 nil" form. Value is the same in the shape protocols use, with lists apart
+//! from other pairs; the unevaluated form is crate::expr::Expr. ToExpr and FromExpr convert
+//! native Rust types to and from Nodes.
+
+use anyhow::{bail, Context, Result};
+use num_bigint::BigInt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::TryFrom, fmt::Formatter, str::FromStr};
+
+use crate::expr::{Expr, Primitive};
+
+// Nesting of cons cells beyond which values parsed or demodulated from outside are rejected.
+// Values are formatted, compared and dropped recursively, taking up to about 1KB of stack
+// per level in debug builds, so deeper ones could overflow the stack.
+pub const MAX_DEPTH: usize = 2000;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Node {
+    Cons(Box<Node>, Box<Node>),
+    Nil,
+    Num(i64),
+    // A number outside i64. Numbers that fit are always Num.
+    Big(BigInt),
+}
+
+impl From<BigInt> for Node {
+    fn from(n: BigInt) -> Self {
+        match i64::try_from(&n) {
+            Ok(i) => Node::Num(i),
+            Err(_) => Node::Big(n),
+        }
+    }
+}
+
+impl FromStr for Node {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut i = s.split(' ');
+        let res = Node::parse(&mut i, 0)?;
+        let remaining = i.collect::<Vec<_>>();
+        if !remaining.is_empty() {
+            bail!("unused tokens: {:?}", remaining.join(" "));
+        }
+        Ok(res)
+    }
+}
+
+impl Node {
+    // The list of items, e.g. [1, 2] is "ap ap cons 1 ap ap cons 2 nil".
+    pub fn list(items: impl IntoIterator<Item = Node>) -> Node {
+        let items = items.into_iter().collect::<Vec<_>>();
+        items
+            .into_iter()
+            .rev()
+            .fold(Node::Nil, |tl, hd| Node::Cons(hd.into(), tl.into()))
+    }
+
+    // Parses a value nested depth cons cells deep.
+    fn parse<'a>(i: &mut impl Iterator<Item = &'a str>, depth: usize) -> Result<Self> {
+        let mut nxt = || i.next().ok_or(anyhow::anyhow!("iterator exhausted"));
+        Ok(match nxt()? {
+            "nil" => Node::Nil,
+            "ap" => {
+                match (nxt()?, nxt()?) {
+                    ("ap", "cons") | ("ap", "vec") => (),
+                    (x, y) => bail!("expected \"ap cons\" but got {:?}", format!("{} {}", x, y)),
+                }
+                if depth >= MAX_DEPTH {
+                    bail!("nesting deeper than {}", MAX_DEPTH);
+                }
+                Node::Cons(
+                    Node::parse(i, depth + 1)?.into(),
+                    Node::parse(i, depth + 1)?.into(),
+                )
+            }
+            s => match s.parse::<i64>() {
+                Ok(i) => Node::Num(i),
+                Err(_) => Node::from(
+                    s.parse::<BigInt>()
+                        .with_context(|| format!("unexpected token {:?}", s))?,
+                ),
+            },
+        })
+    }
+
+    // Whether a number outside i64 appears in self.
+    pub fn has_big(&self) -> bool {
+        match self {
+            Node::Big(_) => true,
+            Node::Cons(x, y) => x.has_big() || y.has_big(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn must_list_rev(self) -> Vec<Node> {
+        match self {
+            Node::Nil => vec![],
+            Node::Cons(x, y) => {
+                let mut res = y.must_list_rev();
+                res.push(*x);
+                res
+            }
+            _ => panic!("not list"),
+        }
+    }
+    pub(crate) fn must_list(self) -> Vec<Node> {
+        self.must_list_rev().into_iter().rev().collect()
+    }
+    pub(crate) fn as_list(&self) -> Option<Vec<&Node>> {
+        let mut res = vec![];
+        let mut node = self;
+        while let Node::Cons(x, y) = node {
+            res.push(x.as_ref());
+            node = y;
+        }
+        match node {
+            Node::Nil => Some(res),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Node::Nil => write!(f, "nil"),
+            Node::Cons(x, y) => write!(f, "ap ap cons {} {}", x, y),
+            Node::Num(i) => write!(f, "{}", i),
+            Node::Big(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+/// Value is a Node as protocols use it: a number, a list, or a pair that doesn't end a list,
+/// like the points of images. Requests are built and responses matched without formatting
+/// strings or walking cons cells:
+///
+/// ```
+/// use app::ast::{Node, Value};
+/// let req = Value::list(vec![Value::num(2), Value::num(1234), Value::list(Vec::<i64>::new())]);
+/// assert_eq!(Node::from(&req).to_string(), "ap ap cons 2 ap ap cons 1234 ap ap cons nil nil");
+/// let res = Value::from(&"ap ap cons 1 ap ap cons ap ap cons 2 3 nil".parse::<Node>().unwrap());
+/// assert_eq!(res.get(1).and_then(Value::as_pair), Some((&Value::num(2), &Value::num(3))));
+/// ```
+///
+/// A pair whose second element is a list is that list with the first element in front, so
+/// each value has a single form. nil is the empty list.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Value {
+    Num(i64),
+    // A number outside i64. Numbers that fit are always Num.
+    Big(BigInt),
+    List(Vec<Value>),
+    Pair(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    pub fn num(n: i64) -> Value {
+        Value::Num(n)
+    }
+    pub fn list<T: Into<Value>>(items: impl IntoIterator<Item = T>) -> Value {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+    // ap ap cons x y: the list y with x in front if y is a list.
+    pub fn pair(x: impl Into<Value>, y: impl Into<Value>) -> Value {
+        match y.into() {
+            Value::List(mut items) => {
+                items.insert(0, x.into());
+                Value::List(items)
+            }
+            y => Value::Pair(x.into().into(), y.into()),
+        }
+    }
+
+    pub fn as_num(&self) -> Option<i64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+    pub fn as_pair(&self) -> Option<(&Value, &Value)> {
+        match self {
+            Value::Pair(x, y) => Some((x, y)),
+            _ => None,
+        }
+    }
+    // The i-th element of a list.
+    pub fn get(&self, i: usize) -> Option<&Value> {
+        self.as_list()?.get(i)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Num(n)
+    }
+}
+
+impl<A: Into<Value>, B: Into<Value>> From<(A, B)> for Value {
+    fn from((x, y): (A, B)) -> Self {
+        Value::pair(x, y)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Self {
+        Value::list(v)
+    }
+}
+
+impl From<&Node> for Value {
+    fn from(n: &Node) -> Self {
+        match n {
+            Node::Nil => Value::List(vec![]),
+            Node::Num(n) => Value::Num(*n),
+            Node::Big(n) => Value::Big(n.clone()),
+            Node::Cons(x, y) => Value::pair(Value::from(x.as_ref()), Value::from(y.as_ref())),
+        }
+    }
+}
+
+impl From<Node> for Value {
+    fn from(n: Node) -> Self {
+        Value::from(&n)
+    }
+}
+
+impl From<&Value> for Node {
+    fn from(v: &Value) -> Self {
+        match v {
+            Value::Num(n) => Node::Num(*n),
+            Value::Big(n) => Node::Big(n.clone()),
+            Value::List(items) => Node::list(items.iter().map(Node::from)),
+            Value::Pair(x, y) => {
+                Node::Cons(Node::from(x.as_ref()).into(), Node::from(y.as_ref()).into())
+            }
+        }
+    }
+}
+
+impl From<Value> for Node {
+    fn from(v: Value) -> Self {
+        Node::from(&v)
+    }
+}
+
+impl FromStr for Value {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Value::from(&s.parse::<Node>()?))
+    }
+}
+
+// Values are written in the "ap ap cons 1 nil" form of Node.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Node::from(self))
+    }
+}
+
+impl From<Value> for ExprBuilder {
+    fn from(v: Value) -> Self {
+        ExprBuilder::from(Node::from(&v))
+    }
+}
+
+/// ToExpr converts Rust values to the Nodes they are sent as, so requests are built from
+/// native types rather than formatted strings:
+///
+/// ```
+/// use app::ast::{FromExpr, ToExpr};
+/// let req = (vec![1i64, 0], (2i64, 3i64)).to_expr();
+/// assert_eq!(req.to_string(), "ap ap cons ap ap cons 1 ap ap cons 0 nil ap ap cons 2 3");
+/// assert_eq!(<(Vec<i64>, (i64, i64))>::from_expr(&req).unwrap(), (vec![1, 0], (2, 3)));
+/// ```
+///
+/// Tuples are pairs and Vecs lists. None is nil and Some(x) the list [x], which keeps
+/// Some(vec![]) apart from None.
+pub trait ToExpr {
+    fn to_expr(&self) -> Node;
+}
+
+/// FromExpr is the inverse of ToExpr, failing on Nodes of another shape.
+pub trait FromExpr: Sized {
+    fn from_expr(n: &Node) -> Result<Self>;
+}
+
+impl<T: ToExpr + ?Sized> ToExpr for &T {
+    fn to_expr(&self) -> Node {
+        (*self).to_expr()
+    }
+}
+
+impl ToExpr for Node {
+    fn to_expr(&self) -> Node {
+        self.clone()
+    }
+}
+
+impl FromExpr for Node {
+    fn from_expr(n: &Node) -> Result<Self> {
+        Ok(n.clone())
+    }
+}
+
+impl ToExpr for Value {
+    fn to_expr(&self) -> Node {
+        Node::from(self)
+    }
+}
+
+impl FromExpr for Value {
+    fn from_expr(n: &Node) -> Result<Self> {
+        Ok(Value::from(n))
+    }
+}
+
+impl ToExpr for i64 {
+    fn to_expr(&self) -> Node {
+        Node::Num(*self)
+    }
+}
+
+impl FromExpr for i64 {
+    fn from_expr(n: &Node) -> Result<Self> {
+        match n {
+            Node::Num(n) => Ok(*n),
+            _ => bail!("want a number, got {}", n),
+        }
+    }
+}
+
+impl<A: ToExpr, B: ToExpr> ToExpr for (A, B) {
+    fn to_expr(&self) -> Node {
+        Node::Cons(self.0.to_expr().into(), self.1.to_expr().into())
+    }
+}
+
+impl<A: FromExpr, B: FromExpr> FromExpr for (A, B) {
+    fn from_expr(n: &Node) -> Result<Self> {
+        match n {
+            Node::Cons(x, y) => Ok((A::from_expr(x)?, B::from_expr(y)?)),
+            _ => bail!("want a pair, got {}", n),
+        }
+    }
+}
+
+impl<T: ToExpr> ToExpr for [T] {
+    fn to_expr(&self) -> Node {
+        Node::list(self.iter().map(ToExpr::to_expr))
+    }
+}
+
+impl<T: ToExpr> ToExpr for Vec<T> {
+    fn to_expr(&self) -> Node {
+        self.as_slice().to_expr()
+    }
+}
+
+impl<T: FromExpr> FromExpr for Vec<T> {
+    fn from_expr(n: &Node) -> Result<Self> {
+        n.as_list()
+            .with_context(|| format!("want a list, got {}", n))?
+            .into_iter()
+            .map(T::from_expr)
+            .collect()
+    }
+}
+
+impl<T: ToExpr> ToExpr for Option<T> {
+    fn to_expr(&self) -> Node {
+        Node::list(self.iter().map(ToExpr::to_expr))
+    }
+}
+
+impl<T: FromExpr> FromExpr for Option<T> {
+    fn from_expr(n: &Node) -> Result<Self> {
+        match n {
+            Node::Nil => Ok(None),
+            Node::Cons(x, y) if **y == Node::Nil => Ok(Some(T::from_expr(x)?)),
+            _ => bail!("want nil or a list of one item, got {}", n),
+        }
+    }
+}
+
+/// ExprBuilder constructs expressions in Rust instead of formatting strings:
+///
+/// ```
+/// use app::ast::ExprBuilder as E;
+/// let e = E::var("galaxy").ap(E::nil()).ap((1, 2)).build();
+/// assert_eq!(e.to_string(), "ap ap galaxy nil ap ap cons 1 2");
+/// ```
+///
+/// Numbers, Nodes and Exprs convert into builders, tuples into pairs and Vecs into lists.
+#[derive(Clone, Debug)]
+pub struct ExprBuilder(Expr);
+
+impl ExprBuilder {
+    pub fn num(n: i64) -> Self {
+        ExprBuilder(Expr::Num(n))
+    }
+    // A reference to a definition like "galaxy" or ":1029". Use op for primitives.
+    pub fn var(name: &str) -> Self {
+        ExprBuilder(Expr::Var(name.into()))
+    }
+    pub fn op(p: Primitive) -> Self {
+        ExprBuilder(Expr::op(p))
+    }
+    pub fn nil() -> Self {
+        ExprBuilder(Expr::nil())
+    }
+    // ap ap cons x y
+    pub fn pair(x: impl Into<ExprBuilder>, y: impl Into<ExprBuilder>) -> Self {
+        ExprBuilder(Expr::cons(x.into().0.into(), y.into().0.into()))
+    }
+    pub fn list<T: Into<ExprBuilder>>(items: impl IntoIterator<Item = T>) -> Self {
+        let items = items.into_iter().map(Into::into).collect::<Vec<_>>();
+        items
+            .into_iter()
+            .rev()
+            .fold(ExprBuilder::nil(), |tl, hd| ExprBuilder::pair(hd, tl))
+    }
+    // Applies self to arg.
+    pub fn ap(self, arg: impl Into<ExprBuilder>) -> Self {
+        ExprBuilder(Expr::Ap(self.0.into(), arg.into().0.into()))
+    }
+    pub fn build(self) -> Expr {
+        self.0
+    }
+}
+
+impl From<i64> for ExprBuilder {
+    fn from(n: i64) -> Self {
+        ExprBuilder::num(n)
+    }
+}
+
+impl From<Expr> for ExprBuilder {
+    fn from(e: Expr) -> Self {
+        ExprBuilder(e)
+    }
+}
+
+impl From<&Node> for ExprBuilder {
+    fn from(n: &Node) -> Self {
+        ExprBuilder(crate::reduce_evaluator::node_to_expr(n))
+    }
+}
+
+impl From<Node> for ExprBuilder {
+    fn from(n: Node) -> Self {
+        ExprBuilder::from(&n)
+    }
+}
+
+impl<A: Into<ExprBuilder>, B: Into<ExprBuilder>> From<(A, B)> for ExprBuilder {
+    fn from((x, y): (A, B)) -> Self {
+        ExprBuilder::pair(x, y)
+    }
+}
+
+impl<T: Into<ExprBuilder>> From<Vec<T>> for ExprBuilder {
+    fn from(v: Vec<T>) -> Self {
+        ExprBuilder::list(v)
+    }
+}
+
+impl From<ExprBuilder> for Expr {
+    fn from(b: ExprBuilder) -> Self {
+        b.0
+    }
+}
+
+// Builds the expression written by the expr! macro. stringify! may put spaces inside
+// tokens like "-3" and ":1029", so they are joined back first.
+#[doc(hidden)]
+pub fn __expr_from_tokens(s: &str) -> Expr {
+    fn parse<'a>(it: &mut impl Iterator<Item = &'a str>) -> ExprBuilder {
+        match it.next().expect("expr!: missing operand") {
+            "ap" => parse(it).ap(parse(it)),
+            s => match (Primitive::from_name(s), s.parse()) {
+                (Some(p), _) => ExprBuilder::op(p),
+                (None, Ok(n)) => ExprBuilder::num(n),
+                (None, Err(_)) => ExprBuilder::var(s),
+            },
+        }
+    }
+    let s = s.replace("- ", "-").replace(": ", ":");
+    let mut it = s.split_whitespace();
+    let res = parse(&mut it).build();
+    if let Some(tok) = it.next() {
+        panic!("expr!: unused token {:?}", tok);
+    }
+    res
+}
+
+impl Node {
+    // The structured JSON form of self, for frontends displaying and editing states as trees.
+    // Lists are arrays, other cons cells {"cons": [car, cdr]}, and numbers outside i64
+    // strings, as JavaScript numbers lose their precision, e.g.
+    //   [1, [[0, {"cons": [2, 3]}]], "9223372036854775808"]
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+    pub fn from_json(s: &str) -> Result<Node> {
+        Node::from_json_value(&serde_json::from_str(s)?)
+    }
+
+    pub fn to_json_value(&self) -> serde_json::Value {
+        use serde_json::Value;
+        if let Some(items) = self.as_list() {
+            return Value::Array(items.into_iter().map(Node::to_json_value).collect());
+        }
+        match self {
+            Node::Num(n) => (*n).into(),
+            Node::Big(n) => n.to_string().into(),
+            Node::Cons(x, y) => serde_json::json!({"cons": [x.to_json_value(), y.to_json_value()]}),
+            Node::Nil => unreachable!("nil is an empty list"),
+        }
+    }
+    pub fn from_json_value(v: &serde_json::Value) -> Result<Node> {
+        use serde_json::Value;
+        Ok(match v {
+            Value::Array(items) => items.iter().rev().try_fold(Node::Nil, |tl, hd| {
+                Ok::<_, anyhow::Error>(Node::Cons(Node::from_json_value(hd)?.into(), tl.into()))
+            })?,
+            Value::Number(n) => Node::Num(
+                n.as_i64()
+                    .with_context(|| format!("not an integer: {}", n))?,
+            ),
+            Value::String(s) => Node::from(
+                s.parse::<BigInt>()
+                    .with_context(|| format!("not an integer: {:?}", s))?,
+            ),
+            Value::Object(o) => match (o.len(), o.get("cons").and_then(Value::as_array)) {
+                (1, Some(pair)) if pair.len() == 2 => Node::Cons(
+                    Node::from_json_value(&pair[0])?.into(),
+                    Node::from_json_value(&pair[1])?.into(),
+                ),
+                _ => bail!("expected {{\"cons\": [car, cdr]}}, got {}", v),
+            },
+            _ => bail!("unexpected {}", v),
+        })
+    }
+}
+
+// Expressions convert to the JSON form of Node::to_json, with the parts that aren't values,
+// like applications not evaluated yet, as {"expr": "ap ap add 1 2"}.
+impl From<&Expr> for serde_json::Value {
+    fn from(e: &Expr) -> Self {
+        use serde_json::{json, Value};
+        match e {
+            Expr::Num(n) => (*n).into(),
+            Expr::Big(n) => n.to_string().into(),
+            Expr::Op(Primitive::Nil, None, None, None) => json!([]),
+            e if as_cons(e).is_some() => {
+                let mut items = vec![];
+                let mut e = e.clone();
+                while let Some((x, y)) = as_cons(&e) {
+                    items.push(Value::from(&x));
+                    e = y;
+                }
+                match e {
+                    Expr::Op(Primitive::Nil, None, None, None) => Value::Array(items),
+                    tl => items
+                        .into_iter()
+                        .rev()
+                        .fold(Value::from(&tl), |tl, hd| json!({"cons": [hd, tl]})),
+                }
+            }
+            e => json!({"expr": e.to_string()}),
+        }
+    }
+}
+
+// The car and cdr of a cons cell, whether evaluated or as parsed, "ap ap cons x y".
+fn as_cons(e: &Expr) -> Option<(Expr, Expr)> {
+    match e {
+        Expr::Op(Primitive::Cons, Some(x), Some(y), None) => Some((x.expr(), y.expr())),
+        Expr::Ap(l, y) => match l.expr() {
+            Expr::Op(Primitive::Cons, Some(x), None, None) => Some((x.expr(), y.expr())),
+            Expr::Ap(cons, x) => match cons.expr() {
+                Expr::Op(Primitive::Cons, None, None, None) => Some((x.expr(), y.expr())),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl From<Expr> for serde_json::Value {
+    fn from(e: Expr) -> Self {
+        serde_json::Value::from(&e)
+    }
+}
+
+// Reads the JSON form of Node::to_json, taking null for nil as well. {"expr": ...} isn't read
+// back, as expressions need definitions to parse against.
+impl TryFrom<serde_json::Value> for Expr {
+    type Error = anyhow::Error;
+    fn try_from(v: serde_json::Value) -> Result<Self> {
+        use serde_json::Value;
+        Ok(match v {
+            Value::Null => Expr::nil(),
+            Value::Array(items) => items.into_iter().rev().try_fold(Expr::nil(), |tl, hd| {
+                Ok::<_, anyhow::Error>(Expr::cons(Expr::try_from(hd)?.into(), tl.into()))
+            })?,
+            Value::Object(mut o) => match o.remove("cons") {
+                Some(Value::Array(mut pair)) if o.is_empty() && pair.len() == 2 => {
+                    let y = Expr::try_from(pair.pop().unwrap())?;
+                    let x = Expr::try_from(pair.pop().unwrap())?;
+                    Expr::cons(x.into(), y.into())
+                }
+                _ => bail!("expected {{\"cons\": [car, cdr]}}"),
+            },
+            v => crate::reduce_evaluator::node_to_expr(&Node::from_json_value(&v)?),
+        })
+    }
+}
+
+// Nodes are serialized in the "ap ap cons 1 nil" form, like states everywhere else.
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde() {
+        let node: Node = "ap ap cons 1 ap ap cons nil nil".parse().unwrap();
+        let json = serde_json::to_string(&node).unwrap();
+        assert_eq!(json, r#""ap ap cons 1 ap ap cons nil nil""#);
+        assert_eq!(serde_json::from_str::<Node>(&json).unwrap(), node);
+        assert!(serde_json::from_str::<Node>(r#""ap ap cons 1""#).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        for (s, json) in [
+            ("nil", "[]"),
+            ("-3", "-3"),
+            (
+                "ap ap cons 1 ap ap cons ap ap cons 2 3 nil",
+                r#"[1,{"cons":[2,3]}]"#,
+            ),
+            ("ap ap cons nil ap ap cons ap ap cons 0 nil nil", "[[],[0]]"),
+            ("9223372036854775808", r#""9223372036854775808""#),
+        ] {
+            let node: Node = s.parse().unwrap();
+            assert_eq!(node.to_json(), json);
+            assert_eq!(Node::from_json(json).unwrap(), node);
+        }
+        for json in ["1.5", "null", r#"{"cons":[1]}"#, r#""x""#, "[1,"] {
+            assert!(Node::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_expr_json() {
+        use serde_json::{json, Value};
+        let env = crate::reduce_evaluator::Env::new();
+        let e = env
+            .parse("ap ap cons 1 ap ap cons ap ap add 1 2 ap ap cons ap ap cons 4 5 nil")
+            .unwrap();
+        assert_eq!(
+            Value::from(&e),
+            json!([1, {"expr": "ap ap add 1 2"}, {"cons": [4, 5]}])
+        );
+
+        for (json, want, back) in [
+            (json!(null), "nil", json!([])),
+            (json!([]), "nil", json!([])),
+            (
+                json!([1, null]),
+                "ap ap cons 1 ap ap cons nil nil",
+                json!([1, []]),
+            ),
+            (
+                json!({"cons": [-1, ["9223372036854775808"]]}),
+                "ap ap cons -1 ap ap cons 9223372036854775808 nil",
+                json!([-1, "9223372036854775808"]),
+            ),
+        ] {
+            let e = Expr::try_from(json).unwrap();
+            assert_eq!(e.to_string(), want);
+            assert_eq!(Value::from(e), back);
+        }
+        for json in [json!(1.5), json!({"cons": [1]}), json!({"expr": "nil"})] {
+            assert!(Expr::try_from(json.clone()).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_big() {
+        for (s, want) in [
+            ("9223372036854775807", Node::Num(i64::MAX)),
+            ("-9223372036854775808", Node::Num(i64::MIN)),
+            ("9223372036854775808", Node::Big(BigInt::from(i64::MAX) + 1)),
+        ] {
+            let node: Node = s.parse().unwrap();
+            assert_eq!(node, want);
+            assert_eq!(node.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let list = |n: usize| "ap ap cons 1 ".repeat(n) + "nil";
+        assert_eq!(
+            list(MAX_DEPTH)
+                .parse::<Node>()
+                .unwrap()
+                .as_list()
+                .unwrap()
+                .len(),
+            MAX_DEPTH
+        );
+        assert_eq!(
+            format!("{:#}", list(MAX_DEPTH + 1).parse::<Node>().unwrap_err()),
+            "nesting deeper than 2000"
+        );
+        let node = list(MAX_DEPTH).parse::<Node>().unwrap();
+        assert_eq!(Node::try_demodulate(&node.modulate()).unwrap(), node);
+        let deeper = Node::Cons(Node::Nil.into(), node.into());
+        assert!(Node::try_demodulate(&deeper.modulate()).is_err());
+    }
+
+    #[test]
+    fn test_value() {
+        for (s, want) in [
+            ("nil", Value::list(Vec::<i64>::new())),
+            ("ap ap cons 1 2", Value::pair(1, 2)),
+            (
+                "ap ap cons 1 nil",
+                Value::pair(1, Value::list(Vec::<i64>::new())),
+            ),
+            ("ap ap cons 1 ap ap cons 2 nil", Value::list(vec![1, 2])),
+            (
+                "ap ap cons 1 ap ap cons 2 3",
+                Value::pair(1, Value::pair(2, 3)),
+            ),
+            (
+                "ap ap cons ap ap cons 0 1 ap ap cons nil nil",
+                vec![Value::pair(0, 1), vec![0i64; 0].into()].into(),
+            ),
+            (
+                "9223372036854775808",
+                Value::Big(BigInt::from(i64::MAX) + 1),
+            ),
+        ] {
+            let node: Node = s.parse().unwrap();
+            assert_eq!(Value::from(&node), want, "{}", s);
+            assert_eq!(Node::from(&want), node);
+            assert_eq!(want.to_string(), s);
+        }
+
+        let v: Value = "ap ap cons 1 ap ap cons ap ap cons 2 3 nil"
+            .parse()
+            .unwrap();
+        assert_eq!(v.get(0).and_then(Value::as_num), Some(1));
+        assert_eq!(
+            v.get(1).unwrap().as_pair(),
+            Some((&Value::num(2), &Value::num(3)))
+        );
+        assert_eq!(v.get(2), None);
+        assert_eq!(v.as_num(), None);
+        assert_eq!(
+            ExprBuilder::var("f").ap(v).build().to_string(),
+            "ap f ap ap cons 1 ap ap cons ap ap cons 2 3 nil"
+        );
+    }
+
+    #[test]
+    fn test_expr_builder() {
+        use ExprBuilder as E;
+        let node: Node = "ap ap cons 1 nil".parse().unwrap();
+        for (e, want) in [
+            (E::num(-3), "-3"),
+            (E::nil(), "nil"),
+            (E::pair(1, E::nil()), "ap ap cons 1 nil"),
+            (E::list(vec![1, 2]), "ap ap cons 1 ap ap cons 2 nil"),
+            (E::list(Vec::<i64>::new()), "nil"),
+            (
+                vec![(0, 1), (2, 3)].into(),
+                "ap ap cons ap ap cons 0 1 ap ap cons ap ap cons 2 3 nil",
+            ),
+            (
+                vec![vec![1], vec![]].into(),
+                "ap ap cons ap ap cons 1 nil ap ap cons nil nil",
+            ),
+            (
+                E::op(Primitive::Add).ap(1).ap(E::var("x0")),
+                "ap ap add 1 x0",
+            ),
+            (
+                E::var("galaxy").ap(&node).ap((0, 0)),
+                "ap ap galaxy ap ap cons 1 nil ap ap cons 0 0",
+            ),
+        ] {
+            assert_eq!(e.build().to_string(), want);
+        }
+    }
+
+    #[test]
+    fn test_to_expr() {
+        let n = |s: &str| s.parse::<Node>().unwrap();
+        assert_eq!((1i64, 2i64).to_expr(), n("ap ap cons 1 2"));
+        assert_eq!(
+            vec![(0i64, 1i64)].to_expr(),
+            n("ap ap cons ap ap cons 0 1 nil")
+        );
+        assert_eq!(Option::<i64>::None.to_expr(), n("nil"));
+        assert_eq!(Some(vec![0i64; 0]).to_expr(), n("ap ap cons nil nil"));
+        assert_eq!((&5i64, n("nil")).to_expr(), n("ap ap cons 5 nil"));
+
+        let v = (vec![Some(1i64), None], (2i64, vec![vec![3i64]]));
+        assert_eq!(FromExpr::from_expr(&v.to_expr()).ok(), Some(v));
+        assert_eq!(
+            Option::<Vec<i64>>::from_expr(&n("ap ap cons nil nil")).unwrap(),
+            Some(vec![])
+        );
+        let err = |res: Result<Vec<(i64, i64)>>| format!("{:#}", res.unwrap_err());
+        assert_eq!(
+            err(FromExpr::from_expr(&n("ap ap cons 1 2"))),
+            "want a list, got ap ap cons 1 2"
+        );
+        assert_eq!(
+            err(FromExpr::from_expr(&n("ap ap cons 1 nil"))),
+            "want a pair, got 1"
+        );
+        assert!(i64::from_expr(&n("99999999999999999999")).is_err());
+        assert!(Option::<i64>::from_expr(&n("ap ap cons 1 ap ap cons 2 nil")).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Representative workloads for performance work, each reporting how much work the reduce
+//! evaluator did. Enabled by the `bench` feature.
+//!
+//! Evaluation recurses deeply; run the workloads on a thread with a large stack, as the
+//! binaries do.
+
+use crate::{
+    ast::Node,
+    eval::{EvalStats, Evaluator},
+    protocol::G,
+    reduce_evaluator,
+};
+
+// Runs f on the current thread and returns the work it did. Setup done before the call,
+// like loading galaxy.txt, is not counted.
+pub fn measure(f: impl FnOnce()) -> EvalStats {
+    EvalStats::run(f).1
+}
+
+// Clicks the galaxy n times from the initial state, going through the countdown and the
+// first tutorial stage.
+pub fn tutorial_clicks(n: usize) -> EvalStats {
+    let g = G::new(Box::new(reduce_evaluator::Eval::new()));
+    measure(|| {
+        let mut state = "nil".to_string();
+        for _ in 0..n {
+            state = g.galaxy(state.clone(), 0, 0, "").unwrap().state;
+        }
+    })
+}
+
+// Evaluates "ap ap add 1 ap ap add 1 ... 0" nested depth times.
+pub fn deep_arithmetic(depth: usize) -> EvalStats {
+    let eval = reduce_evaluator::Eval::new();
+    let expr = format!("{}0", "ap ap add 1 ".repeat(depth));
+    measure(|| {
+        assert_eq!(eval.evaluate(&expr), Node::Num(depth as i64));
+    })
+}
+
+// Modulates and demodulates a state holding len points, then passes it through the
+// evaluator as a protocol result would be.
+pub fn state_modulation(len: usize) -> EvalStats {
+    let eval = reduce_evaluator::Eval::new();
+    let state = (0..len as i64).rev().fold(Node::Nil, |tl, i| {
+        let p = Node::Cons(Node::Num(i).into(), Node::Num(-i).into());
+        Node::Cons(p.into(), tl.into())
+    });
+    measure(|| {
+        let back = Node::demodulate(&state.modulate());
+        assert_eq!(eval.evaluate_ap2("t", &back, &Node::Nil), state);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workloads() {
+        let c = deep_arithmetic(100);
+        assert!(c.total_reductions() >= 100, "{:?}", c);
+        let d = deep_arithmetic(100);
+        assert_eq!(
+            (c.total_reductions(), c.allocs),
+            (d.total_reductions(), d.allocs)
+        );
+
+        let c = state_modulation(100);
+        assert!(c.allocs >= 300, "{:?}", c);
+
+        let c = tutorial_clicks(2);
+        assert!(c.total_reductions() > 0 && c.allocs > 0, "{:?}", c);
+    }
+}
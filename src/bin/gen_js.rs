@@ -5,9 +5,10 @@ use std::io::prelude::*;
 
 // Convert lambda expressions to SKI combinator.
 fn main() {
+    logging::init();
     let child = std::thread::Builder::new()
         .stack_size(256 * 1024 * 1024)
-        .spawn(move || run())
+        .spawn(run)
         .unwrap();
     child.join().unwrap().unwrap();
 }
@@ -0,0 +1,58 @@
+use app::*;
+
+use anyhow::{anyhow, Context, Result};
+use common::{Node, Sender, G};
+use std::io::prelude::*;
+
+// Runs galaxy clicks read from stdin without a browser or network, e.g. in a WASI runtime.
+//
+// Each input line "<x> <y> <state>" clicks (x, y) in state and prints one line
+//   {"state":"...","images":[[[x,y],...],...]}
+// When galaxy sends to the aliens, "send <modulated request>" is printed instead and the next
+// input line must be the modulated response.
+//
+// Exits with a non-zero status if a click fails.
+fn main() -> Result<()> {
+    logging::init();
+    #[cfg(not(target_os = "wasi"))]
+    {
+        let child = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(run)?;
+        child.join().map_err(|_| anyhow!("headless run panicked"))?
+    }
+    // WASI has no threads. The stack size is set with a linker flag in .cargo/config.toml.
+    #[cfg(target_os = "wasi")]
+    run()
+}
+
+struct StdioSender;
+
+impl Sender for StdioSender {
+    fn send(&self, req: &Node, _api_key: &str) -> Result<Node> {
+        println!("send {}", req.modulate());
+        let mut resp = String::new();
+        std::io::stdin().lock().read_line(&mut resp)?;
+        Node::try_demodulate(resp.trim()).with_context(|| format!("bad response {:?}", resp))
+    }
+}
+
+fn run() -> Result<()> {
+    let mut g = G::new(Box::new(reduce_evaluator::Eval::new()));
+    g.set_sender(Box::new(StdioSender));
+
+    loop {
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let v = line.trim().splitn(3, ' ').collect::<Vec<_>>();
+        if v.len() != 3 {
+            return Err(anyhow!("want \"<x> <y> <state>\", got {:?}", line));
+        }
+        println!(
+            "{}",
+            g.galaxy_json(v[2].into(), v[0].parse()?, v[1].parse()?)?
+        );
+    }
+}
@@ -6,9 +6,10 @@ use anyhow::{anyhow, bail, Result};
 use std::io::Read;
 
 fn main() {
+    logging::init();
     let child = std::thread::Builder::new()
         .stack_size(256 * 1024 * 1024)
-        .spawn(move || run())
+        .spawn(run)
         .unwrap();
     child.join().unwrap();
 }
@@ -16,12 +17,13 @@ fn main() {
 fn run() {
     let start = std::time::Instant::now();
 
-    for (name, eval) in vec![
+    for (name, eval) in [
         (
             "reduce_evaluator",
             Box::new(reduce_evaluator::Eval::new()) as Box<dyn common::Evaluator>,
         ),
         ("js_gen_evaluator", Box::new(gen_js::GalaxyEvaluator::new())),
+        ("gmachine", Box::new(gmachine::GMachine::new())),
     ] {
         let g = common::G::new(eval);
 
@@ -30,11 +32,12 @@ fn run() {
         let want_state = "ap ap cons 3 ap ap cons ap ap cons 0 ap ap cons ap ap cons 1 ap ap cons 0 ap ap cons 0 ap ap cons 0 ap ap cons 2 ap ap cons 0 ap ap cons 0 ap ap cons 0 ap ap cons 0 nil ap ap cons nil ap ap cons 0 nil ap ap cons 0 ap ap cons nil nil";
 
         let next_state = g
-            .galaxy(state.into(), vector.0, vector.1, "".into())
+            .galaxy(state.into(), vector.0, vector.1, "")
+            .unwrap()
             .state();
 
         let d = std::time::Instant::now() - start;
-        eprintln!("computed in {:?}", d);
+        tracing::info!(evaluator = name, elapsed = ?d, "computed");
 
         assert_eq!(next_state, want_state);
     }
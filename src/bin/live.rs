@@ -0,0 +1,37 @@
+use app::*;
+
+use anyhow::{Context, Result};
+use session::Session;
+
+// Evaluation time a click may take, all rounds together.
+const CLICK_BUDGET_MS: f64 = 10_000.0;
+
+// Serves galaxy sessions over WebSocket, one per connection. See src/live.rs for the messages.
+//
+//   cargo run --release --features live --bin live [ADDR]
+//
+// ADDR defaults to 127.0.0.1:8080. Requests to the aliens use the API key in API_KEY.
+// Clicks evaluating for more than CLICK_BUDGET_MS fail, so that no client can keep a thread
+// busy forever.
+fn main() {
+    logging::init();
+    if let Err(e) = run() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8080".into());
+    let listener =
+        std::net::TcpListener::bind(&addr).with_context(|| format!("listening on {}", addr))?;
+    println!("listening on ws://{}/", addr);
+    live::serve(listener, || {
+        let mut g = G::new(Box::new(reduce_evaluator::Eval::new()));
+        g.set_config(net::Config::from_env());
+        g.set_budget(Some(eval::EvalBudget::millis(CLICK_BUDGET_MS)));
+        Session::new(g, "galaxy", "nil".into())
+    })
+}
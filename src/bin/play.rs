@@ -0,0 +1,65 @@
+use app::*;
+
+use anyhow::{Context, Result};
+use bot::{OrbitBot, Runner, Strategy};
+use game::{Command, GameClient, GameResponse};
+
+// Plays the aliens' game from the command line.
+//
+//   cargo run --bin play               creates a match and prints the two player keys
+//   cargo run --bin play PLAYER_KEY    plays as PLAYER_KEY with OrbitBot
+//   cargo run --bin play new           creates a match and plays it as the attacker
+//
+// The API key is read from API_KEY; API_KEY=local plays against the local emulator.
+fn main() {
+    logging::init();
+    if let Err(e) = run() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+// Prints every turn of the inner strategy.
+struct Verbose<S>(S);
+
+impl<S: Strategy> Strategy for Verbose<S> {
+    fn commands(&mut self, game: &GameResponse) -> Vec<Command> {
+        print_turn(game);
+        self.0.commands(game)
+    }
+}
+
+fn run() -> Result<()> {
+    let api_key = net::Config::from_env().api_key.unwrap_or_default();
+    let client = GameClient::http(api_key);
+    let player_key = match std::env::args().nth(1) {
+        Some(key) if key == "new" => client.create()?.0,
+        Some(key) => key
+            .parse()
+            .with_context(|| format!("bad player key {:?}", key))?,
+        None => {
+            let (attacker, defender) = client.create()?;
+            println!("attacker {}\ndefender {}", attacker, defender);
+            return Ok(());
+        }
+    };
+    let res = Runner::new(client).run(player_key, &mut Verbose(OrbitBot))?;
+    print_turn(&res);
+    Ok(())
+}
+
+fn print_turn(res: &GameResponse) {
+    let tick = res.state.as_ref().map_or(0, |s| s.tick);
+    println!("{:?} tick {}", res.stage, tick);
+    for (ship, _) in res.state.iter().flat_map(|s| s.ships.iter()) {
+        println!(
+            "  {:?} ship {} at ({}, {}) moving ({}, {})",
+            ship.role,
+            ship.ship_id,
+            ship.position.x,
+            ship.position.y,
+            ship.velocity.x,
+            ship.velocity.y
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use app::*;
+
+use annotations::Annotations;
+use anyhow::{Context, Result};
+use common::{Evaluator, Node};
+use std::{io::prelude::*, path::Path};
+
+const HELP: &str = "\
+<expr>                 evaluates expr, e.g. ap ap add 1 2 or ap ap galaxy nil ap ap vec 0 0
+:def <name> = <expr>   defines name, which may refer to itself
+:mod <expr>            evaluates expr and prints it modulated
+:dem <bits>            demodulates bits
+:ann <file>            loads annotations of symbols from a .json or .toml file
+:sym <name>            shows the definition of name with its annotations and references
+:help                  prints this
+:quit                  exits";
+
+// An interactive evaluator on galaxy's definitions.
+//
+//   cargo run --bin repl [FILE]
+//
+// FILE holds more definitions in the format of galaxy.txt, e.g. a protocol under development.
+fn main() {
+    logging::init();
+    let child = std::thread::Builder::new()
+        .stack_size(256 * 1024 * 1024)
+        .spawn(run)
+        .unwrap();
+    if let Err(e) = child.join().unwrap() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut eval = reduce_evaluator::Eval::new();
+    if let Some(path) = std::env::args().nth(1) {
+        let src = std::fs::read_to_string(&path).with_context(|| format!("reading {}", path))?;
+        eval.env_mut()
+            .merge(Env::from_source(&src).with_context(|| format!("loading {}", path))?);
+    }
+
+    let mut annotations = Annotations::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim();
+        if line == ":quit" || line == ":q" {
+            return Ok(());
+        }
+        match eval_line(&mut eval, &mut annotations, line) {
+            Ok(Some(out)) => println!("{}", out),
+            Ok(None) => (),
+            Err(e) => println!("error: {:#}", e),
+        }
+    }
+}
+
+// The output for a line of input, if any.
+fn eval_line(
+    eval: &mut reduce_evaluator::Eval,
+    annotations: &mut Annotations,
+    line: &str,
+) -> Result<Option<String>> {
+    let (cmd, arg) = match line.split_once(' ') {
+        Some((cmd, arg)) if cmd.starts_with(':') && !is_name(cmd) => (cmd, arg.trim()),
+        _ if line.starts_with(':') && !is_name(line) => (line, ""),
+        _ => ("", line),
+    };
+    Ok(match cmd {
+        "" if arg.is_empty() => None,
+        "" => Some(eval.try_evaluate(arg)?.to_string()),
+        ":def" => {
+            let (name, expr) = arg
+                .split_once(" = ")
+                .context("usage: :def <name> = <expr>")?;
+            eval.env_mut().insert_definition(name, expr)?;
+            None
+        }
+        ":mod" => Some(eval.try_evaluate(arg)?.modulate()),
+        ":dem" => Some(Node::try_demodulate(arg)?.to_string()),
+        ":ann" => {
+            annotations.merge(Annotations::load(Path::new(arg))?);
+            eval.set_annotations(annotations.clone());
+            None
+        }
+        ":sym" => Some(
+            eval.env()
+                .describe(arg, annotations)
+                .with_context(|| format!("{} is not defined", arg))?,
+        ),
+        ":help" => Some(HELP.into()),
+        _ => anyhow::bail!("unknown command {}; try :help", cmd),
+    })
+}
+
+// Whether s is a galaxy name like ":1029" rather than a command.
+fn is_name(s: &str) -> bool {
+    s[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_line() {
+        let mut eval = reduce_evaluator::Eval::new();
+        let mut annotations = Annotations::new();
+        let path = std::env::temp_dir().join(format!("repl_test_{}.toml", std::process::id()));
+        std::fs::write(&path, "[symbols.\":1\"]\nname = \"one\"\n").unwrap();
+        let ann = format!(":ann {}", path.display());
+        for (line, want) in [
+            ("ap ap add 1 2", Some("3")),
+            (":def sq = ap ap s mul i", None),
+            ("ap sq 9", Some("81")),
+            (":mod ap ap cons 1 nil", Some("110110000100")),
+            (":dem 110110000100", Some("ap ap cons 1 nil")),
+            (":def :1 = 1", None),
+            (":def two = ap ap add :1 :1", None),
+            (ann.as_str(), None),
+            (":sym two", Some("two\n= (add one one)\nuses :1 (one)")),
+            ("", None),
+        ] {
+            assert_eq!(
+                eval_line(&mut eval, &mut annotations, line)
+                    .unwrap()
+                    .as_deref(),
+                want,
+                "{}",
+                line
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(eval_line(&mut eval, &mut annotations, ":1029")
+            .unwrap()
+            .is_some());
+        assert!(eval_line(&mut eval, &mut annotations, ":def sq").is_err());
+        assert!(eval_line(&mut eval, &mut annotations, ":x").is_err());
+        assert!(eval_line(&mut eval, &mut annotations, ":sym nope").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use app::*;
+
+use anyhow::{bail, Context, Result};
+use session::{History, ReplaySends, Session};
+use std::path::Path;
+
+// Replays a recorded session against the current evaluator, failing on the first click
+// ending in a different state or drawing different images than recorded, e.g. to check an
+// evaluator change.
+//
+//   cargo run --release --bin replay LOG [FILE]
+//
+// LOG is a History in JSON, e.g. from Session::history, or a file written by Session::save.
+// Requests are answered with the recorded responses. FILE holds more definitions as for
+// repl, e.g. the protocol of the log if it isn't galaxy.
+fn main() {
+    logging::init();
+    let child = std::thread::Builder::new()
+        .stack_size(256 * 1024 * 1024)
+        .spawn(run)
+        .unwrap();
+    if let Err(e) = child.join().unwrap() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn new_g(defs: Option<&str>) -> Result<common::G> {
+    let mut eval = reduce_evaluator::Eval::new();
+    if let Some(path) = defs {
+        let src = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+        eval.env_mut()
+            .merge(Env::from_source(&src).with_context(|| format!("loading {}", path))?);
+    }
+    Ok(common::G::new(Box::new(eval)))
+}
+
+fn run() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 2 || args.len() > 3 {
+        bail!("usage: replay LOG [FILE]");
+    }
+    let (log, defs) = (Path::new(&args[1]), args.get(2).map(String::as_str));
+    let bytes = std::fs::read(log).with_context(|| format!("reading {}", log.display()))?;
+    // Files of Session::save are gzipped, starting with its magic bytes.
+    let history = if bytes.starts_with(&[0x1f, 0x8b]) {
+        Session::from_bytes(new_g(defs)?, &bytes)
+            .with_context(|| format!("loading {}", log.display()))?
+            .history()
+    } else {
+        History::load(log)?
+    };
+
+    let start = std::time::Instant::now();
+    history.replay(new_g(defs)?, ReplaySends::Mock)?;
+    println!(
+        "replayed {} clicks of {} in {:?}",
+        history.clicks.len(),
+        history.protocol,
+        start.elapsed()
+    );
+    Ok(())
+}
@@ -90,6 +90,7 @@ impl Term {
 
     // Converts any lambda term to SKI form.
     // https://en.wikipedia.org/wiki/Combinatory_logic#Completeness_of_the_S-K_basis
+    #[allow(clippy::wrong_self_convention)]
     fn to_ski(self) -> Self {
         match self {
             Term::Ap(x, y) => Term::Ap(x.to_ski().into(), y.to_ski().into()), // Rule 2.
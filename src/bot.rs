@@ -0,0 +1,144 @@
+//! Bots for the aliens' game: a Strategy decides each turn's commands, and a Runner plays it
+//! through a GameClient.
+
+use crate::game::{self, Command, GameClient, GameResponse, ShipState, Stats, Vec2};
+
+use anyhow::Result;
+
+/// Strategy plays one side of a game.
+pub trait Strategy {
+    // The stats to start with, given the response to JOIN.
+    fn stats(&mut self, _join: &GameResponse) -> Stats {
+        DEFAULT_STATS
+    }
+    // The commands for the turn after game.
+    fn commands(&mut self, game: &GameResponse) -> Vec<Command>;
+}
+
+pub const DEFAULT_STATS: Stats = Stats {
+    fuel: 254,
+    power: 0,
+    coolant: 16,
+    spawns: 1,
+};
+
+/// Runner plays a Strategy through a GameClient turn by turn.
+pub struct Runner {
+    client: GameClient,
+    max_turns: usize,
+}
+
+impl Runner {
+    pub fn new(client: GameClient) -> Self {
+        Runner {
+            client,
+            max_turns: game::MAX_TURNS,
+        }
+    }
+    // Gives up games not finished after max_turns turns; the server ends them after 256.
+    pub fn max_turns(mut self, max_turns: usize) -> Self {
+        self.max_turns = max_turns;
+        self
+    }
+    pub fn client(&self) -> &GameClient {
+        &self.client
+    }
+
+    // Plays a game as player_key to the end, returning the last game response.
+    pub fn run(&self, player_key: i64, strategy: &mut dyn Strategy) -> Result<GameResponse> {
+        let join = self.client.join(player_key)?;
+        let res = self.client.start(player_key, strategy.stats(&join))?;
+        self.client
+            .play_turns(player_key, res, self.max_turns, |res| {
+                strategy.commands(res)
+            })
+    }
+}
+
+/// OrbitBot keeps its ships circling the planet counterclockwise, which is enough to survive
+/// gravity and nothing more. It's the reference Strategy.
+#[derive(Default)]
+pub struct OrbitBot;
+
+// The speed along the orbit OrbitBot keeps.
+const ORBIT_SPEED: i64 = 7;
+
+impl OrbitBot {
+    fn thrust(ship: &ShipState) -> Option<Command> {
+        if ship.stats.fuel == 0 {
+            return None;
+        }
+        let p = ship.position;
+        let tangent = Vec2::new(-p.y.signum(), p.x.signum());
+        let speed = ship.velocity.x * tangent.x + ship.velocity.y * tangent.y;
+        if speed >= ORBIT_SPEED {
+            return None;
+        }
+        // Accelerate changes the velocity by the opposite of its vector.
+        Some(Command::Accelerate {
+            ship_id: ship.ship_id,
+            vector: Vec2::new(-tangent.x, -tangent.y),
+        })
+    }
+}
+
+impl Strategy for OrbitBot {
+    fn commands(&mut self, game: &GameResponse) -> Vec<Command> {
+        game.ships(game.role).filter_map(OrbitBot::thrust).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{GameStage, Role},
+        net::LocalSender,
+    };
+
+    fn ship(position: (i64, i64), velocity: (i64, i64), fuel: i64) -> ShipState {
+        ShipState {
+            role: Role::Attacker,
+            ship_id: 0,
+            position: Vec2::new(position.0, position.1),
+            velocity: Vec2::new(velocity.0, velocity.1),
+            stats: Stats {
+                fuel,
+                ..DEFAULT_STATS
+            },
+            heat: 0,
+            max_heat: 64,
+            max_thrust: 1,
+        }
+    }
+
+    #[test]
+    fn test_orbit_bot() {
+        for (ship, want) in [
+            (ship((-48, 0), (0, 0), 10), Some((0, 1))),
+            (ship((48, 0), (0, 0), 10), Some((0, -1))),
+            (ship((0, 48), (-3, 0), 10), Some((1, 0))),
+            (ship((0, 48), (-7, 0), 10), None),
+            (ship((-48, 0), (0, 0), 0), None),
+        ] {
+            let got = OrbitBot::thrust(&ship).map(|c| match c {
+                Command::Accelerate { vector, .. } => (vector.x, vector.y),
+                c => panic!("unexpected {:?}", c),
+            });
+            assert_eq!(got, want, "{:?}", ship);
+        }
+    }
+
+    #[test]
+    fn test_runner() {
+        let runner = Runner::new(GameClient::new(Box::new(LocalSender::new()), ""));
+        let (attacker, _) = runner.client().create().unwrap();
+        let res = runner.run(attacker, &mut OrbitBot).unwrap();
+        assert_eq!(res.stage, GameStage::Finished);
+        assert!(
+            Runner::new(GameClient::new(Box::new(LocalSender::new()), ""))
+                .run(attacker, &mut OrbitBot)
+                .is_err()
+        );
+    }
+}
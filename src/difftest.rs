@@ -0,0 +1,515 @@
+//! Differential testing of interpreters: the same inputs run on two of them are compared,
+//! e.g. one of the fast interpreters against Strict, a plain call-by-value reference.
+
+use crate::{
+    ast::Node,
+    eval::{EvalError, Evaluator},
+    expr::Primitive,
+    fuzz::panic_message,
+    gen_js::GalaxyEvaluator,
+    gmachine::GMachine,
+    protocol::G,
+    reduce_evaluator::{self, MAX_BITS, MAX_CHECKERBOARD},
+};
+
+use num_bigint::BigInt;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::Rc,
+};
+
+/// Interpreter is an Evaluator difftest compares, named as eval::by_name names it.
+pub trait Interpreter: Evaluator {
+    fn name(&self) -> &'static str;
+}
+
+impl Interpreter for reduce_evaluator::Eval {
+    fn name(&self) -> &'static str {
+        "reduce"
+    }
+}
+
+impl Interpreter for GalaxyEvaluator {
+    fn name(&self) -> &'static str {
+        "gen_js"
+    }
+}
+
+impl Interpreter for GMachine {
+    fn name(&self) -> &'static str {
+        "gmachine"
+    }
+}
+
+impl Interpreter for Strict {
+    fn name(&self) -> &'static str {
+        "strict"
+    }
+}
+
+/// Divergence is an input on which two interpreters disagreed. Each side is the value, or the
+/// error or panic message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    pub input: String,
+    pub left: Result<String, String>,
+    pub right: Result<String, String>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?} != {:?}", self.input, self.left, self.right)
+    }
+}
+
+fn run(f: impl FnOnce() -> Result<String, String>) -> Result<String, String> {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|e| Err(panic_message(e)))
+}
+
+// Evaluates every expression with both evaluators, returning the ones they disagree on.
+pub fn compare_exprs(
+    left: &dyn Evaluator,
+    right: &dyn Evaluator,
+    exprs: &[&str],
+) -> Vec<Divergence> {
+    exprs
+        .iter()
+        .filter_map(|expr| {
+            let left = run(|| Ok(left.evaluate(expr).to_string()));
+            let right = run(|| Ok(right.evaluate(expr).to_string()));
+            if left == right {
+                return None;
+            }
+            Some(Divergence {
+                input: expr.to_string(),
+                left,
+                right,
+            })
+        })
+        .collect()
+}
+
+// Evaluates every expression with interpreter and with a Strict given defs, the definitions
+// the expressions need in the form of "f = ap ap ...", returning the ones they disagree on.
+// left is interpreter's side.
+pub fn compare_with_strict(
+    interpreter: &dyn Interpreter,
+    defs: &[&str],
+    exprs: &[&str],
+) -> Vec<Divergence> {
+    let mut strict = Strict::new();
+    for def in defs {
+        strict.add_def(def);
+    }
+    let res = compare_exprs(interpreter, &strict, exprs);
+    for d in &res {
+        tracing::debug!(interpreter = interpreter.name(), %d, "diverged from strict");
+    }
+    res
+}
+
+// Clicks both Gs in lockstep from state and returns the first click whose resulting state or
+// images differ. Clicks after a failure that both Gs agree on are not made.
+pub fn compare_clicks(
+    left: &G,
+    right: &G,
+    protocol: &str,
+    state: &str,
+    clicks: &[(i32, i32)],
+) -> Option<Divergence> {
+    let click = |g: &G, state: &str, (x, y): (i32, i32)| {
+        catch_unwind(AssertUnwindSafe(|| {
+            g.interact(protocol, state.into(), x, y, "")
+        }))
+        .map_err(panic_message)
+        .and_then(|res| res.map_err(|e| e.to_string()))
+        // The state alone is kept to continue from.
+        .map(|res| (format!("{} {:?}", res.state, res.images), res.state))
+    };
+    let mut state = state.to_string();
+    for (i, &c) in clicks.iter().enumerate() {
+        let (l, r) = (click(left, &state, c), click(right, &state, c));
+        if l != r {
+            return Some(Divergence {
+                input: format!("click {} at ({}, {})", i + 1, c.0, c.1),
+                left: l.map(|(shown, _)| shown),
+                right: r.map(|(shown, _)| shown),
+            });
+        }
+        state = l.ok()?.1;
+    }
+    None
+}
+
+/// Strict is the reference interpreter: it evaluates the arguments of every application
+/// before applying the function, with numbers of any size, and caches nothing. Being
+/// obviously right matters more than speed here. Definitions that recurse through t, f or
+/// if0 evaluate both branches and don't terminate; they fail with DivergenceDetected after
+/// MAX_STRICT_DEPTH nested evaluations.
+#[derive(Clone, Debug, Default)]
+pub struct Strict {
+    defs: HashMap<String, Rc<Term>>,
+}
+
+pub const MAX_STRICT_DEPTH: usize = 10_000;
+
+// An expression as written in galaxy.txt.
+#[derive(Clone, Debug)]
+enum Term {
+    Ap(Rc<Term>, Rc<Term>),
+    Num(BigInt),
+    Op(Primitive),
+    Var(String),
+}
+
+// What a Term evaluates to: a number, or a primitive applied to fewer arguments than its
+// arity. Cons cells are cons applied to two.
+#[derive(Clone, Debug)]
+enum Value {
+    Num(BigInt),
+    Fun(Primitive, Vec<Value>),
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Term::Ap(x, y) => write!(f, "ap {} {}", x, y),
+            Term::Num(n) => write!(f, "{}", n),
+            Term::Op(p) => write!(f, "{}", p),
+            Term::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Fun(p, args) => {
+                write!(f, "{}{}", "ap ".repeat(args.len()), p)?;
+                args.iter().try_for_each(|x| write!(f, " {}", x))
+            }
+        }
+    }
+}
+
+impl Strict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn parse(expr: &str) -> Result<Rc<Term>, EvalError> {
+        fn term<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Rc<Term>, EvalError> {
+            let tok = tokens
+                .next()
+                .ok_or_else(|| EvalError::Parse("unexpected end".into()))?;
+            Ok(Rc::new(if tok == "ap" {
+                Term::Ap(term(tokens)?, term(tokens)?)
+            } else if let Ok(n) = tok.parse() {
+                Term::Num(n)
+            } else if let Some(p) = Primitive::from_name(tok) {
+                Term::Op(p)
+            } else {
+                Term::Var(tok.into())
+            }))
+        }
+        let mut tokens = expr.split_whitespace();
+        let res = term(&mut tokens)?;
+        match tokens.next() {
+            Some(tok) => Err(EvalError::Parse(format!("unexpected {:?}", tok))),
+            None => Ok(res),
+        }
+    }
+
+    fn eval(&self, t: &Term, depth: usize) -> Result<Value, EvalError> {
+        if depth > MAX_STRICT_DEPTH {
+            return Err(EvalError::DivergenceDetected {
+                expr: t.to_string().chars().take(80).collect(),
+                definitions: vec![],
+            });
+        }
+        match t {
+            Term::Ap(f, x) => {
+                let f = self.eval(f, depth + 1)?;
+                let x = self.eval(x, depth + 1)?;
+                self.apply(f, x, depth + 1)
+            }
+            Term::Num(n) => Ok(Value::Num(n.clone())),
+            Term::Op(p) => Ok(Value::Fun(*p, vec![])),
+            Term::Var(name) => match self.defs.get(name) {
+                Some(def) => self.eval(def, depth + 1),
+                None => Err(EvalError::UnknownVar(name.clone())),
+            },
+        }
+    }
+
+    fn apply(&self, f: Value, x: Value, depth: usize) -> Result<Value, EvalError> {
+        let (p, mut args) = match f {
+            Value::Fun(p, args) => (p, args),
+            f => return Err(EvalError::NotAFunction(f.to_string())),
+        };
+        args.push(x);
+        if args.len() < p.arity() {
+            return Ok(Value::Fun(p, args));
+        }
+        self.reduce(p, args, depth)
+    }
+
+    // p applied to all its arguments.
+    fn reduce(&self, p: Primitive, args: Vec<Value>, depth: usize) -> Result<Value, EvalError> {
+        use Primitive::*;
+        let num = |v: &Value| match v {
+            Value::Num(n) => Ok(n.clone()),
+            v => Err(EvalError::NotANum(v.to_string())),
+        };
+        let boolean = |b| Value::Fun(if b { T } else { F }, vec![]);
+        let apply = |f: &Value, x: &Value| self.apply(f.clone(), x.clone(), depth + 1);
+        Ok(match (p, args.as_slice()) {
+            (Add, [x, y]) => Value::Num(num(x)? + num(y)?),
+            (Mul, [x, y]) => Value::Num(num(x)? * num(y)?),
+            (Div, [x, y]) => {
+                let y = num(y)?;
+                if y == BigInt::from(0) {
+                    return Err(EvalError::DivisionByZero);
+                }
+                // BigInt division rounds towards zero, as the protocol's does.
+                Value::Num(num(x)? / y)
+            }
+            (Eq, [x, y]) => boolean(num(x)? == num(y)?),
+            (Lt, [x, y]) => boolean(num(x)? < num(y)?),
+            (Neg, [x]) => Value::Num(-num(x)?),
+            (S, [x, y, z]) => self.apply(apply(x, z)?, apply(y, z)?, depth + 1)?,
+            (C, [x, y, z]) => self.apply(apply(x, z)?, y.clone(), depth + 1)?,
+            (B, [x, y, z]) => self.apply(x.clone(), apply(y, z)?, depth + 1)?,
+            (I, [x]) => x.clone(),
+            (T, [x, _]) => x.clone(),
+            (F, [_, y]) => y.clone(),
+            (Cons, [x, y, z]) => self.apply(apply(z, x)?, y.clone(), depth + 1)?,
+            (Car, [x]) => apply(x, &boolean(true))?,
+            (Cdr, [x]) => apply(x, &boolean(false))?,
+            (Nil, [_]) => boolean(true),
+            (Isnil, [x]) => match x {
+                Value::Fun(Nil, args) if args.is_empty() => boolean(true),
+                Value::Fun(Cons, args) if args.len() == 2 => boolean(false),
+                x => return Err(EvalError::NotAValue(x.to_string())),
+            },
+            (If0, [x, y, z]) => {
+                if num(x)? == BigInt::from(0) {
+                    y.clone()
+                } else {
+                    z.clone()
+                }
+            }
+            (Pwr2, [x]) => {
+                let n = num(x)?;
+                if n < BigInt::from(0) {
+                    Value::Num(BigInt::from(0))
+                } else if n <= BigInt::from(MAX_BITS) {
+                    Value::Num(BigInt::from(1) << usize::try_from(&n).unwrap())
+                } else {
+                    return Err(EvalError::Overflow(Pwr2));
+                }
+            }
+            (Checkerboard, [x, _]) => {
+                let n = match i64::try_from(&num(x)?) {
+                    Ok(n) if n <= MAX_CHECKERBOARD => n,
+                    _ => return Err(EvalError::Overflow(Checkerboard)),
+                };
+                let mut res = Value::Fun(Nil, vec![]);
+                for i in (0..n).rev() {
+                    for j in (0..n).rev().filter(|j| (i + j) % 2 == 0) {
+                        let p = Value::Fun(Cons, vec![Value::Num(i.into()), Value::Num(j.into())]);
+                        res = Value::Fun(Cons, vec![p, res]);
+                    }
+                }
+                res
+            }
+            (p, args) => unreachable!("{} applied to {} arguments", p, args.len()),
+        })
+    }
+
+    fn to_node(v: &Value) -> Result<Node, EvalError> {
+        match v {
+            Value::Num(n) => Ok(Node::from(n.clone())),
+            Value::Fun(Primitive::Nil, args) if args.is_empty() => Ok(Node::Nil),
+            Value::Fun(Primitive::Cons, args) if args.len() == 2 => Ok(Node::Cons(
+                Strict::to_node(&args[0])?.into(),
+                Strict::to_node(&args[1])?.into(),
+            )),
+            v => Err(EvalError::NotAValue(v.to_string())),
+        }
+    }
+}
+
+impl Evaluator for Strict {
+    fn evaluate(&self, expr: &str) -> Node {
+        self.try_evaluate(expr).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn add_def(&mut self, s: &str) {
+        let (name, expr) = s
+            .split_once(" = ")
+            .unwrap_or_else(|| panic!("not a definition: {:?}", s));
+        self.try_add_def(name, expr).unwrap();
+    }
+    fn has_def(&self, name: &str) -> bool {
+        self.defs.contains_key(name)
+    }
+    fn try_add_def(&mut self, name: &str, expr: &str) -> anyhow::Result<()> {
+        let def = Strict::parse(expr).map_err(|e| anyhow::anyhow!("defining {}: {}", name, e))?;
+        self.defs.insert(name.into(), def);
+        Ok(())
+    }
+    fn try_evaluate(&self, expr: &str) -> Result<Node, EvalError> {
+        Strict::to_node(&self.eval(Strict::parse(expr)?.as_ref(), 0)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Node, eval};
+
+    // Evaluates everything to 0, to diverge from the real evaluators.
+    struct Zero;
+
+    impl Evaluator for Zero {
+        fn evaluate(&self, _: &str) -> Node {
+            Node::Num(0)
+        }
+        fn add_def(&mut self, _: &str) {}
+        fn has_def(&self, _: &str) -> bool {
+            true
+        }
+    }
+
+    impl Interpreter for Zero {
+        fn name(&self) -> &'static str {
+            "zero"
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let (reduce, gen_js) = (
+            eval::by_name("reduce").unwrap(),
+            eval::by_name("gen_js").unwrap(),
+        );
+        assert!(eval::by_name("vm").is_err());
+
+        let exprs = ["ap ap add 1 2", "ap car ap ap cons 5 nil", "ap ap mul 3 0"];
+        assert_eq!(
+            compare_exprs(reduce.as_ref(), gen_js.as_ref(), &exprs),
+            vec![]
+        );
+        let diffs = compare_exprs(reduce.as_ref(), &Zero, &exprs);
+        assert_eq!(
+            diffs.iter().map(|d| d.input.as_str()).collect::<Vec<_>>(),
+            vec!["ap ap add 1 2", "ap car ap ap cons 5 nil"]
+        );
+        assert_eq!(
+            diffs[0].to_string(),
+            "ap ap add 1 2: Ok(\"3\") != Ok(\"0\")"
+        );
+
+        let def = &crate::testutil::STATEFULDRAW.replacen(":67108929", "statefuldraw", 1);
+        let new_g = |name: &str| {
+            let mut e = eval::by_name(name).unwrap();
+            e.add_def(def);
+            G::builder().interpreter(e).max_rounds(1).build()
+        };
+        let clicks = [(0, 0), (1, 2)];
+        let (left, right) = (new_g("reduce"), new_g("gen_js"));
+        assert_eq!(
+            compare_clicks(&left, &right, "statefuldraw", "nil", &clicks),
+            None
+        );
+
+        let zero = G::builder().interpreter(Box::new(Zero)).build();
+        let d = compare_clicks(&left, &zero, "statefuldraw", "nil", &clicks).unwrap();
+        assert_eq!(d.input, "click 1 at (0, 0)");
+        assert!(d.left.is_ok());
+        assert_eq!(
+            d.right,
+            Err("protocol must return (flag, state, data), got 0".into())
+        );
+    }
+
+    #[test]
+    fn test_strict() {
+        let exprs = [
+            "ap ap add 1 2",
+            "ap ap mul -3 4",
+            "ap ap div -7 2",
+            "ap neg 5",
+            "ap ap ap ap lt 1 2 1 0",
+            "ap ap ap ap eq 3 3 1 0",
+            "ap ap ap s mul ap add 1 6",
+            "ap ap ap c add 1 2",
+            "ap ap ap b neg neg 3",
+            "ap i 4",
+            "ap car ap ap cons 5 nil",
+            "ap cdr ap ap cons 5 nil",
+            "ap ap ap isnil nil 1 0",
+            "ap ap ap nil 0 1 0",
+            "ap ap mul 9223372036854775807 2",
+            "ap ap cons 1 ap ap cons 2 nil",
+            "ap sq 9",
+            // gen_js doesn't know the primitives of the later messages.
+            "ap ap ap if0 1 2 3",
+            "ap pwr2 70",
+            "ap ap checkerboard 3 0",
+        ];
+        let defs = ["sq = ap ap s mul i"];
+        for (mut interpreter, exprs) in [
+            (
+                Box::new(reduce_evaluator::Eval::new()) as Box<dyn Interpreter>,
+                &exprs[..],
+            ),
+            (Box::new(GalaxyEvaluator::new()), &exprs[..17]),
+        ] {
+            interpreter.add_def(defs[0]);
+            assert_eq!(
+                compare_with_strict(interpreter.as_ref(), &defs, exprs),
+                vec![],
+                "{}",
+                interpreter.name()
+            );
+        }
+        let diffs = compare_with_strict(&Zero, &defs, &exprs[..2]);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(
+            diffs[1].to_string(),
+            "ap ap mul -3 4: Ok(\"0\") != Ok(\"-12\")"
+        );
+
+        let mut strict = Strict::new();
+        strict.add_def("loop = ap ap ap if0 0 1 loop");
+        assert_eq!(strict.try_evaluate("ap pwr2 -1"), Ok(Node::Num(0)));
+        assert_eq!(
+            strict.try_evaluate("ap ap div 1 0"),
+            Err(EvalError::DivisionByZero)
+        );
+        assert_eq!(
+            strict.try_evaluate("ap neg x"),
+            Err(EvalError::UnknownVar("x".into()))
+        );
+        assert_eq!(
+            strict.try_evaluate("ap 1 2"),
+            Err(EvalError::NotAFunction("1".into()))
+        );
+        assert_eq!(
+            strict.try_evaluate("ap cons 1"),
+            Err(EvalError::NotAValue("ap cons 1".into()))
+        );
+        assert!(matches!(
+            strict.try_evaluate("loop"),
+            Err(EvalError::DivergenceDetected { .. })
+        ));
+        assert!(strict.try_evaluate("ap ap add 1").is_err());
+        assert!(strict.try_add_def("x", "1 2").is_err());
+        assert_eq!(strict.name(), "strict");
+    }
+}
@@ -0,0 +1,463 @@
+//! The Evaluator interface and its implementations.
+
+use crate::{ast::Node, expr::Primitive};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt::Formatter,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+pub use crate::{
+    expr::{ExprArena, ExprId},
+    gen_js::GalaxyEvaluator,
+    reduce_evaluator::{
+        Env, Eval, ParseError, ParseErrorKind, Trace, TraceLimits, TraceStep, Tracer, Violation,
+    },
+};
+
+/// Evaluator is the common interface of the interpreters, selected per G with
+/// G::builder().interpreter(...).
+pub trait Evaluator {
+    fn evaluate(&self, expr: &str) -> Node;
+    // evaluates "ap ap f x y". Evaluators can override it to avoid printing and parsing x and y.
+    fn evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Node {
+        self.evaluate(&format!("ap ap {} {} {}", f, x, y))
+    }
+    // add definition in the form of "f = ap ap ...".
+    fn add_def(&mut self, s: &str);
+    // whether a definition with the name exists.
+    fn has_def(&self, name: &str) -> bool;
+    // Like add_def with the name and the expression apart, but returns an error instead of
+    // panicking. By default add_def is called, so only its panics tell of errors.
+    fn try_add_def(&mut self, name: &str, expr: &str) -> anyhow::Result<()> {
+        self.add_def(&format!("{} = {}", name, expr));
+        Ok(())
+    }
+    // evaluates expr with names like x0 bound to expressions, leaving the definitions as they
+    // are. Names with a definition can't be bound. By default each binding is substituted
+    // into expr.
+    fn evaluate_with(&self, expr: &str, bindings: &[(&str, &str)]) -> Result<Node, EvalError> {
+        check_bindings(self, bindings)?;
+        let expr = expr
+            .split(' ')
+            .map(|tok| match bindings.iter().find(|(name, _)| *name == tok) {
+                Some((_, value)) => value,
+                None => tok,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.try_evaluate(&expr)
+    }
+    // Like evaluate, but returns an error instead of panicking. By default the panic of
+    // evaluate is caught, which doesn't work where panics abort, e.g. on wasm.
+    fn try_evaluate(&self, expr: &str) -> Result<Node, EvalError> {
+        catch(|| self.evaluate(expr))
+    }
+    fn try_evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Result<Node, EvalError> {
+        catch(|| self.evaluate_ap2(f, x, y))
+    }
+    // drops what the evaluator cached during earlier evaluations. Called when an evaluation
+    // hits the memory limit, before retrying it.
+    fn gc(&self) {}
+    // frees memory unreachable from the evaluator's definitions and results, like reference
+    // cycles. Called by G after each click.
+    fn collect(&self) {}
+    // Number of expression nodes alive, for evaluators keeping them in a heap.
+    fn heap_nodes(&self) -> Option<usize> {
+        None
+    }
+    // The definitions, for evaluators keeping them in an Env, e.g. to browse galaxy.txt.
+    fn env(&self) -> Option<&Env> {
+        None
+    }
+}
+
+// Fails for the first binding naming a definition of eval. Definitions referring to the name
+// would otherwise see the binding, and keep what they evaluate to with it.
+pub(crate) fn check_bindings<E: Evaluator + ?Sized>(
+    eval: &E,
+    bindings: &[(&str, &str)],
+) -> Result<(), EvalError> {
+    match bindings.iter().find(|(name, _)| eval.has_def(name)) {
+        Some((name, _)) => Err(EvalError::Shadowed(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+// Names of the interpreters by_name knows.
+pub const INTERPRETERS: &[&str] = &["reduce", "gen_js", "gmachine"];
+
+// Returns a new interpreter loaded with galaxy: "reduce" for the lazy tree-walker, "gen_js"
+// for the one compiling to JavaScript with BigInt numbers, or "gmachine" for the one
+// compiling to supercombinators.
+pub fn by_name(name: &str) -> anyhow::Result<Box<dyn Evaluator>> {
+    Ok(match name {
+        "reduce" => Box::new(Eval::new()),
+        "gen_js" => Box::new(GalaxyEvaluator::new()),
+        "gmachine" => Box::new(crate::gmachine::GMachine::new()),
+        _ => anyhow::bail!(
+            "unknown interpreter {:?}; expected one of {}",
+            name,
+            INTERPRETERS.join(", ")
+        ),
+    })
+}
+
+/// EvalError is why an evaluation failed. Evaluators return it from try_evaluate so that
+/// embedders like the web pad can show it instead of crashing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EvalError {
+    // The expression is malformed.
+    Parse(String),
+    // A name has no definition.
+    UnknownVar(String),
+    // A binding of evaluate_with names a definition.
+    Shadowed(String),
+    // An argument of a numeric primitive isn't a number. Expressions are abbreviated.
+    NotANum(String),
+    // Something other than a function was applied.
+    NotAFunction(String),
+    // The result isn't built of numbers, nil and cons cells.
+    NotAValue(String),
+    DivisionByZero,
+    // A result of the primitive doesn't fit in the evaluator's fixed-width numbers. The reduce
+    // evaluator switches to big integers instead, and overflows only for results too large to
+    // build, e.g. pwr2 of a million.
+    Overflow(Primitive),
+    // Evaluation needed more live expression nodes than the limit, even after gc.
+    MemoryLimit,
+    // Evaluation took more reductions or time than its EvalBudget.
+    BudgetExceeded,
+    // The value of the expression depends on itself, like x = ap neg x, is infinite, like
+    // x = ap ap cons 0 x, or its reduction nested deeper than Eval::set_max_depth allows,
+    // like g = ap ap b g i applied to anything. expr is where it was detected, abbreviated,
+    // and definitions are the definitions being evaluated then, innermost first. Only the
+    // reduce evaluator detects this; others run out of stack.
+    DivergenceDetected {
+        expr: String,
+        definitions: Vec<String>,
+    },
+    // A check enabled with Eval::set_checks failed.
+    Violation(Violation),
+    // The CancellationToken the evaluation ran with was cancelled.
+    Cancelled,
+    // An evaluator reporting errors by panicking panicked with the message.
+    Panic(String),
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Parse(e) => write!(f, "parse error: {}", e),
+            EvalError::UnknownVar(name) => write!(f, "undefined: {}", name),
+            EvalError::Shadowed(name) => write!(f, "can't bind defined name: {}", name),
+            EvalError::NotANum(e) => write!(f, "not a num: {}", e),
+            EvalError::NotAFunction(e) => write!(f, "not a function: {}", e),
+            EvalError::NotAValue(e) => write!(f, "unconvertible to node: {}", e),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow(p) => write!(f, "{} in {}", OVERFLOW, p),
+            EvalError::MemoryLimit => write!(f, "{}", MEMORY_LIMIT),
+            EvalError::BudgetExceeded => write!(f, "evaluation budget exceeded"),
+            EvalError::DivergenceDetected { expr, definitions } if definitions.is_empty() => {
+                write!(f, "infinite loop: {}", expr)
+            }
+            EvalError::DivergenceDetected { expr, definitions } => {
+                write!(f, "infinite loop in {}: {}", definitions.join(", "), expr)
+            }
+            EvalError::Violation(v) => write!(f, "{}", v),
+            EvalError::Cancelled => write!(f, "evaluation cancelled"),
+            EvalError::Panic(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// EvalBudget bounds the work of an evaluation, so that a UI can abort a click that would
+/// otherwise run effectively forever. Only the reduce evaluator counts reductions; time is
+/// checked every 1024 reductions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvalBudget {
+    pub max_reductions: Option<u64>,
+    pub max_ms: Option<f64>,
+}
+
+impl EvalBudget {
+    pub fn reductions(max_reductions: u64) -> Self {
+        EvalBudget {
+            max_reductions: Some(max_reductions),
+            max_ms: None,
+        }
+    }
+    pub fn millis(max_ms: f64) -> Self {
+        EvalBudget {
+            max_reductions: None,
+            max_ms: Some(max_ms),
+        }
+    }
+    pub fn and_reductions(mut self, max_reductions: u64) -> Self {
+        self.max_reductions = Some(max_reductions);
+        self
+    }
+    pub fn and_millis(mut self, max_ms: f64) -> Self {
+        self.max_ms = Some(max_ms);
+        self
+    }
+
+    // What is left of self after spending reductions and ms.
+    pub(crate) fn remaining(&self, reductions: u64, ms: f64) -> Self {
+        EvalBudget {
+            max_reductions: self.max_reductions.map(|n| n.saturating_sub(reductions)),
+            max_ms: self.max_ms.map(|t| (t - ms).max(0.0)),
+        }
+    }
+
+    // Runs f within self and any budget f is already running in, returning the number of
+    // reductions f made.
+    pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> (T, u64) {
+        struct Restore(Option<Spending>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let used = BUDGET.with(|b| b.get()).map_or(0, |s| s.used);
+                let outer = self.0.map(|mut s| {
+                    s.reductions = s.reductions.saturating_sub(used);
+                    s.used += used;
+                    s
+                });
+                BUDGET.with(|b| b.set(outer));
+            }
+        }
+        let outer = BUDGET.with(|b| b.get());
+        let mut s = Spending {
+            reductions: self.max_reductions.unwrap_or(u64::MAX),
+            deadline_ms: self
+                .max_ms
+                .map_or(f64::INFINITY, |ms| crate::protocol::now_ms() + ms),
+            used: 0,
+        };
+        if let Some(o) = outer {
+            s.reductions = s.reductions.min(o.reductions);
+            s.deadline_ms = s.deadline_ms.min(o.deadline_ms);
+        }
+        let _restore = Restore(outer);
+        BUDGET.with(|b| b.set(Some(s)));
+        let res = f();
+        let used = BUDGET.with(|b| b.get()).map_or(0, |s| s.used);
+        (res, used)
+    }
+}
+
+/// CancellationToken aborts evaluations in progress, e.g. from a stop button of a UI.
+/// Evaluations within CancellationToken::run fail with EvalError::Cancelled at their next
+/// reduction after cancel is called, from any thread. Clones share the token. Like
+/// EvalBudget, only evaluators counting reductions check it.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+    // Makes the token usable for new evaluations after a cancel.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed)
+    }
+
+    // Runs f, whose evaluations fail once self or the token of an enclosing run is cancelled.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        struct Pop;
+        impl Drop for Pop {
+            fn drop(&mut self) {
+                CANCEL.with(|c| c.borrow_mut().pop());
+            }
+        }
+        CANCEL.with(|c| c.borrow_mut().push(self.clone()));
+        let _pop = Pop;
+        f()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Spending {
+    reductions: u64,
+    deadline_ms: f64,
+    used: u64,
+}
+
+thread_local! {
+    // The budget of the running evaluation. See EvalBudget::run.
+    static BUDGET: Cell<Option<Spending>> = const { Cell::new(None) };
+    // The tokens of the running CancellationToken::run calls, innermost last.
+    static CANCEL: RefCell<Vec<CancellationToken>> = const { RefCell::new(Vec::new()) };
+}
+
+// Whether the running evaluation is bounded by a budget or cancellation token, which other
+// threads don't see.
+#[cfg(feature = "parallel")]
+pub(crate) fn bounded() -> bool {
+    BUDGET.with(|b| b.get().is_some()) || CANCEL.with(|c| !c.borrow().is_empty())
+}
+
+// Counts a reduction against the running budget, if any, failing if the evaluation is
+// cancelled.
+pub(crate) fn charge() -> Result<(), EvalError> {
+    if CANCEL.with(|c| c.borrow().iter().any(CancellationToken::is_cancelled)) {
+        return Err(EvalError::Cancelled);
+    }
+    BUDGET.with(|b| {
+        let mut s = match b.get() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        if s.reductions == 0 {
+            return Err(EvalError::BudgetExceeded);
+        }
+        s.reductions -= 1;
+        s.used += 1;
+        b.set(Some(s));
+        if s.used % 1024 == 0 && crate::protocol::now_ms() > s.deadline_ms {
+            return Err(EvalError::BudgetExceeded);
+        }
+        Ok(())
+    })
+}
+
+/// EvalStats is the work done by evaluations, e.g. of a click, so that performance
+/// regressions of the evaluator are measurable. G returns it with every InteractResult. Only
+/// the reduce evaluator counts; others leave everything but elapsed_ms at 0.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EvalStats {
+    // number of reductions of each primitive applied to all its arguments.
+    pub reductions: BTreeMap<Primitive, u64>,
+    // number of definitions looked up by name.
+    pub var_lookups: u64,
+    // evaluations of nodes that had been evaluated before, returning the cached value.
+    pub cache_hits: u64,
+    // evaluations of nodes that hadn't.
+    pub cache_misses: u64,
+    // number of expression nodes allocated.
+    pub allocs: u64,
+    // the most expression nodes alive at once.
+    pub peak_nodes: usize,
+    pub elapsed_ms: f64,
+}
+
+impl EvalStats {
+    // Total number of reductions of all primitives.
+    pub fn total_reductions(&self) -> u64 {
+        self.reductions.values().sum()
+    }
+
+    // Adds the counts of other, e.g. of a later round of the same click.
+    pub fn add(&mut self, other: &EvalStats) {
+        for (p, n) in &other.reductions {
+            *self.reductions.entry(*p).or_default() += n;
+        }
+        self.var_lookups += other.var_lookups;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.allocs += other.allocs;
+        self.peak_nodes = self.peak_nodes.max(other.peak_nodes);
+        self.elapsed_ms += other.elapsed_ms;
+    }
+
+    // Runs f, returning what evaluations within it did. Evaluations within an outer run are
+    // counted by both.
+    pub(crate) fn run<T>(f: impl FnOnce() -> T) -> (T, EvalStats) {
+        struct Restore(Option<EvalStats>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let inner = STATS.with(|s| s.replace(self.0.take()));
+                if let Some(inner) = inner {
+                    count(|s| s.add(&inner));
+                }
+            }
+        }
+        let start = crate::protocol::now_ms();
+        let restore = Restore(STATS.with(|s| s.replace(Some(EvalStats::default()))));
+        let res = f();
+        let mut stats = STATS.with(|s| s.borrow().clone()).unwrap_or_default();
+        stats.elapsed_ms = crate::protocol::now_ms() - start;
+        STATS.with(|s| *s.borrow_mut() = Some(stats.clone()));
+        drop(restore);
+        (res, stats)
+    }
+}
+
+thread_local! {
+    // The counts of the running EvalStats::run, if any.
+    static STATS: RefCell<Option<EvalStats>> = const { RefCell::new(None) };
+}
+
+// Updates the counts of the running EvalStats::run, if any, with f.
+pub(crate) fn count(f: impl FnOnce(&mut EvalStats)) {
+    STATS.with(|s| {
+        if let Some(stats) = s.borrow_mut().as_mut() {
+            f(stats)
+        }
+    })
+}
+
+pub const OVERFLOW: &str = "integer overflow";
+pub const MEMORY_LIMIT: &str = "memory limit exceeded";
+
+// Runs f, turning a panic into EvalError::Panic, or MemoryLimit for evaluators panicking
+// with MEMORY_LIMIT. Panics abort on wasm, so this only helps natively.
+pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Result<T, EvalError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = crate::fuzz::panic_message(payload);
+        if msg.starts_with(MEMORY_LIMIT) {
+            EvalError::MemoryLimit
+        } else {
+            EvalError::Panic(msg)
+        }
+    })
+}
+
+// Runs f, which evaluates with e. If it hits the memory limit, e's caches are dropped and f
+// runs once more.
+pub(crate) fn retry_after_gc<T>(
+    e: &dyn Evaluator,
+    f: impl Fn() -> Result<T, EvalError>,
+) -> Result<T, EvalError> {
+    match f() {
+        Err(EvalError::MemoryLimit) => {
+            tracing::info!("memory limit exceeded; dropping caches and retrying");
+            e.gc();
+            f()
+        }
+        res => res,
+    }
+}
+
+// Splits "ap ap f x y" into f, x and y, for test evaluators that only implement
+// evaluate_ap2 to evaluate the expressions of the default evaluate_ap2 with it.
+#[cfg(test)]
+pub(crate) fn split_ap2(expr: &str) -> Option<(&str, Node, Node)> {
+    // The end of the term starting at toks[i].
+    fn term_end(toks: &[&str], i: usize) -> Option<usize> {
+        match *toks.get(i)? {
+            "ap" => term_end(toks, term_end(toks, i + 1)?),
+            _ => Some(i + 1),
+        }
+    }
+    let (f, rest) = expr.strip_prefix("ap ap ")?.split_once(' ')?;
+    let toks = rest.split(' ').collect::<Vec<_>>();
+    let mid = term_end(&toks, 0)?;
+    let x = toks[..mid].join(" ").parse().ok()?;
+    let y = toks[mid..].join(" ").parse().ok()?;
+    Some((f, x, y))
+}
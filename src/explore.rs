@@ -0,0 +1,359 @@
+//! Automatic exploration of the states of a protocol.
+//!
+//! Explorer crawls the interaction graph from a state by clicking the points the images of
+//! each state suggest (see regions), recording every transition in a StateGraph. States are
+//! identified by their values, so the same state reached by different clicks is expanded once.
+
+use crate::{
+    ast::Node,
+    graph::{state_hash, StateGraph},
+    protocol::G,
+};
+
+use anyhow::{Context, Result};
+use std::collections::{btree_map::Entry, BTreeMap, HashMap, VecDeque};
+
+/// Order is the order an Explorer expands states in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Order {
+    // states nearest to the start first.
+    BreadthFirst,
+    // the most recently found states first, going deep before wide.
+    DepthFirst,
+}
+
+/// Explorer configures an exploration of a protocol. By default it goes breadth first up to
+/// 3 clicks deep, sending at most 100 requests and finding at most 1000 states.
+pub struct Explorer<'a> {
+    g: &'a G,
+    protocol: String,
+    api_key: String,
+    order: Order,
+    max_depth: usize,
+    max_sends: usize,
+    max_states: usize,
+    render_click: (i64, i64),
+}
+
+/// Exploration is what an Explorer found.
+#[derive(Debug, Default)]
+pub struct Exploration {
+    pub graph: StateGraph,
+    // the states found, by state_hash, with the number of clicks from the start.
+    pub states: BTreeMap<u64, (Node, usize)>,
+    pub clicks: usize,
+    pub sends: usize,
+    // clicks that failed, from the state with the hash, with the error.
+    pub failures: Vec<(u64, (i64, i64), String)>,
+    // whether every state within the depth was expanded, i.e. the other budgets were enough.
+    pub complete: bool,
+}
+
+impl<'a> Explorer<'a> {
+    pub fn new(g: &'a G, protocol: &str) -> Self {
+        Explorer {
+            g,
+            protocol: protocol.into(),
+            api_key: String::new(),
+            order: Order::BreadthFirst,
+            max_depth: 3,
+            max_sends: 100,
+            max_states: 1000,
+            render_click: (-1000, -1000),
+        }
+    }
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = api_key.into();
+        self
+    }
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+    // Maximum number of clicks from the start.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    // Requests to the aliens after which no more clicks are made.
+    pub fn max_sends(mut self, max_sends: usize) -> Self {
+        self.max_sends = max_sends;
+        self
+    }
+    pub fn max_states(mut self, max_states: usize) -> Self {
+        self.max_states = max_states;
+        self
+    }
+    // The click drawing the start state, away from anything clickable. (-1000, -1000) by
+    // default, as for the galaxy.
+    pub fn render_click(mut self, click: (i64, i64)) -> Self {
+        self.render_click = click;
+        self
+    }
+
+    // Explores from start, a state like "nil". Fails only if start can't be drawn.
+    pub fn run(&self, start: &str) -> Result<Exploration> {
+        let start: Node = start.parse().context("invalid start state")?;
+        let mut res = Exploration::default();
+        let out = self
+            .g
+            .interact_expr(
+                &self.protocol,
+                start.clone(),
+                self.render_click,
+                &self.api_key,
+            )
+            .context("drawing the start state")?;
+        res.graph.add_output(&start, self.render_click, &out);
+        res.clicks += 1;
+        res.sends += out.sends.len();
+        res.states.insert(state_hash(&start), (start, 0));
+        res.states
+            .insert(state_hash(&out.state), (out.state.clone(), 0));
+        let mut queue = VecDeque::new();
+        queue.push_back((out.state, out.images, 0));
+
+        // The depth each state was expanded at. Depth first, a state can be found again
+        // nearer to the start after it was expanded, and is then expanded again from there.
+        let mut expanded = HashMap::new();
+        let next = |queue: &mut VecDeque<_>| match self.order {
+            Order::BreadthFirst => queue.pop_front(),
+            Order::DepthFirst => queue.pop_back(),
+        };
+        while let Some((state, images, depth)) = next(&mut queue) {
+            let h = state_hash(&state);
+            if depth >= self.max_depth || expanded.get(&h).is_some_and(|&d| d <= depth) {
+                continue;
+            }
+            expanded.insert(h, depth);
+            let layers = images
+                .iter()
+                .map(|img| crate::render::Image::from_points(img))
+                .collect::<Vec<_>>();
+            for s in crate::regions::suggestions(&layers) {
+                if res.sends >= self.max_sends || res.states.len() >= self.max_states {
+                    return Ok(res);
+                }
+                let click = (s.x as i64, s.y as i64);
+                res.clicks += 1;
+                let out =
+                    match self
+                        .g
+                        .interact_expr(&self.protocol, state.clone(), click, &self.api_key)
+                    {
+                        Ok(out) => out,
+                        Err(e) => {
+                            res.failures
+                                .push((state_hash(&state), click, e.to_string()));
+                            continue;
+                        }
+                    };
+                res.graph.add_output(&state, click, &out);
+                res.sends += out.sends.len();
+                match res.states.entry(state_hash(&out.state)) {
+                    Entry::Vacant(e) => {
+                        e.insert((out.state.clone(), depth + 1));
+                    }
+                    Entry::Occupied(mut e) if e.get().1 > depth + 1 => e.get_mut().1 = depth + 1,
+                    Entry::Occupied(_) => continue,
+                }
+                queue.push_back((out.state, out.images, depth + 1));
+            }
+        }
+        res.complete = true;
+        Ok(res)
+    }
+}
+
+impl Exploration {
+    // The graph in DOT, with each state labeled by its depth and its value, cut at 40
+    // characters, and each click by its coordinates.
+    pub fn to_dot(&self) -> String {
+        self.graph.to_dot_with(|s| match self.states.get(&s) {
+            Some((state, depth)) => {
+                let mut state = state.to_string();
+                if state.len() > 40 {
+                    state.truncate(37);
+                    state.push_str("...");
+                }
+                format!("depth {}\\n{}", depth, state)
+            }
+            None => format!("{:016x}", s),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluator;
+
+    // Protocol with state n in 0..4 drawing a button at (10 n, 0), which moves to n + 1 mod
+    // 4, and a dot at (0, 10), which sends a request and resets to 0.
+    struct Buttons;
+
+    impl Evaluator for Buttons {
+        fn evaluate(&self, expr: &str) -> Node {
+            let (f, state, vector) = crate::eval::split_ap2(expr)
+                .unwrap_or_else(|| panic!("unexpected expression {}", expr));
+            self.evaluate_ap2(f, &state, &vector)
+        }
+        fn evaluate_ap2(&self, _: &str, state: &Node, vector: &Node) -> Node {
+            let n = match state {
+                Node::Num(n) => *n,
+                _ => 0,
+            };
+            let click = vector.as_point().unwrap();
+            let (flag, n) = if click == (0, 10) && n != 0 {
+                (1, 0)
+            } else if (10 * n..10 * n + 2).contains(&click.0) && (0..2).contains(&click.1) {
+                (0, (n + 1) % 4)
+            } else {
+                (0, n)
+            };
+            let point = |x, y| Node::Cons(Node::Num(x).into(), Node::Num(y).into());
+            let button = Node::list(
+                vec![(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .into_iter()
+                    .map(|(x, y)| point(10 * n + x, y)),
+            );
+            let data = if flag == 1 {
+                Node::Nil
+            } else {
+                Node::list(vec![button, Node::list(vec![point(0, 10)])])
+            };
+            Node::list(vec![Node::Num(flag), Node::Num(n), data])
+        }
+        // buttons is the only definition, so there is nothing to add to.
+        fn add_def(&mut self, _: &str) {}
+        fn has_def(&self, name: &str) -> bool {
+            name == "buttons"
+        }
+    }
+
+    // Protocol with states 0 to 4, where state n draws a 2x2 square at (10 t, 0) for each
+    // state t a click on it moves to: 0 to 1 and 2, 1 to 4, 2 to 3, and 3 to 4.
+    struct Diamond;
+
+    impl Diamond {
+        fn next(n: i64) -> &'static [i64] {
+            match n {
+                0 => &[1, 2],
+                1 | 3 => &[4],
+                2 => &[3],
+                _ => &[],
+            }
+        }
+    }
+
+    impl Evaluator for Diamond {
+        fn evaluate(&self, expr: &str) -> Node {
+            let (f, state, vector) = crate::eval::split_ap2(expr)
+                .unwrap_or_else(|| panic!("unexpected expression {}", expr));
+            self.evaluate_ap2(f, &state, &vector)
+        }
+        fn evaluate_ap2(&self, _: &str, state: &Node, vector: &Node) -> Node {
+            let n = match state {
+                Node::Num(n) => *n,
+                _ => 0,
+            };
+            let (x, y) = vector.as_point().unwrap();
+            let n = Diamond::next(n)
+                .iter()
+                .copied()
+                .find(|t| (10 * t..10 * t + 2).contains(&x) && (0..2).contains(&y))
+                .unwrap_or(n);
+            let point = |x, y| Node::Cons(Node::Num(x).into(), Node::Num(y).into());
+            let squares = Diamond::next(n).iter().map(|t| {
+                Node::list(
+                    vec![(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .into_iter()
+                        .map(|(x, y)| point(10 * t + x, y)),
+                )
+            });
+            Node::list(vec![Node::Num(0), Node::Num(n), Node::list(squares)])
+        }
+        // diamond is the only definition, so there is nothing to add to.
+        fn add_def(&mut self, _: &str) {}
+        fn has_def(&self, name: &str) -> bool {
+            name == "diamond"
+        }
+    }
+
+    // Answers every request with the click (0, 0).
+    struct Origin;
+
+    impl crate::net::Sender for Origin {
+        fn send(&self, _: &Node, _: &str) -> Result<Node> {
+            Ok("ap ap cons 0 0".parse().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_explore() {
+        let mut g = G::new(Box::new(Buttons));
+        g.set_sender(Box::new(Origin));
+        let res = Explorer::new(&g, "buttons")
+            .max_depth(10)
+            .run("nil")
+            .unwrap();
+        assert!(res.complete);
+        let states = res
+            .states
+            .values()
+            .map(|(s, depth)| (s.to_string(), *depth))
+            .collect::<BTreeMap<_, _>>();
+        let want = vec![("nil", 0), ("0", 0), ("1", 1), ("2", 2), ("3", 3)];
+        assert_eq!(
+            states,
+            want.into_iter().map(|(s, d)| (s.to_string(), d)).collect()
+        );
+        // The button and the dot of each of the 4 states, and the render click.
+        assert_eq!(res.graph.edges().count(), 9);
+        assert_eq!(res.sends, 3);
+        let dot = res.to_dot();
+        assert!(dot.contains("[label=\"depth 2\\n2\"];"));
+        assert!(dot.contains("[label=\"(11, 1)\""));
+
+        let res = Explorer::new(&g, "buttons")
+            .order(Order::DepthFirst)
+            .max_depth(2)
+            .run("nil")
+            .unwrap();
+        assert_eq!(res.states.len(), 4);
+        let res = Explorer::new(&g, "buttons")
+            .max_depth(10)
+            .max_sends(1)
+            .run("nil")
+            .unwrap();
+        assert!(!res.complete);
+        assert_eq!(res.sends, 1);
+    }
+
+    #[test]
+    fn test_depth_first() {
+        let g = G::new(Box::new(Diamond));
+        for order in [Order::BreadthFirst, Order::DepthFirst] {
+            let res = Explorer::new(&g, "diamond")
+                .order(order)
+                .max_depth(10)
+                .run("nil")
+                .unwrap();
+            assert!(res.complete);
+            let depths = res
+                .states
+                .values()
+                .map(|(s, depth)| (s.to_string(), *depth))
+                .collect::<Vec<_>>();
+            let want = [("nil", 0), ("0", 0), ("1", 1), ("2", 1), ("3", 2), ("4", 2)];
+            for (s, depth) in want {
+                assert!(
+                    depths.contains(&(s.to_string(), depth)),
+                    "{:?}: {}",
+                    order,
+                    s
+                );
+            }
+        }
+    }
+}
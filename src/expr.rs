@@ -0,0 +1,685 @@
+//! The expression graphs of the reduce evaluator, and the primitives of galaxy.txt.
+//!
+//! An Expr is one node of an expression; its subexpressions are CachedExprs, reference
+//! counted nodes in a per-thread heap that also cache what they evaluate to. ExprArena
+//! interns nodes so that identical subexpressions are shared. Evaluation itself lives in
+//! reduce_evaluator.
+
+use lazy_static::lazy_static;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    rc::Rc,
+};
+
+lazy_static! {
+    pub(crate) static ref STR_PRIMITIVE: HashMap<&'static str, Primitive> = {
+        use Primitive::*;
+        let mut m = HashMap::new();
+        m.insert("add", Add);
+        m.insert("mul", Mul);
+        m.insert("div", Div);
+        m.insert("eq", Eq);
+        m.insert("lt", Lt);
+        m.insert("neg", Neg);
+        m.insert("s", S);
+        m.insert("c", C);
+        m.insert("b", B);
+        m.insert("i", I);
+        m.insert("f", F);
+        m.insert("t", T);
+        m.insert("cons", Cons);
+        m.insert("car", Car);
+        m.insert("cdr", Cdr);
+        m.insert("nil", Nil);
+        m.insert("isnil", Isnil);
+        m.insert("if0", If0);
+        m.insert("pwr2", Pwr2);
+        m.insert("checkerboard", Checkerboard);
+        m
+    };
+    static ref PRIMITIVE_STR: HashMap<Primitive, &'static str> = {
+        let mut res = HashMap::new();
+        STR_PRIMITIVE.iter().for_each(|(k, v)| {
+            res.insert(*v, *k);
+        });
+        res
+    };
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(into = "ExprAst", from = "ExprAst")]
+pub enum Expr {
+    Ap(CachedExpr, CachedExpr),
+    Op(
+        Primitive,
+        Option<CachedExpr>,
+        Option<CachedExpr>,
+        Option<CachedExpr>,
+    ),
+    Num(i64),
+    // A number outside i64. Numbers that fit are always Num.
+    Big(Rc<BigInt>),
+    Var(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Primitive {
+    Add,   // x y   => x + y
+    Mul,   // x y   => x * y
+    Div,   // x y   => x / y
+    Eq,    // x y   => x == y
+    Lt,    // x y   => x <= y
+    Neg,   // x     => -x
+    S,     // x y z => (x z) (y z)  !
+    C,     // x y z => (x z) y
+    B,     // x y z => x (y z)
+    I,     // x     => x
+    F,     // x y   => y  !
+    T,     // x y   => x  !
+    Cons,  // x y z => (z x) y
+    Car,   // x     => x T
+    Cdr,   // x     => x F
+    Nil,   // x     => T
+    Isnil, // x     => x == Nil ? T : F
+    // From the later messages, for transcribed examples.
+    If0,          // x y z => x == 0 ? y : z
+    Pwr2,         // x     => 2^x, 0 for negative x
+    Checkerboard, // x y   => list of points (i, j) in [0, x)^2 with even i + j
+}
+
+// ExprAst is the serialized form of Expr, e.g. {"ap":[{"op":"neg"},{"num":1}]}.
+// Arguments held by Op are written as applications, so they come back as Ap.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExprAst {
+    Ap(Box<ExprAst>, Box<ExprAst>),
+    Op(Primitive),
+    Num(i64),
+    // written as a string, e.g. {"big":"9223372036854775808"}.
+    Big(String),
+    Var(String),
+}
+
+impl From<Expr> for ExprAst {
+    fn from(e: Expr) -> Self {
+        match e {
+            Expr::Ap(l, r) => ExprAst::Ap(Box::new(l.expr().into()), Box::new(r.expr().into())),
+            Expr::Op(p, x, y, z) => [x, y, z].iter().flatten().fold(ExprAst::Op(p), |f, e| {
+                ExprAst::Ap(Box::new(f), Box::new(e.expr().into()))
+            }),
+            Expr::Num(i) => ExprAst::Num(i),
+            Expr::Big(i) => ExprAst::Big(i.to_string()),
+            Expr::Var(s) => ExprAst::Var(s),
+        }
+    }
+}
+
+impl From<ExprAst> for Expr {
+    fn from(e: ExprAst) -> Self {
+        match e {
+            ExprAst::Ap(l, r) => Expr::Ap(Expr::from(*l).into(), Expr::from(*r).into()),
+            ExprAst::Op(p) => Expr::op(p),
+            ExprAst::Num(i) => Expr::Num(i),
+            // Expr has no fallible conversion from ExprAst, so a malformed number becomes a
+            // variable that fails to evaluate.
+            ExprAst::Big(i) => match i.parse() {
+                Ok(i) => Expr::num(i),
+                Err(_) => Expr::Var(i),
+            },
+            ExprAst::Var(s) => Expr::Var(s),
+        }
+    }
+}
+
+impl Primitive {
+    // Number of arguments the primitive takes before it reduces.
+    pub fn arity(self) -> usize {
+        use Primitive::*;
+        match self {
+            Neg | I | Car | Cdr | Nil | Isnil | Pwr2 => 1,
+            Add | Mul | Div | Eq | Lt | F | T | Checkerboard => 2,
+            S | C | B | Cons | If0 => 3,
+        }
+    }
+    // The primitive named s in galaxy.txt's notation, e.g. "add". "vec" is cons.
+    pub fn from_name(s: &str) -> Option<Primitive> {
+        STR_PRIMITIVE
+            .get(if s == "vec" { "cons" } else { s })
+            .copied()
+    }
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", PRIMITIVE_STR.get(self).unwrap())
+    }
+}
+
+use Expr::*;
+
+impl Expr {
+    pub(crate) fn boolean(b: bool) -> Expr {
+        if b {
+            Expr::op(Primitive::T)
+        } else {
+            Expr::op(Primitive::F)
+        }
+    }
+    pub(crate) fn op(p: Primitive) -> Expr {
+        Op(p, None, None, None)
+    }
+
+    // The nodes self refers to.
+    pub(crate) fn children(&self) -> impl Iterator<Item = &CachedExpr> {
+        let (x, y, z) = match self {
+            Ap(l, r) => (Some(l), Some(r), None),
+            Op(_, x, y, z) => (x.as_ref(), y.as_ref(), z.as_ref()),
+            _ => (None, None, None),
+        };
+        x.into_iter().chain(y).chain(z)
+    }
+
+    pub(crate) fn cons(hd: CachedExpr, tl: CachedExpr) -> Expr {
+        Op(Primitive::Cons, Some(hd), Some(tl), None)
+    }
+    pub(crate) fn nil() -> Expr {
+        Expr::op(Primitive::Nil)
+    }
+    // Num if n fits in i64 and Big otherwise.
+    pub(crate) fn num(n: BigInt) -> Expr {
+        match i64::try_from(&n) {
+            Ok(i) => Num(i),
+            Err(_) => Big(Rc::new(n)),
+        }
+    }
+}
+
+// The nodes of the expressions on this thread, in one vector indexed by ExprId. Nodes are
+// reference counted by hand, and freed slots are reused, so that evaluation, which
+// allocates a node for nearly every step, rarely allocates memory.
+//
+// A node is only accessed in short closures of CachedExpr::read and write, which must not
+// allocate or free nodes. Reference counts are kept apart from the nodes so that cloning
+// within the closures is fine.
+struct Heap {
+    nodes: RefCell<Vec<Option<Cache>>>,
+    meta: RefCell<Vec<Meta>>,
+    free: RefCell<Vec<u32>>,
+    // live nodes after the last collection. See Heap::collect_if_grown.
+    collected: std::cell::Cell<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Meta {
+    refs: u32,
+    // bumped when the slot is freed, telling its nodes apart. See WeakExpr.
+    generation: u32,
+}
+
+thread_local! {
+    static HEAP: Heap = const {
+        Heap {
+            nodes: RefCell::new(Vec::new()),
+            meta: RefCell::new(Vec::new()),
+            free: RefCell::new(Vec::new()),
+            collected: std::cell::Cell::new(0),
+        }
+    };
+}
+
+impl Heap {
+    fn live(&self) -> usize {
+        self.nodes.borrow().len() - self.free.borrow().len()
+    }
+
+    // Frees the nodes unreachable from outside the heap, which reference counting misses when
+    // they form cycles, e.g. a list whose tail is a shared thunk evaluating to the list. Nodes
+    // referenced more often than by other nodes are held from outside; everything reachable
+    // from them is marked, and the rest is emptied, which drops the references among it.
+    // Returns the number of nodes freed.
+    fn collect(&self) -> usize {
+        let garbage = {
+            let nodes = self.nodes.borrow();
+            let meta = self.meta.borrow();
+            let mut inner = vec![0; nodes.len()];
+            for c in nodes.iter().flatten() {
+                for child in c.expr.children() {
+                    inner[child.id.0 as usize] += 1;
+                }
+            }
+            let mut marked = vec![false; nodes.len()];
+            let mut stack = (0..nodes.len())
+                .filter(|&i| nodes[i].is_some() && meta[i].refs > inner[i])
+                .collect::<Vec<_>>();
+            while let Some(i) = stack.pop() {
+                if std::mem::replace(&mut marked[i], true) {
+                    continue;
+                }
+                let c = nodes[i].as_ref().unwrap();
+                stack.extend(c.expr.children().map(|child| child.id.0 as usize));
+            }
+            (0..nodes.len())
+                .filter(|&i| nodes[i].is_some() && !marked[i])
+                .collect::<Vec<_>>()
+        };
+        let exprs = {
+            let mut nodes = self.nodes.borrow_mut();
+            garbage
+                .iter()
+                .map(|&i| std::mem::replace(&mut nodes[i].as_mut().unwrap().expr, Num(0)))
+                .collect::<Vec<_>>()
+        };
+        drop(exprs);
+        self.collected.set(self.live());
+        garbage.len()
+    }
+
+    // Collects if the heap has doubled since the last collection, so that collecting between
+    // clicks costs time proportional to the garbage.
+    fn collect_if_grown(&self) {
+        if self.live() > 2 * self.collected.get() {
+            self.collect();
+        }
+    }
+}
+
+// Number of expression nodes alive on this thread.
+pub(crate) fn live_nodes() -> usize {
+    HEAP.with(Heap::live)
+}
+
+// Frees the nodes on this thread unreachable from outside the heap. See Heap::collect.
+pub(crate) fn collect() -> usize {
+    HEAP.with(Heap::collect)
+}
+
+// Collects if the heap has grown enough since the last collection.
+pub(crate) fn collect_if_grown() {
+    HEAP.with(Heap::collect_if_grown)
+}
+
+/// ExprId is the index of a node of an expression graph in its thread's heap.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ExprId(u32);
+
+// A node that may have been freed, like std::rc::Weak.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct WeakExpr {
+    id: ExprId,
+    generation: u32,
+}
+
+impl WeakExpr {
+    fn upgrade(self) -> Option<CachedExpr> {
+        HEAP.with(|h| {
+            let mut meta = h.meta.borrow_mut();
+            let m = &mut meta[self.id.0 as usize];
+            if m.refs == 0 || m.generation != self.generation {
+                return None;
+            }
+            m.refs += 1;
+            Some(CachedExpr::from_id(self.id))
+        })
+    }
+    fn is_alive(self) -> bool {
+        HEAP.with(|h| {
+            let m = h.meta.borrow()[self.id.0 as usize];
+            m.refs > 0 && m.generation == self.generation
+        })
+    }
+}
+
+impl From<Expr> for CachedExpr {
+    fn from(expr: Expr) -> CachedExpr {
+        crate::eval::count(|s| s.allocs += 1);
+        let cache = Cache {
+            expr,
+            state: Thunk::Unevaluated,
+        };
+        HEAP.with(|h| {
+            let free = h.free.borrow_mut().pop();
+            let i = match free {
+                Some(i) => {
+                    h.nodes.borrow_mut()[i as usize] = Some(cache);
+                    h.meta.borrow_mut()[i as usize].refs = 1;
+                    i
+                }
+                None => {
+                    let mut nodes = h.nodes.borrow_mut();
+                    nodes.push(Some(cache));
+                    h.meta.borrow_mut().push(Meta {
+                        refs: 1,
+                        generation: 0,
+                    });
+                    u32::try_from(nodes.len() - 1).expect("too many expression nodes")
+                }
+            };
+            CachedExpr::from_id(ExprId(i))
+        })
+    }
+}
+
+impl Clone for CachedExpr {
+    fn clone(&self) -> Self {
+        HEAP.with(|h| h.meta.borrow_mut()[self.id.0 as usize].refs += 1);
+        CachedExpr::from_id(self.id)
+    }
+}
+
+impl Drop for CachedExpr {
+    fn drop(&mut self) {
+        let i = self.id.0 as usize;
+        // The heap may already be gone when a thread exits.
+        let _ = HEAP.try_with(|h| {
+            let freed = {
+                let mut meta = h.meta.borrow_mut();
+                meta[i].refs -= 1;
+                if meta[i].refs == 0 {
+                    meta[i].generation = meta[i].generation.wrapping_add(1);
+                }
+                meta[i].refs == 0
+            };
+            if freed {
+                let cache = h.nodes.borrow_mut()[i].take();
+                h.free.borrow_mut().push(self.id.0);
+                // frees the children, after the heap is released.
+                drop(cache);
+            }
+        });
+    }
+}
+
+pub struct CachedExpr {
+    id: ExprId,
+    // Nodes belong to the heap of their thread.
+    _thread: std::marker::PhantomData<Rc<()>>,
+}
+
+impl std::fmt::Debug for CachedExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (expr, state) = self.read(|c| (c.expr.clone(), c.state));
+        f.debug_struct("CachedExpr")
+            .field("id", &self.id)
+            .field("expr", &expr)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// Interned expressions are compared by id before falling back to their structure.
+impl PartialEq for CachedExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.expr() == other.expr()
+    }
+}
+impl Eq for CachedExpr {}
+
+pub(crate) struct Cache {
+    pub(crate) expr: Expr,
+    pub(crate) state: Thunk,
+}
+
+// How far the expression of a node has been evaluated. Evaluation proceeds
+// Unevaluated -> InProgress -> Evaluated -> Reducing -> Reduced, and an error on the way goes
+// back to the state before.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Thunk {
+    Unevaluated,
+    // expr is being evaluated. Needing its value meanwhile is a loop, like x = ap neg x.
+    InProgress,
+    // expr is a number or a primitive short of arguments.
+    Evaluated,
+    // the arguments of expr are being reduced. Reaching it again means the value is
+    // infinite, like x = ap ap cons 0 x.
+    Reducing,
+    // expr is a number or a primitive whose arguments are Reduced, recursively.
+    Reduced,
+}
+
+// Marks a node with a state while it is evaluated, going back to the state before unless
+// finished, so that failed or panicked evaluations can be retried.
+pub(crate) struct Blackhole<'a> {
+    node: &'a CachedExpr,
+    before: Thunk,
+}
+
+impl<'a> Blackhole<'a> {
+    pub(crate) fn new(node: &'a CachedExpr, state: Thunk) -> Self {
+        let before = node.write(|c| std::mem::replace(&mut c.state, state));
+        Blackhole { node, before }
+    }
+    pub(crate) fn finish(self, expr: Expr, state: Thunk) {
+        let old = self.node.write(|c| {
+            c.state = state;
+            std::mem::replace(&mut c.expr, expr)
+        });
+        std::mem::forget(self);
+        drop(old);
+    }
+}
+
+impl Drop for Blackhole<'_> {
+    fn drop(&mut self) {
+        let before = self.before;
+        // A panic may have left the heap borrowed.
+        let _ = HEAP.try_with(|h| {
+            if let Ok(mut nodes) = h.nodes.try_borrow_mut() {
+                if let Some(c) = nodes[self.node.id.0 as usize].as_mut() {
+                    c.state = before;
+                }
+            }
+        });
+    }
+}
+
+/// ExprArena interns expressions, so that structurally identical subexpressions are one
+/// shared node.
+///
+/// Sharing saves memory on galaxy.txt, where the same applications recur throughout, and
+/// also work, as a shared node is evaluated once. Nodes are keyed by their children's
+/// ids, so interning is bottom-up and O(1) per node, and interned nodes are equal iff
+/// they are the same node. Clones share the table.
+#[derive(Clone, Default)]
+pub struct ExprArena {
+    table: Rc<RefCell<InternTable>>,
+}
+
+#[derive(Default)]
+struct InternTable {
+    nodes: HashMap<InternKey, WeakExpr>,
+    // size after the last sweep of dead entries.
+    swept: usize,
+}
+
+#[derive(Eq, Hash, PartialEq)]
+enum InternKey {
+    Ap(WeakExpr, WeakExpr),
+    Op(Primitive, [Option<WeakExpr>; 3]),
+    Num(i64),
+    Big(BigInt),
+    Var(String),
+}
+
+impl ExprArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The node for e, shared with earlier expressions of the same structure if any are
+    // alive. e's children should be interned for it to be found.
+    pub fn intern(&self, e: Expr) -> CachedExpr {
+        let key = ExprArena::key(&e);
+        if let Some(node) = self.get(&key) {
+            return node;
+        }
+        let res = CachedExpr::from(e);
+        self.insert(key, &res);
+        res
+    }
+
+    // Interns node as it is, unless a node of the same structure is already interned.
+    pub(crate) fn share(&self, node: &CachedExpr) {
+        let key = ExprArena::key(&node.expr());
+        if self.get(&key).is_none() {
+            self.insert(key, node);
+        }
+    }
+
+    fn get(&self, key: &InternKey) -> Option<CachedExpr> {
+        let node = *self.table.borrow().nodes.get(key)?;
+        node.upgrade()
+    }
+
+    fn key(e: &Expr) -> InternKey {
+        let weak = CachedExpr::weak;
+        match e {
+            Ap(l, r) => InternKey::Ap(weak(l), weak(r)),
+            Op(p, x, y, z) => InternKey::Op(*p, [x, y, z].map(|c| c.as_ref().map(weak))),
+            Num(i) => InternKey::Num(*i),
+            Big(i) => InternKey::Big((**i).clone()),
+            Var(name) => InternKey::Var(name.clone()),
+        }
+    }
+
+    fn insert(&self, key: InternKey, node: &CachedExpr) {
+        let mut table = self.table.borrow_mut();
+        table.nodes.insert(key, node.weak());
+        if table.nodes.len() > 2 * table.swept + 1024 {
+            table.nodes.retain(|_, n| n.is_alive());
+            table.swept = table.nodes.len();
+        }
+    }
+
+    // The live nodes interned for names, by id. They are the nodes evaluation ties into
+    // cycles through recursive definitions.
+    pub(crate) fn var_nodes(&self) -> HashMap<ExprId, String> {
+        let table = self.table.borrow();
+        table
+            .nodes
+            .iter()
+            .filter_map(|(key, node)| match key {
+                InternKey::Var(name) => Some((node.upgrade()?.id(), name.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Number of entries, including ones whose nodes have been dropped since the last sweep.
+    pub fn len(&self) -> usize {
+        self.table.borrow().nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Forgets all nodes, so that later expressions don't share with earlier ones.
+    pub fn clear(&self) {
+        *self.table.borrow_mut() = InternTable::default();
+    }
+}
+
+impl std::fmt::Debug for ExprArena {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExprArena")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl CachedExpr {
+    // Replaces the cached expression with expr, to be evaluated again.
+    pub(crate) fn reset(&self, expr: Expr) {
+        let old = self.write(|c| {
+            c.state = Thunk::Unevaluated;
+            std::mem::replace(&mut c.expr, expr)
+        });
+        drop(old);
+    }
+
+    pub(crate) fn expr(&self) -> Expr {
+        self.read(|c| c.expr.clone())
+    }
+    // Whether self and other are the same node, e.g. interned expressions of the same
+    // structure.
+    pub fn ptr_eq(&self, other: &CachedExpr) -> bool {
+        self.id == other.id
+    }
+    // The index of the node in the heap of the thread. It may be reused once the node is
+    // freed.
+    pub fn id(&self) -> ExprId {
+        self.id
+    }
+
+    // Takes a reference counted by the caller.
+    fn from_id(id: ExprId) -> Self {
+        CachedExpr {
+            id,
+            _thread: std::marker::PhantomData,
+        }
+    }
+    fn weak(&self) -> WeakExpr {
+        let generation = HEAP.with(|h| h.meta.borrow()[self.id.0 as usize].generation);
+        WeakExpr {
+            id: self.id,
+            generation,
+        }
+    }
+    pub(crate) fn read<R>(&self, f: impl FnOnce(&Cache) -> R) -> R {
+        HEAP.with(|h| f(h.nodes.borrow()[self.id.0 as usize].as_ref().unwrap()))
+    }
+    pub(crate) fn write<R>(&self, f: impl FnOnce(&mut Cache) -> R) -> R {
+        HEAP.with(|h| f(h.nodes.borrow_mut()[self.id.0 as usize].as_mut().unwrap()))
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Ap(l, r) => write!(f, "ap {} {}", l.expr(), r.expr()),
+            Expr::Op(s, x, y, z) => {
+                let args = [x, y, z].iter().copied().flatten().collect::<Vec<_>>();
+                for _ in 0..args.len() {
+                    write!(f, "ap ")?;
+                }
+                write!(f, "{}", s)?;
+                for e in args {
+                    write!(f, " {}", e.expr())?;
+                }
+                Ok(())
+            }
+            Expr::Num(i) => write!(f, "{}", i),
+            Expr::Big(i) => write!(f, "{}", i),
+            Expr::Var(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heap() {
+        let before = live_nodes();
+        let x = CachedExpr::from(Num(1));
+        let weak = x.weak();
+        let y = CachedExpr::from(Ap(x.clone(), x.clone()));
+        assert_eq!(live_nodes(), before + 2);
+        drop(x);
+        assert_eq!(weak.upgrade().map(|x| x.expr()), Some(Num(1)));
+        // Dropping y frees x, whose slot is reused by the next node.
+        let id = y.id();
+        drop(y);
+        assert_eq!(live_nodes(), before);
+        assert!(!weak.is_alive());
+        let z = CachedExpr::from(Num(2));
+        assert!(z.id() == weak.id || z.id() == id);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,251 @@
+//! C ABI over G with the reduce evaluator. The declarations are in include/galaxy.h.
+//!
+//! Functions returning a pointer return NULL on failure; galaxy_last_error() then describes
+//! the failure. Strings returned by this module must be freed with galaxy_string_free.
+
+use crate::{
+    protocol::{InteractResult, G},
+    reduce_evaluator,
+};
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+pub struct Galaxy(G);
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+// Runs f, converting errors and panics to NULL plus the last error.
+fn guard<T>(f: impl FnOnce() -> anyhow::Result<*mut T>) -> *mut T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(p)) => p,
+        Ok(Err(e)) => {
+            set_last_error(format!("{:#}", e));
+            ptr::null_mut()
+        }
+        Err(e) => {
+            let msg = e
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| e.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".into());
+            set_last_error(format!("panic: {}", msg));
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> anyhow::Result<&'a str> {
+    if s.is_null() {
+        anyhow::bail!("null string");
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+fn to_c_string(s: String) -> anyhow::Result<*mut c_char> {
+    Ok(CString::new(s)?.into_raw())
+}
+
+/// Returns the message of the last failure on this thread. Valid until the next call.
+#[no_mangle]
+pub extern "C" fn galaxy_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn galaxy_new() -> *mut Galaxy {
+    guard(|| {
+        let g = G::new(Box::new(reduce_evaluator::Eval::new()));
+        Ok(Box::into_raw(Box::new(Galaxy(g))))
+    })
+}
+
+/// # Safety
+/// g must come from galaxy_new and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_free(g: *mut Galaxy) {
+    if !g.is_null() {
+        drop(Box::from_raw(g));
+    }
+}
+
+/// Evaluates an expression like "ap ap add 1 2" and returns the result as a string.
+///
+/// # Safety
+/// g must be a live Galaxy and expr a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_eval(g: *const Galaxy, expr: *const c_char) -> *mut c_char {
+    guard(|| {
+        let expr = to_str(expr)?;
+        let g = g.as_ref().ok_or_else(|| anyhow::anyhow!("null galaxy"))?;
+        to_c_string(g.0.evaluator().evaluate(expr).to_string())
+    })
+}
+
+/// Clicks (x, y) on the galaxy protocol in the given state.
+///
+/// # Safety
+/// g must be a live Galaxy and state a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_click(
+    g: *const Galaxy,
+    state: *const c_char,
+    x: i32,
+    y: i32,
+) -> *mut InteractResult {
+    guard(|| {
+        let state = to_str(state)?;
+        let g = g.as_ref().ok_or_else(|| anyhow::anyhow!("null galaxy"))?;
+        let res = g.0.galaxy(state.into(), x, y, "")?;
+        Ok(Box::into_raw(Box::new(res)))
+    })
+}
+
+/// Clicks (x, y) on the galaxy protocol in the given state and returns the result as JSON,
+/// {"state":"ap ap cons ...","images":[[[x,y],...],...]}, for hosts with a JSON parser at
+/// hand.
+///
+/// # Safety
+/// g must be a live Galaxy and state a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_interact(
+    g: *const Galaxy,
+    state: *const c_char,
+    x: i32,
+    y: i32,
+) -> *mut c_char {
+    guard(|| {
+        let state = to_str(state)?;
+        let g = g.as_ref().ok_or_else(|| anyhow::anyhow!("null galaxy"))?;
+        to_c_string(g.0.galaxy_json(state.into(), x, y)?)
+    })
+}
+
+/// # Safety
+/// res must come from galaxy_click.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_result_state(res: *const InteractResult) -> *mut c_char {
+    guard(|| {
+        let res = res.as_ref().ok_or_else(|| anyhow::anyhow!("null result"))?;
+        to_c_string(res.state.clone())
+    })
+}
+
+/// # Safety
+/// res must come from galaxy_click.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_result_image_count(res: *const InteractResult) -> usize {
+    res.as_ref().map_or(0, |res| res.images.len())
+}
+
+/// Copies up to cap points of the i-th image into out as interleaved x, y pairs and returns
+/// the number of points in the image. Call with cap = 0 to query the size.
+///
+/// # Safety
+/// res must come from galaxy_click and out must have room for 2 * cap values.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_result_image(
+    res: *const InteractResult,
+    i: usize,
+    out: *mut i32,
+    cap: usize,
+) -> usize {
+    let img = match res.as_ref().and_then(|res| res.images.get(i)) {
+        Some(img) => img,
+        None => return 0,
+    };
+    for (j, p) in img.iter().take(cap).enumerate() {
+        *out.add(2 * j) = p.0 as i32;
+        *out.add(2 * j + 1) = p.1 as i32;
+    }
+    img.len()
+}
+
+/// # Safety
+/// res must come from galaxy_click and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_result_free(res: *mut InteractResult) {
+    if !res.is_null() {
+        drop(Box::from_raw(res));
+    }
+}
+
+/// # Safety
+/// s must come from this module and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn galaxy_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let g = galaxy_new();
+
+            let expr = CString::new("ap ap add 1 2").unwrap();
+            let s = galaxy_eval(g, expr.as_ptr());
+            assert_eq!(CStr::from_ptr(s).to_str().unwrap(), "3");
+            galaxy_string_free(s);
+
+            let state = CString::new("ap ap cons 1").unwrap();
+            assert!(galaxy_click(g, state.as_ptr(), 0, 0).is_null());
+            assert!(CStr::from_ptr(galaxy_last_error())
+                .to_str()
+                .unwrap()
+                .starts_with("invalid galaxy state"));
+
+            let state = CString::new("nil").unwrap();
+            let res = galaxy_click(g, state.as_ptr(), 0, 0);
+            assert!(!res.is_null());
+            assert_eq!(galaxy_result_image_count(res), 3);
+            let n = galaxy_result_image(res, 1, ptr::null_mut(), 0);
+            let mut buf = vec![0; 2 * n];
+            galaxy_result_image(res, 1, buf.as_mut_ptr(), n);
+            assert_eq!(buf, vec![-8, -2, -7, -3]);
+            galaxy_result_free(res);
+
+            let s = galaxy_interact(g, state.as_ptr(), 0, 0);
+            let json: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(s).to_str().unwrap()).unwrap();
+            assert_eq!(json["images"][1], serde_json::json!([[-8, -2], [-7, -3]]));
+            assert!(json["state"].as_str().unwrap().starts_with("ap ap cons"));
+            galaxy_string_free(s);
+            assert!(galaxy_interact(ptr::null(), state.as_ptr(), 0, 0).is_null());
+
+            galaxy_free(g);
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let header = include_str!("../include/galaxy.h");
+        for line in include_str!("ffi.rs").lines() {
+            if let Some(rest) = line.split("extern \"C\" fn ").nth(1) {
+                let name = rest.split('(').next().unwrap();
+                assert!(
+                    header.contains(&format!("{}(", name)),
+                    "{} is not in the header",
+                    name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{
+    ast::Node, eval::EvalBudget, gen_js, net::ReplaySender, protocol::G, reduce_evaluator,
+    session::Session, state::State,
+};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Mutex},
+};
+
+/// Fuzzer clicks random points within the bounds of the last frame of a session.
+/// The same seed and starting session always produce the same clicks.
+pub struct Fuzzer {
+    seed: u64,
+    rng: SmallRng,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    // The click succeeded and moved to the state.
+    Ok(String),
+    Error(String),
+    Panic(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FuzzStep {
+    pub click: (i32, i32),
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Debug)]
+pub struct FuzzReport {
+    pub seed: u64,
+    pub steps: Vec<FuzzStep>,
+    // the (request, response) pairs exchanged with the aliens, in order.
+    pub sends: Vec<(Node, Node)>,
+}
+
+impl FuzzReport {
+    pub fn failures(&self) -> impl Iterator<Item = &FuzzStep> {
+        self.steps
+            .iter()
+            .filter(|s| !matches!(s.outcome, Outcome::Ok(_)))
+    }
+}
+
+// Clicks fall in this box until something is drawn.
+const DEFAULT_BOUNDS: (i64, i64, i64, i64) = (-10, -10, 10, 10);
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Self {
+        Fuzzer {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn run(&mut self, session: &mut Session, steps: usize) -> FuzzReport {
+        let mut bounds = DEFAULT_BOUNDS;
+        let mut report = FuzzReport {
+            seed: self.seed,
+            steps: vec![],
+            sends: vec![],
+        };
+        for _ in 0..steps {
+            let (min_x, min_y, max_x, max_y) = bounds;
+            let click = (
+                self.rng.gen_range(min_x..=max_x) as i32,
+                self.rng.gen_range(min_y..=max_y) as i32,
+            );
+            let outcome = match catch_unwind(AssertUnwindSafe(|| session.click(click.0, click.1))) {
+                Ok(Ok(res)) => {
+                    report.sends.extend(res.sends.iter().cloned());
+                    bounds = frame_bounds(&res.images).unwrap_or(DEFAULT_BOUNDS);
+                    Outcome::Ok(res.state)
+                }
+                Ok(Err(e)) => Outcome::Error(format!("{:#}", e)),
+                Err(e) => Outcome::Panic(panic_message(e)),
+            };
+            report.steps.push(FuzzStep { click, outcome });
+        }
+        report
+    }
+}
+
+// Returns len clicks drawn uniformly from the box (min_x, min_y, max_x, max_y). The same seed
+// always gives the same clicks.
+pub fn click_sequence(seed: u64, len: usize, bounds: (i32, i32, i32, i32)) -> Vec<(i32, i32)> {
+    let (min_x, min_y, max_x, max_y) = bounds;
+    let mut rng = SmallRng::seed_from_u64(seed);
+    (0..len)
+        .map(|_| (rng.gen_range(min_x..=max_x), rng.gen_range(min_y..=max_y)))
+        .collect()
+}
+
+// Identifies the galaxy.txt compiled in, so a repro recorded against another galaxy is
+// rejected. FNV-1a, which unlike DefaultHasher is stable across Rust releases.
+pub fn galaxy_version() -> u64 {
+    include_str!("../galaxy.txt")
+        .bytes()
+        .fold(0xcbf29ce484222325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Repro is what's needed to reproduce a fuzz run: the seed, the galaxy it ran against and
+/// the responses of the aliens. Reporting a bug only needs the seed when no sends happened.
+#[derive(Clone, Debug)]
+pub struct Repro {
+    pub seed: u64,
+    pub galaxy_version: u64,
+    pub report: FuzzReport,
+}
+
+impl Repro {
+    pub fn new(report: FuzzReport) -> Self {
+        Repro {
+            seed: report.seed,
+            galaxy_version: galaxy_version(),
+            report,
+        }
+    }
+
+    // Runs the fuzzer again from the given protocol and state, answering sends from the
+    // recording, and fails at the first step that differs.
+    pub fn replay(&self, mut g: G, protocol: &str, state: String) -> Result<FuzzReport> {
+        if self.galaxy_version != galaxy_version() {
+            bail!(
+                "recorded against galaxy {:016x}, but this is {:016x}",
+                self.galaxy_version,
+                galaxy_version()
+            );
+        }
+        g.set_sender(Box::new(ReplaySender::new(self.report.sends.clone())));
+        let mut session = Session::new(g, protocol, state);
+        let report = Fuzzer::new(self.seed).run(&mut session, self.report.steps.len());
+        for (i, (want, got)) in self
+            .report
+            .steps
+            .iter()
+            .zip(report.steps.iter())
+            .enumerate()
+        {
+            if want != got {
+                bail!("step {} differs: want {:?}, got {:?}", i, want, got);
+            }
+        }
+        Ok(report)
+    }
+}
+
+// Entry points for coverage-guided fuzzers such as cargo-fuzz, e.g.
+//   fuzz_target!(|data: &[u8]| app::fuzz::fuzz_parse(data));
+// They never panic on malformed input; a panic means a round trip broke.
+
+// Expressions with more tokens are skipped, as the parsers and the evaluator recurse as deep
+// as expressions nest and would overflow the stack rather than fail.
+const MAX_TOKENS: usize = 1000;
+
+#[doc(hidden)]
+pub fn fuzz_parse(data: &[u8]) {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) if s.split(' ').count() <= MAX_TOKENS => s,
+        _ => return,
+    };
+    if let Ok(node) = s.parse::<Node>() {
+        assert_eq!(node.to_string().parse::<Node>().unwrap(), node);
+    }
+    let _ = s.parse::<gen_js::Value>();
+    let _ = reduce_evaluator::try_parse_string(&Default::default(), s);
+}
+
+#[doc(hidden)]
+pub fn fuzz_demodulate(data: &[u8]) {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    // Numbers may be encoded with redundant nibbles, so compare nodes rather than strings.
+    if let Ok(node) = Node::try_demodulate(s) {
+        assert_eq!(Node::try_demodulate(&node.modulate()).unwrap(), node);
+    }
+}
+
+// Evaluates src with the galaxy definitions within budget, e.g.
+//   fuzz_target!(|src: &str| app::fuzz::fuzz_eval(src, EvalBudget::reductions(10000)));
+// Values evaluate to themselves. Evaluation runs on a worker thread with a stack deep enough
+// for FUZZ_MAX_DEPTH, so that inputs nesting deeper fail instead of overflowing the stack;
+// a panic there is raised again in the caller.
+#[doc(hidden)]
+pub fn fuzz_eval(src: &str, budget: EvalBudget) {
+    type Job = (String, EvalBudget, mpsc::Sender<Result<(), String>>);
+    lazy_static! {
+        static ref WORKER: Mutex<mpsc::Sender<Job>> = {
+            let (tx, rx) = mpsc::channel::<Job>();
+            std::thread::Builder::new()
+                .stack_size(256 * 1024 * 1024)
+                .spawn(move || {
+                    let mut eval = reduce_evaluator::Eval::new();
+                    eval.set_max_depth(Some(FUZZ_MAX_DEPTH));
+                    let mut g = G::new(Box::new(eval));
+                    for (src, budget, done) in rx {
+                        let res =
+                            catch_unwind(AssertUnwindSafe(|| check_eval(&mut g, &src, budget)));
+                        let _ = done.send(res.map_err(panic_message));
+                    }
+                })
+                .unwrap();
+            Mutex::new(tx)
+        };
+    }
+    if src.split(' ').count() > MAX_TOKENS {
+        return;
+    }
+    let (done, res) = mpsc::channel();
+    let worker = WORKER.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    worker.send((src.into(), budget, done)).unwrap();
+    if let Err(msg) = res.recv().expect("fuzz_eval worker is gone") {
+        panic!("{}", msg);
+    }
+}
+
+// Nesting of reductions fuzz_eval allows. Debug builds take about 13KB of stack per level.
+const FUZZ_MAX_DEPTH: usize = 10000;
+
+fn check_eval(g: &mut G, src: &str, budget: EvalBudget) {
+    g.set_budget(Some(budget));
+    if let Ok(value) = g.eval(src) {
+        // Without the budget, which the value may need all of to be built again.
+        g.set_budget(None);
+        assert_eq!(g.eval(&value.to_string()).ok(), Some(value));
+    }
+}
+
+#[doc(hidden)]
+pub fn fuzz_state(data: &[u8]) {
+    let state = match std::str::from_utf8(data).map(str::parse::<State>) {
+        Ok(Ok(state)) => state,
+        _ => return,
+    };
+    let _ = state.validate_galaxy();
+    let _ = state.get(&[1, 0]);
+    assert_eq!(state.to_string().parse::<State>().unwrap(), state);
+}
+
+fn frame_bounds(images: &[Vec<(i64, i64)>]) -> Option<(i64, i64, i64, i64)> {
+    let mut points = images.iter().flatten();
+    let &(x, y) = points.next()?;
+    Some(points.fold((x, y, x, y), |(x0, y0, x1, y1), &(x, y)| {
+        (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+    }))
+}
+
+pub(crate) fn panic_message(e: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::Evaluator, reduce_evaluator};
+
+    fn new_session_g() -> G {
+        let mut eval = reduce_evaluator::Eval::new();
+        eval.add_def(crate::testutil::STATELESSDRAW);
+        G::new(Box::new(eval))
+    }
+
+    fn new_session() -> Session {
+        Session::new(new_session_g(), "statelessdraw", "nil".into())
+    }
+
+    #[test]
+    fn test_fuzzer() {
+        let mut session = new_session();
+        let report = Fuzzer::new(42).run(&mut session, 5);
+        assert_eq!(report.steps.len(), 5);
+        assert_eq!(report.failures().count(), 0);
+        let clicks = report.steps.iter().map(|s| s.click).collect::<Vec<_>>();
+        assert_eq!(session.clicks(), &clicks[..]);
+
+        // statelessdraw draws the click, so every click after the first hits the same point.
+        assert!(clicks[1..].iter().all(|&c| c == clicks[0]));
+
+        let mut session = new_session();
+        assert_eq!(Fuzzer::new(42).run(&mut session, 5).steps, report.steps);
+    }
+
+    #[test]
+    fn test_repro() {
+        assert_eq!(
+            click_sequence(7, 20, (-3, -3, 3, 3)),
+            click_sequence(7, 20, (-3, -3, 3, 3))
+        );
+        assert!(click_sequence(7, 20, (-3, 0, 3, 0))
+            .iter()
+            .all(|&(x, y)| (-3..=3).contains(&x) && y == 0));
+
+        let mut session = new_session();
+        let repro = Repro::new(Fuzzer::new(3).run(&mut session, 4));
+        let replayed = repro
+            .replay(new_session_g(), "statelessdraw", "nil".into())
+            .unwrap();
+        assert_eq!(replayed.steps, repro.report.steps);
+
+        let mut other = repro.clone();
+        other.galaxy_version ^= 1;
+        assert!(other
+            .replay(new_session_g(), "statelessdraw", "nil".into())
+            .is_err());
+        let mut other = repro.clone();
+        other.report.steps[2].click = (100, 100);
+        assert!(format!(
+            "{:#}",
+            other
+                .replay(new_session_g(), "statelessdraw", "nil".into())
+                .unwrap_err()
+        )
+        .starts_with("step 2 differs"));
+    }
+
+    #[test]
+    fn test_replay_sender() {
+        use crate::net::Sender;
+        let (a, b): (Node, Node) = ("1".parse().unwrap(), "nil".parse().unwrap());
+        let sender = ReplaySender::new(vec![(a.clone(), b.clone())]);
+        assert!(sender.send(&b, "").is_err());
+        let sender = ReplaySender::new(vec![(a.clone(), b.clone())]);
+        assert_eq!(sender.send(&a, "").unwrap(), b);
+        assert_eq!(sender.remaining(), 0);
+        assert!(sender.send(&a, "").is_err());
+    }
+
+    #[test]
+    fn test_fuzz_entry_points() {
+        for s in [
+            "",
+            " ",
+            "ap",
+            "ap ap cons 1",
+            "ap ap vec 1 2",
+            "ap ap cons 1 nil nil",
+            "( 1 , ( 2",
+            ")",
+            "-9223372036854775808",
+            "99999999999999999999",
+            "\u{0}",
+            &"ap ap cons 1 ".repeat(20000),
+        ] {
+            fuzz_parse(s.as_bytes());
+            fuzz_state(s.as_bytes());
+        }
+        for s in [
+            "",
+            "1",
+            "11",
+            "0110",
+            "01100001",
+            "1011111111111111110",
+            "01111111111111111110",
+            "10111111111111111110000000000000000000000000000000000000000000000000000000000000",
+            "0111111111111111110111111111111111111111111111111111111111111111111111111111111",
+            "002",
+            "0000",
+            &("11".repeat(20000) + "00"),
+            &("1101".repeat(20000) + "00"),
+        ] {
+            fuzz_demodulate(s.as_bytes());
+        }
+        fuzz_parse(&[0xff, 0xfe]);
+        fuzz_parse("ap neg ".repeat(MAX_TOKENS).as_bytes());
+
+        let deep = "ap neg ".repeat(MAX_TOKENS / 2 - 1) + "1";
+        for s in [
+            "",
+            "ap",
+            "ap ap add 1 2",
+            "ap ap div 1 0",
+            "ap ap cons 1 ap ap cons ( 2 , 3 ) nil",
+            "ap ap ap s i i ap ap s i i",
+            "ap pwr2 100000000",
+            "ap car galaxy",
+            ":1338",
+            "x0",
+            deep.as_str(),
+        ] {
+            fuzz_eval(s, EvalBudget::reductions(1000));
+        }
+        // Nests a reduction per step without end.
+        let runaway = "ap ap ap s i i ap ap b ap add 1 ap ap s i i";
+        fuzz_eval(runaway, EvalBudget::reductions(100000));
+        fuzz_eval(runaway, EvalBudget::default());
+
+        assert_eq!(
+            Node::try_demodulate(
+                "10111111111111111101000000000000000000000000000000000000000000000000000000000000000"
+            )
+            .unwrap(),
+            Node::Num(i64::MIN)
+        );
+        assert_eq!(
+            Node::demodulate(&Node::Num(i64::MIN).modulate()),
+            Node::Num(i64::MIN)
+        );
+        assert!(Node::try_demodulate("0000").is_err());
+        assert!(Node::try_demodulate("011").is_err());
+    }
+}
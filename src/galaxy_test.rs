@@ -14,31 +14,69 @@ fn evaluators() -> Vec<Box<dyn Evaluator>> {
 #[test]
 fn test_statelessdraw() {
     for mut eval in evaluators() {
-        eval.add_def(
-        "statelessdraw = ap ap c ap ap b b ap ap b ap b ap cons 0 ap ap c ap ap b b cons ap ap c cons nil ap ap c ap ap b cons ap ap c cons nil nil"
-    );
+        eval.add_def(crate::testutil::STATELESSDRAW);
         let g = common::G::new(eval);
 
-        let res = g.interact("statelessdraw", "nil".into(), 1, 0, "");
+        let res = g.interact("statelessdraw", "nil".into(), 1, 0, "").unwrap();
         assert_eq!(res.state, "nil");
         assert_eq!(res.images, vec![vec![(1, 0)]]);
+        assert_eq!(res.raw_flag(), &common::Node::Num(0));
+        assert_eq!(res.data(), "ap ap cons ap ap cons ap ap cons 1 0 nil nil");
+    }
+}
+
+#[test]
+fn test_not_a_protocol() {
+    for mut eval in evaluators() {
+        eval.add_def("notprotocol = ap ap b ap c cons ap ap c cons nil");
+        let g = common::G::new(eval);
+
+        match g.interact("notprotocol", "nil".into(), 1, 0, "") {
+            Err(common::InteractError::NotAProtocol(res)) => {
+                assert_eq!(
+                    res.to_string(),
+                    "ap ap cons ap ap cons 1 0 ap ap cons nil nil"
+                )
+            }
+            res => panic!("unexpected result: {:?}", res.map(|r| r.state)),
+        }
+        assert!(matches!(
+            g.interact("undefined", "nil".into(), 1, 0, ""),
+            Err(common::InteractError::UnknownProtocol(_))
+        ));
     }
 }
 
 #[test]
 fn test_statefulldraw() {
     for mut eval in evaluators() {
-        eval.add_def(
-        ":67108929 = ap ap b ap b ap ap s ap ap b ap b ap cons 0 ap ap c ap ap b b cons ap ap c cons nil ap ap c cons nil ap c cons"
-    );
+        eval.add_def(crate::testutil::STATEFULDRAW);
         let g = crate::common::G::new(eval);
 
-        let res = g.interact(":67108929", "nil".into(), 0, 0, "");
+        let res = g.interact(":67108929", "nil".into(), 0, 0, "").unwrap();
         assert_eq!(res.state, "ap ap cons ap ap cons 0 0 nil");
         assert_eq!(res.images, vec![vec![(0, 0)]]);
     }
 }
 
+#[test]
+fn test_interact_expr() {
+    for mut eval in evaluators() {
+        eval.add_def(crate::testutil::STATEFULDRAW);
+        let g = common::G::new(eval);
+
+        let res = g
+            .interact_expr(":67108929", "nil".parse().unwrap(), (1, 2), "")
+            .unwrap();
+        let res = g.interact_expr(":67108929", res.state, (3, 4), "").unwrap();
+        assert_eq!(
+            res.state.to_string(),
+            "ap ap cons ap ap cons 3 4 ap ap cons ap ap cons 1 2 nil"
+        );
+        assert_eq!(res.images, vec![vec![(1, 2), (3, 4)]]);
+    }
+}
+
 #[test]
 fn test_galaxy() {
     for (name, eval) in vec!["pattern match", "gen_js"]
@@ -46,7 +84,7 @@ fn test_galaxy() {
         .zip(evaluators())
     {
         let g = common::G::new(eval);
-        for tc in vec![
+        for tc in [
             (
                 "nil",
                 (0, 0),
@@ -110,10 +148,151 @@ fn test_galaxy() {
                 ],
             ),
         ] {
-            let res = g.galaxy(tc.0.to_string(), (tc.1).0, (tc.1).1, "".into());
+            let res = g.galaxy(tc.0.to_string(), (tc.1).0, (tc.1).1, "").unwrap();
             eprintln!("test {}: {}", name, tc.0);
             assert_eq!(res.state, tc.2);
             assert_eq!(res.images, tc.3);
         }
     }
 }
+
+#[test]
+fn test_eval_with() {
+    for eval in evaluators() {
+        let g = G::new(eval);
+        for (expr, bindings, want) in [
+            (
+                "ap ap add x0 x1",
+                vec![("x0", "1"), ("x1", "ap neg 3")],
+                "-2",
+            ),
+            ("ap car x0", vec![("x0", "ap ap cons 5 nil")], "5"),
+            (
+                "ap ap cons x0 x0",
+                vec![("x0", "nil")],
+                "ap ap cons nil nil",
+            ),
+            // Any name without a definition can be bound, not only x-variables.
+            ("ap ap mul arg arg", vec![("arg", "7")], "49"),
+        ] {
+            assert_eq!(
+                g.eval_with(expr, &bindings).unwrap().to_string(),
+                want,
+                "{} {:?}",
+                expr,
+                bindings
+            );
+        }
+        assert!(!g.evaluator().has_def("x0"));
+        assert!(!g.evaluator().has_def("arg"));
+    }
+
+    // Names with definitions can't be bound, as definitions referring to them would keep
+    // what they evaluate to with the binding.
+    let g = G::from_source(":1 = ap add 1\n:2 = ap :1 10").unwrap();
+    assert_eq!(
+        g.eval_with(":2", &[(":1", "ap add 100")]),
+        Err(eval::EvalError::Shadowed(":1".into()))
+    );
+    assert_eq!(g.eval(":2").unwrap(), Value::num(11));
+    assert!(matches!(
+        g.eval_with("ap car x0", &[("x0", "ap car")]),
+        Err(eval::EvalError::Parse(_))
+    ));
+}
+
+#[test]
+fn test_big_numbers() {
+    // 2^62 * 4 / 8 exceeds i64 in the middle, but the result fits.
+    let def = "big = ap t ap t ap ap cons 0 ap ap cons ap ap div ap ap mul 4611686018427387904 4 8 ap ap cons nil nil";
+    for mut eval in evaluators() {
+        eval.add_def(def);
+        let g = G::new(eval);
+        let res = g.interact("big", "nil".into(), 0, 0, "").unwrap();
+        assert_eq!(res.state, "2305843009213693952");
+    }
+
+    let eval = reduce_evaluator::Eval::new();
+    assert_eq!(
+        eval.evaluate("ap ap mul 4611686018427387904 4").to_string(),
+        "18446744073709551616"
+    );
+}
+
+// The reduce evaluator failing on results outside i64, like evaluators with fixed-width numbers.
+struct I64Only(reduce_evaluator::Eval);
+
+impl Evaluator for I64Only {
+    fn evaluate(&self, expr: &str) -> Node {
+        self.0.evaluate(expr)
+    }
+    fn add_def(&mut self, s: &str) {
+        self.0.add_def(s)
+    }
+    fn has_def(&self, name: &str) -> bool {
+        self.0.has_def(name)
+    }
+    fn try_evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Result<Node, eval::EvalError> {
+        let n = self.0.try_evaluate_ap2(f, x, y)?;
+        if n.has_big() {
+            return Err(eval::EvalError::Overflow(expr::Primitive::Mul));
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_bigint_fallback() {
+    let def = "overflow = ap t ap t ap ap cons 0 ap ap cons ap ap mul 4611686018427387904 4 ap ap cons nil nil";
+    let new_g = || {
+        let mut eval = reduce_evaluator::Eval::new();
+        eval.add_def(def);
+        G::new(Box::new(I64Only(eval)))
+    };
+
+    let g = new_g();
+    match g.interact("overflow", "nil".into(), 0, 0, "") {
+        Err(common::InteractError::Overflow { round: 0 }) => (),
+        res => panic!("unexpected {:?}", res.map(|r| r.state)),
+    }
+
+    let mut g = new_g();
+    let mut bigint = gen_js::GalaxyEvaluator::new();
+    bigint.add_def(def);
+    g.set_bigint_evaluator(Box::new(bigint));
+    assert_eq!(g.numeric(), Numeric::I64);
+    let res = g.interact("overflow", "nil".into(), 0, 0, "").unwrap();
+    assert_eq!(res.state, "18446744073709551616");
+    assert_eq!(g.numeric(), Numeric::BigInt);
+
+    // The gmachine evaluator has i64 numbers and overflows for real.
+    let mut gmachine = eval::by_name("gmachine").unwrap();
+    gmachine.add_def(def);
+    let mut g = G::new(gmachine);
+    let mut bigint = reduce_evaluator::Eval::new();
+    bigint.add_def(def);
+    g.set_bigint_evaluator(Box::new(bigint));
+    let res = g.interact("overflow", "nil".into(), 0, 0, "").unwrap();
+    assert_eq!(res.state, "18446744073709551616");
+    assert_eq!(g.numeric(), Numeric::BigInt);
+
+    // A state already holding a number above i64::MAX, which gmachine can't even read, goes
+    // to the bigint evaluator without trying gmachine.
+    let mut gmachine = eval::by_name("gmachine").unwrap();
+    gmachine.add_def(crate::testutil::STATEFULDRAW);
+    let mut g = G::new(gmachine);
+    let state = "ap ap cons ap ap cons 99999999999999999999999 0 nil";
+    match g.interact(":67108929", state.into(), 1, 2, "") {
+        Err(common::InteractError::Eval { round: 0, .. }) => (),
+        res => panic!("unexpected {:?}", res.map(|r| r.state)),
+    }
+    let mut bigint = reduce_evaluator::Eval::new();
+    bigint.add_def(crate::testutil::STATEFULDRAW);
+    g.set_bigint_evaluator(Box::new(bigint));
+    let res = g.interact(":67108929", state.into(), 1, 2, "").unwrap();
+    assert_eq!(
+        res.state,
+        "ap ap cons ap ap cons 1 2 ap ap cons ap ap cons 99999999999999999999999 0 nil"
+    );
+    assert_eq!(g.numeric(), Numeric::BigInt);
+}
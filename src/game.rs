@@ -0,0 +1,513 @@
+//! Client of the aliens' game, the contest's second half: two players' bots fight with ships
+//! over a planet, talking to the game server through the same send API as galaxy.
+//!
+//! A match is created with CREATE, which hands out the attacker's and the defender's player
+//! keys. Each player JOINs, STARTs with an allocation of its ship's stats, and then sends
+//! COMMANDS once per turn until the game finishes. Every request is answered by a game
+//! response [1, stage, static info, game state], or [0] if the server rejected it.
+
+use crate::{
+    ast::{FromExpr, Node, ToExpr},
+    net::{HttpSender, Sender},
+};
+
+use anyhow::{bail, Context, Result};
+use std::convert::TryFrom;
+
+pub const CREATE: i64 = 1;
+pub const JOIN: i64 = 2;
+pub const START: i64 = 3;
+pub const COMMANDS: i64 = 4;
+
+// Turns after which GameClient::play gives up a game; the server ends them after 256.
+pub const MAX_TURNS: usize = 1000;
+
+/// Stats allocates the initial points of a ship in START.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub fuel: i64,
+    pub power: i64,
+    pub coolant: i64,
+    pub spawns: i64,
+}
+
+impl Stats {
+    pub fn to_node(self) -> Node {
+        vec![self.fuel, self.power, self.coolant, self.spawns].to_expr()
+    }
+}
+
+impl TryFrom<&Node> for Stats {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        match Vec::<i64>::from_expr(n)?.as_slice() {
+            &[fuel, power, coolant, spawns] => Ok(Stats {
+                fuel,
+                power,
+                coolant,
+                spawns,
+            }),
+            _ => bail!("want [fuel, power, coolant, spawns], got {}", n),
+        }
+    }
+}
+
+/// Vec2 is a position or a velocity, sent as a pair "ap ap cons x y" rather than a list.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Vec2 {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl Vec2 {
+    pub fn new(x: i64, y: i64) -> Self {
+        Vec2 { x, y }
+    }
+    pub fn to_node(self) -> Node {
+        (self.x, self.y).to_expr()
+    }
+}
+
+impl TryFrom<&Node> for Vec2 {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        let (x, y) = FromExpr::from_expr(n)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    Attacker,
+    Defender,
+}
+
+impl TryFrom<&Node> for Role {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        match i64::from_expr(n)? {
+            0 => Ok(Role::Attacker),
+            1 => Ok(Role::Defender),
+            r => bail!("unknown role {}", r),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GameStage {
+    NotStarted,
+    Started,
+    Finished,
+}
+
+/// Command is an order for one ship in a turn.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    // Changes the velocity by -vector.
+    Accelerate {
+        ship_id: i64,
+        vector: Vec2,
+    },
+    Detonate {
+        ship_id: i64,
+    },
+    Shoot {
+        ship_id: i64,
+        target: Vec2,
+        power: i64,
+    },
+    // Splits off a new ship with stats taken from the ship's.
+    Split {
+        ship_id: i64,
+        stats: Stats,
+    },
+}
+
+impl Command {
+    pub fn ship_id(&self) -> i64 {
+        match self {
+            Command::Accelerate { ship_id, .. }
+            | Command::Detonate { ship_id }
+            | Command::Shoot { ship_id, .. }
+            | Command::Split { ship_id, .. } => *ship_id,
+        }
+    }
+    pub fn to_node(&self) -> Node {
+        let id = Node::Num(self.ship_id());
+        Node::list(match self {
+            Command::Accelerate { vector, .. } => vec![Node::Num(0), id, vector.to_node()],
+            Command::Detonate { .. } => vec![Node::Num(1), id],
+            Command::Shoot { target, power, .. } => {
+                vec![Node::Num(2), id, target.to_node(), Node::Num(*power)]
+            }
+            Command::Split { stats, .. } => vec![Node::Num(3), id, stats.to_node()],
+        })
+    }
+}
+
+// Applied commands in game states carry more fields than sent ones, e.g. the shots' damage;
+// they are ignored.
+impl TryFrom<&Node> for Command {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        let items = items(n)?;
+        let field = |i: usize| {
+            items
+                .get(i)
+                .copied()
+                .with_context(|| format!("missing field {} in command {}", i, n))
+        };
+        let ship_id = i64::from_expr(field(1)?)?;
+        Ok(match i64::from_expr(field(0)?)? {
+            0 => Command::Accelerate {
+                ship_id,
+                vector: Vec2::try_from(field(2)?)?,
+            },
+            1 => Command::Detonate { ship_id },
+            2 => Command::Shoot {
+                ship_id,
+                target: Vec2::try_from(field(2)?)?,
+                power: i64::from_expr(field(3)?)?,
+            },
+            3 => Command::Split {
+                ship_id,
+                stats: Stats::try_from(field(2)?)?,
+            },
+            c => bail!("unknown command {}", c),
+        })
+    }
+}
+
+// The commands of a turn as sent in COMMANDS.
+pub fn commands_to_node(commands: &[Command]) -> Node {
+    Node::list(commands.iter().map(Command::to_node))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShipState {
+    pub role: Role,
+    pub ship_id: i64,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub stats: Stats,
+    pub heat: i64,
+    pub max_heat: i64,
+    pub max_thrust: i64,
+}
+
+impl TryFrom<&Node> for ShipState {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        match items(n)?.as_slice() {
+            &[role, ship_id, position, velocity, stats, heat, max_heat, max_thrust] => {
+                Ok(ShipState {
+                    role: Role::try_from(role)?,
+                    ship_id: i64::from_expr(ship_id)?,
+                    position: Vec2::try_from(position)?,
+                    velocity: Vec2::try_from(velocity)?,
+                    stats: Stats::try_from(stats)?,
+                    heat: i64::from_expr(heat)?,
+                    max_heat: i64::from_expr(max_heat)?,
+                    max_thrust: i64::from_expr(max_thrust)?,
+                })
+            }
+            _ => bail!("want a ship of 8 fields, got {}", n),
+        }
+    }
+}
+
+/// GameState is the field after a tick: every ship with the commands applied to it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GameState {
+    pub tick: i64,
+    pub ships: Vec<(ShipState, Vec<Command>)>,
+}
+
+impl TryFrom<&Node> for GameState {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        let (tick, ships) = match items(n)?.as_slice() {
+            &[tick, _, ships] => (i64::from_expr(tick)?, ships),
+            _ => bail!("want [tick, _, ships], got {}", n),
+        };
+        let ships = items(ships)?
+            .into_iter()
+            .map(|s| match items(s)?.as_slice() {
+                &[ship, commands] => Ok((
+                    ShipState::try_from(ship)?,
+                    items(commands)?
+                        .into_iter()
+                        .map(Command::try_from)
+                        .collect::<Result<_>>()?,
+                )),
+                _ => bail!("want [ship, commands], got {}", s),
+            })
+            .collect::<Result<_>>()?;
+        Ok(GameState { tick, ships })
+    }
+}
+
+/// GameResponse is the server's answer to JOIN, START and COMMANDS. Fields of the static info
+/// other than the role are kept undecoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GameResponse {
+    pub stage: GameStage,
+    pub role: Role,
+    pub static_info: Node,
+    // None before the game starts.
+    pub state: Option<GameState>,
+}
+
+impl GameResponse {
+    // The ships of role.
+    pub fn ships(&self, role: Role) -> impl Iterator<Item = &ShipState> {
+        self.state
+            .iter()
+            .flat_map(|s| s.ships.iter())
+            .map(|(ship, _)| ship)
+            .filter(move |ship| ship.role == role)
+    }
+}
+
+impl TryFrom<&Node> for GameResponse {
+    type Error = anyhow::Error;
+    fn try_from(n: &Node) -> Result<Self> {
+        let stage = match stage(n)? {
+            0 => GameStage::NotStarted,
+            1 => GameStage::Started,
+            2 => GameStage::Finished,
+            s => bail!("unknown stage {}", s),
+        };
+        let (static_info, state) = match items(n)?.as_slice() {
+            &[_, _, static_info, state] => (static_info, state),
+            _ => bail!("want [1, stage, static info, state], got {}", n),
+        };
+        let role = items(static_info)?
+            .get(1)
+            .copied()
+            .with_context(|| format!("no role in {}", static_info))?;
+        Ok(GameResponse {
+            stage,
+            role: Role::try_from(role)?,
+            static_info: static_info.clone(),
+            state: match state {
+                Node::Nil => None,
+                _ => Some(GameState::try_from(state)?),
+            },
+        })
+    }
+}
+
+/// GameClient sends the game's requests with a Sender, e.g. an HttpSender to the real server
+/// or a LocalSender offline.
+pub struct GameClient {
+    sender: Box<dyn Sender>,
+    api_key: String,
+}
+
+impl GameClient {
+    pub fn new(sender: Box<dyn Sender>, api_key: impl Into<String>) -> Self {
+        GameClient {
+            sender,
+            api_key: api_key.into(),
+        }
+    }
+    // Client of the real server.
+    pub fn http(api_key: impl Into<String>) -> Self {
+        GameClient::new(Box::new(HttpSender::default()), api_key)
+    }
+
+    // Creates a match, returning the attacker's and the defender's player keys.
+    pub fn create(&self) -> Result<(i64, i64)> {
+        let res = self.request(vec![CREATE, 0].to_expr())?;
+        let keys = || -> Option<(i64, i64)> {
+            let players = res.as_list()?.get(1)?.as_list()?;
+            let mut keys = [0, 0];
+            for player in players {
+                match player.as_list()?.as_slice() {
+                    [Node::Num(role @ 0..=1), Node::Num(key)] => keys[*role as usize] = *key,
+                    _ => return None,
+                }
+            }
+            Some((keys[0], keys[1]))
+        };
+        keys().with_context(|| format!("unexpected CREATE response {}", res))
+    }
+    pub fn join(&self, player_key: i64) -> Result<GameResponse> {
+        self.game_request(JOIN, player_key, Node::Nil)
+    }
+    pub fn start(&self, player_key: i64, stats: Stats) -> Result<GameResponse> {
+        self.game_request(START, player_key, stats.to_node())
+    }
+    pub fn commands(&self, player_key: i64, commands: &[Command]) -> Result<GameResponse> {
+        self.game_request(COMMANDS, player_key, commands_to_node(commands))
+    }
+
+    // Plays a game as player_key to the end: joins, starts with stats, and sends the commands
+    // turn returns for each game response. Returns the last game response.
+    pub fn play(
+        &self,
+        player_key: i64,
+        stats: Stats,
+        turn: impl FnMut(&GameResponse) -> Vec<Command>,
+    ) -> Result<GameResponse> {
+        self.join(player_key)?;
+        let res = self.start(player_key, stats)?;
+        self.play_turns(player_key, res, MAX_TURNS, turn)
+    }
+
+    // Sends the commands turn returns for each game response from res on until the game
+    // finishes, returning the last game response. Fails if it hasn't after max_turns turns.
+    pub fn play_turns(
+        &self,
+        player_key: i64,
+        mut res: GameResponse,
+        max_turns: usize,
+        mut turn: impl FnMut(&GameResponse) -> Vec<Command>,
+    ) -> Result<GameResponse> {
+        for _ in 0..max_turns {
+            if res.stage == GameStage::Finished {
+                return Ok(res);
+            }
+            let commands = turn(&res);
+            tracing::debug!(?commands, "turn");
+            res = self.commands(player_key, &commands)?;
+        }
+        if res.stage == GameStage::Finished {
+            return Ok(res);
+        }
+        bail!("game not finished after {} turns", max_turns)
+    }
+
+    fn game_request(&self, kind: i64, player_key: i64, arg: Node) -> Result<GameResponse> {
+        let req = Node::list(vec![Node::Num(kind), Node::Num(player_key), arg]);
+        let res = self.request(req)?;
+        GameResponse::try_from(&res).with_context(|| format!("bad game response {}", res))
+    }
+
+    // Sends req, failing unless the server accepted it.
+    fn request(&self, req: Node) -> Result<Node> {
+        let res = self.sender.send(&req, &self.api_key)?;
+        match res.as_list().as_deref() {
+            Some([Node::Num(1), ..]) => Ok(res),
+            _ => bail!("game server rejected {} with {}", req, res),
+        }
+    }
+}
+
+// The stage of a game response: 0 before the game starts, 1 while it runs and 2 after it
+// finished.
+pub fn stage(res: &Node) -> Result<i64> {
+    match res.as_list().as_deref() {
+        Some([Node::Num(1), Node::Num(stage), ..]) => Ok(*stage),
+        _ => bail!("not a game response: {}", res),
+    }
+}
+
+fn items(n: &Node) -> Result<Vec<&Node>> {
+    n.as_list()
+        .with_context(|| format!("want a list, got {}", n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::LocalSender;
+
+    #[test]
+    fn test_play() {
+        let client = GameClient::new(Box::new(LocalSender::new()), "");
+        let (attacker, defender) = client.create().unwrap();
+        assert_eq!(defender, attacker + 1);
+        let res = client.join(defender).unwrap();
+        assert_eq!(res.stage, GameStage::NotStarted);
+        assert_eq!(res.role, Role::Defender);
+        assert_eq!(res.state, None);
+
+        let stats = Stats {
+            fuel: 254,
+            power: 0,
+            coolant: 16,
+            spawns: 1,
+        };
+        assert_eq!(
+            stats.to_node().to_string(),
+            "ap ap cons 254 ap ap cons 0 ap ap cons 16 ap ap cons 1 nil"
+        );
+        let mut turns = 0;
+        let res = client
+            .play(attacker, stats, |res| {
+                assert_eq!(res.stage, GameStage::Started);
+                turns += 1;
+                vec![]
+            })
+            .unwrap();
+        assert_eq!(res.stage, GameStage::Finished);
+        assert_eq!(
+            res.ships(Role::Attacker).next().unwrap().position,
+            Vec2::new(-48, 0)
+        );
+        assert_eq!(res.ships(Role::Defender).count(), 0);
+        assert_eq!(turns, 1);
+
+        let (attacker, _) = client.create().unwrap();
+        client.join(attacker).unwrap();
+        let res = client.start(attacker, stats).unwrap();
+        let err = client.play_turns(attacker, res, 0, |_| vec![]).unwrap_err();
+        assert!(err.to_string().contains("not finished"), "{}", err);
+
+        let err = client.join(42).unwrap_err();
+        assert!(err.to_string().contains("rejected"), "{}", err);
+        assert!(stage(&Node::list(vec![Node::Num(0)])).is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let stats = Stats {
+            fuel: 1,
+            power: 2,
+            coolant: 3,
+            spawns: 4,
+        };
+        for (command, want) in [
+            (
+                Command::Accelerate {
+                    ship_id: 1,
+                    vector: Vec2::new(-1, 0),
+                },
+                "[0, 1, {\"cons\": [-1, 0]}]",
+            ),
+            (Command::Detonate { ship_id: 2 }, "[1, 2]"),
+            (
+                Command::Shoot {
+                    ship_id: 0,
+                    target: Vec2::new(3, 4),
+                    power: 5,
+                },
+                "[2, 0, {\"cons\": [3, 4]}, 5]",
+            ),
+            (Command::Split { ship_id: 0, stats }, "[3, 0, [1, 2, 3, 4]]"),
+        ] {
+            let node = command.to_node();
+            assert_eq!(node, Node::from_json(want).unwrap());
+            assert_eq!(Command::try_from(&node).unwrap(), command);
+        }
+        // Applied shots have the damage appended.
+        let applied = Node::from_json("[2, 0, {\"cons\": [3, 4]}, 5, 10, 4]").unwrap();
+        assert!(matches!(
+            Command::try_from(&applied).unwrap(),
+            Command::Shoot { power: 5, .. }
+        ));
+        for bad in ["[9, 0]", "[0, 1]", "[0, 1, [1, 2]]", "[]"] {
+            assert!(
+                Command::try_from(&Node::from_json(bad).unwrap()).is_err(),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(
+            commands_to_node(&[Command::Detonate { ship_id: 2 }]),
+            Node::from_json("[[1, 2]]").unwrap()
+        );
+    }
+}
@@ -1,10 +1,12 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use num_bigint::BigInt;
 use std::io::prelude::*;
 use std::{
     collections::HashMap,
     process::{Command, Stdio},
     str::FromStr,
 };
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
 use crate::*;
@@ -20,8 +22,15 @@ impl GalaxyEvaluator {
     }
 }
 
-impl common::Evaluator for GalaxyEvaluator {
-    fn evaluate(&self, expr: &str) -> common::Node {
+impl Default for GalaxyEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl eval::Evaluator for GalaxyEvaluator {
+    fn evaluate(&self, expr: &str) -> ast::Node {
+        let _span = tracing::debug_span!("eval", expr).entered();
         let v: Value = expr.parse().unwrap();
         let res = evaluate(&self.env, &v).unwrap();
         res.parse().unwrap()
@@ -29,8 +38,16 @@ impl common::Evaluator for GalaxyEvaluator {
     fn add_def(&mut self, s: &str) {
         self.env.add_parse(s).unwrap()
     }
+    fn try_add_def(&mut self, name: &str, expr: &str) -> Result<()> {
+        self.env.add_parse(&format!("{} = {}", name, expr))
+    }
+    fn has_def(&self, name: &str) -> bool {
+        let name = Value::Var(name.into()).to_string();
+        self.env.0.iter().any(|(k, _)| k == &name)
+    }
 }
 
+#[derive(Default)]
 pub struct Env(Vec<(String, Value)>);
 
 impl Env {
@@ -38,15 +55,23 @@ impl Env {
         Self(Vec::new())
     }
     pub fn add_parse(&mut self, line: &str) -> Result<()> {
-        let v: Vec<_> = line.split(" = ").map(str::trim).collect();
-        let name = format!("{}", v[0].parse::<Value>()?);
-        self.0.push((name, v[1].parse()?));
+        let (name, value) = line
+            .split_once(" = ")
+            .ok_or_else(|| anyhow!("not a definition: {:?}", line))?;
+        let name = format!("{}", name.trim().parse::<Value>()?);
+        let value = value
+            .trim()
+            .parse()
+            .with_context(|| format!("defining {}", name))?;
+        self.0.push((name, value));
         Ok(())
     }
     fn new_galaxy() -> Self {
         let mut env = Self::new();
-        for line in include_str!("../galaxy.txt").split("\n") {
-            env.add_parse(line).unwrap();
+        for (i, line) in include_str!("../galaxy.txt").split('\n').enumerate() {
+            env.add_parse(line)
+                .with_context(|| format!("galaxy.txt:{}", i + 1))
+                .unwrap();
         }
         env
     }
@@ -56,36 +81,44 @@ impl Env {
 fn eval_js(prog: &str) -> Result<String> {
     let prog = format!("(() => {{ {} }})()", prog);
     // for debug.
-    let mut f = std::fs::File::create("/tmp/hoge.js")?;
+    let mut f = std::fs::File::create("/tmp/hoge.js").context("writing /tmp/hoge.js")?;
     write!(f, "{}", prog).unwrap();
 
     let p = Command::new("node")
         // 100M.
-        .args(&["--stack-size=100000"])
+        .args(["--stack-size=100000"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .spawn()?;
+        .spawn()
+        .context("starting node")?;
     p.stdin.unwrap().write_all(prog.as_bytes())?;
     let mut res = String::new();
     p.stdout.unwrap().read_to_string(&mut res)?;
     res = res.trim().into();
-    eprintln!("Got result: {}", res);
+    tracing::debug!(result = %res, "node finished");
     Ok(res)
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 fn eval_js(prog: &str) -> Result<String> {
     Ok(js_eval_js(prog))
 }
 
+#[cfg(not(any(target_os = "linux", all(feature = "wasm", target_arch = "wasm32"))))]
+fn eval_js(_prog: &str) -> Result<String> {
+    Err(anyhow!(
+        "gen_js needs node or the wasm feature's JavaScript engine"
+    ))
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 #[wasm_bindgen(module = "/js/wasm_define.js")]
-#[cfg(target_arch = "wasm32")]
 extern "C" {
     fn js_eval_js(s: &str) -> String;
 }
 
 pub fn evaluate(env: &Env, expr: &Value) -> Result<String> {
-    let js = to_js_program(&env, &expr);
+    let js = to_js_program(env, expr);
     eval_js(&js)
 }
 
@@ -101,7 +134,7 @@ fn to_js_program(env: &Env, expr: &Value) -> String {
     }
     writeln!(js, "const result = to_string(eval({}));", expr).unwrap();
     writeln!(js, "console.log(result);").unwrap();
-    writeln!(js, "return result;");
+    writeln!(js, "return result;").unwrap();
 
     format!("{}\n{}", pre, js)
 }
@@ -109,7 +142,7 @@ fn to_js_program(env: &Env, expr: &Value) -> String {
 #[derive(Debug, Eq, PartialEq)]
 pub enum Value {
     Ap(Box<Value>, Box<Value>),
-    Num(i64),
+    Num(BigInt),
     Var(String),
 }
 
@@ -126,7 +159,7 @@ impl std::fmt::Display for Value {
 impl FromStr for Value {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Value::parse(&mut s.split(" ").into_iter())
+        Value::parse(&mut s.split(" "))
     }
 }
 
@@ -134,7 +167,7 @@ impl Value {
     fn parse<'a>(i: &mut impl Iterator<Item = &'a str>) -> Result<Self> {
         Ok(match i.next().ok_or(anyhow!("iterator expected"))? {
             "ap" => Value::Ap(Box::new(Value::parse(i)?), Box::new(Value::parse(i)?)),
-            s => match s.parse::<i64>() {
+            s => match s.parse::<BigInt>() {
                 Ok(x) => Value::Num(x),
                 Err(_) => Value::Var(s.into()),
             },
@@ -149,9 +182,13 @@ mod tests {
     fn test_parse() {
         use Value::*;
 
-        for tc in vec![
-            ("1", Num(1)),
-            ("ap inc 1", Ap(Var("inc".into()).into(), Num(1).into())),
+        for tc in [
+            ("1", Num(1.into())),
+            (
+                "ap inc 1",
+                Ap(Var("inc".into()).into(), Num(1.into()).into()),
+            ),
+            ("9223372036854775808", Num(BigInt::from(i64::MAX) + 1)),
         ] {
             let got: Value = tc.0.parse().unwrap();
             assert_eq!(got, tc.1);
@@ -161,17 +198,19 @@ mod tests {
     #[test]
     fn test_evaluate() {
         let mut env = Env::new();
-        for line in vec!["pwr2 = ap ap s ap ap c ap eq 0 1 ap ap b ap mul 2 ap ap b pwr2 ap add -1"]
-        {
+        for line in ["pwr2 = ap ap s ap ap c ap eq 0 1 ap ap b ap mul 2 ap ap b pwr2 ap add -1"] {
             env.add_parse(line).unwrap();
         }
 
-        for tc in vec![
+        for tc in [
             ("1", "1"),
             ("ap pwr2 3", "8"),
             ("ap ap t 1 2", "1"),
             ("ap ap t 1 ap ap ap s i i ap ap s i i", "1"),
             ("ap ap cons 1 2", "ap ap cons 1 2"),
+            ("ap ap ap ap lt 0 1 5 6", "5"),
+            ("ap ap ap ap lt 1 1 5 6", "6"),
+            ("ap ap ap ap lt 2 1 5 6", "6"),
         ] {
             eprintln!("{}", tc.0);
             let expr: Value = tc.0.parse().unwrap();
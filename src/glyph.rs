@@ -0,0 +1,155 @@
+//! Recognition of the numbers protocols draw in images, in the glyphs of the alien messages.
+//!
+//! A number is an (n + 1) x (n + 1) square: the top-left cell is blank, the rest of the top
+//! row and of the left column are set, and the n x n cells inside hold the absolute value in
+//! binary, least significant bit first, row by row. A negative number has one more set cell
+//! under the left column. E.g. 5 and -1:
+//!
+//! ```text
+//! .##     .#
+//! ##.     ##
+//! ##.     #.
+//! ```
+//!
+//! Glyphs must have an empty margin of one cell around them, as the galaxy draws them, which
+//! keeps other shapes from being read as numbers.
+
+use crate::render::Image;
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, convert::TryFrom};
+
+// n of the largest glyphs read, whose values still fit in i64 when small enough.
+const MAX_SIZE: i32 = 8;
+
+/// GlyphNumber is a number read from an image, with the position of its top-left cell and
+/// its size in cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlyphNumber {
+    pub value: i64,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Image {
+    // The numbers drawn in self, from top to bottom and left to right.
+    pub fn numbers(&self) -> Vec<GlyphNumber> {
+        let cells = (0..self.count())
+            .map(|i| self.point(i))
+            .map(|p| (p.x, p.y))
+            .collect::<HashSet<_>>();
+        let mut res = cells
+            .iter()
+            .filter_map(|&(x, y)| read(&cells, x - 1, y))
+            .collect::<Vec<_>>();
+        res.sort_by_key(|g| (g.y, g.x));
+        res
+    }
+}
+
+// The number whose top-left cell is (x, y), if one is there.
+fn read(cells: &HashSet<(i32, i32)>, x: i32, y: i32) -> Option<GlyphNumber> {
+    let set = |dx: i32, dy: i32| cells.contains(&(x + dx, y + dy));
+    if set(0, 0) {
+        return None;
+    }
+    let run = |dx: i32, dy: i32| {
+        (1..=MAX_SIZE + 2)
+            .take_while(|&k| set(dx * k, dy * k))
+            .count() as i32
+    };
+    let n = run(1, 0);
+    let negative = match run(0, 1) - n {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    if n == 0 || n > MAX_SIZE {
+        return None;
+    }
+    let (width, height) = (n + 1, n + 1 + negative as i32);
+    // Nothing else under the negative sign, nor around the glyph.
+    if negative && (1..width).any(|dx| set(dx, n + 1)) {
+        return None;
+    }
+    let margin = (-1..=width).flat_map(|dx| vec![(dx, -1), (dx, height)]);
+    let margin = margin.chain((0..height).flat_map(|dy| vec![(-1, dy), (width, dy)]));
+    if margin.into_iter().any(|(dx, dy)| set(dx, dy)) {
+        return None;
+    }
+    let mut value = 0u64;
+    for i in 0..n * n {
+        if set(1 + i % n, 1 + i / n) {
+            value |= 1 << i;
+        }
+    }
+    let value = i64::try_from(value).ok()?;
+    Some(GlyphNumber {
+        value: if negative { -value } else { value },
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The cells of the glyph of value with its top-left cell at (x, y).
+    fn draw(value: i64, x: i64, y: i64) -> Vec<(i64, i64)> {
+        let abs = value.unsigned_abs();
+        let n = (1..)
+            .find(|&n: &i64| n * n >= 64 || abs >> (n * n) == 0)
+            .unwrap();
+        let mut res = vec![];
+        for k in 1..=n {
+            res.push((x + k, y));
+            res.push((x, y + k));
+        }
+        if value < 0 {
+            res.push((x, y + n + 1));
+        }
+        for i in 0..n * n {
+            if abs >> i & 1 == 1 {
+                res.push((x + 1 + i % n, y + 1 + i / n));
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_numbers() {
+        let values = [0, 1, 5, -1, 16, 255, -4096, 1 << 40, i64::MAX, -i64::MAX];
+        let mut points = vec![];
+        for (i, &v) in values.iter().enumerate() {
+            points.extend(draw(v, 12 * i as i64 - 30, 7));
+        }
+        let got = Image::from_points(&points).numbers();
+        assert_eq!(
+            got.iter().map(|g| g.value).collect::<Vec<_>>(),
+            values.to_vec()
+        );
+        assert_eq!(
+            got[3],
+            GlyphNumber {
+                value: -1,
+                x: 6,
+                y: 7,
+                width: 2,
+                height: 3
+            }
+        );
+
+        // Shapes touching glyphs, and glyphs with a filled corner, aren't numbers.
+        let mut touching = draw(5, 0, 0);
+        touching.push((3, 1));
+        assert_eq!(Image::from_points(&touching).numbers(), []);
+        let mut filled = draw(5, 0, 0);
+        filled.push((0, 0));
+        assert_eq!(Image::from_points(&filled).numbers(), []);
+    }
+}
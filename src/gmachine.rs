@@ -0,0 +1,842 @@
+//! An evaluator compiling definitions to supercombinators run by a G-machine.
+//!
+//! Each definition becomes a supercombinator: the definition applied to as many parameters
+//! as the combinators at its head take, reduced symbolically, e.g. statelessdraw, a chain of
+//! b and c, becomes "statelessdraw x0 x1 = cons 0 (cons nil (cons (cons (cons x1 nil) nil)
+//! nil))". Subexpressions without parameters are lifted into definitions of their own, so
+//! that their values are shared between calls as they were before. The supercombinators are
+//! compiled to G-code instantiating their bodies, which the machine runs by unwinding the
+//! spine of the expression being evaluated, with an explicit dump instead of recursion.
+//!
+//! Numbers are i64; results that don't fit are EvalError::Overflow. The reduce evaluator is
+//! the reference, and the two are cross-checked in the tests.
+
+use crate::{
+    ast::Node,
+    eval::{EvalError, Evaluator},
+    expr::Primitive,
+};
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+// The primitives, in the order of their global ids.
+const PRIMITIVES: [Primitive; 20] = {
+    use Primitive::*;
+    [
+        Add,
+        Mul,
+        Div,
+        Eq,
+        Lt,
+        Neg,
+        S,
+        C,
+        B,
+        I,
+        F,
+        T,
+        Cons,
+        Car,
+        Cdr,
+        Nil,
+        Isnil,
+        If0,
+        Pwr2,
+        Checkerboard,
+    ]
+};
+
+fn prim(p: Primitive) -> usize {
+    PRIMITIVES.iter().position(|&q| q == p).unwrap()
+}
+
+// A supercombinator body.
+#[derive(Clone, Debug, PartialEq)]
+enum Body {
+    Arg(usize),
+    Global(usize),
+    Num(i64),
+    Ap(Rc<Body>, Rc<Body>),
+}
+
+impl Body {
+    fn ap(f: Body, x: Body) -> Body {
+        Body::Ap(f.into(), x.into())
+    }
+    fn has_args(&self) -> bool {
+        match self {
+            Body::Arg(_) => true,
+            Body::Ap(f, x) => f.has_args() || x.has_args(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Instr {
+    // pushes the node at the offset from the top of the stack.
+    Push(usize),
+    PushGlobal(usize),
+    PushNum(i64),
+    // pops f, then x, and pushes ap f x.
+    MkAp,
+    // pops the result and makes the node at the offset an indirection to it.
+    Update(usize),
+    Pop(usize),
+    Unwind,
+    // evaluates the top to weak head normal form.
+    Eval,
+    // applies the primitive to the evaluated arguments on the top.
+    Prim(Primitive),
+    // fails with the unknown name of the global.
+    Unknown(usize),
+}
+
+struct Global {
+    name: String,
+    arity: usize,
+    code: Rc<[Instr]>,
+}
+
+// The compiled definitions.
+struct Program {
+    globals: Vec<Global>,
+    ids: HashMap<String, usize>,
+}
+
+// Limits of the symbolic reduction finding supercombinators, beyond which a definition is
+// left a constant applicative form of arity 0.
+const MAX_ARITY: usize = 16;
+const MAX_STEPS: usize = 1000;
+
+impl Program {
+    fn new(defs: &[(String, String)]) -> Result<Self, EvalError> {
+        let mut globals = PRIMITIVES.iter().map(|&p| primitive(p)).collect::<Vec<_>>();
+        let mut ids = PRIMITIVES
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.to_string(), i))
+            .collect::<HashMap<_, _>>();
+        ids.insert("vec".into(), prim(Primitive::Cons));
+        for (name, _) in defs {
+            let id = globals.len();
+            ids.insert(name.clone(), id);
+            globals.push(Global {
+                name: name.clone(),
+                arity: 0,
+                code: Rc::from([]),
+            });
+        }
+        let mut program = Program { globals, ids };
+        for (name, src) in defs {
+            let body = parse(src, &mut |name| match program.ids.get(name) {
+                Some(&id) => Body::Global(id),
+                None => program.unknown(name),
+            })?;
+            let (arity, body) = supercombinator(&body);
+            let body = program.lift(name, body, arity);
+            let g = &mut program.globals[program.ids[name]];
+            g.arity = arity;
+            g.code = compile(&body, arity);
+        }
+        Ok(program)
+    }
+
+    // The global for a name not defined, failing when evaluated.
+    fn unknown(&mut self, name: &str) -> Body {
+        let id = self.globals.len();
+        self.ids.insert(name.to_string(), id);
+        self.globals.push(Global {
+            name: name.to_string(),
+            arity: 0,
+            code: Rc::from([Instr::Unknown(id)]),
+        });
+        Body::Global(id)
+    }
+
+    // Replaces the subexpressions of body without parameters by new globals of arity 0, so
+    // that each is evaluated once rather than once per call.
+    fn lift(&mut self, name: &str, body: Body, arity: usize) -> Body {
+        if arity == 0 {
+            return body;
+        }
+        match body {
+            Body::Ap(f, x) if !body.has_args() => {
+                let id = self.globals.len();
+                let lifted = Body::Ap(f, x);
+                self.globals.push(Global {
+                    name: format!("{}#{}", name, id),
+                    arity: 0,
+                    code: compile(&lifted, 0),
+                });
+                Body::Global(id)
+            }
+            Body::Ap(f, x) => {
+                let f = self.lift(name, (*f).clone(), arity);
+                let x = self.lift(name, (*x).clone(), arity);
+                Body::ap(f, x)
+            }
+            body => body,
+        }
+    }
+}
+
+// Parses an expression in the notation of galaxy.txt, resolving names with global.
+fn parse(src: &str, global: &mut impl FnMut(&str) -> Body) -> Result<Body, EvalError> {
+    fn go<'a>(
+        tokens: &mut impl Iterator<Item = &'a str>,
+        global: &mut impl FnMut(&str) -> Body,
+    ) -> Result<Body, EvalError> {
+        let tok = tokens
+            .next()
+            .ok_or_else(|| EvalError::Parse("unexpected end".into()))?;
+        if tok == "ap" {
+            let f = go(tokens, global)?;
+            let x = go(tokens, global)?;
+            return Ok(Body::ap(f, x));
+        }
+        if tok.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            return tok
+                .parse()
+                .map(Body::Num)
+                .map_err(|_| EvalError::Parse(format!("number out of range: {}", tok)));
+        }
+        Ok(global(tok))
+    }
+    let mut tokens = src.split_whitespace();
+    let res = go(&mut tokens, global)?;
+    match tokens.next() {
+        Some(tok) => Err(EvalError::Parse(format!("unused token {}", tok))),
+        None => Ok(res),
+    }
+}
+
+// The code of a primitive. The lazy ones are supercombinators like any other; the strict ones
+// evaluate their arguments first.
+fn primitive(p: Primitive) -> Global {
+    use Primitive::*;
+    let g = |p| Body::Global(prim(p));
+    let a = Body::Arg;
+    let lazy = |body| compile(&body, p.arity());
+    let code = match p {
+        S => lazy(Body::ap(Body::ap(a(0), a(2)), Body::ap(a(1), a(2)))),
+        C => lazy(Body::ap(Body::ap(a(0), a(2)), a(1))),
+        B => lazy(Body::ap(a(0), Body::ap(a(1), a(2)))),
+        I => lazy(a(0)),
+        T => lazy(a(0)),
+        F => lazy(a(1)),
+        Cons => lazy(Body::ap(Body::ap(a(2), a(0)), a(1))),
+        Car => lazy(Body::ap(a(0), g(T))),
+        Cdr => lazy(Body::ap(a(0), g(F))),
+        Nil => lazy(g(T)),
+        Add | Mul | Div | Eq | Lt | Checkerboard | Neg | Isnil | If0 | Pwr2 => {
+            use Instr::*;
+            let n = p.arity();
+            let mut code = match p {
+                Add | Mul | Div | Eq | Lt => vec![Push(1), Eval, Push(1), Eval],
+                _ => vec![Push(0), Eval],
+            };
+            code.extend([Prim(p), Update(n), Pop(n), Unwind].iter());
+            code.into()
+        }
+    };
+    Global {
+        name: p.to_string(),
+        arity: p.arity(),
+        code,
+    }
+}
+
+// The supercombinator for a definition: its arity and body. The body is the definition
+// applied to parameters as long as its head is a primitive short of arguments other than
+// cons and nil, reduced at the head by the rules of the lazy combinators. S is only reduced
+// when its argument to share is atomic, as duplicating an expression would duplicate its
+// evaluation.
+fn supercombinator(def: &Body) -> (usize, Body) {
+    let mut body = def.clone();
+    let mut steps = 0;
+    for arity in 0..=MAX_ARITY {
+        loop {
+            match step(&body) {
+                Step::Reduced(b) if steps < MAX_STEPS => {
+                    body = b;
+                    steps += 1;
+                }
+                Step::Reduced(_) => return (0, def.clone()),
+                Step::Stuck if arity == 0 => return (0, def.clone()),
+                Step::Stuck => return (arity, reduce_args(body, &mut steps)),
+                Step::Partial => break,
+            }
+        }
+        body = Body::ap(body, Body::Arg(arity));
+    }
+    (0, def.clone())
+}
+
+// Reduces the arguments of the stuck body the same way, within what is left of MAX_STEPS.
+// An argument whose reduction runs out of steps is left as it was.
+fn reduce_args(body: Body, steps: &mut usize) -> Body {
+    match body {
+        Body::Ap(f, x) => {
+            let f = reduce_args((*f).clone(), steps);
+            let mut x = (*x).clone();
+            let orig = x.clone();
+            loop {
+                match step(&x) {
+                    Step::Reduced(_) if *steps >= MAX_STEPS => return Body::ap(f, orig),
+                    Step::Reduced(b) => {
+                        x = b;
+                        *steps += 1;
+                    }
+                    Step::Partial | Step::Stuck => break,
+                }
+            }
+            Body::ap(f, reduce_args(x, steps))
+        }
+        body => body,
+    }
+}
+
+enum Step {
+    Reduced(Body),
+    // the head is a lazy combinator needing more arguments.
+    Partial,
+    Stuck,
+}
+
+fn step(body: &Body) -> Step {
+    use Primitive::*;
+    let mut args = vec![];
+    let mut head = body;
+    while let Body::Ap(f, x) = head {
+        args.push((**x).clone());
+        head = f;
+    }
+    args.reverse();
+    let p = match head {
+        Body::Global(g) if *g < PRIMITIVES.len() => PRIMITIVES[*g],
+        _ => return Step::Stuck,
+    };
+    if args.len() < p.arity() {
+        // cons and nil short of arguments are data, whose form readback and isnil look for.
+        if matches!(p, Cons | Nil) {
+            return Step::Stuck;
+        }
+        return Step::Partial;
+    }
+    let lazy = matches!(p, S | C | B | I | F | T | Cons | Car | Cdr | Nil);
+    if !lazy {
+        return Step::Stuck;
+    }
+    let g = |p| Body::Global(prim(p));
+    let mut rest = args.split_off(p.arity());
+    let mut a = args.into_iter();
+    let mut arg = || a.next().unwrap();
+    let res = match p {
+        S => {
+            let (x, y, z) = (arg(), arg(), arg());
+            if matches!(z, Body::Ap(..)) {
+                return Step::Stuck;
+            }
+            Body::ap(Body::ap(x, z.clone()), Body::ap(y, z))
+        }
+        C => {
+            let (x, y, z) = (arg(), arg(), arg());
+            Body::ap(Body::ap(x, z), y)
+        }
+        B => {
+            let (x, y, z) = (arg(), arg(), arg());
+            Body::ap(x, Body::ap(y, z))
+        }
+        I => arg(),
+        T => arg(),
+        F => {
+            arg();
+            arg()
+        }
+        Cons => {
+            let (x, y, z) = (arg(), arg(), arg());
+            Body::ap(Body::ap(z, x), y)
+        }
+        Car => Body::ap(arg(), g(T)),
+        Cdr => Body::ap(arg(), g(F)),
+        Nil => g(T),
+        _ => unreachable!(),
+    };
+    rest.reverse();
+    let mut res = res;
+    while let Some(x) = rest.pop() {
+        res = Body::ap(res, x);
+    }
+    Step::Reduced(res)
+}
+
+// The code instantiating body with arity parameters on the stack, then updating the root of
+// the redex with it.
+fn compile(body: &Body, arity: usize) -> Rc<[Instr]> {
+    fn go(b: &Body, depth: usize, code: &mut Vec<Instr>) {
+        match b {
+            Body::Arg(i) => code.push(Instr::Push(i + depth)),
+            Body::Global(g) => code.push(Instr::PushGlobal(*g)),
+            Body::Num(n) => code.push(Instr::PushNum(*n)),
+            Body::Ap(f, x) => {
+                go(x, depth, code);
+                go(f, depth + 1, code);
+                code.push(Instr::MkAp);
+            }
+        }
+    }
+    let mut code = vec![];
+    go(body, 0, &mut code);
+    code.extend([Instr::Update(arity), Instr::Pop(arity), Instr::Unwind].iter());
+    code.into()
+}
+
+#[derive(Clone, Copy, Debug)]
+enum GNode {
+    Num(i64),
+    Ap(usize, usize),
+    Global(usize),
+    Ind(usize),
+}
+
+// A run of the program, with its own heap. Constant applicative forms are shared within
+// the run only.
+struct Machine<'a> {
+    program: &'a Program,
+    heap: Vec<GNode>,
+    globals: Vec<Option<usize>>,
+    // names in the evaluated expression that the program doesn't define, as globals after
+    // the program's.
+    unknown: Vec<String>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(program: &'a Program) -> Self {
+        Machine {
+            program,
+            heap: vec![],
+            globals: vec![None; program.globals.len()],
+            unknown: vec![],
+        }
+    }
+
+    fn parse(&mut self, src: &str) -> Result<Body, EvalError> {
+        let (program, unknown) = (self.program, &mut self.unknown);
+        parse(src, &mut |name| match program.ids.get(name) {
+            Some(&id) => Body::Global(id),
+            None => {
+                unknown.push(name.to_string());
+                Body::Global(program.globals.len() + unknown.len() - 1)
+            }
+        })
+    }
+    fn name(&self, g: usize) -> &str {
+        match self.program.globals.get(g) {
+            Some(global) => &global.name,
+            None => &self.unknown[g - self.program.globals.len()],
+        }
+    }
+
+    fn alloc(&mut self, n: GNode) -> usize {
+        self.heap.push(n);
+        self.heap.len() - 1
+    }
+    fn global(&mut self, g: usize) -> usize {
+        if g >= self.globals.len() {
+            self.globals.resize(g + 1, None);
+        }
+        match self.globals[g] {
+            Some(a) => a,
+            None => {
+                let a = self.alloc(GNode::Global(g));
+                self.globals[g] = Some(a);
+                a
+            }
+        }
+    }
+    fn boolean(&mut self, b: bool) -> usize {
+        self.global(prim(if b { Primitive::T } else { Primitive::F }))
+    }
+    fn resolve(&self, mut a: usize) -> usize {
+        while let GNode::Ind(b) = self.heap[a] {
+            a = b;
+        }
+        a
+    }
+
+    // Builds the graph of body, which has no parameters.
+    fn instantiate(&mut self, body: &Body) -> usize {
+        match body {
+            Body::Arg(_) => unreachable!("parameter outside a supercombinator"),
+            Body::Global(g) => self.global(*g),
+            Body::Num(n) => self.alloc(GNode::Num(*n)),
+            Body::Ap(f, x) => {
+                let (f, x) = (self.instantiate(f), self.instantiate(x));
+                self.alloc(GNode::Ap(f, x))
+            }
+        }
+    }
+
+    // Evaluates the node at a to weak head normal form, returning its address.
+    fn whnf(&mut self, a: usize) -> Result<usize, EvalError> {
+        let unwind: Rc<[Instr]> = Rc::from([Instr::Unwind]);
+        let mut stack = vec![a];
+        let mut dump: Vec<(Vec<usize>, Rc<[Instr]>, usize)> = vec![];
+        let mut code = unwind.clone();
+        let mut pc = 0;
+        loop {
+            let instr = code[pc];
+            pc += 1;
+            match instr {
+                Instr::Push(k) => stack.push(stack[stack.len() - 1 - k]),
+                Instr::PushGlobal(g) => {
+                    let a = self.global(g);
+                    stack.push(a)
+                }
+                Instr::PushNum(n) => {
+                    let a = self.alloc(GNode::Num(n));
+                    stack.push(a)
+                }
+                Instr::MkAp => {
+                    let f = stack.pop().unwrap();
+                    let x = stack.pop().unwrap();
+                    let a = self.alloc(GNode::Ap(f, x));
+                    stack.push(a);
+                }
+                Instr::Update(n) => {
+                    let a = stack.pop().unwrap();
+                    let root = stack[stack.len() - 1 - n];
+                    if self.resolve(a) != root {
+                        self.heap[root] = GNode::Ind(a);
+                    }
+                }
+                Instr::Pop(n) => stack.truncate(stack.len() - n),
+                Instr::Eval => {
+                    let a = stack.pop().unwrap();
+                    dump.push((std::mem::replace(&mut stack, vec![a]), code, pc));
+                    code = unwind.clone();
+                    pc = 0;
+                }
+                Instr::Prim(p) => {
+                    let a = self.primitive(p, &mut stack)?;
+                    stack.push(a);
+                }
+                Instr::Unknown(g) => return Err(EvalError::UnknownVar(self.name(g).to_string())),
+                Instr::Unwind => {
+                    let top = *stack.last().unwrap();
+                    let whnf = match self.heap[top] {
+                        GNode::Ind(b) => {
+                            *stack.last_mut().unwrap() = b;
+                            pc -= 1;
+                            continue;
+                        }
+                        GNode::Ap(f, _) => {
+                            stack.push(f);
+                            pc -= 1;
+                            continue;
+                        }
+                        GNode::Num(_) if stack.len() > 1 => {
+                            return Err(EvalError::NotAFunction(self.show(top, 80)))
+                        }
+                        GNode::Num(_) => top,
+                        GNode::Global(g) if g >= self.program.globals.len() => {
+                            return Err(EvalError::UnknownVar(self.name(g).to_string()))
+                        }
+                        GNode::Global(g) => {
+                            let global = &self.program.globals[g];
+                            let n = global.arity;
+                            if stack.len() - 1 < n {
+                                // a partial application, whose root is at the bottom.
+                                stack[0]
+                            } else {
+                                crate::eval::charge()?;
+                                // The spine is replaced by the arguments, the first on top,
+                                // above the root of the redex to update with the result.
+                                let len = stack.len();
+                                let args = (1..=n)
+                                    .rev()
+                                    .map(|i| match self.heap[stack[len - 1 - i]] {
+                                        GNode::Ap(_, x) => x,
+                                        _ => unreachable!("spine of non-applications"),
+                                    })
+                                    .collect::<Vec<_>>();
+                                stack.truncate(len - n);
+                                stack.extend(args);
+                                code = global.code.clone();
+                                pc = 0;
+                                continue;
+                            }
+                        }
+                    };
+                    match dump.pop() {
+                        Some((s, c, p)) => {
+                            stack = s;
+                            stack.push(whnf);
+                            code = c;
+                            pc = p;
+                        }
+                        None => return Ok(whnf),
+                    }
+                }
+            }
+        }
+    }
+
+    fn primitive(&mut self, p: Primitive, stack: &mut Vec<usize>) -> Result<usize, EvalError> {
+        use Primitive::*;
+        let mut num = |m: &mut Machine| {
+            let a = m.resolve(stack.pop().unwrap());
+            match m.heap[a] {
+                GNode::Num(n) => Ok(n),
+                _ => Err(EvalError::NotANum(m.show(a, 80))),
+            }
+        };
+        let overflow = || EvalError::Overflow(p);
+        let res = match p {
+            Add | Mul | Div | Eq | Lt => {
+                let x = num(self)?;
+                let y = num(self)?;
+                match p {
+                    Add => x.checked_add(y).ok_or_else(overflow)?,
+                    Mul => x.checked_mul(y).ok_or_else(overflow)?,
+                    Div if y == 0 => return Err(EvalError::DivisionByZero),
+                    Div => x.checked_div(y).ok_or_else(overflow)?,
+                    Eq => return Ok(self.boolean(x == y)),
+                    _ => return Ok(self.boolean(x < y)),
+                }
+            }
+            Neg => num(self)?.checked_neg().ok_or_else(overflow)?,
+            Pwr2 => match num(self)? {
+                n if n < 0 => 0,
+                n if n < 63 => 1 << n,
+                _ => return Err(overflow()),
+            },
+            If0 => {
+                let x = num(self)?;
+                return Ok(stack[stack.len() - if x == 0 { 2 } else { 3 }]);
+            }
+            Isnil => {
+                let a = self.resolve(stack.pop().unwrap());
+                return match self.as_list(a) {
+                    Some(None) => Ok(self.boolean(true)),
+                    Some(Some(_)) => Ok(self.boolean(false)),
+                    None => Err(EvalError::NotAValue(self.show(a, 80))),
+                };
+            }
+            Checkerboard => {
+                let n = num(self)?;
+                if n > 1 << 10 {
+                    return Err(overflow());
+                }
+                let (cons, mut res) = (self.global(prim(Cons)), self.global(prim(Nil)));
+                for i in (0..n).rev() {
+                    for j in (0..n).rev().filter(|j| (i + j) % 2 == 0) {
+                        let (i, j) = (self.alloc(GNode::Num(i)), self.alloc(GNode::Num(j)));
+                        let p = self.pair(cons, i, j);
+                        res = self.pair(cons, p, res);
+                    }
+                }
+                return Ok(res);
+            }
+            _ => unreachable!("{} is lazy", p),
+        };
+        Ok(self.alloc(GNode::Num(res)))
+    }
+
+    fn pair(&mut self, cons: usize, x: usize, y: usize) -> usize {
+        let f = self.alloc(GNode::Ap(cons, x));
+        self.alloc(GNode::Ap(f, y))
+    }
+
+    // For a node in weak head normal form, Some(None) for nil, Some(Some((x, y))) for a
+    // cons cell, and None for anything else.
+    fn as_list(&self, a: usize) -> Option<Option<(usize, usize)>> {
+        let nil = prim(Primitive::Nil);
+        let cons = prim(Primitive::Cons);
+        match self.heap[self.resolve(a)] {
+            GNode::Global(g) if g == nil => Some(None),
+            GNode::Ap(f, y) => match self.heap[self.resolve(f)] {
+                GNode::Ap(c, x) => match self.heap[self.resolve(c)] {
+                    GNode::Global(g) if g == cons => Some(Some((x, y))),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Evaluates the node at a fully, into a value.
+    fn read(&mut self, a: usize) -> Result<Node, EvalError> {
+        let a = self.whnf(a)?;
+        if let GNode::Num(n) = self.heap[self.resolve(a)] {
+            return Ok(Node::Num(n));
+        }
+        match self.as_list(a) {
+            Some(None) => Ok(Node::Nil),
+            Some(Some((x, y))) => Ok(Node::Cons(self.read(x)?.into(), self.read(y)?.into())),
+            None => Err(EvalError::NotAValue(self.show(a, 80))),
+        }
+    }
+
+    // The graph at a in the notation of galaxy.txt, cut at about max characters.
+    fn show(&self, a: usize, max: usize) -> String {
+        fn go(m: &Machine, a: usize, out: &mut String, max: usize) {
+            if out.len() > max {
+                return;
+            }
+            match m.heap[m.resolve(a)] {
+                GNode::Num(n) => out.push_str(&n.to_string()),
+                GNode::Global(g) => out.push_str(m.name(g)),
+                GNode::Ap(f, x) => {
+                    out.push_str("ap ");
+                    go(m, f, out, max);
+                    out.push(' ');
+                    go(m, x, out, max);
+                }
+                GNode::Ind(_) => unreachable!(),
+            }
+        }
+        let mut out = String::new();
+        go(self, a, &mut out, max);
+        if out.len() > max {
+            let n = out.floor_char_boundary(max.saturating_sub(3));
+            out.truncate(n);
+            out.push_str("...");
+        }
+        out
+    }
+}
+
+/// GMachine evaluates by compiling the definitions to supercombinators run on a G-machine.
+/// The definitions are compiled on the first evaluation after a change.
+#[derive(Default)]
+pub struct GMachine {
+    defs: Vec<(String, String)>,
+    program: RefCell<Option<Rc<Program>>>,
+}
+
+impl GMachine {
+    // A machine with the definitions in galaxy.txt.
+    pub fn new() -> Self {
+        let mut m = GMachine::default();
+        for line in include_str!("../galaxy.txt").lines() {
+            if !line.trim().is_empty() {
+                m.add_def(line);
+            }
+        }
+        m
+    }
+
+    fn program(&self) -> Result<Rc<Program>, EvalError> {
+        if let Some(p) = self.program.borrow().as_ref() {
+            return Ok(p.clone());
+        }
+        let p = Rc::new(Program::new(&self.defs)?);
+        *self.program.borrow_mut() = Some(p.clone());
+        Ok(p)
+    }
+
+    // Number of supercombinators with at least one parameter, and the total number of
+    // definitions compiled, including the lifted ones.
+    pub fn stats(&self) -> Result<(usize, usize), EvalError> {
+        let p = self.program()?;
+        let defs = &p.globals[PRIMITIVES.len()..];
+        Ok((defs.iter().filter(|g| g.arity > 0).count(), defs.len()))
+    }
+}
+
+// The infallible methods panic with the message of the EvalError.
+impl Evaluator for GMachine {
+    fn evaluate(&self, expr: &str) -> Node {
+        self.try_evaluate(expr).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn try_evaluate(&self, expr: &str) -> Result<Node, EvalError> {
+        let _span = tracing::debug_span!("eval", expr).entered();
+        let program = self.program()?;
+        let mut m = Machine::new(&program);
+        let body = m.parse(expr)?;
+        let a = m.instantiate(&body);
+        m.read(a)
+    }
+    // Overridden so that overflows in clicks are EvalError::Overflow rather than panics, for
+    // G to fall back to its bigint evaluator.
+    fn try_evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Result<Node, EvalError> {
+        self.try_evaluate(&format!("ap ap {} {} {}", f, x, y))
+    }
+    fn add_def(&mut self, line: &str) {
+        let (name, expr) = line
+            .split_once(" = ")
+            .unwrap_or_else(|| panic!("not a definition: {:?}", line));
+        match self.defs.iter_mut().find(|(n, _)| n == name) {
+            Some(def) => def.1 = expr.to_string(),
+            None => self.defs.push((name.to_string(), expr.to_string())),
+        }
+        *self.program.get_mut() = None;
+    }
+    fn has_def(&self, name: &str) -> bool {
+        self.defs.iter().any(|(n, _)| n == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest, eval, protocol::G};
+
+    #[test]
+    fn test_supercombinators() {
+        let mut m = GMachine::default();
+        m.add_def(crate::testutil::STATELESSDRAW);
+        m.add_def("one = ap ap add 0 1");
+        m.add_def("inc = ap add one");
+        let p = m.program().unwrap();
+        let arity = |name: &str| p.globals[p.ids[name]].arity;
+        assert_eq!(arity("statelessdraw"), 2);
+        assert_eq!(arity("one"), 0);
+        assert_eq!(arity("inc"), 1);
+        assert_eq!(m.stats().unwrap(), (2, 5));
+        assert_eq!(m.evaluate("ap inc ap inc one"), Node::Num(3));
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let reduce = eval::by_name("reduce").unwrap();
+        let gmachine = eval::by_name("gmachine").unwrap();
+        let exprs = [
+            "ap ap add 1 2",
+            "ap ap div -7 2",
+            "ap ap lt 1 2",
+            "ap neg ap pwr2 10",
+            "ap ap ap if0 0 1 x99",
+            "ap car ap cdr ap ap checkerboard 3 0",
+            "ap isnil ap ap ap s t t nil",
+            "ap ap ap c cons 1 2",
+            "ap :1029 ap ap cons 1 nil",
+            "ap ap t 1 x9",
+            "ap ap add 1 x9",
+            "ap 1 2",
+            "ap ap div 1 0",
+            "ap ap mul 9223372036854775807 1",
+        ];
+        assert_eq!(
+            difftest::compare_exprs(reduce.as_ref(), gmachine.as_ref(), &exprs),
+            vec![]
+        );
+
+        let (reduce, gmachine) = (
+            G::builder().interpreter(reduce).build(),
+            G::builder().interpreter(gmachine).build(),
+        );
+        let clicks = [(0, 0); 8]
+            .iter()
+            .chain(&[(8, 4), (2, -8), (3, 6), (0, -14), (-4, 10)])
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            difftest::compare_clicks(&reduce, &gmachine, "galaxy", "nil", &clicks),
+            None
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{ast::Node, protocol::InteractOutput};
+
+use serde::Serialize;
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    fmt::Write,
+    hash::{Hash, Hasher},
+};
+
+/// StateGraph accumulates transitions (state, click) -> state observed while exploring a
+/// protocol. States and frames are identified by hashes.
+#[derive(Default, Debug)]
+pub struct StateGraph {
+    edges: BTreeMap<(u64, (i64, i64)), Edge>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+    pub from: u64,
+    pub click: (i64, i64),
+    pub to: u64,
+    // hash of the images drawn by the transition.
+    pub frame: u64,
+}
+
+pub fn state_hash(state: &Node) -> u64 {
+    let mut h = DefaultHasher::new();
+    state.hash(&mut h);
+    h.finish()
+}
+
+pub fn frame_hash(images: &[Vec<(i64, i64)>]) -> u64 {
+    let mut h = DefaultHasher::new();
+    images.hash(&mut h);
+    h.finish()
+}
+
+impl StateGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records a transition. A later transition for the same (from, click) replaces the
+    // earlier one.
+    pub fn add(&mut self, from: &Node, click: (i64, i64), to: &Node, images: &[Vec<(i64, i64)>]) {
+        let from = state_hash(from);
+        self.edges.insert(
+            (from, click),
+            Edge {
+                from,
+                click,
+                to: state_hash(to),
+                frame: frame_hash(images),
+            },
+        );
+    }
+
+    pub fn add_output(&mut self, from: &Node, click: (i64, i64), out: &InteractOutput) {
+        self.add(from, click, &out.state, &out.images)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.values()
+    }
+
+    pub fn states(&self) -> BTreeSet<u64> {
+        self.edges().flat_map(|e| [e.from, e.to]).collect()
+    }
+
+    // The frame drawn on entering each state, by the first transition into it in the order of
+    // edges().
+    pub fn thumbnails(&self) -> BTreeMap<u64, u64> {
+        let mut res = BTreeMap::new();
+        for e in self.edges() {
+            res.entry(e.to).or_insert(e.frame);
+        }
+        res
+    }
+
+    // Nodes are labeled with the start of the state hash and of its thumbnail frame hash.
+    pub fn to_dot(&self) -> String {
+        let thumbnails = self.thumbnails();
+        self.to_dot_with(|s| match thumbnails.get(&s) {
+            Some(f) => format!("{:08x}\\nframe {:08x}", s >> 32, f >> 32),
+            None => format!("{:08x}", s >> 32),
+        })
+    }
+
+    // Like to_dot, labeling each node with label(state hash). Quotes in labels are escaped,
+    // while the DOT escape \n is kept as a line break.
+    pub fn to_dot_with(&self, label: impl Fn(u64) -> String) -> String {
+        let mut res = String::new();
+        writeln!(res, "digraph {{").unwrap();
+        writeln!(res, "  node [shape=box, fontname=monospace];").unwrap();
+        for s in self.states() {
+            writeln!(
+                res,
+                "  \"{:016x}\" [label=\"{}\"];",
+                s,
+                label(s).replace('"', "\\\"")
+            )
+            .unwrap();
+        }
+        for e in self.edges() {
+            writeln!(
+                res,
+                "  \"{:016x}\" -> \"{:016x}\" [label=\"({}, {})\", frame=\"{:016x}\"];",
+                e.from, e.to, e.click.0, e.click.1, e.frame
+            )
+            .unwrap();
+        }
+        writeln!(res, "}}").unwrap();
+        res
+    }
+
+    // Hashes are written as hex strings since they don't fit in JSON numbers.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct EdgeJson {
+            from: String,
+            click: (i64, i64),
+            to: String,
+            frame: String,
+        }
+        #[derive(Serialize)]
+        struct GraphJson {
+            edges: Vec<EdgeJson>,
+        }
+        let hex = |h: u64| format!("{:016x}", h);
+        let edges = self
+            .edges()
+            .map(|e| EdgeJson {
+                from: hex(e.from),
+                click: e.click,
+                to: hex(e.to),
+                frame: hex(e.frame),
+            })
+            .collect();
+        serde_json::to_string(&GraphJson { edges }).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_graph() {
+        let s0: Node = "nil".parse().unwrap();
+        let s1: Node = "ap ap cons 1 nil".parse().unwrap();
+
+        let mut g = StateGraph::new();
+        g.add(&s0, (0, 0), &s1, &[vec![(0, 0)]]);
+        g.add(&s1, (1, 2), &s0, &[]);
+        g.add(&s1, (1, 2), &s1, &[]);
+        assert_eq!(g.edges().count(), 2);
+        assert_eq!(g.states().len(), 2);
+
+        let (h0, h1) = (state_hash(&s0), state_hash(&s1));
+        assert_eq!(
+            g.edges()
+                .map(|e| (e.from, e.click, e.to))
+                .collect::<BTreeSet<_>>(),
+            vec![(h0, (0, 0), h1), (h1, (1, 2), h1)]
+                .into_iter()
+                .collect()
+        );
+
+        let dot = g.to_dot();
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.contains(&format!(
+            "\"{:016x}\" -> \"{:016x}\" [label=\"(0, 0)\"",
+            h0, h1
+        )));
+        assert_eq!(g.thumbnails().len(), 1);
+        assert!(dot.contains(&format!(
+            "\"{:016x}\" [label=\"{:08x}\\nframe {:08x}\"];",
+            h1,
+            h1 >> 32,
+            g.thumbnails()[&h1] >> 32
+        )));
+        let dot = g.to_dot_with(|s| {
+            if s == h0 {
+                "\"nil\"".into()
+            } else {
+                "1".into()
+            }
+        });
+        assert!(dot.contains(&format!("\"{:016x}\" [label=\"\\\"nil\\\"\"];", h0)));
+
+        let json = g.to_json();
+        assert!(json.starts_with("{\"edges\":[{\"from\":"));
+        assert!(json.contains(&format!(
+            "{{\"from\":\"{:016x}\",\"click\":[1,2],\"to\":\"{:016x}\",\"frame\":\"{:016x}\"}}",
+            h1,
+            h1,
+            frame_hash(&[])
+        )));
+    }
+}
@@ -0,0 +1,313 @@
+//! A readable front end for writing protocols: lambdas and lets, compiled to the combinators
+//! of galaxy.txt by bracket abstraction.
+//!
+//! ```
+//! let statelessdraw =
+//!     app::lambda::compile(r"\state vector. cons 0 (cons nil (cons (cons (cons vector nil) nil) nil))")
+//!         .unwrap();
+//! assert_eq!(
+//!     statelessdraw.to_string(),
+//!     "ap t ap ap b ap cons 0 ap ap b ap cons nil ap ap c ap ap b cons ap ap c ap ap b cons ap ap c cons nil nil nil"
+//! );
+//! ```
+//!
+//! The syntax is
+//!
+//! ```text
+//! expr := \x y ... . expr | let name = expr in expr | atom atom ...
+//! atom := name | number | ( expr )
+//! ```
+//!
+//! Application is juxtaposition, left associative. Names are bound variables, primitives like
+//! add or cons, or else references to definitions like :1029, which may be the definition
+//! being written.
+
+use crate::{
+    ast::ExprBuilder,
+    expr::{Expr, Primitive},
+    reduce_evaluator::Env,
+};
+
+use anyhow::{bail, Context, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    Bound(String),
+    Free(String),
+    Prim(Primitive),
+    Num(i64),
+    Ap(Box<Term>, Box<Term>),
+    Lam(String, Box<Term>),
+}
+
+// Compiles src to an expression of combinators.
+pub fn compile(src: &str) -> Result<Expr> {
+    let tokens = lex(src);
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        bound: vec![],
+    };
+    let term = parser.expr()?;
+    if let Some(tok) = tokens.get(parser.pos) {
+        bail!("token {}: unexpected {:?}", parser.pos, tok);
+    }
+    Ok(build(&lower(term)))
+}
+
+impl Env {
+    // Compiles src with lambda::compile and defines name as it, returning the previous
+    // definition.
+    pub fn define_lambda(&mut self, name: &str, src: &str) -> Result<Option<Expr>> {
+        let e = compile(src).with_context(|| format!("defining {}", name))?;
+        Ok(self.define(name, e))
+    }
+}
+
+fn lex(src: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    for c in src.chars() {
+        if c.is_whitespace() || "\\λ.()=".contains(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    // Variables in scope, innermost last.
+    bound: Vec<String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+    fn next(&mut self) -> Result<&str> {
+        let tok = self
+            .tokens
+            .get(self.pos)
+            .with_context(|| format!("token {}: unexpected end of input", self.pos))?;
+        self.pos += 1;
+        Ok(tok)
+    }
+    fn expect(&mut self, want: &str) -> Result<()> {
+        let pos = self.pos;
+        match self.next()? {
+            tok if tok == want => Ok(()),
+            tok => bail!("token {}: expected {:?}, got {:?}", pos, want, tok),
+        }
+    }
+    fn name(&mut self) -> Result<String> {
+        let pos = self.pos;
+        match self.next()? {
+            tok if is_name(tok) => Ok(tok.to_string()),
+            tok => bail!("token {}: expected a name, got {:?}", pos, tok),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Term> {
+        match self.peek() {
+            Some("\\") | Some("λ") => {
+                self.next()?;
+                let mut params = vec![self.name()?];
+                while self.peek() != Some(".") {
+                    params.push(self.name()?);
+                }
+                self.next()?;
+                let n = self.bound.len();
+                self.bound.extend(params.iter().cloned());
+                let body = self.expr();
+                self.bound.truncate(n);
+                Ok(params
+                    .into_iter()
+                    .rev()
+                    .fold(body?, |body, x| Term::Lam(x, body.into())))
+            }
+            Some("let") => {
+                self.next()?;
+                let name = self.name()?;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect("in")?;
+                self.bound.push(name.clone());
+                let body = self.expr();
+                self.bound.pop();
+                Ok(Term::Ap(Term::Lam(name, body?.into()).into(), value.into()))
+            }
+            _ => {
+                let mut res = self.atom()?;
+                while let Some(tok) = self.peek() {
+                    if tok == ")" || tok == "in" || tok == "=" {
+                        break;
+                    }
+                    res = Term::Ap(res.into(), self.atom()?.into());
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<Term> {
+        let pos = self.pos;
+        let tok = self.next()?.to_string();
+        Ok(match tok.as_str() {
+            "(" => {
+                let e = self.expr()?;
+                self.expect(")")?;
+                e
+            }
+            // A lambda as the last argument, e.g. "f \x. x".
+            "\\" | "λ" => {
+                self.pos -= 1;
+                self.expr()?
+            }
+            s if self.bound.iter().any(|b| b == s) => Term::Bound(tok),
+            s => match (s.parse(), Primitive::from_name(s)) {
+                (Ok(n), _) => Term::Num(n),
+                (_, Some(p)) => Term::Prim(p),
+                _ if is_name(s) => Term::Free(tok),
+                _ => bail!("token {}: unexpected {:?}", pos, s),
+            },
+        })
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    !["let", "in"].contains(&s) && !s.contains(|c| "\\λ.()=".contains(c))
+}
+
+fn free_in(x: &str, t: &Term) -> bool {
+    match t {
+        Term::Bound(y) => x == y,
+        Term::Ap(f, g) => free_in(x, f) || free_in(x, g),
+        Term::Lam(y, body) => x != y && free_in(x, body),
+        _ => false,
+    }
+}
+
+// t without lambdas.
+fn lower(t: Term) -> Term {
+    match t {
+        Term::Ap(f, g) => Term::Ap(lower(*f).into(), lower(*g).into()),
+        Term::Lam(x, body) => abstract_var(&x, lower(*body)),
+        t => t,
+    }
+}
+
+// [x] t: a lambda-free term that applied to v is t with x replaced by v.
+fn abstract_var(x: &str, t: Term) -> Term {
+    use Primitive::*;
+    let ap = |f: Term, g: Term| Term::Ap(f.into(), g.into());
+    if !free_in(x, &t) {
+        return ap(Term::Prim(T), t);
+    }
+    match t {
+        Term::Bound(_) => Term::Prim(I),
+        Term::Ap(f, g) => match (free_in(x, &f), *g) {
+            (false, Term::Bound(y)) if y == x => *f,
+            (true, g) if free_in(x, &g) => {
+                ap(ap(Term::Prim(S), abstract_var(x, *f)), abstract_var(x, g))
+            }
+            (true, g) => ap(ap(Term::Prim(C), abstract_var(x, *f)), g),
+            (false, g) => ap(ap(Term::Prim(B), *f), abstract_var(x, g)),
+        },
+        t => unreachable!("{:?} has no lambdas and contains {}", t, x),
+    }
+}
+
+fn build(t: &Term) -> Expr {
+    fn go(t: &Term) -> ExprBuilder {
+        match t {
+            Term::Free(name) | Term::Bound(name) => ExprBuilder::var(name),
+            Term::Prim(p) => ExprBuilder::op(*p),
+            Term::Num(n) => ExprBuilder::num(*n),
+            Term::Ap(f, g) => go(f).ap(go(g)),
+            Term::Lam(..) => unreachable!("lowered"),
+        }
+    }
+    go(t).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::Node,
+        eval::{Eval, Evaluator},
+    };
+
+    #[test]
+    fn test_compile() {
+        for (src, want) in [
+            (r"\x. x", "i"),
+            (r"\x. 1", "ap t 1"),
+            (r"\x. add 1 x", "ap add 1"),
+            (r"\x y. x", "t"),
+            (r"\x y. y x", "ap c i"),
+            (r"\f x. f (f x)", "ap ap s b i"),
+            (
+                r"let sq = \x. mul x x in sq 3",
+                "ap ap ap c i 3 ap ap s mul i",
+            ),
+            (r"\t. t", "i"),
+            ("galaxy", "galaxy"),
+        ] {
+            assert_eq!(compile(src).unwrap().to_string(), want, "{}", src);
+        }
+        for src in [
+            r"\x.",
+            "(add 1",
+            "let x = 1",
+            "add )",
+            r"\. x",
+            "let in = 1 in 2",
+        ] {
+            assert!(compile(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut eval = Eval::new();
+        let env = eval.env_mut();
+        env.define_lambda("fact", r"\n. if0 n 1 (mul n (fact (add n -1)))")
+            .unwrap();
+        env.define_lambda("sum", r"\l. isnil l 0 (add (car l) (sum (cdr l)))")
+            .unwrap();
+        for (expr, want) in [
+            ("ap fact 10", "3628800"),
+            ("ap sum ap ap cons 1 ap ap cons 2 ap ap cons 3 nil", "6"),
+        ] {
+            assert_eq!(
+                eval.try_evaluate(expr).unwrap(),
+                want.parse::<Node>().unwrap()
+            );
+        }
+
+        let mut env = Env::new();
+        env.define_lambda(
+            "statelessdraw",
+            r"\state vector. cons 0 (cons nil (cons (cons (cons vector nil) nil) nil))",
+        )
+        .unwrap();
+        let g = crate::protocol::G::new(Box::new(Eval::with_env(env)));
+        let res = g.interact("statelessdraw", "nil".into(), 1, 0, "").unwrap();
+        assert_eq!(res.state, "nil");
+        assert_eq!(res.images, vec![vec![(1, 0)]]);
+        assert_eq!(res.data(), "ap ap cons ap ap cons ap ap cons 1 0 nil nil");
+    }
+}
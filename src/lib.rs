@@ -1,12 +1,117 @@
+//! Interpreter for the ICFPC 2020 galaxy language and its interaction protocol.
+//!
+//! The crate is organized in layers, from low to high level:
+//!
+//! - `ast`: values (Node) and expressions (Expr), built with ExprBuilder or `expr!`/`list!`.
+//! - `modulate`: the bit string codec for values.
+//! - `render`: decoding of drawn images.
+//! - `eval`: the Evaluator trait and its implementations.
+//! - `lambda`: lambda and let syntax compiled to combinators, for writing protocols.
+//! - `net`: sending requests to the aliens.
+//! - `protocol`: the interact loop (G) tying the above together.
+//! - `game`: a client of the aliens' game, on top of `net`, and `bot`: strategies playing it.
+//!
+//! The most used types are re-exported at the crate root. Natively this is a plain Rust
+//! library; the wasm-bindgen exports used by the web UI are behind the `wasm` feature.
+
 #![allow(unused_imports)]
 
+pub mod annotations;
+
+pub mod ast;
+
+#[cfg(feature = "bench")]
+pub mod bench;
+
+pub mod bot;
+
 pub mod common;
 
+pub mod difftest;
+
+pub mod eval;
+
+pub mod explore;
+
+pub mod expr;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub mod fuzz;
+
+pub mod game;
+
 pub mod gen_js;
 
+pub mod glyph;
+
+pub mod gmachine;
+
+pub mod graph;
+
+pub mod lambda;
+
+pub mod logging;
+pub mod macros;
+
+pub mod modulate;
+
+pub mod net;
+
+#[cfg(feature = "parallel")]
+pub mod parallel;
+
+#[cfg(feature = "node")]
+pub mod node;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "live")]
+pub mod live;
+
+pub mod pretty;
+
+pub mod protocol;
+
 pub mod reduce_evaluator;
 
+pub mod regions;
+
+pub mod render;
+
+pub mod session;
+
+pub mod shared;
+
+pub mod simplify;
+
+pub mod state;
+
+pub mod tournament;
+
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+
+#[cfg(feature = "wasm")]
 pub mod wasm_entrypoint;
 
 #[cfg(test)]
 mod galaxy_test;
+
+pub use ast::{ExprBuilder, FromExpr, Node, ToExpr, Value};
+pub use eval::{CancellationToken, Env, Eval, EvalBudget, EvalStats, Evaluator};
+pub use explore::{Exploration, Explorer, Order};
+pub use expr::Expr;
+pub use glyph::GlyphNumber;
+pub use net::Sender;
+pub use protocol::{
+    GBuilder, InteractError, InteractOutput, InteractResult, Interaction, Numeric, Progress,
+    Timing, G,
+};
+pub use regions::{Suggestion, Target};
+pub use render::{Bitmap, Image, ImageDiff, Point};
+pub use session::{CheckpointConfig, ClickRecord, History, Preset, ReplaySends, Session};
+pub use shared::SharedEnv;
+pub use state::State;
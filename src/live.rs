@@ -0,0 +1,197 @@
+//! WebSocket server holding a Session per connection, for UIs that show clicks live, e.g. to
+//! spectators, rather than only their results.
+//!
+//! Clients send clicks as {"type":"click","x":0,"y":0}. The server streams what happens as it
+//! happens, one JSON message each:
+//!
+//!   {"type":"state","state":"nil"}                        the state, on connecting and after
+//!                                                         each round of a click
+//!   {"type":"send","request":"ap ap cons 0 nil"}          a request to the aliens
+//!   {"type":"response","response":"ap ap cons 1 nil"}     their response
+//!   {"type":"frames","images":[[[x,y],...],...]}          the images of a finished click
+//!   {"type":"done","state":"...","eval_ms":1.5}           the end of a click
+//!   {"type":"error","message":"..."}                      a failed click or a bad message
+//!
+//! Each connection is served on its own thread with its own G, so that clicks of different
+//! clients don't wait for each other.
+
+use crate::{ast::Node, protocol::InteractObserver, session::Session};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{TcpListener, TcpStream};
+use tungstenite::{Message, WebSocket};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Click { x: i32, y: i32 },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event<'a> {
+    State { state: String },
+    Send { request: String },
+    Response { response: String },
+    Frames { images: &'a [Vec<(i64, i64)>] },
+    Done { state: &'a str, eval_ms: f64 },
+    Error { message: String },
+}
+
+// Accepts connections on listener forever, serving each with a session from new_session.
+// Failing to accept a connection is logged and doesn't stop the server. Clicks come from
+// anyone connecting, so new_session should bound them with G::set_budget, or a click that
+// never ends keeps its thread busy for good.
+pub fn serve(
+    listener: TcpListener,
+    new_session: impl Fn() -> Session + Send + Sync + Clone + 'static,
+) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!(error = %e, "accepting a connection");
+                continue;
+            }
+        };
+        let new_session = new_session.clone();
+        std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle(stream, new_session()) {
+                    tracing::info!(?peer, "connection closed: {:#}", e);
+                }
+            })
+            .context("spawning a connection thread")?;
+    }
+    Ok(())
+}
+
+// Serves one connection until the client closes it.
+fn handle(stream: TcpStream, mut session: Session) -> Result<()> {
+    let mut ws = tungstenite::accept(stream).context("websocket handshake")?;
+    send(
+        &mut ws,
+        &Event::State {
+            state: session.state().into(),
+        },
+    )?;
+    loop {
+        let text = match ws.read()? {
+            Message::Text(text) => text,
+            Message::Close(_) => return Ok(()),
+            _ => continue,
+        };
+        let (x, y) = match serde_json::from_str(&text) {
+            Ok(Request::Click { x, y }) => (x, y),
+            Err(e) => {
+                let message = format!("bad message {:?}: {}", text, e);
+                send(&mut ws, &Event::Error { message })?;
+                continue;
+            }
+        };
+        let mut stream = Stream {
+            ws: &mut ws,
+            failed: None,
+        };
+        let res = session.click_with(x, y, &mut stream);
+        if let Some(e) = stream.failed {
+            return Err(e);
+        }
+        let event = match &res {
+            Ok(res) => Event::Done {
+                state: &res.state,
+                eval_ms: res.timing.eval_ms,
+            },
+            Err(e) => Event::Error {
+                message: format!("{:#}", e),
+            },
+        };
+        send(&mut ws, &event)?;
+    }
+}
+
+fn send(ws: &mut WebSocket<TcpStream>, event: &Event) -> Result<()> {
+    ws.send(Message::Text(serde_json::to_string(event)?))?;
+    Ok(())
+}
+
+// Streams the progress of a click to the client, keeping the first error writing to it.
+struct Stream<'a> {
+    ws: &'a mut WebSocket<TcpStream>,
+    failed: Option<anyhow::Error>,
+}
+
+impl Stream<'_> {
+    fn send(&mut self, event: Event) {
+        if self.failed.is_none() {
+            self.failed = send(self.ws, &event).err();
+        }
+    }
+}
+
+impl InteractObserver for Stream<'_> {
+    fn on_state(&mut self, state: &Node) {
+        self.send(Event::State {
+            state: state.to_string(),
+        });
+    }
+    fn on_send(&mut self, request: &Node) {
+        self.send(Event::Send {
+            request: request.to_string(),
+        });
+    }
+    fn on_response(&mut self, response: &Node) {
+        self.send(Event::Response {
+            response: response.to_string(),
+        });
+    }
+    fn on_frames(&mut self, images: &[Vec<(i64, i64)>]) {
+        self.send(Event::Frames { images });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::G;
+
+    #[test]
+    fn test_live() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            serve(listener, || {
+                let g = G::from_source(crate::testutil::STATELESSDRAW).unwrap();
+                Session::new(g, "statelessdraw", "nil".into())
+            })
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut ws, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+        fn next(ws: &mut WebSocket<TcpStream>) -> serde_json::Value {
+            match ws.read().unwrap() {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                m => panic!("unexpected {:?}", m),
+            }
+        }
+        assert_eq!(
+            next(&mut ws),
+            serde_json::json!({"type": "state", "state": "nil"})
+        );
+        ws.send(Message::Text(r#"{"type":"click","x":1,"y":2}"#.into()))
+            .unwrap();
+        assert_eq!(next(&mut ws)["type"], "state");
+        assert_eq!(
+            next(&mut ws),
+            serde_json::json!({"type": "frames", "images": [[[1, 2]]]})
+        );
+        assert_eq!(next(&mut ws)["type"], "done");
+
+        ws.send(Message::Text(r#"{"type":"drag"}"#.into())).unwrap();
+        assert_eq!(next(&mut ws)["type"], "error");
+        ws.close(None).unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+use tracing_subscriber::EnvFilter;
+
+// Installs the default subscriber of the binaries: events are written to stderr, filtered by
+// RUST_LOG (e.g. RUST_LOG=app=debug), "info" if unset. stdout is left to the binaries, some of
+// which speak a line protocol over it.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .try_init();
+}
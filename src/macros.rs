@@ -0,0 +1,54 @@
+//! Macros for writing expressions literally, mostly in tests.
+
+/// Expression in galaxy.txt's notation, e.g. `expr!(ap ap add 1 2)` or `expr!(ap :1029 nil)`.
+/// Names other than primitives become variables.
+#[macro_export]
+macro_rules! expr {
+    ($($t:tt)+) => {
+        $crate::ast::__expr_from_tokens(stringify!($($t)+))
+    };
+}
+
+/// List expression whose elements are anything convertible into an ExprBuilder, e.g.
+/// `list![1, list![2, 3], (4, 5)]`.
+#[macro_export]
+macro_rules! list {
+    ($($x:expr),* $(,)?) => {
+        $crate::ast::ExprBuilder::list(
+            ::std::vec![$($crate::ast::ExprBuilder::from($x)),*] as ::std::vec::Vec<$crate::ast::ExprBuilder>
+        )
+        .build()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Node;
+
+    #[test]
+    fn test_macros() {
+        for (e, want) in [
+            (expr!(ap ap add 1 2), "ap ap add 1 2"),
+            (expr!(ap neg -3), "ap neg -3"),
+            (expr!(ap :1029 nil), "ap :1029 nil"),
+            (expr!(ap ap vec 1 x0), "ap ap cons 1 x0"),
+            (expr!(ap galaxy nil), "ap galaxy nil"),
+            (list![], "nil"),
+            (
+                list![1, list![2, 3]],
+                "ap ap cons 1 ap ap cons ap ap cons 2 ap ap cons 3 nil nil",
+            ),
+            (
+                list![(0, -1), expr!(x0),],
+                "ap ap cons ap ap cons 0 -1 ap ap cons x0 nil",
+            ),
+        ] {
+            assert_eq!(e.to_string(), want);
+        }
+        let node: Node = "ap ap cons 1 nil".parse().unwrap();
+        assert_eq!(
+            list![node.clone()].to_string(),
+            "ap ap cons ap ap cons 1 nil nil"
+        );
+    }
+}
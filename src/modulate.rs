@@ -0,0 +1,439 @@
+//! The bit string codec used to talk to the aliens.
+
+use crate::ast::{Node, MAX_DEPTH};
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint::{BigInt, BigUint, Sign};
+use std::{
+    convert::TryFrom,
+    io::{BufReader, Read},
+};
+
+impl Node {
+    pub fn modulate(&self) -> String {
+        match self {
+            Node::Nil => "00".into(),
+            Node::Cons(x, y) => "11".to_string() + &x.modulate() + &y.modulate(),
+            Node::Num(n) => {
+                let mut res = String::new();
+                res.push_str(if *n >= 0 { "01" } else { "10" });
+                let n = n.unsigned_abs();
+
+                let keta = 64 - n.leading_zeros();
+                let t = keta.div_ceil(4);
+
+                for _ in 0..t {
+                    res.push('1');
+                }
+                res.push('0');
+
+                for i in (0..4 * t).rev() {
+                    res.push(if (n >> i & 1) == 1 { '1' } else { '0' });
+                }
+                res
+            }
+            Node::Big(n) => {
+                let mut res = String::new();
+                res.push_str(if n.sign() != Sign::Minus { "01" } else { "10" });
+                let n = n.magnitude();
+
+                let t = (n.bits() as usize).div_ceil(4);
+
+                for _ in 0..t {
+                    res.push('1');
+                }
+                res.push('0');
+
+                res.push_str(&format!("{:0>1$}", n.to_str_radix(2), 4 * t));
+                res
+            }
+        }
+    }
+
+    pub fn demodulate(s: &str) -> Node {
+        Node::try_demodulate(s).unwrap()
+    }
+
+    // Like demodulate, but returns an error on malformed input instead of panicking.
+    pub fn try_demodulate(s: &str) -> Result<Node, DemodError> {
+        if let Some((offset, c)) = s.chars().enumerate().find(|(_, c)| *c != '0' && *c != '1') {
+            return Err(DemodError {
+                offset,
+                kind: DemodErrorKind::UnexpectedChar(c),
+            });
+        }
+        let mut bits = Bits::new(s.chars().map(|c| Ok(c == '1')));
+        let res = Node::demodulate_iter(&mut bits, 0)?;
+        if bits.it.next().is_some() {
+            return Err(DemodError {
+                offset: bits.offset,
+                kind: DemodErrorKind::TrailingBits,
+            });
+        }
+        Ok(res)
+    }
+
+    // The modulated bits packed into bytes, most significant bit first, with the last byte
+    // padded with zeros. An eighth of the size of modulate.
+    pub fn modulate_bytes(&self) -> Vec<u8> {
+        self.modulate()
+            .as_bytes()
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |b, (i, &c)| b | ((c == b'1') as u8) << (7 - i))
+            })
+            .collect()
+    }
+
+    pub fn demodulate_bytes(bytes: &[u8]) -> Result<Node, DemodError> {
+        let mut bits = Bits::new(
+            bytes
+                .iter()
+                .flat_map(|&b| (0..8).rev().map(move |i| Ok(b >> i & 1 == 1))),
+        );
+        let res = Node::demodulate_iter(&mut bits, 0)?;
+        let rest = bits.it.collect::<Vec<_>>();
+        if rest.len() >= 8 || rest.contains(&Ok(true)) {
+            return Err(DemodError {
+                offset: bits.offset,
+                kind: DemodErrorKind::TrailingBits,
+            });
+        }
+        Ok(res)
+    }
+
+    // modulate_bytes in standard base64 with padding, for text transports.
+    pub fn modulate_base64(&self) -> String {
+        STANDARD.encode(self.modulate_bytes())
+    }
+
+    pub fn demodulate_base64(s: &str) -> Result<Node> {
+        Ok(Node::demodulate_bytes(&STANDARD.decode(s)?)?)
+    }
+
+    // Reads a value nested depth cons cells deep.
+    fn demodulate_iter(bits: &mut Bits<impl BitIter>, depth: usize) -> Result<Node, DemodError> {
+        use Token::*;
+        Ok(match (bits.next(Tag)?, bits.next(Tag)?) {
+            (false, false) => Node::Nil,
+            (true, true) if depth >= MAX_DEPTH => {
+                return Err(DemodError {
+                    offset: bits.offset,
+                    kind: DemodErrorKind::TooDeep,
+                })
+            }
+            (true, true) => Node::Cons(
+                Node::demodulate_iter(bits, depth + 1)?.into(),
+                Node::demodulate_iter(bits, depth + 1)?.into(),
+            ),
+            (_, pos) => {
+                let mut t = 0;
+                while bits.next(Width)? {
+                    t += 1;
+                }
+                if t <= 16 {
+                    let mut v = 0u64;
+                    for i in (0..4 * t).rev() {
+                        v |= (bits.next(Digits)? as u64) << i;
+                    }
+                    match if pos {
+                        i64::try_from(v).ok()
+                    } else {
+                        0i64.checked_sub_unsigned(v)
+                    } {
+                        Some(n) => Node::Num(n),
+                        None => Node::from(BigInt::from_biguint(
+                            if pos { Sign::Plus } else { Sign::Minus },
+                            v.into(),
+                        )),
+                    }
+                } else {
+                    let mut digits = Vec::with_capacity(4 * t);
+                    for _ in 0..4 * t {
+                        digits.push(bits.next(Digits)? as u8);
+                    }
+                    let v = BigUint::from_radix_be(&digits, 2).unwrap();
+                    Node::from(BigInt::from_biguint(
+                        if pos { Sign::Plus } else { Sign::Minus },
+                        v,
+                    ))
+                }
+            }
+        })
+    }
+}
+
+/// Token is a part of a modulated value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Token {
+    // The 2-bit prefix telling nil, cons, or a number's sign.
+    Tag,
+    // A number's width in unary.
+    Width,
+    // A number's binary digits.
+    Digits,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DemodErrorKind {
+    UnexpectedChar(char),
+    // Reading the input failed.
+    Io(String),
+    UnexpectedEnd { expected: Token },
+    // Bits remain after a complete value.
+    TrailingBits,
+    // Cons cells nest deeper than ast::MAX_DEPTH.
+    TooDeep,
+}
+
+/// DemodError tells why and where, as an offset in bits, demodulation failed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DemodError {
+    pub offset: usize,
+    pub kind: DemodErrorKind,
+}
+
+impl std::fmt::Display for DemodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bit {}: ", self.offset)?;
+        match &self.kind {
+            DemodErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            DemodErrorKind::Io(e) => write!(f, "{}", e),
+            DemodErrorKind::UnexpectedEnd { expected } => {
+                write!(f, "unexpected end of input, expected {:?}", expected)
+            }
+            DemodErrorKind::TrailingBits => write!(f, "trailing bits"),
+            DemodErrorKind::TooDeep => write!(f, "nesting deeper than {}", MAX_DEPTH),
+        }
+    }
+}
+
+impl std::error::Error for DemodError {}
+
+trait BitIter: Iterator<Item = Result<bool, DemodErrorKind>> {}
+
+impl<I: Iterator<Item = Result<bool, DemodErrorKind>>> BitIter for I {}
+
+// Bits counts the bits taken from an iterator for DemodError offsets.
+struct Bits<I> {
+    it: I,
+    offset: usize,
+}
+
+impl<I: BitIter> Bits<I> {
+    fn new(it: I) -> Self {
+        Bits { it, offset: 0 }
+    }
+    fn next(&mut self, expected: Token) -> Result<bool, DemodError> {
+        let offset = self.offset;
+        let err = |kind| DemodError { offset, kind };
+        let bit = match self.it.next() {
+            Some(Ok(bit)) => bit,
+            Some(Err(kind)) => return Err(err(kind)),
+            None => return Err(err(DemodErrorKind::UnexpectedEnd { expected })),
+        };
+        self.offset += 1;
+        Ok(bit)
+    }
+}
+
+/// Demodulator reads modulated values one after another from a stream of '0' and '1'
+/// characters, e.g. a socket or a replay file with a value per line, without reading the whole
+/// stream first. Whitespace is skipped. Values are yielded as soon as their last bit arrives:
+///
+/// ```
+/// use app::modulate::Demodulator;
+/// let input = "010\n110110000100\n00".as_bytes();
+/// let values = Demodulator::from_reader(input).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(values.len(), 3);
+/// assert_eq!(values[1].to_string(), "ap ap cons 1 nil");
+/// ```
+///
+/// Iteration stops after the first error, as the stream is out of sync from there on. Error
+/// offsets count bits from the start of the stream.
+pub struct Demodulator<I: Iterator> {
+    bytes: std::iter::Peekable<I>,
+    offset: usize,
+    failed: bool,
+}
+
+impl<R: Read> Demodulator<std::io::Bytes<BufReader<R>>> {
+    pub fn from_reader(r: R) -> Self {
+        Demodulator::new(BufReader::new(r).bytes())
+    }
+}
+
+impl<I: Iterator<Item = std::io::Result<u8>>> Demodulator<I> {
+    // Reads bytes, e.g. bytes.into_iter().map(Ok) for plain bytes.
+    pub fn new(bytes: I) -> Self {
+        Demodulator {
+            bytes: bytes.peekable(),
+            offset: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = std::io::Result<u8>>> Iterator for Demodulator<I> {
+    type Item = Result<Node, DemodError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        while let Some(Ok(b)) = self.bytes.peek() {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            self.bytes.next();
+        }
+        self.bytes.peek()?;
+
+        let bytes = &mut self.bytes;
+        let mut bits = Bits {
+            it: std::iter::from_fn(|| loop {
+                return Some(match bytes.next()? {
+                    Ok(b'0') => Ok(false),
+                    Ok(b'1') => Ok(true),
+                    Ok(b) if b.is_ascii_whitespace() => continue,
+                    Ok(b) => Err(DemodErrorKind::UnexpectedChar(b as char)),
+                    Err(e) => Err(DemodErrorKind::Io(e.to_string())),
+                });
+            }),
+            offset: self.offset,
+        };
+        let res = Node::demodulate_iter(&mut bits, 0);
+        self.offset = bits.offset;
+        self.failed = res.is_err();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes() {
+        for (node, bytes, base64) in [
+            ("nil", vec![0b0000_0000], "AA=="),
+            ("ap ap cons 1 nil", vec![0b1101_1000, 0b0100_0000], "2EA="),
+            ("-1", vec![0b1010_0001], "oQ=="),
+            (
+                "ap ap cons 0 ap ap cons 1 2",
+                vec![0b1101_0110, 0b1100_0010, 0b1100_0100],
+                "1sLE",
+            ),
+        ] {
+            let node: Node = node.parse().unwrap();
+            assert_eq!(node.modulate_bytes(), bytes, "{}", node);
+            assert_eq!(node.modulate_base64(), base64, "{}", node);
+            assert_eq!(Node::demodulate_bytes(&bytes).unwrap(), node);
+            assert_eq!(Node::demodulate_base64(base64).unwrap(), node);
+        }
+        let big: Node = "ap ap cons 123456789012345678901234567890 nil"
+            .parse()
+            .unwrap();
+        assert_eq!(Node::demodulate_bytes(&big.modulate_bytes()).unwrap(), big);
+
+        assert_eq!(
+            Node::demodulate_bytes(&[0, 0]).unwrap_err().kind,
+            DemodErrorKind::TrailingBits
+        );
+        assert!(Node::demodulate_bytes(&[0b0000_0001]).is_err());
+        assert!(Node::demodulate_bytes(&[]).is_err());
+        assert!(Node::demodulate_base64("A*").is_err());
+        // Truncated input, one character past a whole group.
+        assert!(Node::demodulate_base64("A").is_err());
+        assert!(Node::demodulate_base64("aAAAA").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        use DemodErrorKind::*;
+        for (s, offset, kind) in [
+            (
+                "",
+                0,
+                UnexpectedEnd {
+                    expected: Token::Tag,
+                },
+            ),
+            (
+                "1",
+                1,
+                UnexpectedEnd {
+                    expected: Token::Tag,
+                },
+            ),
+            (
+                "0111",
+                4,
+                UnexpectedEnd {
+                    expected: Token::Width,
+                },
+            ),
+            (
+                "01100",
+                5,
+                UnexpectedEnd {
+                    expected: Token::Digits,
+                },
+            ),
+            (
+                "110100",
+                6,
+                UnexpectedEnd {
+                    expected: Token::Tag,
+                },
+            ),
+            ("0000", 2, TrailingBits),
+            ("01x", 2, UnexpectedChar('x')),
+            (&("11".repeat(20000) + "00"), 2 * MAX_DEPTH + 2, TooDeep),
+        ] {
+            let err = Node::try_demodulate(s).unwrap_err();
+            assert_eq!(err, DemodError { offset, kind }, "{:?}", s);
+        }
+        assert_eq!(
+            Node::try_demodulate("0111").unwrap_err().to_string(),
+            "bit 4: unexpected end of input, expected Width"
+        );
+    }
+
+    #[test]
+    fn test_demodulator() {
+        let values = ["nil", "ap ap cons 1 nil", "-123456789012345678901234567890"]
+            .iter()
+            .map(|s| s.parse::<Node>().unwrap())
+            .collect::<Vec<_>>();
+        let stream = values.iter().map(Node::modulate).collect::<String>();
+        let got = Demodulator::new(stream.bytes().map(Ok))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(got, values);
+
+        assert_eq!(Demodulator::from_reader(" \n".as_bytes()).count(), 0);
+        let mut it = Demodulator::from_reader("00 0110\n0".as_bytes());
+        assert_eq!(it.next(), Some(Ok(Node::Nil)));
+        assert_eq!(
+            it.next(),
+            Some(Err(DemodError {
+                offset: 7,
+                kind: DemodErrorKind::UnexpectedEnd {
+                    expected: Token::Digits
+                }
+            }))
+        );
+        assert_eq!(it.next(), None);
+
+        let mut it = Demodulator::from_reader("01x".as_bytes());
+        assert_eq!(
+            it.next().unwrap().unwrap_err().kind,
+            DemodErrorKind::UnexpectedChar('x')
+        );
+    }
+}
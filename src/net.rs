@@ -0,0 +1,622 @@
+//! Delivery of protocol requests to the aliens.
+
+use crate::ast::Node;
+
+use anyhow::{bail, Context, Result};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Sender delivers a protocol's data to the aliens when the protocol returns flag 1.
+pub trait Sender {
+    fn send(&self, req: &Node, api_key: &str) -> Result<Node>;
+}
+
+/// HttpSender sends modulated requests to the alien proxy, retrying transient failures.
+#[derive(Clone, Debug, Default)]
+pub struct HttpSender {
+    pub config: Config,
+}
+
+impl HttpSender {
+    pub fn new(config: Config) -> Self {
+        HttpSender { config }
+    }
+}
+
+impl Sender for HttpSender {
+    fn send(&self, req: &Node, api_key: &str) -> Result<Node> {
+        send(req, api_key, &self.config)
+    }
+}
+
+pub const DEFAULT_BASE_URL: &str = "https://api.pegovka.space";
+
+/// Config tells where and how to reach the aliens:
+///
+/// ```
+/// use app::net::Config;
+/// let config = Config::default()
+///     .base_url("http://localhost:8000")
+///     .timeout(std::time::Duration::from_secs(10))
+///     .api_key("0123");
+/// assert_eq!(config.send_url("0123"), "http://localhost:8000/aliens/send?apiKey=0123");
+/// ```
+///
+/// The API key is used for clicks that don't come with one. from_env reads it from the
+/// API_KEY environment variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub base_url: String,
+    // An HTTP proxy for all requests, e.g. "http://proxy:3128".
+    pub proxy: Option<String>,
+    pub timeout: Option<Duration>,
+    pub api_key: Option<String>,
+    pub retry: Retry,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            base_url: DEFAULT_BASE_URL.into(),
+            proxy: None,
+            timeout: None,
+            api_key: None,
+            retry: Retry::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            api_key: std::env::var("API_KEY").ok().filter(|k| !k.is_empty()),
+            ..Default::default()
+        }
+    }
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn send_url(&self, api_key: &str) -> String {
+        format!(
+            "{}/aliens/send?apiKey={}",
+            self.base_url.trim_end_matches('/'),
+            api_key
+        )
+    }
+    // api_key, or the configured one if it's empty.
+    pub fn resolve_api_key<'a>(&'a self, api_key: &'a str) -> &'a str {
+        match &self.api_key {
+            Some(key) if api_key.is_empty() => key,
+            _ => api_key,
+        }
+    }
+}
+
+/// Retry configures how often and how patiently a request is retried after transient
+/// failures: timeouts, connection errors, 429 and 5xx. The n-th retry waits
+/// initial_backoff * 2^(n-1), capped at max_backoff, plus up to jitter times that at random so
+/// that clients don't retry in lockstep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retry {
+    // Attempts including the first one; 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl Retry {
+    pub fn none() -> Self {
+        Retry {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    // The wait before the given retry, counting from 1, for a random r in [0, 1). Retry 0 waits
+    // as long as retry 1.
+    pub fn backoff(&self, retry: u32, r: f64) -> Duration {
+        let exp = self
+            .initial_backoff
+            .checked_mul(1 << retry.saturating_sub(1).min(31))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        exp.mul_f64(1.0 + self.jitter * r)
+    }
+}
+
+/// SendError is why a request didn't get a response.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SendError {
+    /// The proxy rejected the request, e.g. 403 for a bad API key. Not retried.
+    Status(u16),
+    /// Every attempt failed transiently; last is the failure of the last one.
+    Exhausted { attempts: u32, last: String },
+    /// A failure not worth retrying, e.g. an invalid URL.
+    Other(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Status(status) => write!(f, "HTTP status {}", status),
+            SendError::Exhausted { attempts, last } => {
+                write!(f, "gave up after {} attempts: {}", attempts, last)
+            }
+            SendError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+// Whether a response with the HTTP status is worth retrying.
+pub fn is_transient_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+/// ReplaySender answers with recorded (request, response) pairs in order, e.g. the sends of
+/// an InteractResult, so that a run can be reproduced without the aliens.
+pub struct ReplaySender {
+    sends: RefCell<VecDeque<(Node, Node)>>,
+}
+
+impl ReplaySender {
+    pub fn new(sends: Vec<(Node, Node)>) -> Self {
+        ReplaySender {
+            sends: RefCell::new(sends.into()),
+        }
+    }
+    // Number of recorded responses not used yet.
+    pub fn remaining(&self) -> usize {
+        self.sends.borrow().len()
+    }
+}
+
+impl Sender for ReplaySender {
+    fn send(&self, req: &Node, _api_key: &str) -> Result<Node> {
+        let (want, res) = match self.sends.borrow_mut().pop_front() {
+            Some(send) => send,
+            None => bail!("replay: no recorded response for {}", req),
+        };
+        if &want != req {
+            bail!("replay: expected request {}, got {}", want, req);
+        }
+        Ok(res)
+    }
+}
+
+/// CachingSender answers repeated requests with the response the inner Sender gave the first
+/// time, so that exploring or testing never sends the same request to the aliens twice.
+/// Responses are kept in memory and, with with_file, in a JSON file mapping modulated requests
+/// to modulated responses, which is rewritten on every new response.
+///
+/// Requests aren't always idempotent, e.g. creating a game yields new player keys each time;
+/// cached games replay the first one.
+pub struct CachingSender {
+    inner: Box<dyn Sender>,
+    responses: RefCell<HashMap<String, String>>,
+    path: Option<std::path::PathBuf>,
+}
+
+impl CachingSender {
+    pub fn new(inner: Box<dyn Sender>) -> Self {
+        CachingSender {
+            inner,
+            responses: Default::default(),
+            path: None,
+        }
+    }
+    // Loads the responses in path, if it exists, and saves new ones there.
+    pub fn with_file(mut self, path: impl Into<std::path::PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if path.exists() {
+            let json = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let responses: HashMap<String, String> = serde_json::from_str(&json)
+                .with_context(|| format!("parsing {}", path.display()))?;
+            self.responses.get_mut().extend(responses);
+        }
+        self.path = Some(path);
+        Ok(self)
+    }
+    // Adds known responses to the cache, e.g. the sends recorded in a History.
+    pub fn with_responses(self, sends: impl IntoIterator<Item = (Node, Node)>) -> Self {
+        self.responses.borrow_mut().extend(
+            sends
+                .into_iter()
+                .map(|(req, res)| (req.modulate(), res.modulate())),
+        );
+        self
+    }
+    // Number of cached responses.
+    pub fn len(&self) -> usize {
+        self.responses.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let json = serde_json::to_string_pretty(&*self.responses.borrow())?;
+            std::fs::write(path, json).with_context(|| format!("writing {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Sender for CachingSender {
+    fn send(&self, req: &Node, api_key: &str) -> Result<Node> {
+        let key = req.modulate();
+        if let Some(res) = self.responses.borrow().get(&key) {
+            tracing::debug!(request = %req, "cached response");
+            return Ok(Node::try_demodulate(res)?);
+        }
+        let res = self.inner.send(req, api_key)?;
+        self.responses.borrow_mut().insert(key, res.modulate());
+        self.save()?;
+        Ok(res)
+    }
+}
+
+/// LocalSender emulates the alien proxy for the requests galaxy is known to make, so that it
+/// can be explored offline:
+///
+///   [0]                countdown     -> [1, 0]: the countdown has long run out
+///   [1, 0]             create a game -> [1, [[0, attacker key], [1, defender key]]]
+///   [2, key, _]        join          -> [1, 0, static info, nil]
+///   [3, key, ship]     start         -> [1, 1, static info, state]
+///   [4, key, commands] commands      -> [1, 2, static info, state]: the game ends at once
+///
+/// Anything else, e.g. an unknown player key, gets [0] like from the proxy. The game infos are
+/// plausible rather than faithful; they only need to satisfy galaxy's screens.
+#[derive(Default)]
+pub struct LocalSender {
+    // Game stage by player key.
+    players: RefCell<HashMap<i64, i64>>,
+}
+
+impl LocalSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The response to req, if it is a known interaction.
+    fn respond(&self, req: &Node) -> Option<serde_json::Value> {
+        use serde_json::json;
+        let req = req.as_list()?;
+        let num = |i: usize| match req.get(i) {
+            Some(Node::Num(n)) => Some(*n),
+            _ => None,
+        };
+        let mut players = self.players.borrow_mut();
+        match (num(0)?, num(1)) {
+            (0, _) if req.len() == 1 => Some(json!([1, 0])),
+            (1, Some(0)) => {
+                let attacker = 1_000_000 + players.len() as i64;
+                players.insert(attacker, 0);
+                players.insert(attacker + 1, 0);
+                Some(json!([1, [[0, attacker], [1, attacker + 1]]]))
+            }
+            (kind @ 2..=4, Some(key)) => {
+                let stage = players.get_mut(&key)?;
+                *stage = kind - 2;
+                let role = key % 2;
+                let info = json!([256, role, [512, 1, 64], [16, 128], []]);
+                let ship = json!([
+                    role,
+                    0,
+                    {"cons": [-48, 0]},
+                    {"cons": [0, 0]},
+                    [254, 0, 16, 1],
+                    0,
+                    64,
+                    1
+                ]);
+                let state = match *stage {
+                    0 => json!([]),
+                    _ => json!([*stage, [16, 128], [[ship, []]]]),
+                };
+                Some(json!([1, *stage, info, state]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Sender for LocalSender {
+    fn send(&self, req: &Node, _api_key: &str) -> Result<Node> {
+        match self.respond(req) {
+            Some(res) => Node::from_json_value(&res),
+            None => {
+                tracing::warn!(request = %req, "local: unknown request");
+                Ok(Node::Cons(Node::Num(0).into(), Node::Nil.into()))
+            }
+        }
+    }
+}
+
+/// Sends with this API key are answered by a LocalSender instead of the aliens, e.g. when the
+/// key is entered in the browser UI.
+pub const LOCAL_API_KEY: &str = "local";
+
+thread_local! {
+    static LOCAL: LocalSender = LocalSender::new();
+}
+
+pub fn send_url(api_key: &str) -> String {
+    Config::default().send_url(api_key)
+}
+
+fn send(req: &Node, api_key: &str, config: &Config) -> Result<Node> {
+    let _span = tracing::info_span!("send").entered();
+    let api_key = config.resolve_api_key(api_key);
+    if api_key == LOCAL_API_KEY {
+        return LOCAL.with(|local| local.send(req, api_key));
+    }
+    let req = req.modulate();
+    tracing::info!(bits = req.len(), "sending to the aliens");
+    tracing::debug!(request = %req);
+    let url = config.send_url(api_key);
+    // The query holds the API key, so it's left out of errors.
+    let res = request_retrying(&url, req, config)
+        .with_context(|| format!("POST {}", url.split('?').next().unwrap_or_default()))?;
+    tracing::debug!(response = %res);
+    Node::try_demodulate(res.trim()).with_context(|| format!("bad response {:?}", res))
+}
+
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+pub fn request(url: &str, req: String) -> Result<String> {
+    Ok(request_retrying(url, req, &Config::default())?)
+}
+
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+pub fn request_retrying(url: &str, req: String, config: &Config) -> Result<String, SendError> {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    let mut client = reqwest::blocking::Client::builder();
+    if let Some(timeout) = config.timeout {
+        client = client.timeout(timeout);
+    }
+    if let Some(proxy) = &config.proxy {
+        client = client.proxy(
+            reqwest::Proxy::all(proxy.as_str()).map_err(|e| SendError::Other(e.to_string()))?,
+        );
+    }
+    let client = client
+        .build()
+        .map_err(|e| SendError::Other(e.to_string()))?;
+    let retry = &config.retry;
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let last = match client.post(url).body(req.clone()).send() {
+            Ok(resp) if resp.status().is_success() => match resp.text() {
+                Ok(text) => return Ok(text),
+                Err(e) => e.to_string(),
+            },
+            Ok(resp) if is_transient_status(resp.status().as_u16()) => {
+                format!("HTTP status {}", resp.status().as_u16())
+            }
+            Ok(resp) => return Err(SendError::Status(resp.status().as_u16())),
+            Err(e) if e.is_timeout() || e.is_connect() => e.to_string(),
+            Err(e) => return Err(SendError::Other(e.to_string())),
+        };
+        if attempts >= retry.max_attempts {
+            return Err(SendError::Exhausted { attempts, last });
+        }
+        let wait = retry.backoff(attempts, rng.gen());
+        tracing::warn!(attempts, ?wait, error = %last, "retrying");
+        std::thread::sleep(wait);
+    }
+}
+
+// Targets without reqwest don't retry.
+#[cfg(not(all(feature = "http", not(target_arch = "wasm32"))))]
+fn request_retrying(url: &str, req: String, _config: &Config) -> Result<String> {
+    request(url, req)
+}
+
+// Without reqwest or the JS glue there is no network access, e.g. on WASI or with
+// --no-default-features. Hosts provide responses with G::set_sender instead, e.g. the
+// StdioSender of the headless binary.
+#[cfg(not(any(
+    all(feature = "http", not(target_arch = "wasm32")),
+    all(feature = "wasm", target_arch = "wasm32")
+)))]
+pub fn request(_url: &str, _req: String) -> Result<String> {
+    bail!("no network on this target; set a Sender with G::set_sender")
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub fn request(url: &str, req: String) -> Result<String> {
+    Ok(js_request(url, req))
+}
+
+// Like send, but with fetch, which doesn't block the browser's main thread. For
+// G::galaxy_async.
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub async fn fetch_send(req: Node, api_key: String, config: Config) -> Result<Node> {
+    let api_key = config.resolve_api_key(&api_key).to_string();
+    if api_key == LOCAL_API_KEY {
+        return LOCAL.with(|local| local.send(&req, &api_key));
+    }
+    let req = req.modulate();
+    tracing::info!(bits = req.len(), "sending to the aliens with fetch");
+    let url = config.send_url(&api_key);
+    let res = wasm_bindgen_futures::JsFuture::from(js_fetch_request(&url, req))
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))
+        .and_then(|res| res.as_string().context("non-string response"))
+        .with_context(|| format!("POST {}", url.split('?').next().unwrap_or_default()))?;
+    tracing::debug!(response = %res);
+    Node::try_demodulate(res.trim()).with_context(|| format!("bad response {:?}", res))
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen(module = "/js/wasm_define.js")]
+extern "C" {
+    fn name() -> String;
+
+    #[wasm_bindgen(js_name = request)]
+    fn js_request(url: &str, req: String) -> String;
+
+    // A Promise resolving to the response text.
+    #[wasm_bindgen(js_name = fetch_request)]
+    fn js_fetch_request(url: &str, req: String) -> js_sys::Promise;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_sender() {
+        let local = LocalSender::new();
+        for (req, want) in [
+            ("[0]", "[1, 0]"),
+            ("[1, 0]", "[1, [[0, 1000000], [1, 1000001]]]"),
+            ("[1, 0]", "[1, [[0, 1000002], [1, 1000003]]]"),
+            (
+                "[2, 1000001, []]",
+                "[1, 0, [256, 1, [512, 1, 64], [16, 128], []], []]",
+            ),
+            (
+                "[3, 1000000, [1, 2, 3, 4]]",
+                "[1, 1, [256, 0, [512, 1, 64], [16, 128], []], [1, [16, 128], [[[0, 0, {\"cons\": [-48, 0]}, {\"cons\": [0, 0]}, [254, 0, 16, 1], 0, 64, 1], []]]]]",
+            ),
+            ("[4, 1000000, []]", "[1, 2, [256, 0, [512, 1, 64], [16, 128], []], [2, [16, 128], [[[0, 0, {\"cons\": [-48, 0]}, {\"cons\": [0, 0]}, [254, 0, 16, 1], 0, 64, 1], []]]]]"),
+            ("[2, 42, []]", "[0]"),
+            ("[5]", "[0]"),
+            ("{\"cons\": [0, 1]}", "[0]"),
+        ] {
+            let req = Node::from_json(req).unwrap();
+            let got = local.send(&req, "").unwrap();
+            assert_eq!(got, Node::from_json(want).unwrap(), "{}", req);
+        }
+
+        let create = Node::from_json("[1, 0]").unwrap();
+        assert!(
+            send(&create, LOCAL_API_KEY, &Config::default())
+                .unwrap()
+                .as_list()
+                .unwrap()[0]
+                == &Node::Num(1)
+        );
+    }
+
+    #[test]
+    fn test_caching_sender() {
+        let path = std::env::temp_dir().join(format!("send_cache_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let create = Node::from_json("[1, 0]").unwrap();
+        let join = Node::from_json("[2, 1000000, []]").unwrap();
+
+        let cache = CachingSender::new(Box::new(LocalSender::new()))
+            .with_file(&path)
+            .unwrap();
+        let first = cache.send(&create, "").unwrap();
+        assert_eq!(cache.send(&create, "").unwrap(), first);
+        assert_eq!(cache.len(), 1);
+        cache.send(&join, "").unwrap();
+
+        // A new cache on the same file needs no network.
+        let cache = CachingSender::new(Box::new(ReplaySender::new(vec![])))
+            .with_file(&path)
+            .unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.send(&create, "").unwrap(), first);
+        assert!(cache.send(&Node::Nil, "").is_err());
+        assert_eq!(cache.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let cache = CachingSender::new(Box::new(ReplaySender::new(vec![])))
+            .with_responses(vec![(create.clone(), first.clone())]);
+        assert_eq!(cache.send(&create, "").unwrap(), first);
+    }
+
+    #[test]
+    fn test_retry() {
+        let retry = Retry::default();
+        for (n, r, want) in [
+            (0, 0.0, 200),
+            (1, 0.0, 200),
+            (2, 0.0, 400),
+            (3, 0.5, 1000),
+            (6, 0.0, 5000),
+            (100, 1.0, 7500),
+        ] {
+            assert_eq!(retry.backoff(n, r), Duration::from_millis(want), "{}", n);
+        }
+        for (status, want) in [
+            (429, true),
+            (500, true),
+            (503, true),
+            (403, false),
+            (404, false),
+        ] {
+            assert_eq!(is_transient_status(status), want, "{}", status);
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Config::default().base_url("http://localhost/").api_key("k");
+        assert_eq!(
+            config.send_url("x"),
+            "http://localhost/aliens/send?apiKey=x"
+        );
+        assert_eq!(config.resolve_api_key(""), "k");
+        assert_eq!(config.resolve_api_key("x"), "x");
+        assert_eq!(Config::default().resolve_api_key(""), "");
+        assert_eq!(
+            send_url("x"),
+            "https://api.pegovka.space/aliens/send?apiKey=x"
+        );
+
+        // The configured key reaches the local emulator.
+        let config = Config::default().api_key(LOCAL_API_KEY);
+        let create = Node::from_json("[1, 0]").unwrap();
+        assert!(send(&create, "", &config).is_ok());
+    }
+}
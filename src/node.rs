@@ -0,0 +1,157 @@
+//! Node.js native addon, an alternative to the wasm build for server-side bots.
+//!
+//! G is not Send, so each Galaxy owns a worker thread holding its G. Async methods hand jobs
+//! to the worker from libuv's thread pool, keeping the JS thread free while galaxy evaluates
+//! and sends.
+//!
+//! Build with `cargo build --lib --release --features node` and copy target/release/libapp.so
+//! to app.node (the binaries can't link against napi symbols, hence --lib).
+//!
+//! ```js
+//! const { Galaxy } = require('./app.node');
+//! const g = new Galaxy();
+//! const res = await g.interact('galaxy', 'nil', 0, 0);
+//! // res.images[i] is a Buffer of little-endian int32 x, y pairs.
+//! ```
+
+use crate::{fuzz::panic_message, protocol::G, reduce_evaluator};
+
+use napi::{bindgen_prelude::*, Task};
+use napi_derive::napi;
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+type Job = Box<dyn FnOnce(&mut G) + Send>;
+
+#[napi]
+pub struct Galaxy {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+#[napi(object)]
+pub struct JsInteractResult {
+    pub state: String,
+    pub images: Vec<Buffer>,
+}
+
+pub struct Interact {
+    jobs: mpsc::Sender<Job>,
+    protocol: String,
+    state: String,
+    x: i32,
+    y: i32,
+}
+
+pub struct Output {
+    state: String,
+    images: Vec<Vec<u8>>,
+}
+
+impl Galaxy {
+    // Runs f on the worker thread and waits for its result. A panic in f fails this call
+    // only: the worker drops the caches f may have left half-reduced and takes the next job.
+    fn run<T: Send + 'static>(
+        jobs: &mpsc::Sender<Job>,
+        f: impl FnOnce(&mut G) -> anyhow::Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (tx, rx) = mpsc::channel();
+        jobs.send(Box::new(move |g| {
+            let res = catch_unwind(AssertUnwindSafe(|| f(g))).unwrap_or_else(|e| {
+                g.evaluator().gc();
+                Err(anyhow::anyhow!("galaxy panicked: {}", panic_message(e)))
+            });
+            let _ = tx.send(res);
+        }))
+        .map_err(|_| Error::from_reason("galaxy worker is gone"))?;
+        rx.recv()
+            .map_err(|_| Error::from_reason("galaxy worker is gone"))?
+            .map_err(|e| Error::from_reason(format!("{:#}", e)))
+    }
+
+    fn jobs(&self) -> mpsc::Sender<Job> {
+        self.jobs.lock().unwrap().clone()
+    }
+}
+
+#[napi]
+impl Galaxy {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(move || {
+                let mut g = G::new(Box::new(reduce_evaluator::Eval::new()));
+                for job in rx {
+                    job(&mut g);
+                }
+            })
+            .unwrap();
+        Galaxy {
+            jobs: Mutex::new(tx),
+        }
+    }
+
+    // Adds a definition like "f = ap ap ...".
+    #[napi]
+    pub fn add_def(&self, line: String) -> Result<()> {
+        Galaxy::run(&self.jobs(), move |g| {
+            let (name, expr) = line
+                .split_once(" = ")
+                .ok_or_else(|| anyhow::anyhow!("not a definition: {:?}", line))?;
+            g.evaluator_mut().try_add_def(name, expr)
+        })
+    }
+
+    #[napi]
+    pub fn interact(&self, protocol: String, state: String, x: i32, y: i32) -> AsyncTask<Interact> {
+        AsyncTask::new(Interact {
+            jobs: self.jobs(),
+            protocol,
+            state,
+            x,
+            y,
+        })
+    }
+}
+
+impl Default for Galaxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Task for Interact {
+    type Output = Output;
+    type JsValue = JsInteractResult;
+
+    fn compute(&mut self) -> Result<Output> {
+        let (protocol, state, x, y) = (self.protocol.clone(), self.state.clone(), self.x, self.y);
+        Galaxy::run(&self.jobs, move |g| {
+            let res = g.interact(&protocol, state, x, y, "")?;
+            Ok(Output {
+                state: res.state.clone(),
+                images: res
+                    .images
+                    .iter()
+                    .map(|img| {
+                        img.iter()
+                            .flat_map(|p| vec![p.0 as i32, p.1 as i32])
+                            .flat_map(|v| v.to_le_bytes().to_vec())
+                            .collect()
+                    })
+                    .collect(),
+            })
+        })
+    }
+
+    fn resolve(&mut self, _: Env, output: Output) -> Result<JsInteractResult> {
+        Ok(JsInteractResult {
+            state: output.state,
+            images: output.images.into_iter().map(Buffer::from).collect(),
+        })
+    }
+}
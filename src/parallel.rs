@@ -0,0 +1,86 @@
+//! Parallel evaluation of list results, enabled by the `parallel` feature and turned on with
+//! Eval::set_parallel.
+//!
+//! The elements of a list, like the flag, state and data a protocol returns, or the images
+//! of the data, are often computed independently, so they are evaluated on rayon's threads.
+//! Nodes belong to the heap of their thread, so nothing is evaluated in place: each element
+//! is copied as a Portable and evaluated by the worker thread's own Eval of the same
+//! definitions, from a SharedEnv, and only the values come back. No memoization cell is
+//! ever touched by two threads; each worker memoizes its definitions as a single thread
+//! would, and work shared between elements is done once per thread that needs it.
+//!
+//! Evaluations that are traced, recorded, checked, or bounded by a memory limit, a budget or
+//! a cancellation token run sequentially, as do lists whose elements evaluation has tied
+//! into cycles.
+
+use crate::{
+    ast::Node,
+    eval::EvalError,
+    expr::{Expr, Primitive},
+    reduce_evaluator::{expr_to_node, Env},
+    shared::{Portable, SharedEnv},
+};
+
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+
+// Levels of nested lists evaluated in parallel: the protocol's result and its data.
+pub(crate) const DEPTH: usize = 2;
+
+lazy_static! {
+    // Evaluation recurses deeply, so the workers get stacks as large as the binaries'
+    // evaluation threads.
+    static ref POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .stack_size(256 * 1024 * 1024)
+        .thread_name(|i| format!("eval-{}", i))
+        .build()
+        .unwrap();
+}
+
+// Evaluates e fully in env, evaluating the elements of lists up to depth levels deep on the
+// pool against shared, which has env's definitions.
+pub(crate) fn reduce(
+    env: &Env,
+    shared: &SharedEnv,
+    e: Expr,
+    depth: usize,
+) -> Result<Node, EvalError> {
+    if depth == 0 {
+        return e.reduce(env).and_then(expr_to_node);
+    }
+    let whnf = e.eval(env)?;
+    let mut items = vec![];
+    let mut cell = whnf.clone();
+    loop {
+        match cell {
+            Expr::Op(Primitive::Cons, Some(x), Some(y), None) => {
+                items.push(x);
+                cell = y.eval(env)?;
+            }
+            Expr::Op(Primitive::Nil, None, _, _) => break,
+            _ => return whnf.reduce(env).and_then(expr_to_node),
+        }
+    }
+    let portable = match items
+        .iter()
+        .map(|x| Portable::new(&x.expr(), env.arena()))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(p) if p.len() > 1 => p,
+        _ => return whnf.reduce(env).and_then(expr_to_node),
+    };
+    let values = POOL.install(|| {
+        portable
+            .into_par_iter()
+            .map(|p| {
+                let eval = shared.thread_eval();
+                let e = p.expr(eval.env().arena());
+                reduce(eval.env(), shared, e, depth - 1)
+            })
+            .collect::<Vec<_>>()
+    });
+    values
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map(Node::list)
+}
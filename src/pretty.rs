@@ -0,0 +1,209 @@
+//! Readable rendering of expressions and values, for logs and the REPL:
+//!
+//! ```
+//! use app::expr;
+//! let e = expr!(ap ap cons 1 ap ap cons ap ap cons 2 3 ap ap cons ap s x nil);
+//! assert_eq!(e.pretty(), "( 1 , <2, 3> , (s x) )");
+//! ```
+//!
+//! Lists are written ( a , b , c ), other cons cells <x, y>, and applications, including
+//! partially applied combinators, in parentheses. Huge terms are cut at a depth and a width,
+//! leaving "...". pretty_annotated also writes annotated symbols by their names, reading a
+//! definition like decompiled code.
+
+use crate::{
+    annotations::Annotations,
+    ast::{ExprBuilder, Node},
+    expr::{Expr, Primitive},
+};
+
+/// Limits of pretty_with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PrettyLimits {
+    // Nesting deeper than this is written "...".
+    pub max_depth: usize,
+    // List items and arguments after this many are written "...".
+    pub max_width: usize,
+}
+
+impl Default for PrettyLimits {
+    fn default() -> Self {
+        PrettyLimits {
+            max_depth: 32,
+            max_width: 64,
+        }
+    }
+}
+
+impl Expr {
+    pub fn pretty(&self) -> String {
+        self.pretty_with(PrettyLimits::default())
+    }
+    pub fn pretty_with(&self, limits: PrettyLimits) -> String {
+        let mut out = String::new();
+        write(self, 0, limits, None, &mut out);
+        out
+    }
+    // Like pretty, writing symbols with an annotation by its name, e.g. "(draw_frame nil)"
+    // for "ap :1029 nil" when :1029 is annotated as draw_frame.
+    pub fn pretty_annotated(&self, annotations: &Annotations) -> String {
+        let mut out = String::new();
+        write(
+            self,
+            0,
+            PrettyLimits::default(),
+            Some(annotations),
+            &mut out,
+        );
+        out
+    }
+}
+
+impl Node {
+    pub fn pretty(&self) -> String {
+        self.pretty_with(PrettyLimits::default())
+    }
+    pub fn pretty_with(&self, limits: PrettyLimits) -> String {
+        ExprBuilder::from(self).build().pretty_with(limits)
+    }
+}
+
+// The head of e's application spine and its arguments, e.g. f and [x, y] for "ap ap f x y".
+fn spine(e: &Expr) -> (Expr, Vec<Expr>) {
+    match e {
+        Expr::Ap(f, x) => {
+            let (head, mut args) = spine(&f.expr());
+            args.push(x.expr());
+            (head, args)
+        }
+        Expr::Op(p, x, y, z) => (
+            Expr::Op(*p, None, None, None),
+            [x, y, z]
+                .iter()
+                .copied()
+                .flatten()
+                .map(|e| e.expr())
+                .collect(),
+        ),
+        e => (e.clone(), vec![]),
+    }
+}
+
+// The car and cdr of e if it's a cons cell.
+fn cons(e: &Expr) -> Option<(Expr, Expr)> {
+    match spine(e) {
+        (Expr::Op(Primitive::Cons, ..), args) if args.len() == 2 => {
+            let mut args = args.into_iter();
+            Some((args.next()?, args.next()?))
+        }
+        _ => None,
+    }
+}
+
+fn is_nil(e: &Expr) -> bool {
+    matches!(spine(e), (Expr::Op(Primitive::Nil, ..), args) if args.is_empty())
+}
+
+// The items of e if it's a non-empty list.
+fn list(e: &Expr) -> Option<Vec<Expr>> {
+    let mut items = vec![];
+    let mut e = e.clone();
+    while let Some((car, cdr)) = cons(&e) {
+        items.push(car);
+        e = cdr;
+    }
+    (!items.is_empty() && is_nil(&e)).then_some(items)
+}
+
+fn write(
+    e: &Expr,
+    depth: usize,
+    limits: PrettyLimits,
+    annotations: Option<&Annotations>,
+    out: &mut String,
+) {
+    if depth > limits.max_depth {
+        out.push_str("...");
+        return;
+    }
+    let items = |out: &mut String, items: &[Expr], sep: &str| {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                out.push_str(sep);
+            }
+            if i == limits.max_width {
+                out.push_str("...");
+                break;
+            }
+            write(item, depth + 1, limits, annotations, out);
+        }
+    };
+    if let Some(list) = list(e) {
+        out.push_str("( ");
+        items(out, &list, " , ");
+        out.push_str(" )");
+    } else if let Some((car, cdr)) = cons(e) {
+        out.push('<');
+        items(out, &[car, cdr], ", ");
+        out.push('>');
+    } else {
+        match spine(e) {
+            (Expr::Var(v), args) if args.is_empty() => {
+                match annotations.and_then(|a| a.symbol(&v)) {
+                    Some(a) => out.push_str(&a.name),
+                    None => out.push_str(&v),
+                }
+            }
+            (head, args) if args.is_empty() => out.push_str(&head.to_string()),
+            (head, args) => {
+                out.push('(');
+                items(out, &[vec![head], args].concat(), " ");
+                out.push(')');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{annotations::Annotation, expr, list};
+
+    #[test]
+    fn test_pretty() {
+        for (e, want) in [
+            (expr!(42), "42"),
+            (expr!(nil), "nil"),
+            (list![1, 2, 3], "( 1 , 2 , 3 )"),
+            (list![list![], list![1]], "( nil , ( 1 ) )"),
+            (expr!(ap ap cons 1 2), "<1, 2>"),
+            (expr!(ap ap cons 1 ap ap cons 2 3), "<1, <2, 3>>"),
+            (expr!(ap ap add 1 x0), "(add 1 x0)"),
+            (expr!(ap ap c ap b b cons), "(c (b b) cons)"),
+            (expr!(ap cons 1), "(cons 1)"),
+            (expr!(ap :1029 nil), "(:1029 nil)"),
+        ] {
+            assert_eq!(e.pretty(), want, "{}", e);
+        }
+
+        let limits = PrettyLimits {
+            max_depth: 2,
+            max_width: 2,
+        };
+        assert_eq!(list![1, 2, 3].pretty_with(limits), "( 1 , 2 , ... )");
+        assert_eq!(
+            list![list![list![list![1]]]].pretty_with(limits),
+            "( ( ( ... ) ) )"
+        );
+        let node: Node = "ap ap cons 1 ap ap cons 2 nil".parse().unwrap();
+        assert_eq!(node.pretty(), "( 1 , 2 )");
+
+        let mut a = Annotations::new();
+        a.annotate(":1029", Annotation::new("draw_frame"));
+        assert_eq!(
+            expr!(ap ap :1029 ap :1029 nil :1030).pretty_annotated(&a),
+            "(draw_frame (draw_frame nil) :1030)"
+        );
+        assert_eq!(list![expr!(:1029)].pretty_annotated(&a), "( draw_frame )");
+    }
+}
@@ -0,0 +1,1816 @@
+//! The interact loop driving a protocol: evaluating clicks, sending requests and collecting
+//! images.
+
+use crate::{
+    ast::{Node, ToExpr, Value},
+    eval::{CancellationToken, Env, EvalBudget, EvalError, EvalStats, Evaluator},
+    net::{Config, HttpSender, ReplaySender, Sender},
+    regions::Suggestion,
+    render::{Image, ImageDiff},
+    state::State,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt::Formatter,
+    future::Future,
+    path::Path,
+    rc::Rc,
+};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InteractResult {
+    #[serde(deserialize_with = "deserialize_state")]
+    pub(crate) state: String,
+    pub(crate) images: Vec<Vec<(i64, i64)>>,
+    pub(crate) flag: Node,
+    pub(crate) data: Node,
+    pub(crate) sends: Vec<(Node, Node)>,
+    pub(crate) timing: Timing,
+    #[serde(default)]
+    pub(crate) stats: EvalStats,
+}
+
+/// Timing of a single click.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timing {
+    // time spent evaluating the protocol.
+    pub eval_ms: f64,
+    // time spent waiting for alien responses.
+    pub network_ms: f64,
+    // number of protocol evaluations.
+    pub rounds: usize,
+    // expression nodes alive after the click, or 0 if the evaluator doesn't tell. Steady
+    // growth over a session means a leak.
+    pub heap_nodes: usize,
+}
+
+/// InteractOutput is the result of G::interact_expr.
+/// flag and data are the protocol's raw output of the last round; images is data decoded as
+/// a list of point lists, or empty if data isn't in that form.
+/// sends lists the (request, response) pairs exchanged with the aliens, in order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InteractOutput {
+    pub state: Node,
+    pub images: Vec<Vec<(i64, i64)>>,
+    pub flag: Node,
+    pub data: Node,
+    pub sends: Vec<(Node, Node)>,
+    pub timing: Timing,
+    // work of the evaluator during the click, all rounds together.
+    #[serde(default)]
+    pub stats: EvalStats,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl InteractResult {
+    // TODO: String cannot be used as a return value due to
+    // https://github.com/rustwasm/wasm-bindgen/issues/2279.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn state(&self) -> JsValue {
+        JsValue::from_str(&self.state)
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn state(&self) -> String {
+        self.state.clone()
+    }
+
+    // state in the compact form of State::to_token, which clicks take as well.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn state_token(&self) -> JsValue {
+        JsValue::from_str(&State::new(self.state_node()).to_token())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn state_token(&self) -> String {
+        State::new(self.state_node()).to_token()
+    }
+
+    // state in the structured JSON form of Node::to_json.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn state_json(&self) -> JsValue {
+        JsValue::from_str(&self.state_node().to_json())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn state_json(&self) -> String {
+        self.state_node().to_json()
+    }
+
+    // data in the "ap ap cons ..." form.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn data(&self) -> JsValue {
+        JsValue::from_str(&self.data.to_string())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn data(&self) -> String {
+        self.data.to_string()
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    // stats in JSON, e.g. {"reductions":{"add":3},"var_lookups":1,...}.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn stats_json(&self) -> JsValue {
+        JsValue::from_str(&serde_json::to_string(&self.stats).unwrap())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn stats_json(&self) -> String {
+        serde_json::to_string(&self.stats).unwrap()
+    }
+
+    pub fn send_count(&self) -> usize {
+        self.sends.len()
+    }
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn send_request(&self, i: usize) -> JsValue {
+        JsValue::from_str(&self.sends[i].0.to_string())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn send_request(&self, i: usize) -> String {
+        self.sends[i].0.to_string()
+    }
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub fn send_response(&self, i: usize) -> JsValue {
+        JsValue::from_str(&self.sends[i].1.to_string())
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn send_response(&self, i: usize) -> String {
+        self.sends[i].1.to_string()
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+    pub fn image(&self, i: usize) -> Image {
+        Image::from_points(&self.images[i])
+    }
+
+    // The points of image i as x0, y0, x1, y1, ..., an Int32Array in JS, so that renderers
+    // copy a frame at once instead of calling point per pixel.
+    pub fn image_flat(&self, i: usize) -> Vec<i32> {
+        let mut res = Vec::with_capacity(2 * self.images[i].len());
+        flatten(&self.images[i], &mut res);
+        res
+    }
+
+    // The points of all images in the form of image_flat, one after another. Image i is
+    // frames_flat()[frame_offsets()[i]..frame_offsets()[i + 1]].
+    pub fn frames_flat(&self) -> Vec<i32> {
+        let mut res = Vec::with_capacity(2 * self.images.iter().map(Vec::len).sum::<usize>());
+        for image in &self.images {
+            flatten(image, &mut res);
+        }
+        res
+    }
+
+    // The change of image i from the same layer of previous, e.g. the result of the click
+    // before. See diff.
+    pub fn layer_diff(&self, previous: &InteractResult, i: usize) -> ImageDiff {
+        self.layer(i).diff(&previous.layer(i))
+    }
+
+    // image_count() + 1 indices into frames_flat, from 0 to its length.
+    pub fn frame_offsets(&self) -> Vec<u32> {
+        std::iter::once(0)
+            .chain(self.images.iter().scan(0, |end, image| {
+                *end += 2 * image.len() as u32;
+                Some(*end)
+            }))
+            .collect()
+    }
+}
+
+// Appends the coordinates of points to res. Ones out of the range of i32, which no
+// protocol draws, are clamped.
+fn flatten(points: &[(i64, i64)], res: &mut Vec<i32>) {
+    let clamp = |v: i64| v.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+    res.extend(points.iter().flat_map(|&(x, y)| [clamp(x), clamp(y)]));
+}
+
+// Deserializes the state of an InteractResult, rejecting ones that don't parse.
+fn deserialize_state<'de, D: serde::Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    Node::deserialize(d).map(|n| n.to_string())
+}
+
+impl InteractResult {
+    // state was formatted from a Node or checked by deserialize_state, so it always parses.
+    fn state_node(&self) -> Node {
+        self.state.parse().unwrap()
+    }
+    pub fn raw_flag(&self) -> &Node {
+        &self.flag
+    }
+    pub fn raw_data(&self) -> &Node {
+        &self.data
+    }
+    pub fn sends(&self) -> &[(Node, Node)] {
+        &self.sends
+    }
+    pub fn stats(&self) -> &EvalStats {
+        &self.stats
+    }
+    // The change of each layer from previous, e.g. the result of the click before, so that a
+    // UI can highlight what a click changed. Layers only one of them has are compared with an
+    // empty image.
+    pub fn diff(&self, previous: &InteractResult) -> Vec<ImageDiff> {
+        (0..self.images.len().max(previous.images.len()))
+            .map(|i| self.layer_diff(previous, i))
+            .collect()
+    }
+    // The bounds of all images together, (min_x, min_y, max_x, max_y) inclusive, or None if
+    // nothing is drawn. See render::common_bounds.
+    pub fn common_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let images = (0..self.images.len())
+            .map(|i| self.image(i))
+            .collect::<Vec<_>>();
+        crate::render::common_bounds(&images)
+    }
+    // Points worth clicking in the images, buttons first. See regions::suggestions.
+    pub fn suggestions(&self) -> Vec<Suggestion> {
+        let images = (0..self.images.len())
+            .map(|i| self.image(i))
+            .collect::<Vec<_>>();
+        crate::regions::suggestions(&images)
+    }
+    // Image i, or an empty one past the last.
+    fn layer(&self, i: usize) -> Image {
+        Image::from_points(self.images.get(i).map_or(&[], Vec::as_slice))
+    }
+}
+
+impl From<InteractOutput> for InteractResult {
+    fn from(out: InteractOutput) -> Self {
+        InteractResult {
+            state: out.state.to_string(),
+            images: out.images,
+            flag: out.flag,
+            data: out.data,
+            sends: out.sends,
+            timing: out.timing,
+            stats: out.stats,
+        }
+    }
+}
+
+impl InteractOutput {
+    fn new(state: Node, flag: Node, data: Node, sends: Vec<(Node, Node)>, work: Work) -> Self {
+        InteractOutput {
+            state,
+            images: data.as_images().unwrap_or_default(),
+            flag,
+            data,
+            sends,
+            timing: work.timing,
+            stats: work.stats,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InteractError {
+    // The protocol kept returning flag 1 for more than the allowed number of rounds.
+    // sends holds the exchanges made so far.
+    TooManyRounds {
+        rounds: usize,
+        sends: Vec<(Node, Node)>,
+    },
+    UnexpectedFlag(Node),
+    // The state passed to interact isn't a value.
+    InvalidState(String),
+    // No definition with the protocol's name exists.
+    UnknownProtocol(String),
+    // The protocol returned something other than a 3-element list.
+    NotAProtocol(Node),
+    // The sender failed to deliver the request of the round.
+    Send {
+        round: usize,
+        source: anyhow::Error,
+    },
+    // A number exceeded i64 in the round and no bigint evaluator is set.
+    Overflow {
+        round: usize,
+    },
+    // Evaluation of the round failed, e.g. on a malformed expression or by exceeding the
+    // memory limit.
+    Eval {
+        round: usize,
+        source: EvalError,
+    },
+    // The click ran out of the budget set with G::set_budget in the round. state is the state
+    // the round started from and sends holds the exchanges made so far, so a UI can show
+    // the progress.
+    BudgetExceeded {
+        round: usize,
+        state: Node,
+        sends: Vec<(Node, Node)>,
+    },
+    // The click was cancelled with G::cancel in the round. state and sends are the progress
+    // as for BudgetExceeded.
+    Cancelled {
+        round: usize,
+        state: Node,
+        sends: Vec<(Node, Node)>,
+    },
+}
+
+impl std::fmt::Display for InteractError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InteractError::TooManyRounds { rounds, sends } => write!(
+                f,
+                "too many rounds: gave up after {} rounds and {} sends",
+                rounds,
+                sends.len()
+            ),
+            InteractError::UnexpectedFlag(flag) => write!(f, "unexpected flag: {}", flag),
+            InteractError::InvalidState(e) => write!(f, "invalid state {}", e),
+            InteractError::UnknownProtocol(name) => write!(f, "unknown protocol: {}", name),
+            InteractError::NotAProtocol(res) => {
+                write!(f, "protocol must return (flag, state, data), got {}", res)
+            }
+            InteractError::Send { round, source } => {
+                write!(f, "send failed in round {}: {:#}", round, source)
+            }
+            InteractError::Overflow { round } => {
+                write!(f, "integer overflow in round {}", round)
+            }
+            InteractError::Eval { round, source } => {
+                write!(f, "evaluation failed in round {}: {}", round, source)
+            }
+            InteractError::BudgetExceeded { round, sends, .. } => write!(
+                f,
+                "evaluation budget exceeded in round {} after {} sends",
+                round,
+                sends.len()
+            ),
+            InteractError::Cancelled { round, sends, .. } => write!(
+                f,
+                "cancelled in round {} after {} sends",
+                round,
+                sends.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InteractError {}
+
+impl InteractError {
+    // Turns running out of the budget and cancellation into BudgetExceeded and Cancelled
+    // with the progress of the click.
+    fn with_progress(self, state: &Node, sends: &[(Node, Node)]) -> Self {
+        let (state, sends) = (state.clone(), sends.to_vec());
+        match self {
+            InteractError::Eval {
+                round,
+                source: EvalError::BudgetExceeded,
+            } => InteractError::BudgetExceeded {
+                round,
+                state,
+                sends,
+            },
+            InteractError::Eval {
+                round,
+                source: EvalError::Cancelled,
+            } => InteractError::Cancelled {
+                round,
+                state,
+                sends,
+            },
+            e => e,
+        }
+    }
+}
+
+pub const DEFAULT_MAX_ROUNDS: usize = 100;
+
+/// InteractEvent describes one round of the interact loop, passed to the telemetry hook.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InteractEvent {
+    // 0-based index of the round within the click.
+    pub round: usize,
+    pub flag: i64,
+    // length of the modulated request in bits, if the round sent one.
+    pub send_bits: Option<usize>,
+    // number of images drawn. 0 unless the round finished the click.
+    pub frames: usize,
+}
+
+/// Numeric tells which integer representation G currently evaluates with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Numeric {
+    // fixed-width integers of the main evaluator.
+    I64,
+    // arbitrary-precision integers of the evaluator set by G::set_bigint_evaluator.
+    BigInt,
+}
+
+pub struct G {
+    evaluator: Box<dyn Evaluator>,
+    bigint: Option<Box<dyn Evaluator>>,
+    numeric: Cell<Numeric>,
+    sender: Box<dyn Sender>,
+    config: Config,
+    max_rounds: usize,
+    telemetry: Option<Telemetry>,
+    budget: Option<EvalBudget>,
+    cancel: CancellationToken,
+    undo: RefCell<UndoStack>,
+}
+
+// States before and after the current one, for G::undo and G::redo.
+#[derive(Default)]
+struct UndoStack {
+    limit: usize,
+    past: VecDeque<String>,
+    future: Vec<String>,
+    current: Option<String>,
+}
+
+impl UndoStack {
+    fn push(&mut self, from: &str, to: &str) {
+        if self.limit == 0 {
+            return;
+        }
+        // Clicks may start from tokens, which are given back in the text form like the rest.
+        let from = match from.parse::<State>() {
+            Ok(s) if State::is_token(from) => s.to_string(),
+            _ => from.to_string(),
+        };
+        if from == to {
+            return;
+        }
+        self.past.push_back(from);
+        if self.past.len() > self.limit {
+            self.past.pop_front();
+        }
+        self.future.clear();
+        self.current = Some(to.into());
+    }
+}
+
+pub type Telemetry = Box<dyn Fn(&InteractEvent)>;
+
+/// InteractObserver follows a click of G::interact_with as it happens, e.g. so that a UI can
+/// show progress while the aliens are answering. The methods default to doing nothing.
+pub trait InteractObserver {
+    // The state after each round.
+    fn on_state(&mut self, _state: &Node) {}
+    // A request about to be sent.
+    fn on_send(&mut self, _request: &Node) {}
+    // The aliens' response to the request of the last on_send.
+    fn on_response(&mut self, _response: &Node) {}
+    // The images of the finished click, if its data are images.
+    fn on_frames(&mut self, _images: &[Vec<(i64, i64)>]) {}
+}
+
+impl InteractObserver for () {}
+
+/// GBuilder configures a G. The interpreter defaults to the reduce evaluator with galaxy.
+#[derive(Default)]
+pub struct GBuilder {
+    interpreter: Option<Box<dyn Evaluator>>,
+    bigint: Option<Box<dyn Evaluator>>,
+    sender: Option<Box<dyn Sender>>,
+    config: Option<Config>,
+    max_rounds: Option<usize>,
+    telemetry: Option<Telemetry>,
+    budget: Option<EvalBudget>,
+    cancel: Option<CancellationToken>,
+}
+
+impl GBuilder {
+    // Selects the evaluator, e.g. eval::by_name("gen_js")?.
+    pub fn interpreter(mut self, interpreter: Box<dyn Evaluator>) -> Self {
+        self.interpreter = Some(interpreter);
+        self
+    }
+    pub fn bigint_evaluator(mut self, evaluator: Box<dyn Evaluator>) -> Self {
+        self.bigint = Some(evaluator);
+        self
+    }
+    pub fn sender(mut self, sender: Box<dyn Sender>) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+    // Configures the aliens' endpoint. The sender, if set, is used with its API key only.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+    pub fn max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = Some(max_rounds);
+        self
+    }
+    pub fn telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+    pub fn budget(mut self, budget: EvalBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+    pub fn build(self) -> G {
+        let interpreter = self
+            .interpreter
+            .unwrap_or_else(|| Box::new(crate::reduce_evaluator::Eval::new()));
+        let mut g = G::new(interpreter);
+        g.bigint = self.bigint;
+        if let Some(config) = self.config {
+            g.set_config(config);
+        }
+        if let Some(sender) = self.sender {
+            g.sender = sender;
+        }
+        if let Some(max_rounds) = self.max_rounds {
+            g.max_rounds = max_rounds;
+        }
+        g.telemetry = self.telemetry;
+        g.budget = self.budget;
+        if let Some(cancel) = self.cancel {
+            g.cancel = cancel;
+        }
+        g
+    }
+}
+
+impl G {
+    pub fn new(evaluator: Box<dyn Evaluator>) -> G {
+        G {
+            evaluator,
+            bigint: None,
+            numeric: Cell::new(Numeric::I64),
+            sender: Box::new(HttpSender::default()),
+            config: Config::default(),
+            max_rounds: DEFAULT_MAX_ROUNDS,
+            telemetry: None,
+            budget: None,
+            cancel: CancellationToken::new(),
+            undo: RefCell::default(),
+        }
+    }
+    pub fn builder() -> GBuilder {
+        GBuilder::default()
+    }
+    // G evaluating the definitions in src, in the format of galaxy.txt, with the reduce
+    // evaluator. Use this to run protocols like statelessdraw or bots without recompiling.
+    pub fn from_source(src: &str) -> Result<G> {
+        let env = crate::reduce_evaluator::Env::from_source(src)?;
+        Ok(G::new(Box::new(crate::reduce_evaluator::Eval::with_env(
+            env,
+        ))))
+    }
+    // Like from_source, reading the definitions from path.
+    pub fn load_file(path: impl AsRef<Path>) -> Result<G> {
+        let path = path.as_ref();
+        let src =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        G::from_source(&src).with_context(|| format!("loading {}", path.display()))
+    }
+    pub fn evaluator(&self) -> &dyn Evaluator {
+        self.evaluator.as_ref()
+    }
+    pub fn evaluator_mut(&mut self) -> &mut dyn Evaluator {
+        self.evaluator.as_mut()
+    }
+    // The definitions of the evaluator, if it keeps them in an Env like the reduce evaluator.
+    pub fn env(&self) -> Option<&Env> {
+        self.evaluator.env()
+    }
+    // Evaluates expr, like "ap ap add 1 2" or "ap car :1", against the definitions, e.g. for
+    // a console. The budget and the cancellation of clicks apply as they do to a click.
+    pub fn eval(&self, expr: &str) -> Result<Value, EvalError> {
+        let res = self.cancel.run(|| match self.budget {
+            Some(budget) => budget.run(|| self.evaluator.try_evaluate(expr)).0,
+            None => self.evaluator.try_evaluate(expr),
+        });
+        if let Err(EvalError::Cancelled) = res {
+            self.cancel.reset();
+        }
+        res.map(Value::from)
+    }
+    // Defines name as source, an expression like "ap ap c ...", e.g. a custom protocol or a
+    // helper of one, which may refer to name itself. Fails if name is already defined, is a
+    // number or a primitive, or if source doesn't parse. The bigint evaluator, if set, gets
+    // the definition as well.
+    pub fn define(&mut self, name: &str, source: &str) -> Result<()> {
+        if self.evaluator.has_def(name) {
+            anyhow::bail!("{} is already defined", name);
+        }
+        self.redefine(name, source)
+    }
+    // Like define, replacing the definition of name if there is one. Definitions evaluated
+    // with the previous one drop what they cached.
+    pub fn redefine(&mut self, name: &str, source: &str) -> Result<()> {
+        let reserved = name == "ap"
+            || crate::expr::Primitive::from_name(name).is_some()
+            || name.parse::<i64>().is_ok();
+        if name.is_empty() || name.contains(char::is_whitespace) || reserved {
+            anyhow::bail!("invalid name {:?}", name);
+        }
+        self.check_source(name, source)
+            .with_context(|| format!("defining {}", name))?;
+        let replaced = self.evaluator.has_def(name);
+        self.evaluator.try_add_def(name, source)?;
+        if let Some(bigint) = self.bigint.as_mut() {
+            bigint.try_add_def(name, source)?;
+        }
+        if replaced {
+            self.evaluator.gc();
+            if let Some(bigint) = &self.bigint {
+                bigint.gc();
+            }
+        }
+        Ok(())
+    }
+    // Parses source with name and the names defined in every evaluator, so that redefine
+    // fails before changing any of them rather than after only the first took it.
+    fn check_source(&self, name: &str, source: &str) -> Result<()> {
+        let mut env = crate::reduce_evaluator::Env::new();
+        for tok in source.split(' ') {
+            let defined = |e: &dyn Evaluator| e.has_def(tok);
+            if tok == name
+                || defined(self.evaluator()) && self.bigint.as_deref().is_none_or(defined)
+            {
+                env.define(tok, crate::expr::Expr::nil());
+            }
+        }
+        crate::reduce_evaluator::try_parse_string(&env, source)?;
+        Ok(())
+    }
+    // Sets a hook called after every round of interact.
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = Some(telemetry);
+    }
+    // Sets the maximum number of protocol evaluations per click.
+    pub fn set_max_rounds(&mut self, max_rounds: usize) {
+        self.max_rounds = max_rounds;
+    }
+    // Bounds the evaluation of each click, all rounds together. max_ms counts evaluation
+    // time only, not waits for the aliens. Clicks exceeding it fail with
+    // InteractError::BudgetExceeded.
+    pub fn set_budget(&mut self, budget: Option<EvalBudget>) {
+        self.budget = budget;
+    }
+    // The token cancelling clicks of self, e.g. to hand to another thread.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+    // Aborts the click in progress, which fails with InteractError::Cancelled at its next
+    // reduction or round. The token is reset when the click fails, so the next click runs.
+    // Natively, call it from another thread through cancellation_token; on wasm, clicks
+    // only yield while galaxy_async awaits the aliens.
+    pub fn cancel(&self) {
+        self.cancel.cancel()
+    }
+    // Keeps up to limit states before the current one, so that undo can go back to them.
+    // Clicks that don't change the state, e.g. rendering clicks outside the screen, aren't
+    // steps. 0, the default, keeps none.
+    pub fn set_undo_limit(&self, limit: usize) {
+        let mut undo = self.undo.borrow_mut();
+        undo.limit = limit;
+        while undo.past.len() > limit {
+            undo.past.pop_front();
+        }
+    }
+    // Goes back to the state before the last click, returning it, or None if there is none.
+    pub fn undo(&self) -> Option<String> {
+        let mut undo = self.undo.borrow_mut();
+        let prev = undo.past.pop_back()?;
+        if let Some(current) = undo.current.replace(prev.clone()) {
+            undo.future.push(current);
+        }
+        Some(prev)
+    }
+    // Goes forward to the state undo went back from, returning it, or None if there is none.
+    pub fn redo(&self) -> Option<String> {
+        let mut undo = self.undo.borrow_mut();
+        let next = undo.future.pop()?;
+        if let Some(current) = undo.current.replace(next.clone()) {
+            undo.past.push_back(current);
+        }
+        Some(next)
+    }
+    pub fn set_sender(&mut self, sender: Box<dyn Sender>) {
+        self.sender = sender;
+    }
+    // Replaces the sender with f(sender), e.g. to put a CachingSender in front of it.
+    pub fn wrap_sender(&mut self, f: impl FnOnce(Box<dyn Sender>) -> Box<dyn Sender>) {
+        let sender = std::mem::replace(&mut self.sender, Box::new(ReplaySender::new(vec![])));
+        self.sender = f(sender);
+    }
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    // Sets where and how to reach the aliens, replacing the sender with an HttpSender using
+    // config. Clicks with an empty API key use config's.
+    pub fn set_config(&mut self, config: Config) {
+        self.sender = Box::new(HttpSender::new(config.clone()));
+        self.config = config;
+    }
+    // Sets an evaluator with arbitrary-precision integers, e.g. GalaxyEvaluator whose numbers
+    // are JS BigInts. The main evaluator stays in use until a round overflows i64, or starts
+    // from a state or a vector with a number outside i64, which main evaluators with i64
+    // numbers can't even read. The round is then run with the bigint evaluator, which is used
+    // for all later clicks. Only needed for main evaluators with fixed-width numbers, like the
+    // gmachine one of eval::by_name; without a bigint evaluator, their rounds from such states
+    // fail with the evaluator's error. The reduce evaluator has big integers itself and only
+    // overflows on pwr2 and checkerboard arguments too large for any evaluator, so with it the
+    // fallback stays unused.
+    // Definitions added through evaluator_mut must be added to it as well.
+    pub fn set_bigint_evaluator(&mut self, evaluator: Box<dyn Evaluator>) {
+        self.bigint = Some(evaluator);
+    }
+    pub fn numeric(&self) -> Numeric {
+        self.numeric.get()
+    }
+    // Selects the evaluator to use. BigInt is ignored unless a bigint evaluator is set.
+    pub fn set_numeric(&self, numeric: Numeric) {
+        if self.bigint.is_some() {
+            self.numeric.set(numeric);
+        }
+    }
+    fn active(&self) -> &dyn Evaluator {
+        match (self.numeric.get(), self.bigint.as_ref()) {
+            (Numeric::BigInt, Some(e)) => e.as_ref(),
+            _ => self.evaluator.as_ref(),
+        }
+    }
+    // Evaluates one round, switching to the bigint evaluator on overflow, or beforehand if
+    // the state or the vector already has numbers the main evaluator may not represent.
+    fn evaluate_round(
+        &self,
+        round: usize,
+        protocol: &str,
+        state: &Node,
+        vector: &Node,
+    ) -> Result<Node, InteractError> {
+        if self.numeric.get() == Numeric::I64
+            && self.bigint.is_some()
+            && (state.has_big() || vector.has_big())
+        {
+            tracing::info!(round, "number outside i64; switching to bigint");
+            self.numeric.set(Numeric::BigInt);
+        }
+        match self.active().try_evaluate_ap2(protocol, state, vector) {
+            Ok(n) => Ok(n),
+            Err(EvalError::Overflow(_)) => {
+                if self.numeric.get() == Numeric::BigInt || self.bigint.is_none() {
+                    return Err(InteractError::Overflow { round });
+                }
+                tracing::info!(round, "i64 overflow; switching to bigint");
+                self.numeric.set(Numeric::BigInt);
+                self.evaluate_round(round, protocol, state, vector)
+            }
+            Err(source) => Err(InteractError::Eval { round, source }),
+        }
+    }
+    // Evaluates expr with temporary bindings of names without definitions, e.g. probing a
+    // galaxy function:
+    //   g.eval_with("ap :1029 x0", &[("x0", "ap ap cons 1 nil")])
+    // The budget and the cancellation of clicks apply as for eval.
+    pub fn eval_with(&self, expr: &str, bindings: &[(&str, &str)]) -> Result<Node, EvalError> {
+        let evaluate = || self.active().evaluate_with(expr, bindings);
+        let res = self.cancel.run(|| match self.budget {
+            Some(budget) => budget.run(evaluate).0,
+            None => evaluate(),
+        });
+        if let Err(EvalError::Cancelled) = res {
+            self.cancel.reset();
+        }
+        res
+    }
+    // Validates state before evaluation so a malformed state is reported instead of panicking.
+    pub fn galaxy(&self, state: String, x: i32, y: i32, api_key: &str) -> Result<InteractResult> {
+        validate_galaxy_state(&state)?;
+        self.interact("galaxy", state, x, y, api_key)
+            .with_context(|| format!("galaxy click at ({}, {})", x, y))
+    }
+    // Like galaxy, but delivers requests with send, e.g. fetch in browsers, where blocking
+    // requests are unavailable.
+    pub async fn galaxy_async<F, Fut>(
+        &self,
+        state: String,
+        x: i32,
+        y: i32,
+        send: F,
+    ) -> Result<InteractResult>
+    where
+        F: FnMut(Node) -> Fut,
+        Fut: Future<Output = Result<Node>>,
+    {
+        validate_galaxy_state(&state)?;
+        self.interact_async("galaxy", state, x, y, send)
+            .await
+            .with_context(|| format!("galaxy click at ({}, {})", x, y))
+    }
+    // Like galaxy with the configured API key, but returns the result as one JSON string,
+    // {"state":"ap ap cons ...","images":[[[x,y],...],...]}, for frontends without the
+    // wasm-bindgen classes, e.g. workers exchanging messages.
+    pub fn galaxy_json(&self, state: String, x: i32, y: i32) -> Result<String> {
+        #[derive(Serialize)]
+        struct GalaxyJson<'a> {
+            state: &'a str,
+            images: &'a [Vec<(i64, i64)>],
+        }
+        let res = self.galaxy(state, x, y, "")?;
+        Ok(serde_json::to_string(&GalaxyJson {
+            state: &res.state,
+            images: &res.images,
+        })?)
+    }
+
+    pub fn interact(
+        &self,
+        protocol: &str,
+        state: String,
+        x: i32,
+        y: i32,
+        api_key: &str,
+    ) -> Result<InteractResult, InteractError> {
+        let from = parse_state(&state)?;
+        let out = self.interact_expr(protocol, from, (x as i64, y as i64), api_key)?;
+        let res = InteractResult::from(out);
+        self.undo.borrow_mut().push(&state, &res.state);
+        Ok(res)
+    }
+    // Starts a click to be evaluated a slice at a time by Interaction::poll, e.g. in a web
+    // worker that reports progress between slices. The click is recorded for undo when it
+    // finishes.
+    pub fn start_interact(
+        self: &Rc<Self>,
+        protocol: &str,
+        state: String,
+        x: i32,
+        y: i32,
+        api_key: &str,
+    ) -> Result<Interaction, InteractError> {
+        let from = parse_state(&state)?;
+        let vector = self.first_vector(protocol, (x as i64, y as i64))?;
+        Ok(Interaction {
+            g: self.clone(),
+            protocol: protocol.into(),
+            api_key: api_key.into(),
+            from: state,
+            state: from,
+            vector,
+            round: 0,
+            sends: vec![],
+            work: Work::default(),
+            slice: DEFAULT_SLICE,
+            next_slice: DEFAULT_SLICE,
+            reductions: 0,
+            finished: None,
+        })
+    }
+    // Like interact, but delivers requests with send instead of the Sender.
+    pub async fn interact_async<F, Fut>(
+        &self,
+        protocol: &str,
+        state: String,
+        x: i32,
+        y: i32,
+        send: F,
+    ) -> Result<InteractResult, InteractError>
+    where
+        F: FnMut(Node) -> Fut,
+        Fut: Future<Output = Result<Node>>,
+    {
+        let from = parse_state(&state)?;
+        let out = self
+            .interact_expr_async(protocol, from, (x as i64, y as i64), send)
+            .await?;
+        let res = InteractResult::from(out);
+        self.undo.borrow_mut().push(&state, &res.state);
+        Ok(res)
+    }
+
+    // Like interact, but tells observer about states, sends and frames as they happen.
+    pub fn interact_with(
+        &self,
+        protocol: &str,
+        state: String,
+        x: i32,
+        y: i32,
+        api_key: &str,
+        observer: &mut dyn InteractObserver,
+    ) -> Result<InteractResult, InteractError> {
+        let from = parse_state(&state)?;
+        let out =
+            self.interact_observed(protocol, from, (x as i64, y as i64), api_key, observer)?;
+        let res = InteractResult::from(out);
+        self.undo.borrow_mut().push(&state, &res.state);
+        Ok(res)
+    }
+
+    // Performs clicks in order starting from state, returning the result of each. The state
+    // is passed from click to click as a Node, without formatting and parsing it.
+    pub fn interact_many(
+        &self,
+        protocol: &str,
+        state: String,
+        clicks: &[(i32, i32)],
+        api_key: &str,
+    ) -> Result<Vec<InteractResult>, InteractError> {
+        let mut node = parse_state(&state)?;
+        let mut from = state;
+        let mut res = Vec::with_capacity(clicks.len());
+        for &(x, y) in clicks {
+            let out = self.interact_expr(protocol, node, (x as i64, y as i64), api_key)?;
+            node = out.state.clone();
+            let r = InteractResult::from(out);
+            self.undo.borrow_mut().push(&from, &r.state);
+            from = r.state.clone();
+            res.push(r);
+        }
+        Ok(res)
+    }
+
+    // Same as interact, but takes and returns the state as a Node.
+    pub fn interact_expr(
+        &self,
+        protocol: &str,
+        state: Node,
+        click: (i64, i64),
+        api_key: &str,
+    ) -> Result<InteractOutput, InteractError> {
+        self.interact_observed(protocol, state, click, api_key, &mut ())
+    }
+    fn interact_observed(
+        &self,
+        protocol: &str,
+        mut state: Node,
+        click: (i64, i64),
+        api_key: &str,
+        observer: &mut dyn InteractObserver,
+    ) -> Result<InteractOutput, InteractError> {
+        let _span = tracing::debug_span!("interact", protocol, ?click).entered();
+        let mut vector = self.first_vector(protocol, click)?;
+        let mut sends = vec![];
+        let mut work = Work::default();
+        for round in 0..self.max_rounds {
+            let round_result = self
+                .run_round(round, protocol, &mut state, &vector, &mut work)
+                .map_err(|e| e.with_progress(&state, &sends))?;
+            observer.on_state(&state);
+            let data = match round_result {
+                Round::Done { flag, data } => {
+                    if let Some(images) = data.as_images() {
+                        observer.on_frames(&images);
+                    }
+                    work.timing.heap_nodes = self.collect();
+                    return Ok(InteractOutput::new(state, flag, data, sends, work));
+                }
+                Round::Send(data) => data,
+            };
+            observer.on_send(&data);
+            let start = now_ms();
+            vector = self
+                .sender
+                .send(&data, self.config.resolve_api_key(api_key))
+                .map_err(|source| InteractError::Send { round, source })?;
+            work.timing.network_ms += now_ms() - start;
+            observer.on_response(&vector);
+            sends.push((data, vector.clone()));
+        }
+        Err(InteractError::TooManyRounds {
+            rounds: self.max_rounds,
+            sends,
+        })
+    }
+    // Same as interact_expr, but awaits send for each request.
+    pub async fn interact_expr_async<F, Fut>(
+        &self,
+        protocol: &str,
+        mut state: Node,
+        click: (i64, i64),
+        mut send: F,
+    ) -> Result<InteractOutput, InteractError>
+    where
+        F: FnMut(Node) -> Fut,
+        Fut: Future<Output = Result<Node>>,
+    {
+        let mut vector = self.first_vector(protocol, click)?;
+        let mut sends = vec![];
+        let mut work = Work::default();
+        for round in 0..self.max_rounds {
+            let data = match self
+                .run_round(round, protocol, &mut state, &vector, &mut work)
+                .map_err(|e| e.with_progress(&state, &sends))?
+            {
+                Round::Done { flag, data } => {
+                    work.timing.heap_nodes = self.collect();
+                    return Ok(InteractOutput::new(state, flag, data, sends, work));
+                }
+                Round::Send(data) => data,
+            };
+            let start = now_ms();
+            vector = send(data.clone())
+                .await
+                .map_err(|source| InteractError::Send { round, source })?;
+            work.timing.network_ms += now_ms() - start;
+            sends.push((data, vector.clone()));
+        }
+        Err(InteractError::TooManyRounds {
+            rounds: self.max_rounds,
+            sends,
+        })
+    }
+
+    // Frees what the click left unreachable, returning the nodes alive for Timing.
+    fn collect(&self) -> usize {
+        self.evaluator.collect();
+        self.evaluator.heap_nodes().unwrap_or(0)
+    }
+    // The vector of the first round of a click.
+    fn first_vector(&self, protocol: &str, click: (i64, i64)) -> Result<Node, InteractError> {
+        if !self.active().has_def(protocol) {
+            return Err(InteractError::UnknownProtocol(protocol.into()));
+        }
+        Ok(click.to_expr())
+    }
+    // Evaluates a round of interact, replacing state with the new one and adding to work.
+    fn run_round(
+        &self,
+        round: usize,
+        protocol: &str,
+        state: &mut Node,
+        vector: &Node,
+        work: &mut Work,
+    ) -> Result<Round, InteractError> {
+        let cancelled = || {
+            self.cancel.reset();
+            InteractError::Eval {
+                round,
+                source: EvalError::Cancelled,
+            }
+        };
+        if self.cancel.is_cancelled() {
+            return Err(cancelled());
+        }
+        let start = now_ms();
+        let (flag, new_state, data) = {
+            let (n, stats) = EvalStats::run(|| {
+                self.cancel.run(|| match self.budget {
+                    Some(budget) => {
+                        let (n, used) = budget
+                            .remaining(work.reductions, work.timing.eval_ms)
+                            .run(|| self.evaluate_round(round, protocol, state, vector));
+                        work.reductions += used;
+                        n
+                    }
+                    None => self.evaluate_round(round, protocol, state, vector),
+                })
+            });
+            work.stats.add(&stats);
+            let n = match n {
+                Err(InteractError::Eval {
+                    source: EvalError::Cancelled,
+                    ..
+                }) => return Err(cancelled()),
+                n => n?,
+            };
+            match n.as_list().map(|v| v.len()) {
+                Some(3) => (),
+                _ => return Err(InteractError::NotAProtocol(n)),
+            }
+            let mut v = n.must_list();
+            (v.remove(0), v.remove(0), v.remove(0))
+        };
+        work.timing.eval_ms += now_ms() - start;
+        work.timing.rounds += 1;
+
+        let flag_num = match flag {
+            Node::Num(f) => f,
+            _ => return Err(InteractError::UnexpectedFlag(flag)),
+        };
+        tracing::debug!(
+            round,
+            flag = flag_num,
+            eval_ms = work.timing.eval_ms,
+            "round"
+        );
+        if let Some(telemetry) = self.telemetry.as_ref() {
+            let flag = flag_num;
+            telemetry(&InteractEvent {
+                round,
+                flag,
+                send_bits: if flag == 1 {
+                    Some(data.modulate().len())
+                } else {
+                    None
+                },
+                frames: if flag == 0 {
+                    data.as_images().map_or(0, |images| images.len())
+                } else {
+                    0
+                },
+            });
+        }
+
+        *state = new_state;
+        match flag_num {
+            0 => Ok(Round::Done { flag, data }),
+            1 => Ok(Round::Send(data)),
+            _ => Err(InteractError::UnexpectedFlag(flag)),
+        }
+    }
+}
+
+// Reductions of the first slice of each round of an Interaction.
+pub const DEFAULT_SLICE: u64 = 1 << 20;
+
+/// Interaction is a click of G::start_interact, evaluated as poll is called.
+///
+/// Each poll evaluates the current round for up to a slice of reductions, sending its
+/// request if it finishes. Evaluation can't stop in the middle and resume, so an unfinished
+/// slice is thrown away except for what it cached, and the next poll retries the round with
+/// twice the slice. A round thus takes at most about four times its reductions, usually far
+/// less as its subexpressions stay evaluated, and takes polls logarithmic in its length.
+/// Only the reduce evaluator counts reductions; others finish a round in each poll.
+pub struct Interaction {
+    g: Rc<G>,
+    protocol: String,
+    api_key: String,
+    // the state the click started from, for undo.
+    from: String,
+    state: Node,
+    vector: Node,
+    round: usize,
+    sends: Vec<(Node, Node)>,
+    work: Work,
+    // reductions of the first slice of a round, and of the next poll.
+    slice: u64,
+    next_slice: u64,
+    reductions: u64,
+    finished: Option<Progress>,
+}
+
+/// Progress of an Interaction after a poll.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug)]
+pub struct Progress {
+    // reductions of the click so far, all rounds and polls together, including those of
+    // unfinished slices.
+    pub reductions: u64,
+    // the round being evaluated, or the last one once done.
+    pub round: usize,
+    pub done: bool,
+    result: Option<InteractResult>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Progress {
+    // The result of the click once done.
+    pub fn result(&self) -> Option<InteractResult> {
+        self.result.clone()
+    }
+}
+
+impl Interaction {
+    // Sets the reductions of the first slice of each round, DEFAULT_SLICE by default.
+    pub fn set_slice(&mut self, reductions: u64) {
+        self.slice = reductions.max(1);
+        self.next_slice = self.slice;
+    }
+
+    // Evaluates a slice of the click. Once the click is done, returns the same Progress
+    // again. Errors end the click as they would end G::interact.
+    pub fn poll(&mut self) -> Result<Progress, InteractError> {
+        if let Some(finished) = &self.finished {
+            return Ok(finished.clone());
+        }
+        let g = self.g.clone();
+        if self.round == g.max_rounds {
+            return Err(InteractError::TooManyRounds {
+                rounds: g.max_rounds,
+                sends: std::mem::take(&mut self.sends),
+            });
+        }
+        let slice = self.next_slice;
+        let (res, used) = EvalBudget::reductions(slice).run(|| {
+            g.run_round(
+                self.round,
+                &self.protocol,
+                &mut self.state,
+                &self.vector,
+                &mut self.work,
+            )
+        });
+        self.reductions += used;
+        let data = match res {
+            Err(InteractError::Eval {
+                source: EvalError::BudgetExceeded,
+                ..
+            }) if used >= slice => {
+                self.next_slice = slice.saturating_mul(2);
+                return Ok(self.progress(None));
+            }
+            Err(e) => return Err(e.with_progress(&self.state, &self.sends)),
+            Ok(Round::Done { flag, data }) => {
+                self.work.timing.heap_nodes = g.collect();
+                let out = InteractOutput::new(
+                    self.state.clone(),
+                    flag,
+                    data,
+                    std::mem::take(&mut self.sends),
+                    std::mem::take(&mut self.work),
+                );
+                let res = InteractResult::from(out);
+                g.undo.borrow_mut().push(&self.from, &res.state);
+                let finished = self.progress(Some(res));
+                self.finished = Some(finished.clone());
+                return Ok(finished);
+            }
+            Ok(Round::Send(data)) => data,
+        };
+        let start = now_ms();
+        let vector = g
+            .sender
+            .send(&data, g.config.resolve_api_key(&self.api_key))
+            .map_err(|source| InteractError::Send {
+                round: self.round,
+                source,
+            })?;
+        self.work.timing.network_ms += now_ms() - start;
+        self.sends.push((data, vector.clone()));
+        self.vector = vector;
+        self.round += 1;
+        self.next_slice = self.slice;
+        Ok(self.progress(None))
+    }
+
+    fn progress(&self, result: Option<InteractResult>) -> Progress {
+        Progress {
+            reductions: self.reductions,
+            round: self.round,
+            done: result.is_some(),
+            result,
+        }
+    }
+}
+
+// What a click has done so far.
+#[derive(Default)]
+struct Work {
+    timing: Timing,
+    stats: EvalStats,
+    // reductions spent against the budget.
+    reductions: u64,
+}
+
+// The outcome of a round: the protocol finished with flag 0, or asks to send data.
+enum Round {
+    Done { flag: Node, data: Node },
+    Send(Node),
+}
+
+// Parses state in either form State takes.
+fn parse_state(state: &str) -> Result<Node, InteractError> {
+    state
+        .parse::<State>()
+        .map(State::into_node)
+        .map_err(|e| InteractError::InvalidState(format!("{:?}: {:#}", state, e)))
+}
+
+fn validate_galaxy_state(state: &str) -> Result<()> {
+    let s: State = state.parse().context("invalid galaxy state")?;
+    s.validate_galaxy().context("invalid galaxy state")
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen(module = "/js/wasm_define.js")]
+extern "C" {
+    // std::time::Instant is unavailable on wasm32-unknown-unknown.
+    pub(crate) fn now_ms() -> f64;
+}
+
+// Milliseconds elapsed from an arbitrary fixed point.
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn now_ms() -> f64 {
+    lazy_static::lazy_static! {
+        static ref START: std::time::Instant = std::time::Instant::now();
+    }
+    START.elapsed().as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::STATELESSDRAW;
+
+    // Protocol with state n: sends n and increments it while n < 2.
+    struct Counter;
+
+    impl Evaluator for Counter {
+        fn evaluate(&self, expr: &str) -> Node {
+            let (f, state, vector) = crate::eval::split_ap2(expr)
+                .unwrap_or_else(|| panic!("unexpected expression {}", expr));
+            self.evaluate_ap2(f, &state, &vector)
+        }
+        fn evaluate_ap2(&self, _: &str, state: &Node, vector: &Node) -> Node {
+            let n = match state {
+                Node::Num(n) => *n,
+                _ => panic!("unexpected state {}", state),
+            };
+            let (flag, state, data) = if n < 2 {
+                (
+                    1,
+                    Node::Num(n + 1),
+                    Node::Cons(state.clone().into(), vector.clone().into()),
+                )
+            } else {
+                (0, state.clone(), vector.clone())
+            };
+            Node::Cons(
+                Node::Num(flag).into(),
+                Node::Cons(
+                    state.into(),
+                    Node::Cons(data.into(), Node::Nil.into()).into(),
+                )
+                .into(),
+            )
+        }
+        // counter is the only definition, so there is nothing to add to.
+        fn add_def(&mut self, _: &str) {}
+        fn has_def(&self, name: &str) -> bool {
+            name == "counter"
+        }
+    }
+
+    // Responds with the car of the request.
+    struct Car;
+
+    impl Sender for Car {
+        fn send(&self, req: &Node, _: &str) -> Result<Node> {
+            match req {
+                Node::Cons(x, _) => Ok(x.as_ref().clone()),
+                _ => anyhow::bail!("unexpected request {}", req),
+            }
+        }
+    }
+
+    struct Down;
+
+    impl Sender for Down {
+        fn send(&self, _: &Node, _: &str) -> Result<Node> {
+            anyhow::bail!("connection refused")
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let res = InteractResult {
+            state: "nil".into(),
+            images: vec![vec![(1, 2)]],
+            flag: Node::Num(0),
+            data: Node::Nil,
+            sends: vec![(Node::Num(1), Node::Nil)],
+            timing: Timing::default(),
+            stats: EvalStats::default(),
+        };
+        let json = serde_json::to_string(&res).unwrap();
+        let back: InteractResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+        assert_eq!(back.sends(), res.sends());
+
+        let bad = json.replace(r#""state":"nil""#, r#""state":"ap car""#);
+        assert!(serde_json::from_str::<InteractResult>(&bad).is_err());
+    }
+
+    #[test]
+    fn test_frames_flat() {
+        let res = InteractResult {
+            state: "nil".into(),
+            images: vec![vec![(1, 2), (-3, 4)], vec![], vec![(5, 1 << 40)]],
+            flag: Node::Num(0),
+            data: Node::Nil,
+            sends: vec![],
+            timing: Timing::default(),
+            stats: EvalStats::default(),
+        };
+        assert_eq!(res.image_flat(0), [1, 2, -3, 4]);
+        assert!(res.image_flat(1).is_empty());
+        assert_eq!(res.image_flat(2), [5, i32::MAX]);
+        let (flat, offsets) = (res.frames_flat(), res.frame_offsets());
+        assert_eq!(offsets, [0, 4, 4, 6]);
+        for i in 0..res.image_count() {
+            let (start, end) = (offsets[i] as usize, offsets[i + 1] as usize);
+            assert_eq!(flat[start..end], res.image_flat(i)[..]);
+        }
+    }
+
+    #[test]
+    fn test_sends() {
+        assert_eq!(
+            Counter
+                .evaluate("ap ap counter 2 ap ap cons 5 6")
+                .to_string(),
+            "ap ap cons 0 ap ap cons 2 ap ap cons ap ap cons 5 6 nil"
+        );
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+
+        let res = g.interact("counter", "0".into(), 5, 6, "").unwrap();
+        assert_eq!(res.state, "2");
+        assert_eq!(res.state_json(), "2");
+        assert_eq!(res.data(), "1");
+        assert_eq!(
+            res.sends()
+                .iter()
+                .map(|(req, resp)| (req.to_string(), resp.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ap ap cons 0 ap ap cons 5 6".to_string(), "0".to_string()),
+                ("ap ap cons 1 0".to_string(), "1".to_string())
+            ]
+        );
+        assert_eq!(res.timing().rounds, 3);
+        // Counter has no heap to report.
+        assert_eq!(res.timing().heap_nodes, 0);
+
+        let events = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let events2 = events.clone();
+        g.set_telemetry(Box::new(move |e| events2.borrow_mut().push(e.clone())));
+        g.interact("counter", "1".into(), 0, 0, "").unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                InteractEvent {
+                    round: 0,
+                    flag: 1,
+                    send_bits: Some(18), // ap ap cons 1 ap ap cons 0 0
+                    frames: 0,
+                },
+                InteractEvent {
+                    round: 1,
+                    flag: 0,
+                    send_bits: None,
+                    frames: 0,
+                },
+            ]
+        );
+
+        g.set_max_rounds(2);
+        match g.interact("counter", "0".into(), 5, 6, "") {
+            Err(InteractError::TooManyRounds { rounds, sends }) => {
+                assert_eq!(rounds, 2);
+                assert_eq!(sends.len(), 2);
+            }
+            res => panic!("unexpected result: {:?}", res.map(|r| r.state)),
+        }
+        match g.interact("galaxy", "0".into(), 0, 0, "") {
+            Err(InteractError::UnknownProtocol(name)) => assert_eq!(name, "galaxy"),
+            res => panic!("unexpected result: {:?}", res.map(|r| r.state)),
+        }
+
+        g.set_sender(Box::new(Down));
+        match g.interact("counter", "1".into(), 0, 0, "") {
+            Err(e @ InteractError::Send { round: 0, .. }) => {
+                assert_eq!(e.to_string(), "send failed in round 0: connection refused")
+            }
+            res => panic!("unexpected result: {:?}", res.map(|r| r.state)),
+        }
+    }
+
+    #[test]
+    fn test_from_source() {
+        let src = format!("{}\n", STATELESSDRAW);
+        let g = G::from_source(&src).unwrap();
+        assert!(!g.evaluator().has_def("galaxy"));
+        assert_eq!(g.env().unwrap().names(), ["statelessdraw"]);
+        assert_eq!(
+            format!(
+                "statelessdraw = {}\n",
+                g.env().unwrap().source("statelessdraw").unwrap()
+            ),
+            src
+        );
+        let res = g.interact("statelessdraw", "nil".into(), 1, 0, "").unwrap();
+        assert_eq!(res.images, vec![vec![(1, 0)]]);
+
+        let path = std::env::temp_dir().join(format!("protocol_test_{}.txt", std::process::id()));
+        std::fs::write(&path, &src).unwrap();
+        let g = G::load_file(&path).unwrap();
+        assert!(g.evaluator().has_def("statelessdraw"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(G::load_file(&path).is_err());
+        assert!(G::from_source("statelessdraw").is_err());
+    }
+
+    #[test]
+    fn test_define() {
+        let mut g = G::from_source("").unwrap();
+        g.define(":1", "ap ap cons 7 nil").unwrap();
+        g.define("first", "ap car :1").unwrap();
+        assert_eq!(g.evaluator().try_evaluate("first").unwrap(), Node::Num(7));
+
+        let err = |res: Result<()>| format!("{:#}", res.unwrap_err());
+        assert_eq!(err(g.define(":1", "nil")), ":1 is already defined");
+        assert_eq!(
+            err(g.define(":2", "ap car undefined")),
+            "defining :2: token 2: unknown var undefined"
+        );
+        assert!(!g.evaluator().has_def(":2"));
+        for name in ["", "a b", "cons", "ap", "-3"] {
+            assert_eq!(
+                err(g.define(name, "nil")),
+                format!("invalid name {:?}", name)
+            );
+        }
+
+        g.redefine(":1", "ap ap cons 8 nil").unwrap();
+        assert_eq!(g.evaluator().try_evaluate("first").unwrap(), Node::Num(8));
+
+        // A name only one of the evaluators has fails before either is changed.
+        g.set_bigint_evaluator(Box::new(crate::reduce_evaluator::Eval::with_env(
+            crate::reduce_evaluator::Env::from_source(":1 = nil").unwrap(),
+        )));
+        assert_eq!(
+            err(g.redefine(":1", "ap car first")),
+            "defining :1: token 2: unknown var first"
+        );
+        assert_eq!(g.evaluator().try_evaluate("first").unwrap(), Node::Num(8));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut g = G::from_source(":1 = ap ap cons 7 ap ap cons 8 nil\n:2 = ap ap s ap ap c ap eq 0 1 ap ap b ap mul 2 ap ap b :2 ap add -1").unwrap();
+        assert_eq!(g.eval("ap ap add 1 2").unwrap(), Value::num(3));
+        assert_eq!(g.eval(":1").unwrap(), Value::list(vec![7, 8]));
+        assert!(matches!(g.eval("ap car"), Err(EvalError::Parse(_))));
+        assert!(matches!(g.eval(":3"), Err(EvalError::UnknownVar(_))));
+
+        g.set_budget(Some(EvalBudget::reductions(1000)));
+        assert_eq!(g.eval("ap :2 1000").unwrap_err(), EvalError::BudgetExceeded);
+        assert_eq!(g.eval("ap car :1").unwrap(), Value::num(7));
+    }
+
+    // Runs f to completion, polling it until ready.
+    fn block_on<T>(f: impl Future<Output = T>) -> T {
+        struct Noop;
+        impl std::task::Wake for Noop {
+            fn wake(self: std::sync::Arc<Self>) {}
+        }
+        let waker = std::sync::Arc::new(Noop).into();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            if let std::task::Poll::Ready(res) = f.as_mut().poll(&mut cx) {
+                return res;
+            }
+        }
+    }
+
+    #[test]
+    fn test_interact_async() {
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Down));
+        let res = block_on(g.interact_async("counter", "0".into(), 5, 6, |req| {
+            std::future::ready(Car.send(&req, ""))
+        }))
+        .unwrap();
+        assert_eq!(res.state, "2");
+        assert_eq!(res.send_count(), 2);
+        assert_eq!(res.timing().rounds, 3);
+
+        match block_on(g.interact_async("counter", "1".into(), 0, 0, |req| {
+            std::future::ready(Down.send(&req, ""))
+        })) {
+            Err(e @ InteractError::Send { round: 0, .. }) => {
+                assert_eq!(e.to_string(), "send failed in round 0: connection refused")
+            }
+            res => panic!("unexpected result: {:?}", res.map(|r| r.state)),
+        }
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+        assert_eq!(g.undo(), None);
+        g.interact("counter", "0".into(), 0, 0, "").unwrap();
+        assert_eq!(g.undo(), None);
+
+        g.set_undo_limit(2);
+        for state in ["0", "1", "2", "0"] {
+            g.interact("counter", state.into(), 0, 0, "").unwrap();
+        }
+        // Every click ends in 2. The one from 2 isn't a step, and the one from 0 is dropped.
+        assert_eq!(g.undo(), Some("0".into()));
+        assert_eq!(g.undo(), Some("1".into()));
+        assert_eq!(g.undo(), None);
+        assert_eq!(g.redo(), Some("0".into()));
+        assert_eq!(g.redo(), Some("2".into()));
+        assert_eq!(g.redo(), None);
+
+        g.undo();
+        g.interact("counter", "1".into(), 0, 0, "").unwrap();
+        assert_eq!(g.redo(), None);
+        assert_eq!(g.undo(), Some("1".into()));
+
+        let res = g.interact("counter", "0".into(), 0, 0, "").unwrap();
+        let token = res.state_token();
+        assert!(token.starts_with('~'));
+        assert_eq!(
+            g.interact("counter", token, 0, 0, "").unwrap().state,
+            res.state
+        );
+        assert_eq!(g.undo(), Some("0".into()));
+    }
+
+    #[test]
+    fn test_interact_many() {
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+        let res = g
+            .interact_many("counter", "0".into(), &[(5, 6), (7, 8)], "")
+            .unwrap();
+        assert_eq!(
+            res.iter().map(|r| r.state.as_str()).collect::<Vec<_>>(),
+            ["2", "2"]
+        );
+        assert_eq!(res[0].send_count(), 2);
+        assert_eq!(res[1].data(), "ap ap cons 7 8");
+        assert!(g
+            .interact_many("counter", "0".into(), &[], "")
+            .unwrap()
+            .is_empty());
+        assert!(g
+            .interact_many("counter", "ap".into(), &[(0, 0)], "")
+            .is_err());
+    }
+
+    #[test]
+    fn test_interact_with() {
+        #[derive(Default)]
+        struct Log(Vec<String>);
+        impl InteractObserver for Log {
+            fn on_state(&mut self, state: &Node) {
+                self.0.push(format!("state {}", state));
+            }
+            fn on_send(&mut self, request: &Node) {
+                self.0.push(format!("send {}", request));
+            }
+            fn on_response(&mut self, response: &Node) {
+                self.0.push(format!("response {}", response));
+            }
+            fn on_frames(&mut self, images: &[Vec<(i64, i64)>]) {
+                self.0.push(format!("frames {:?}", images));
+            }
+        }
+
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+        let mut log = Log::default();
+        let res = g
+            .interact_with("counter", "0".into(), 5, 6, "", &mut log)
+            .unwrap();
+        assert_eq!(res.state, "2");
+        assert_eq!(
+            log.0,
+            [
+                "state 1",
+                "send ap ap cons 0 ap ap cons 5 6",
+                "response 0",
+                "state 2",
+                "send ap ap cons 1 0",
+                "response 1",
+                "state 2",
+            ]
+        );
+
+        let g = G::from_source(STATELESSDRAW).unwrap();
+        let mut log = Log::default();
+        g.interact_with("statelessdraw", "nil".into(), 1, 0, "", &mut log)
+            .unwrap();
+        assert_eq!(log.0, ["state nil", "frames [[(1, 0)]]"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let result = |images| InteractResult {
+            state: "nil".into(),
+            images,
+            flag: Node::Num(0),
+            data: Node::Nil,
+            sends: vec![],
+            timing: Timing::default(),
+            stats: EvalStats::default(),
+        };
+        let before = result(vec![vec![(0, 0), (1, 1)], vec![(5, 5)]]);
+        let after = result(vec![vec![(1, 1), (2, 2)], vec![(5, 5)], vec![(7, 7)]]);
+        let diff = after.diff(&before);
+        assert_eq!(diff.len(), 3);
+        assert_eq!(
+            (diff[0].added_flat(), diff[0].removed_flat()),
+            (vec![2, 2], vec![0, 0])
+        );
+        assert!(diff[1].is_empty());
+        assert_eq!(diff[2].added_flat(), [7, 7]);
+        assert_eq!(before.diff(&after)[2].removed_flat(), [7, 7]);
+
+        assert_eq!(after.common_bounds(), Some((1, 1, 7, 7)));
+        assert_eq!(result(vec![vec![]]).common_bounds(), None);
+
+        let suggestions = after.suggestions();
+        assert_eq!(
+            suggestions.iter().map(|s| (s.x, s.y)).collect::<Vec<_>>(),
+            [(2, 2), (5, 5), (7, 7)]
+        );
+    }
+
+    #[test]
+    fn test_galaxy_json() {
+        let g = G::from_source(&STATELESSDRAW.replacen("statelessdraw", "galaxy", 1)).unwrap();
+        assert_eq!(
+            g.galaxy_json("nil".into(), 1, -2).unwrap(),
+            r#"{"state":"nil","images":[[[1,-2]]]}"#
+        );
+        assert!(g.galaxy_json("ap 1".into(), 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_interaction() {
+        let g = Rc::new(G::from_source(STATELESSDRAW).unwrap());
+        let want = g.interact("statelessdraw", "nil".into(), 1, 0, "").unwrap();
+        let mut click = g
+            .start_interact("statelessdraw", "nil".into(), 1, 0, "")
+            .unwrap();
+        click.set_slice(1);
+        let mut polls = vec![click.poll().unwrap()];
+        while !polls.last().unwrap().done {
+            polls.push(click.poll().unwrap());
+        }
+        assert!(polls.len() > 2, "{:?}", polls);
+        assert!(polls.windows(2).all(|p| p[0].reductions < p[1].reductions));
+        let res = polls.last().unwrap().result().unwrap();
+        assert_eq!(res.images, want.images);
+        assert_eq!(res.state, want.state);
+        assert_eq!(
+            click.poll().unwrap().reductions,
+            polls.last().unwrap().reductions
+        );
+
+        // Rounds sending requests.
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+        let g = Rc::new(g);
+        let mut click = g.start_interact("counter", "0".into(), 0, 0, "").unwrap();
+        let rounds = std::iter::from_fn(|| Some(click.poll().unwrap()))
+            .take_while(|p| !p.done)
+            .map(|p| p.round)
+            .collect::<Vec<_>>();
+        assert_eq!(rounds, [1, 2]);
+        let res = click.poll().unwrap().result().unwrap();
+        assert_eq!(res.state, "2");
+        assert_eq!(res.send_count(), 2);
+
+        assert!(matches!(
+            g.start_interact("nothing", "0".into(), 0, 0, ""),
+            Err(InteractError::UnknownProtocol(_))
+        ));
+    }
+
+    #[test]
+    fn test_stats() {
+        let g = G::from_source(STATELESSDRAW).unwrap();
+        let res = g.interact("statelessdraw", "nil".into(), 1, 0, "").unwrap();
+        let stats = res.stats();
+        assert!(
+            stats.reductions[&crate::expr::Primitive::B] > 0,
+            "{:?}",
+            stats
+        );
+        assert!(stats.total_reductions() > stats.reductions.len() as u64);
+        assert_eq!(stats.var_lookups, 1);
+        assert!(stats.cache_hits > 0 && stats.cache_misses > 0);
+        assert!(stats.peak_nodes > 0);
+        let back: EvalStats = serde_json::from_str(&res.stats_json()).unwrap();
+        assert_eq!(back.reductions, stats.reductions);
+
+        // Evaluators that don't count report time only.
+        let mut g = G::new(Box::new(Counter));
+        g.set_sender(Box::new(Car));
+        let res = g.interact("counter", "0".into(), 0, 0, "").unwrap();
+        assert_eq!(
+            res.stats(),
+            &EvalStats {
+                elapsed_ms: res.stats().elapsed_ms,
+                ..EvalStats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Python extension module built with PyO3, for exploring galaxy and decoding alien traffic
+//! from scripts and notebooks.
+//!
+//! As in the Node.js addon, each Galaxy owns a worker thread holding its G, which isn't Send,
+//! on a stack deep enough for galaxy. Calls release the GIL while the worker evaluates.
+//!
+//! Build with `cargo build --lib --release --features python` and copy
+//! target/release/libapp.so to galaxy.so next to the script.
+//!
+//! ```python
+//! import galaxy
+//! g = galaxy.Galaxy()
+//! state, images = g.interact("nil", 0, 0)
+//! galaxy.demodulate(galaxy.modulate("ap ap cons 1 nil"))  # "ap ap cons 1 nil"
+//! ```
+
+// PyO3's macros convert the PyErr of PyResult into PyErr.
+#![allow(clippy::useless_conversion)]
+
+use crate::{ast::Node, protocol::G, reduce_evaluator};
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+type Job = Box<dyn FnOnce(&mut G) + Send>;
+
+// Point lists, as in InteractResult.
+type Images = Vec<Vec<(i64, i64)>>;
+
+/// Galaxy evaluates galaxy.txt with the reduce evaluator.
+#[pyclass]
+pub struct Galaxy {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl Galaxy {
+    // Runs f on the worker thread without the GIL and waits for its result.
+    fn run<T: Send + 'static>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&mut G) -> anyhow::Result<T> + Send + 'static,
+    ) -> PyResult<T> {
+        let jobs = self.jobs.lock().unwrap().clone();
+        py.allow_threads(move || {
+            let (tx, rx) = mpsc::channel();
+            jobs.send(Box::new(move |g| {
+                let _ = tx.send(f(g));
+            }))
+            .map_err(|_| PyValueError::new_err("galaxy worker is gone"))?;
+            rx.recv()
+                .map_err(|_| PyValueError::new_err("galaxy worker panicked"))?
+                .map_err(|e| PyValueError::new_err(format!("{:#}", e)))
+        })
+    }
+}
+
+#[pymethods]
+impl Galaxy {
+    #[new]
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(move || {
+                let mut g = G::new(Box::new(reduce_evaluator::Eval::new()));
+                for job in rx {
+                    job(&mut g);
+                }
+            })
+            .unwrap();
+        Galaxy {
+            jobs: Mutex::new(tx),
+        }
+    }
+
+    // Adds a definition like "f = ap ap ...".
+    fn add_def(&self, py: Python<'_>, line: String) -> PyResult<()> {
+        self.run(py, move |g| {
+            g.evaluator_mut().add_def(&line);
+            Ok(())
+        })
+    }
+
+    // Clicks (x, y) on protocol in state, returning the next state and the images as lists
+    // of (x, y) tuples. Requests are sent with the API key in $API_KEY.
+    #[pyo3(signature = (state, x, y, protocol = "galaxy"))]
+    fn interact(
+        &self,
+        py: Python<'_>,
+        state: String,
+        x: i32,
+        y: i32,
+        protocol: &str,
+    ) -> PyResult<(String, Images)> {
+        let protocol = protocol.to_string();
+        self.run(py, move |g| {
+            let res = g.interact(&protocol, state, x, y, "")?;
+            Ok((res.state, res.images))
+        })
+    }
+
+    // Evaluates an expression like "ap ap add 1 2", returning the value in the same form.
+    fn eval(&self, py: Python<'_>, expr: String) -> PyResult<String> {
+        self.run(py, move |g| {
+            Ok(g.evaluator().try_evaluate(&expr)?.to_string())
+        })
+    }
+}
+
+// The bits sent to the aliens for a value like "ap ap cons 1 nil".
+#[pyfunction]
+fn modulate(value: &str) -> PyResult<String> {
+    let node: Node = value
+        .parse()
+        .map_err(|e| PyValueError::new_err(format!("{:#}", e)))?;
+    Ok(node.modulate())
+}
+
+// The value of bits received from the aliens, in the "ap ap cons ..." form.
+#[pyfunction]
+fn demodulate(bits: &str) -> PyResult<String> {
+    Node::try_demodulate(bits)
+        .map(|n| n.to_string())
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+#[pymodule]
+fn galaxy(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Galaxy>()?;
+    m.add_function(wrap_pyfunction!(modulate, m)?)?;
+    m.add_function(wrap_pyfunction!(demodulate, m)?)?;
+    Ok(())
+}
@@ -3,319 +3,1099 @@ extern crate anyhow;
 extern crate itertools;
 extern crate lazy_static;
 
-#[cfg(target_os = "linux")]
-extern crate reqwest;
-
-use crate::common::{self, Node};
+use crate::{
+    annotations::Annotations,
+    ast::Node,
+    eval::{EvalBudget, EvalError},
+    expr::{self, Blackhole, CachedExpr, Expr, ExprArena, ExprId, Primitive, Thunk, STR_PRIMITIVE},
+    simplify::Simplifier,
+};
+use anyhow::Context;
 use lazy_static::lazy_static;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    convert::TryFrom,
     fmt::{Display, Formatter},
     rc::Rc,
-    sync::Mutex,
 };
 
-lazy_static! {
-    pub static ref API_KEY: Mutex<String> =
-        Mutex::new(std::env::var("API_KEY").unwrap_or(String::new()));
-    static ref STR_PRIMITIVE: HashMap<&'static str, Primitive> = {
-        use Primitive::*;
-        let mut m = HashMap::new();
-        m.insert("add", Add);
-        m.insert("mul", Mul);
-        m.insert("div", Div);
-        m.insert("eq", Eq);
-        m.insert("lt", Lt);
-        m.insert("neg", Neg);
-        m.insert("s", S);
-        m.insert("c", C);
-        m.insert("b", B);
-        m.insert("i", I);
-        m.insert("f", F);
-        m.insert("t", T);
-        m.insert("cons", Cons);
-        m.insert("car", Car);
-        m.insert("cdr", Cdr);
-        m.insert("nil", Nil);
-        m.insert("isnil", Isnil);
-        m
-    };
-    static ref PRIMITIVE_STR: HashMap<Primitive, &'static str> = {
-        let mut res = HashMap::new();
-        STR_PRIMITIVE.iter().for_each(|(k, v)| {
-            res.insert(*v, *k);
-        });
-        res
-    };
-}
-
-#[derive(Clone, Eq, PartialEq, Debug)]
-pub enum Expr {
-    Ap(CachedExpr, CachedExpr),
-    Op(
-        Primitive,
-        Option<CachedExpr>,
-        Option<CachedExpr>,
-        Option<CachedExpr>,
-    ),
-    Num(i64),
-    Var(String),
-}
-
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub enum Primitive {
-    Add,   // x y   => x + y
-    Mul,   // x y   => x * y
-    Div,   // x y   => x / y
-    Eq,    // x y   => x == y
-    Lt,    // x y   => x <= y
-    Neg,   // x     => -x
-    S,     // x y z => (x z) (y z)  !
-    C,     // x y z => (x z) y
-    B,     // x y z => x (y z)
-    I,     // x     => x
-    F,     // x y   => y  !
-    T,     // x y   => x  !
-    Cons,  // x y z => (z x) y
-    Car,   // x     => x T
-    Cdr,   // x     => x F
-    Nil,   // x     => T
-    Isnil, // x     => x == Nil ? T : F
-}
-
-impl Display for Primitive {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", PRIMITIVE_STR.get(self).unwrap())
-    }
-}
-
 use Expr::*;
 
-impl Into<CachedExpr> for Expr {
-    fn into(self) -> CachedExpr {
-        CachedExpr {
-            cache: Rc::new(RefCell::new(Cache {
-                expr: self,
-                state: 0,
-            })),
-        }
-    }
-}
-
 impl Expr {
-    fn boolean(b: bool) -> Expr {
-        if b {
-            Expr::op(Primitive::T)
-        } else {
-            Expr::op(Primitive::F)
-        }
-    }
-    fn op(p: Primitive) -> Expr {
-        Op(p, None, None, None)
-    }
-    fn reduce(self, env: &Env) -> Expr {
-        match self {
-            Op(p, x, y, z) => Op(
-                p,
-                x.map(|e| e.reduce(env).into()),
-                y.map(|e| e.reduce(env).into()),
-                z.map(|e| e.reduce(env).into()),
-            ),
-            x @ Num(_) => x,
-            x => x.eval(env).reduce(env),
-        }
+    pub(crate) fn reduce(self, env: &Env) -> Result<Expr, EvalError> {
+        Ok(match self {
+            Op(p, x, y, z) => {
+                let f = |e: Option<CachedExpr>| -> Result<_, EvalError> {
+                    e.map(|e| Ok(e.reduce(env)?.into())).transpose()
+                };
+                Op(p, f(x)?, f(y)?, f(z)?)
+            }
+            x @ Num(_) | x @ Big(_) => x,
+            Var(name) => Var(name.clone())
+                .eval(env)?
+                .reduce(env)
+                .map_err(|e| within(e, &name))?,
+            x => x.eval(env)?.reduce(env)?,
+        })
     }
-    fn eval(self, env: &Env) -> Expr {
+    pub(crate) fn eval(self, env: &Env) -> Result<Expr, EvalError> {
         use Primitive::*;
+        if env.record {
+            record_step(&self);
+        }
+        if let Some(tracer) = env.tracer.as_ref() {
+            trace(tracer.0.as_ref(), &self);
+        }
+        crate::eval::count(|s| {
+            match &self {
+                Op(p, ..) if self.children().count() == p.arity() => {
+                    *s.reductions.entry(*p).or_default() += 1
+                }
+                Var(_) => s.var_lookups += 1,
+                _ => (),
+            }
+            s.peak_nodes = s.peak_nodes.max(Eval::live_nodes());
+        });
+        if let Some(limit) = env.memory_limit {
+            if Eval::live_nodes() > limit {
+                return Err(EvalError::MemoryLimit);
+            }
+        }
+        crate::eval::charge()?;
+        let _depth = match env.max_depth {
+            Some(max) => Some(Depth::enter(&self, env, max)?),
+            None => None,
+        };
 
-        match self {
-            Ap(l, r) => match l.eval(env) {
+        Ok(match self {
+            Ap(l, r) => match l.eval(env)? {
                 Op(name, None, _, _) => Op(name, Some(r), None, None),
                 Op(name, x, None, _) => Op(name, x.clone(), Some(r), None),
                 Op(name, x, y, None) => Op(name, x.clone(), y.clone(), Some(r)),
-                _ => panic!("unexpected lhs: {:?}", l),
+                e => return Err(EvalError::NotAFunction(abbreviate(&e, 80))),
             }
-            .eval(env),
-            Op(B, Some(x), Some(y), Some(z)) => Ap(x, Ap(y, z).into()).eval(env),
-            Op(C, Some(x), Some(y), Some(z)) => Ap(Ap(x, z).into(), y).eval(env),
+            .eval(env)?,
+            Op(B, Some(x), Some(y), Some(z)) => Ap(x, Ap(y, z).into()).eval(env)?,
+            Op(C, Some(x), Some(y), Some(z)) => Ap(Ap(x, z).into(), y).eval(env)?,
             Op(S, Some(x), Some(y), Some(z)) => {
-                Ap(Ap(x, z.clone()).into(), Ap(y, z).into()).eval(env)
+                Ap(Ap(x, z.clone()).into(), Ap(y, z).into()).eval(env)?
             }
-            Op(Cons, Some(x), Some(y), Some(z)) => Ap(Ap(z, x).into(), y).eval(env),
+            Op(Cons, Some(x), Some(y), Some(z)) => Ap(Ap(z, x).into(), y).eval(env)?,
 
-            Op(I, Some(x), _, _) => x.eval(env),
-            Op(Car, Some(x), _, _) => Ap(x, Expr::boolean(true).into()).eval(env),
-            Op(Cdr, Some(x), _, _) => Ap(x, Expr::boolean(false).into()).eval(env),
-            Op(Neg, Some(x), _, _) => Num(-x.eval(env).must_num()),
+            Op(I, Some(x), _, _) => x.eval(env)?,
+            Op(Car, Some(x), _, _) => {
+                x.check_list(Car, env)?;
+                Ap(x, Expr::boolean(true).into()).eval(env)?
+            }
+            Op(Cdr, Some(x), _, _) => {
+                x.check_list(Cdr, env)?;
+                Ap(x, Expr::boolean(false).into()).eval(env)?
+            }
+            Op(Neg, Some(x), _, _) => arith(
+                Int::Small(0),
+                x.eval_num(Neg, env)?,
+                i64::checked_sub,
+                |x, y| x - y,
+            ),
             Op(Nil, Some(_), _, _) => Expr::boolean(true),
-            Op(Isnil, Some(x), _, _) => match x.eval(env) {
+            Op(Isnil, Some(x), _, _) => match x.eval(env)? {
                 Op(Nil, None, _, _) => Expr::boolean(true),
                 Op(Cons, Some(_), Some(_), None) => Expr::boolean(false),
-                _ => panic!("unexpected x: {:?}", x),
+                e => {
+                    violation(env, Isnil, &e)?;
+                    return Err(EvalError::NotAValue(abbreviate(&e, 80)));
+                }
             },
-            Op(T, Some(x), Some(_), _) => x.eval(env),
-            Op(F, Some(_), Some(y), _) => y.eval(env),
+            Op(If0, Some(x), Some(y), Some(z)) => {
+                if x.eval_num(If0, env)? == Int::Small(0) {
+                    y.eval(env)?
+                } else {
+                    z.eval(env)?
+                }
+            }
+            Op(Pwr2, Some(x), _, _) => match x.eval_num(Pwr2, env)? {
+                Int::Small(n) if n < 0 => Num(0),
+                Int::Small(n) if n < 63 => Num(1 << n),
+                Int::Small(n) if n <= MAX_BITS => Expr::num(BigInt::from(1) << n as usize),
+                _ => return Err(EvalError::Overflow(Pwr2)),
+            },
+            Op(Checkerboard, Some(x), Some(_), _) => {
+                let n = match x.eval_num(Checkerboard, env)? {
+                    Int::Small(n) if n <= MAX_CHECKERBOARD => n,
+                    _ => return Err(EvalError::Overflow(Checkerboard)),
+                };
+                let mut res = Expr::nil();
+                for i in (0..n).rev() {
+                    for j in (0..n).rev().filter(|j| (i + j) % 2 == 0) {
+                        res =
+                            Expr::cons(Expr::cons(Num(i).into(), Num(j).into()).into(), res.into());
+                    }
+                }
+                res
+            }
+            Op(T, Some(x), Some(_), _) => x.eval(env)?,
+            Op(F, Some(_), Some(y), _) => y.eval(env)?,
 
-            Op(Add, Some(x), Some(y), _) => Num(x.eval(env).must_num() + y.eval(env).must_num()),
-            Op(Mul, Some(x), Some(y), _) => Num(x.eval(env).must_num() * y.eval(env).must_num()),
-            Op(Div, Some(x), Some(y), _) => Num(x.eval(env).must_num() / y.eval(env).must_num()),
+            Op(Add, Some(x), Some(y), _) => arith(
+                x.eval_num(Add, env)?,
+                y.eval_num(Add, env)?,
+                i64::checked_add,
+                |x, y| x + y,
+            ),
+            Op(Mul, Some(x), Some(y), _) => arith(
+                x.eval_num(Mul, env)?,
+                y.eval_num(Mul, env)?,
+                i64::checked_mul,
+                |x, y| x * y,
+            ),
+            Op(Div, Some(x), Some(y), _) => {
+                let (x, y) = (x.eval_num(Div, env)?, y.eval_num(Div, env)?);
+                if y == Int::Small(0) {
+                    violation(env, Div, &Num(0))?;
+                    return Err(EvalError::DivisionByZero);
+                }
+                // Both round towards zero. i64::MIN / -1 is the only i64 quotient that overflows.
+                arith(x, y, i64::checked_div, |x, y| x / y)
+            }
             Op(Eq, Some(x), Some(y), _) => {
-                Expr::boolean(x.eval(env).must_num() == y.eval(env).must_num())
+                Expr::boolean(x.eval_num(Eq, env)? == y.eval_num(Eq, env)?)
             }
             Op(Lt, Some(x), Some(y), _) => {
-                Expr::boolean(x.eval(env).must_num() < y.eval(env).must_num())
+                Expr::boolean(match (x.eval_num(Lt, env)?, y.eval_num(Lt, env)?) {
+                    (Int::Small(x), Int::Small(y)) => x < y,
+                    (x, y) => x.big() < y.big(),
+                })
             }
 
-            Var(name) => env.get(&name).unwrap().clone().eval(env),
+            Var(name) => {
+                let e = match env.get(&name) {
+                    Some(e) => e.clone(),
+                    None => return Err(EvalError::UnknownVar(name)),
+                };
+                if !env.checks && !env.record {
+                    return e.eval(env).map_err(|e| within(e, &name));
+                }
+                CHECK_CONTEXT.with(|c| c.borrow_mut().push(name.clone()));
+                let res = e.eval(env).map_err(|e| within(e, &name))?;
+                CHECK_CONTEXT.with(|c| c.borrow_mut().pop());
+                res
+            }
             _ => self,
+        })
+    }
+}
+
+/// Env holds the definitions names are resolved against during evaluation.
+///
+/// Every change bumps version(), so callers holding results derived from an Env can tell
+/// whether they are stale. Note that definitions cache what they evaluate to, so redefining
+/// a name doesn't change definitions already evaluated through it.
+#[derive(Clone, Debug, Default)]
+pub struct Env {
+    defs: HashMap<String, Expr>,
+    version: u64,
+    // copies of the definitions as they were defined, never evaluated. See gc.
+    pristine: HashMap<String, Expr>,
+    // whether primitive applications are checked. See Eval::set_checks.
+    checks: bool,
+    // maximum number of live expression nodes. See Eval::set_memory_limit.
+    memory_limit: Option<usize>,
+    // maximum nesting of reductions. See Eval::set_max_depth.
+    max_depth: Option<usize>,
+    // bound of each evaluation. See Eval::set_budget.
+    budget: Option<EvalBudget>,
+    // whether reduction steps are recorded. See Eval::record.
+    record: bool,
+    // See Eval::set_tracer.
+    tracer: Option<TracerRef>,
+    // names violations label definitions with. See Eval::set_annotations.
+    annotations: Option<Rc<Annotations>>,
+    // interns parsed expressions. Nodes cache what they evaluate to, and a node referring
+    // to a name is only valid while the name means the same, so any change of definitions
+    // clears it.
+    arena: ExprArena,
+    // interns the pristine copies. They are never evaluated, so it is never cleared.
+    pristine_arena: ExprArena,
+    // whether lists are evaluated in parallel, and the definitions for the workers as of a
+    // version. See Eval::set_parallel.
+    #[cfg(feature = "parallel")]
+    parallel: bool,
+    #[cfg(feature = "parallel")]
+    shared: RefCell<Option<(u64, crate::shared::SharedEnv)>>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The definitions in galaxy.txt, simplified.
+    pub fn galaxy() -> Self {
+        let mut env = Env::from_source(include_str!("../galaxy.txt"))
+            .context("galaxy.txt")
+            .unwrap();
+        env.simplify();
+        env
+    }
+
+    // Definitions in the format of galaxy.txt, one "name = expr" per line. Blank lines are
+    // skipped, and definitions may refer to ones on later lines.
+    pub fn from_source(src: &str) -> anyhow::Result<Self> {
+        let lines = src
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .collect::<Vec<_>>();
+        let mut env = Env::new();
+        // placeholders, so that names can be resolved before their definitions.
+        for (i, line) in &lines {
+            match line.split_once(" = ") {
+                Some((name, _)) => env.defs.insert(name.to_string(), Expr::nil()),
+                None => anyhow::bail!("line {}: not a definition: {:?}", i + 1, line),
+            };
+        }
+        // Nothing has been evaluated yet, so the definitions replacing the placeholders can
+        // share nodes.
+        for (i, line) in lines {
+            let (name, expr) = line.split_once(" = ").unwrap();
+            let e = env
+                .parse(expr)
+                .with_context(|| format!("defining {}", name))
+                .with_context(|| format!("line {}", i + 1))?;
+            env.insert(name, e);
         }
+        Ok(env)
     }
 
-    fn must_num(&self) -> i64 {
-        match self {
-            Expr::Num(x) => *x,
-            _ => panic!("not a num: {}", self),
+    // Defines or redefines name, returning the previous definition.
+    pub fn define(&mut self, name: &str, expr: Expr) -> Option<Expr> {
+        self.arena.clear();
+        self.insert(name, expr)
+    }
+    // Defines name without clearing the arena, for expressions interned in it before any
+    // evaluation.
+    pub(crate) fn insert(&mut self, name: &str, expr: Expr) -> Option<Expr> {
+        self.version += 1;
+        self.pristine
+            .insert(name.to_string(), fresh(&expr, &self.pristine_arena));
+        self.defs.insert(name.to_string(), expr)
+    }
+
+    // Defines a name from a line in the form of "f = ap ap ...".
+    pub fn define_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let (name, expr) = line
+            .split_once(" = ")
+            .ok_or_else(|| anyhow::anyhow!("not a definition: {:?}", line))?;
+        self.insert_definition(name, expr)?;
+        Ok(())
+    }
+
+    // Parses expr and defines name as it, returning the previous definition. expr may refer
+    // to name itself, e.g. "ap ap s ... pwr2" for pwr2.
+    pub fn insert_definition(&mut self, name: &str, expr: &str) -> anyhow::Result<Option<Expr>> {
+        let placeholder = !self.contains(name);
+        if placeholder {
+            self.defs.insert(name.to_string(), Expr::nil());
+        }
+        let e = self.parse(expr);
+        if placeholder {
+            self.defs.remove(name);
+        }
+        let e = e.with_context(|| format!("defining {}", name))?;
+        Ok(self.define(name, e))
+    }
+
+    // Parses expr, resolving names against this Env.
+    pub fn parse(&self, expr: &str) -> Result<Expr, ParseError> {
+        try_parse_string(self, expr)
+    }
+
+    // The arena parse interns expressions in.
+    pub fn arena(&self) -> &ExprArena {
+        &self.arena
+    }
+
+    // The definition of name, including what evaluation cached in it. See source for the
+    // definition as defined.
+    pub fn get(&self, name: &str) -> Option<&Expr> {
+        self.defs.get(name)
+    }
+    // The names of the definitions, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut res = self.defs.keys().map(String::as_str).collect::<Vec<_>>();
+        res.sort_unstable();
+        res
+    }
+    // The definition of name as defined, in the "ap ap ..." form of galaxy.txt, so that
+    // "name = source" defines it again.
+    pub fn source(&self, name: &str) -> Option<String> {
+        self.pristine.get(name).map(|e| e.to_string())
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.defs.contains_key(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Expr> {
+        self.pristine.remove(name);
+        let res = self.defs.remove(name);
+        if res.is_some() {
+            self.version += 1;
+            self.arena.clear();
+        }
+        res
+    }
+
+    // Adds all definitions of other, replacing ones with the same name.
+    pub fn merge(&mut self, other: Env) {
+        if other.defs.is_empty() {
+            return;
+        }
+        self.version += 1;
+        self.arena.clear();
+        self.defs.extend(other.defs);
+        self.pristine.extend(other.pristine);
+    }
+
+    // Drops the results definitions cached during evaluation, restoring them as they were
+    // defined. Only the subexpressions directly under each definition are cached, so
+    // resetting those frees everything evaluation attached to the definition.
+    pub fn gc(&self) {
+        self.arena.clear();
+        let mut pairs = vec![];
+        for (name, e) in self.defs.iter() {
+            let orig = match self.pristine.get(name) {
+                Some(orig) => orig,
+                None => continue,
+            };
+            let pairs_of_def: Vec<_> = match (e, orig) {
+                (Ap(l, r), Ap(l0, r0)) => vec![(l, l0), (r, r0)],
+                (Op(_, x, y, z), Op(_, x0, y0, z0)) => [(x, x0), (y, y0), (z, z0)]
+                    .iter()
+                    .filter_map(|(a, b)| Some((a.as_ref()?, b.as_ref()?)))
+                    .collect(),
+                _ => vec![],
+            };
+            pairs.extend(pairs_of_def);
+        }
+        // Evaluation may tie shared nodes into cycles, which resetting only the nodes under
+        // the definitions would leak, so everything reachable from them is emptied first.
+        let mut seen = HashSet::new();
+        let mut stack: Vec<CachedExpr> = pairs.iter().map(|(c, _)| (*c).clone()).collect();
+        let mut reachable = vec![];
+        while let Some(c) = stack.pop() {
+            if seen.insert(c.id()) {
+                stack.extend(c.expr().children().cloned());
+                reachable.push(c);
+            }
+        }
+        for c in reachable {
+            c.reset(Expr::nil());
+        }
+        // The pristine copies are interned, so restoring one node per pristine node shares
+        // subexpressions as they were shared when defined.
+        let mut live = HashMap::new();
+        for (c, c0) in &pairs {
+            live.entry(c0.id()).or_insert_with(|| (*c).clone());
+        }
+        for (c, c0) in pairs {
+            c.reset(restore(&c0.expr(), &mut live, &self.arena));
+            self.arena.share(c);
+        }
+    }
+
+    // Folds the applications in the definitions that need no evaluation, like "ap neg 3" or
+    // "ap ap t x y", returning how many. See the simplify module. The definitions are
+    // rebuilt from the copies as they were defined, dropping what they cached.
+    pub fn simplify(&mut self) -> usize {
+        let mut simplifier = Simplifier::new(&self.pristine_arena);
+        let defs = self
+            .pristine
+            .iter()
+            .map(|(name, e)| (name.clone(), simplifier.expr(e)))
+            .collect::<Vec<_>>();
+        let rewrites = simplifier.rewrites;
+        drop(simplifier);
+        self.arena.clear();
+        for (name, e) in defs {
+            let e = fresh(&e, &self.arena);
+            self.insert(&name, e);
+        }
+        rewrites
+    }
+
+    // The names each definition refers to, for every definition. Names without a
+    // definition, like the x0 of bindings, are left out.
+    pub fn dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.pristine
+            .iter()
+            .map(|(name, e)| {
+                let mut deps = BTreeSet::new();
+                let mut seen = HashSet::new();
+                let mut stack = e.children().cloned().collect::<Vec<_>>();
+                let mut visit = |e: &Expr| match e {
+                    Var(v) if self.contains(v) => {
+                        deps.insert(v.clone());
+                    }
+                    _ => (),
+                };
+                visit(e);
+                while let Some(c) = stack.pop() {
+                    if seen.insert(c.id()) {
+                        let e = c.expr();
+                        visit(&e);
+                        stack.extend(e.children().cloned());
+                    }
+                }
+                (name.clone(), deps)
+            })
+            .collect()
+    }
+
+    // The number of definitions referring to each definition, 0 for unused ones like the
+    // roots of protocols. A definition referring to itself counts.
+    pub fn reference_counts(&self) -> BTreeMap<String, usize> {
+        let mut res = self
+            .defs
+            .keys()
+            .map(|name| (name.clone(), 0))
+            .collect::<BTreeMap<_, _>>();
+        for deps in self.dependencies().into_values() {
+            for name in deps {
+                *res.entry(name).or_default() += 1;
+            }
+        }
+        res
+    }
+
+    // The definitions evaluating root may need, root included if defined, e.g.
+    // env.reachable_from("galaxy") for the part of galaxy.txt clicks can run.
+    pub fn reachable_from(&self, root: &str) -> BTreeSet<String> {
+        let deps = self.dependencies();
+        let mut res = BTreeSet::new();
+        let mut stack = vec![root.to_string()];
+        while let Some(name) = stack.pop() {
+            if let Some(ds) = deps.get(&name) {
+                if res.insert(name) {
+                    stack.extend(ds.iter().cloned());
+                }
+            }
+        }
+        res
+    }
+
+    // name as a symbol explorer shows it: its label with the tags and description of its
+    // annotation, its definition as Expr::pretty_annotated writes it, and the definitions it
+    // refers to and that refer to it. None if name isn't defined.
+    //   :2 (negate) [arith]
+    //     negates :3
+    //   = (neg one)
+    //   uses :3 (one)
+    //   used by :1
+    pub fn describe(&self, name: &str, annotations: &Annotations) -> Option<String> {
+        let def = self.pristine.get(name)?;
+        let deps = self.dependencies();
+        let labels = |names: Vec<&String>| {
+            names
+                .into_iter()
+                .map(|n| annotations.label(n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut res = annotations.label(name);
+        if let Some(a) = annotations.symbol(name) {
+            if !a.tags.is_empty() {
+                res += &format!(" [{}]", a.tags.join(", "));
+            }
+            if !a.description.is_empty() {
+                res += &format!("\n  {}", a.description);
+            }
+        }
+        res += &format!("\n= {}", def.pretty_annotated(annotations));
+        let uses = deps[name].iter().collect::<Vec<_>>();
+        if !uses.is_empty() {
+            res += &format!("\nuses {}", labels(uses));
         }
+        let used_by = deps
+            .iter()
+            .filter(|(_, ds)| ds.contains(name))
+            .map(|(n, _)| n)
+            .collect::<Vec<_>>();
+        if !used_by.is_empty() {
+            res += &format!("\nused by {}", labels(used_by));
+        }
+        Some(res)
     }
-    fn cons(hd: CachedExpr, tl: CachedExpr) -> Expr {
-        Op(Primitive::Cons, Some(hd), Some(tl), None)
+
+    // Removes the definitions unreachable from root, returning their names.
+    pub fn strip_unreachable(&mut self, root: &str) -> Vec<String> {
+        let reachable = self.reachable_from(root);
+        let mut removed = self
+            .defs
+            .keys()
+            .filter(|name| !reachable.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        removed.sort();
+        for name in &removed {
+            self.remove(name);
+        }
+        removed
+    }
+
+    // The copies of the definitions as they were defined.
+    pub(crate) fn pristine(&self) -> impl Iterator<Item = (&str, &Expr)> {
+        self.pristine.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Expr)> {
+        self.defs.iter().map(|(k, v)| (k.as_str(), v))
+    }
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    // Incremented on every change to the definitions.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // The definitions for the workers evaluating lists in parallel, unless evaluation here
+    // must be sequential.
+    #[cfg(feature = "parallel")]
+    fn parallel(&self) -> Option<crate::shared::SharedEnv> {
+        let sequential = self.checks
+            || self.record
+            || self.tracer.is_some()
+            || self.memory_limit.is_some()
+            || crate::eval::bounded();
+        if !self.parallel || sequential {
+            return None;
+        }
+        let mut shared = self.shared.borrow_mut();
+        match shared.as_ref() {
+            Some((version, s)) if *version == self.version => Some(s.clone()),
+            _ => {
+                let s = crate::shared::SharedEnv::new(self);
+                *shared = Some((self.version, s.clone()));
+                Some(s)
+            }
+        }
     }
-    fn nil() -> Expr {
-        Expr::op(Primitive::Nil)
+}
+
+/// Violation is a primitive applied to an argument it isn't meant for, found by the checks
+/// enabled with Eval::set_checks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub primitive: Primitive,
+    // the offending argument, abbreviated if long.
+    pub arg: String,
+    // names of the definitions being evaluated, outermost first.
+    pub context: Vec<String>,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} applied to {}", self.primitive, self.arg)?;
+        if !self.context.is_empty() {
+            write!(f, " in {}", self.context.join(" > "))?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-pub struct CachedExpr {
-    cache: Rc<RefCell<Cache>>,
+impl std::error::Error for Violation {}
+
+/// Trace is the sequence of reductions of an evaluation, recorded by Eval::record.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    // number of steps taken, including the ones beyond TraceLimits::max_steps.
+    pub total_steps: usize,
+    pub result: Result<Node, EvalError>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceStep {
+    // the expression about to be reduced, abbreviated to TraceLimits::max_expr_len.
+    pub expr: String,
+    // the innermost definition being evaluated, if any.
+    pub def: Option<String>,
 }
-#[derive(Clone, Eq, PartialEq, Debug)]
 
-struct Cache {
-    expr: Expr,
-    state: u8, // 1: cached, 2: reduced
+/// TraceLimits bounds the detail of a Trace, as a click takes millions of steps.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceLimits {
+    pub max_steps: usize,
+    pub max_expr_len: usize,
 }
 
-impl std::ops::Deref for Cache {
-    type Target = Expr;
-    fn deref(&self) -> &Self::Target {
-        &self.expr
+impl Default for TraceLimits {
+    fn default() -> Self {
+        TraceLimits {
+            max_steps: 100_000,
+            max_expr_len: 200,
+        }
     }
 }
 
-type Env = HashMap<String, Expr>;
+impl Trace {
+    // The n-th step (0-based), or None if it wasn't recorded.
+    pub fn step(&self, n: usize) -> Option<&TraceStep> {
+        self.steps.get(n)
+    }
+    // Steps taken while evaluating the definition name, with their indices.
+    pub fn steps_in<'a>(&'a self, name: &'a str) -> impl Iterator<Item = (usize, &'a TraceStep)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter(move |(_, s)| s.def.as_deref() == Some(name))
+    }
+    // One line per recorded step, with definitions labeled by their annotated names:
+    //   12 :1029 (draw_frame): ap ap cons 1 nil
+    pub fn render(&self, annotations: &Annotations) -> String {
+        let mut res = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let def = step.def.as_ref().map(|d| annotations.label(d));
+            res += &format!("{} {}: {}\n", i, def.as_deref().unwrap_or("-"), step.expr);
+        }
+        res
+    }
+}
 
-fn default_env() -> Env {
-    let mut env = Env::new();
+thread_local! {
+    // limits, steps and the total number of steps of the recording in progress.
+    static RECORDING: RefCell<Option<(TraceLimits, Vec<TraceStep>, usize)>> =
+        const { RefCell::new(None) };
+}
 
-    for line in include_str!("../galaxy.txt").split("\n") {
-        let ss = line.split(" = ").collect::<Vec<_>>();
-        let (name, expr) = (ss[0], ss[1]);
-        let e = parse_string(&env, expr);
-        env.insert(name.to_string(), e);
+/// Tracer is called back as the reduce evaluator reduces, e.g. for a UI showing which galaxy
+/// definitions a click evaluates. Set it with Eval::set_tracer.
+pub trait Tracer {
+    // A primitive applied to all its arguments is about to be reduced.
+    fn on_reduce(&self, _primitive: Primitive, _args: &[Expr]) {}
+    // The definition of name is about to be evaluated.
+    fn on_var_lookup(&self, _name: &str) {}
+}
+
+#[derive(Clone)]
+struct TracerRef(Rc<dyn Tracer>);
+
+impl std::fmt::Debug for TracerRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Tracer")
     }
+}
 
-    env
+fn trace(tracer: &dyn Tracer, e: &Expr) {
+    match e {
+        Op(p, x, y, z) => {
+            let args = [x, y, z]
+                .iter()
+                .copied()
+                .flatten()
+                .map(CachedExpr::expr)
+                .collect::<Vec<_>>();
+            if args.len() == p.arity() {
+                tracer.on_reduce(*p, &args);
+            }
+        }
+        Var(name) => tracer.on_var_lookup(name),
+        _ => (),
+    }
 }
 
-impl CachedExpr {
-    fn eval(&self, env: &Env) -> Expr {
-        let state = self.cache.borrow().state;
-        if state == 0 {
-            let expr = { self.cache.borrow().expr.clone().eval(env) };
-            {
-                self.cache.borrow_mut().expr = expr
-            };
+fn record_step(e: &Expr) {
+    RECORDING.with(|r| {
+        if let Some((limits, steps, total)) = r.borrow_mut().as_mut() {
+            *total += 1;
+            if steps.len() < limits.max_steps {
+                steps.push(TraceStep {
+                    expr: abbreviate(e, limits.max_expr_len),
+                    def: CHECK_CONTEXT.with(|c| c.borrow().last().cloned()),
+                });
+            }
         }
-        self.cache.borrow().expr.clone()
+    })
+}
+
+thread_local! {
+    static CHECK_CONTEXT: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    // Number of Expr::eval calls in progress on the thread, counted with Eval::set_max_depth.
+    static DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// An Expr::eval call in progress, counted in DEPTH until dropped.
+struct Depth;
+
+impl Depth {
+    fn enter(e: &Expr, env: &Env, max: usize) -> Result<Depth, EvalError> {
+        DEPTH.with(|d| {
+            if d.get() >= max {
+                return Err(referenced_definitions(env, e)
+                    .iter()
+                    .fold(divergence(e), |res, name| within(res, name)));
+            }
+            d.set(d.get() + 1);
+            Ok(Depth)
+        })
     }
-    fn reduce(&self, env: &Env) -> Expr {
-        let state = self.cache.borrow().state;
-        if state < 2 {
-            let expr = { self.cache.borrow().expr.clone().reduce(env) };
-            {
-                self.cache.borrow_mut().expr = expr
-            };
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        let _ = DEPTH.try_with(|d| d.set(d.get() - 1));
+    }
+}
+
+// The definitions whose names the first 1000 nodes of e, from the top, were parsed from.
+// Nodes are interned, so a name evaluated and cached long ago is still found.
+fn referenced_definitions(env: &Env, e: &Expr) -> Vec<String> {
+    let vars = env.arena.var_nodes();
+    let mut res = vec![];
+    let mut seen = HashSet::new();
+    let mut queue = e.children().cloned().collect::<VecDeque<_>>();
+    while let Some(node) = queue.pop_front() {
+        if seen.len() >= 1000 || !seen.insert(node.id()) {
+            continue;
         }
-        self.cache.borrow().expr.clone()
+        if let Some(name) = vars.get(&node.id()) {
+            if !res.contains(name) {
+                res.push(name.clone());
+            }
+        }
+        queue.extend(node.expr().children().cloned());
     }
-    fn expr(&self) -> Expr {
-        self.cache.borrow().expr.clone()
+    res
+}
+
+fn divergence(e: &Expr) -> EvalError {
+    EvalError::DivergenceDetected {
+        expr: abbreviate(e, 80),
+        definitions: vec![],
     }
 }
 
-impl std::fmt::Display for Expr {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+// Adds name to the definitions of a divergence detected while evaluating it.
+fn within(e: EvalError, name: &str) -> EvalError {
+    match e {
+        EvalError::DivergenceDetected {
+            expr,
+            mut definitions,
+        } => {
+            if !definitions.iter().any(|d| d == name) {
+                definitions.push(name.into());
+            }
+            EvalError::DivergenceDetected { expr, definitions }
+        }
+        e => e,
+    }
+}
+
+// Formats e, cutting it to max bytes ending with "..." if longer. The rest of e isn't
+// formatted at all, so this is cheap for huge expressions.
+fn abbreviate(e: &Expr, max: usize) -> String {
+    struct Bounded(String, usize);
+    impl std::fmt::Write for Bounded {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0.push_str(s);
+            if self.0.len() > self.1 {
+                return Err(std::fmt::Error);
+            }
+            Ok(())
+        }
+    }
+    let mut w = Bounded(String::new(), max);
+    if std::fmt::Write::write_fmt(&mut w, format_args!("{}", e)).is_err() {
+        let n = w.0.floor_char_boundary(max.saturating_sub(3));
+        w.0.truncate(n);
+        w.0.push_str("...");
+    }
+    w.0
+}
+
+// Copies e with new caches interned in arena, so that evaluating one doesn't affect the other.
+fn fresh(e: &Expr, arena: &ExprArena) -> Expr {
+    let f = |c: &CachedExpr| arena.intern(fresh(&c.expr(), arena));
+    match e {
+        Ap(l, r) => Ap(f(l), f(r)),
+        Op(p, x, y, z) => Op(*p, x.as_ref().map(f), y.as_ref().map(f), z.as_ref().map(f)),
+        e => e.clone(),
+    }
+}
+
+// Copies e, a pristine expression, with the node in live for each pristine node, creating
+// ones missing in arena.
+fn restore(e: &Expr, live: &mut HashMap<ExprId, CachedExpr>, arena: &ExprArena) -> Expr {
+    let mut f = |c0: &CachedExpr| match live.get(&c0.id()) {
+        Some(c) => c.clone(),
+        None => {
+            let c = arena.intern(restore(&c0.expr(), live, arena));
+            live.insert(c0.id(), c.clone());
+            c
+        }
+    };
+    match e {
+        Ap(l, r) => Ap(f(l), f(r)),
+        Op(p, x, y, z) => Op(
+            *p,
+            x.as_ref().map(&mut f),
+            y.as_ref().map(&mut f),
+            z.as_ref().map(&mut f),
+        ),
+        e => e.clone(),
+    }
+}
+
+// Bounds of the results of pwr2 and checkerboard, beyond which they overflow rather than
+// exhaust memory.
+pub(crate) const MAX_BITS: i64 = 1 << 20;
+pub(crate) const MAX_CHECKERBOARD: i64 = 1 << 10;
+
+// A number argument of an arithmetic primitive.
+#[derive(PartialEq)]
+enum Int {
+    Small(i64),
+    Big(Rc<BigInt>),
+}
+
+impl Int {
+    fn big(&self) -> BigInt {
         match self {
-            Expr::Ap(l, r) => write!(f, "ap {} {}", l.expr().to_string(), r.expr().to_string()),
-            Expr::Op(s, x, y, z) => {
-                let mut res = format!("{}", s);
-                for e in [x, y, z].iter() {
-                    if let Some(e) = e {
-                        res = format!("ap {} {}", res, e.expr().to_string());
-                    }
-                }
-                write!(f, "{}", res)
+            Int::Small(i) => BigInt::from(*i),
+            Int::Big(i) => (**i).clone(),
+        }
+    }
+}
+
+// Computes x op y with small, falling back to big when an argument is big or small overflows.
+fn arith(
+    x: Int,
+    y: Int,
+    small: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+) -> Expr {
+    if let (Int::Small(x), Int::Small(y)) = (&x, &y) {
+        if let Some(n) = small(*x, *y) {
+            return Num(n);
+        }
+    }
+    Expr::num(big(x.big(), y.big()))
+}
+
+// With checks on, reports that p was applied to arg as a Violation.
+fn violation(env: &Env, p: Primitive, arg: &Expr) -> Result<(), EvalError> {
+    if !env.checks {
+        return Ok(());
+    }
+    let v = Violation {
+        primitive: p,
+        arg: abbreviate(arg, 80),
+        context: CHECK_CONTEXT.with(|c| match env.annotations.as_ref() {
+            Some(a) => c.borrow().iter().map(|name| a.label(name)).collect(),
+            None => c.borrow().clone(),
+        }),
+    };
+    Err(EvalError::Violation(v))
+}
+
+impl CachedExpr {
+    // Evaluates self, an argument of p that must be a number.
+    fn eval_num(&self, p: Primitive, env: &Env) -> Result<Int, EvalError> {
+        match self.eval(env)? {
+            Num(x) => Ok(Int::Small(x)),
+            Big(x) => Ok(Int::Big(x)),
+            e => {
+                violation(env, p, &e)?;
+                Err(EvalError::NotANum(abbreviate(&e, 80)))
+            }
+        }
+    }
+    // With checks on, verifies that self, an argument of p, is a cons cell or nil.
+    fn check_list(&self, p: Primitive, env: &Env) -> Result<(), EvalError> {
+        if !env.checks {
+            return Ok(());
+        }
+        match self.eval(env)? {
+            Op(Primitive::Cons, Some(_), Some(_), None) | Op(Primitive::Nil, None, _, _) => Ok(()),
+            e => violation(env, p, &e),
+        }
+    }
+
+    pub(crate) fn eval(&self, env: &Env) -> Result<Expr, EvalError> {
+        let (expr, state) = self.read(|c| (c.expr.clone(), c.state));
+        match state {
+            Thunk::Unevaluated => crate::eval::count(|s| s.cache_misses += 1),
+            Thunk::InProgress => return Err(divergence(&expr)),
+            _ => {
+                crate::eval::count(|s| s.cache_hits += 1);
+                return Ok(expr);
             }
-            Expr::Num(i) => write!(f, "{}", i),
-            Expr::Var(s) => write!(f, "{}", s),
         }
+        let hole = Blackhole::new(self, Thunk::InProgress);
+        let expr = expr.eval(env)?;
+        hole.finish(expr.clone(), Thunk::Evaluated);
+        Ok(expr)
+    }
+    fn reduce(&self, env: &Env) -> Result<Expr, EvalError> {
+        let expr = self.eval(env)?;
+        match self.read(|c| c.state) {
+            Thunk::Reduced => return Ok(expr),
+            Thunk::Reducing => return Err(divergence(&expr)),
+            _ => (),
+        }
+        let hole = Blackhole::new(self, Thunk::Reducing);
+        let expr = expr.reduce(env)?;
+        hole.finish(expr.clone(), Thunk::Reduced);
+        Ok(expr)
     }
 }
 
+#[cfg(test)]
 pub(crate) fn parse_string(env: &Env, expr: &str) -> Expr {
-    parse(
-        env,
-        &mut expr.split(" ").map(String::from).into_iter().peekable(),
-    )
+    try_parse_string(env, expr).unwrap()
+}
+
+// Parses expr, returning an error on malformed input.
+pub(crate) fn try_parse_string(env: &Env, expr: &str) -> Result<Expr, ParseError> {
+    let mut tokens = Tokens {
+        it: expr.split(' ').enumerate().peekable(),
+        len: 0,
+    };
+    let res = parse(env, &mut tokens)?;
+    if let Some((index, s)) = tokens.it.next() {
+        return Err(ParseError {
+            index,
+            token: Some(s.to_string()),
+            kind: ParseErrorKind::UnusedToken,
+        });
+    }
+    Ok(res)
+}
+
+/// ParseError tells where an expression is malformed: the index of the offending token among
+/// its space-separated tokens, and the token unless the input ended early.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub index: usize,
+    pub token: Option<String>,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedEnd,
+    // A name that is neither defined nor a galaxy-style :N or xN variable.
+    UnknownVar,
+    // A "(" without its ")".
+    UnclosedParen,
+    // A token after a complete expression.
+    UnusedToken,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "token {}: ", self.index)?;
+        let token = self.token.as_deref().unwrap_or_default();
+        match self.kind {
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseErrorKind::UnknownVar => write!(f, "unknown var {}", token),
+            ParseErrorKind::UnclosedParen => write!(f, "unclosed \"(\""),
+            ParseErrorKind::UnusedToken => write!(f, "unused token {:?}", token),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Tokens<'a, I: Iterator<Item = (usize, &'a str)>> {
+    it: std::iter::Peekable<I>,
+    // Number of tokens taken so far, the index of the end of input.
+    len: usize,
 }
 
-fn parse(
+impl<'a, I: Iterator<Item = (usize, &'a str)>> Tokens<'a, I> {
+    fn next(&mut self) -> Result<(usize, &'a str), ParseError> {
+        let (i, s) = self.it.next().ok_or(ParseError {
+            index: self.len,
+            token: None,
+            kind: ParseErrorKind::UnexpectedEnd,
+        })?;
+        self.len = i + 1;
+        Ok((i, s))
+    }
+    fn peek(&mut self) -> Option<&'a str> {
+        self.it.peek().map(|(_, s)| *s)
+    }
+}
+
+fn parse<'a>(
     env: &Env,
-    mut it: &mut std::iter::Peekable<impl std::iter::Iterator<Item = String>>,
-) -> Expr {
+    tokens: &mut Tokens<'a, impl Iterator<Item = (usize, &'a str)>>,
+) -> Result<Expr, ParseError> {
     use Expr::*;
 
-    let mut s: &str = &it.next().expect("iterator exhausted");
+    let (index, mut s) = tokens.next()?;
     if s == "vec" {
         s = "cons";
     }
-    match s {
+    Ok(match s {
         "(" => {
             let mut lst = vec![];
             loop {
-                if it.peek().unwrap() == ")" {
-                    it.next().unwrap();
-                    break;
+                match tokens.peek() {
+                    Some(")") => {
+                        tokens.next()?;
+                        break;
+                    }
+                    None => {
+                        return Err(ParseError {
+                            index,
+                            token: Some(s.to_string()),
+                            kind: ParseErrorKind::UnclosedParen,
+                        })
+                    }
+                    _ => (),
                 }
-                lst.push(parse(env, it));
-                if it.peek().unwrap() == "," {
-                    it.next().unwrap();
+                lst.push(parse(env, tokens)?);
+                if tokens.peek() == Some(",") {
+                    tokens.next()?;
                 }
             }
             let mut res = Expr::nil();
             for x in lst.into_iter().rev() {
-                res = Expr::cons(x.into(), res.into());
+                res = Expr::cons(env.arena.intern(x), env.arena.intern(res));
             }
             res
         }
-        "ap" => Ap(parse(env, &mut it).into(), parse(env, &mut it).into()),
+        "ap" => Ap(
+            env.arena.intern(parse(env, tokens)?),
+            env.arena.intern(parse(env, tokens)?),
+        ),
         s => {
             if let Some(p) = STR_PRIMITIVE.get(s) {
-                Expr::op(*p).into()
+                Expr::op(*p)
             } else if let Ok(i) = s.parse::<i64>() {
                 Num(i)
-            } else if env.contains_key(s)
-                || s.chars().next().unwrap() == ':'
-                || s.chars().next().unwrap() == 'x'
-            {
+            } else if let Ok(i) = s.parse::<BigInt>() {
+                Expr::num(i)
+            } else if env.contains(s) || s.starts_with(':') || s.starts_with('x') {
                 Var(s.to_string())
             } else {
-                panic!("unknown var {}", s);
+                return Err(ParseError {
+                    index,
+                    token: Some(s.to_string()),
+                    kind: ParseErrorKind::UnknownVar,
+                });
             }
         }
-    }
+    })
 }
 
 pub struct Eval {
@@ -324,38 +1104,246 @@ pub struct Eval {
 
 impl Eval {
     pub fn new() -> Self {
-        Eval { env: default_env() }
+        Eval { env: Env::galaxy() }
+    }
+    pub fn with_env(env: Env) -> Self {
+        Eval { env }
+    }
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    // Turns on checks of primitive applications: car and cdr must be applied to cons cells
+    // or nil, numeric primitives to numbers, and div to a non-zero divisor. The first
+    // violation fails the evaluation with EvalError::Violation, which has the names of the
+    // definitions being evaluated. Checks slow evaluation down, so they are off by default.
+    pub fn set_checks(&mut self, checks: bool) {
+        self.env.checks = checks;
+    }
+
+    // Evaluates expr with checks on, failing with EvalError::Violation on the first
+    // violation, or with the other errors of try_evaluate, e.g. on malformed expr.
+    pub fn check(&self, expr: &str) -> Result<Node, EvalError> {
+        let mut env = self.env.clone();
+        env.checks = true;
+        crate::eval::Evaluator::try_evaluate(&Eval::with_env(env), expr)
+    }
+
+    // Limits the number of live expression nodes, about 64 bytes each. Evaluations
+    // exceeding it fail with EvalError::MemoryLimit, after dropping the results cached by
+    // definitions and retrying once. G reports it as InteractError::Eval.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.env.memory_limit = limit;
+    }
+
+    // Bounds how deep reductions nest, failing evaluations nesting deeper with
+    // EvalError::DivergenceDetected instead of overflowing the stack. Non-productive cycles,
+    // like g = ap ap b g i applied to anything, nest without bound; the error names the
+    // definitions the expression nesting too deep refers to. Clicks of galaxy nest about
+    // 1200 deep, but a recursion like counting down from 10000 nests 100000 deep, and each
+    // level takes up to about 1.5KB of stack in release builds.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.env.max_depth = max_depth;
+    }
+
+    // Bounds the reductions and time of each evaluation. Evaluations exceeding it fail with
+    // EvalError::BudgetExceeded. For clicks, G::set_budget bounds all the rounds together.
+    pub fn set_budget(&mut self, budget: Option<EvalBudget>) {
+        self.env.budget = budget;
+    }
+
+    // Number of expression nodes alive on this thread, including the definitions.
+    pub fn live_nodes() -> usize {
+        expr::live_nodes()
+    }
+
+    // Frees the expression nodes on this thread that are unreachable but kept alive by
+    // reference cycles, returning how many. Cycles form as shared nodes are evaluated, and
+    // gc frees the ones reachable from definitions, but not those of finished evaluations.
+    pub fn collect_cycles() -> usize {
+        expr::collect()
+    }
+
+    // Evaluates expr, recording its reductions within limits so that a debugger can go to any
+    // step, e.g. to see why a click drew a weird frame:
+    //   eval.record("ap ap galaxy nil ap ap cons 0 0", TraceLimits::default())
+    // The results cached by definitions are dropped first, so every recording of expr takes
+    // the same steps.
+    pub fn record(&self, expr: &str, limits: TraceLimits) -> Trace {
+        let mut env = self.env.clone();
+        env.record = true;
+        env.gc();
+        let expr = match env.parse(expr) {
+            Ok(expr) => expr,
+            Err(e) => {
+                return Trace {
+                    steps: vec![],
+                    total_steps: 0,
+                    result: Err(EvalError::Parse(format!("{:#}", e))),
+                }
+            }
+        };
+        CHECK_CONTEXT.with(|c| c.borrow_mut().clear());
+        RECORDING.with(|r| *r.borrow_mut() = Some((limits, vec![], 0)));
+        let result = expr.reduce(&env).and_then(expr_to_node);
+        let (_, steps, total_steps) = RECORDING.with(|r| r.borrow_mut().take()).unwrap();
+        Trace {
+            steps,
+            total_steps,
+            result,
+        }
+    }
+
+    // Evaluates the elements of list results, like a protocol's flag, state and data, on
+    // rayon's threads. See the parallel module.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.env.parallel = parallel;
+    }
+
+    // Sets the tracer called back during evaluations, or unsets it with None. Without a tracer
+    // evaluation only pays for checking that there is none.
+    pub fn set_tracer(&mut self, tracer: Option<Rc<dyn Tracer>>) {
+        self.env.tracer = tracer.map(TracerRef);
+    }
+
+    // Sets the annotations that label definitions in the contexts of violations.
+    pub fn set_annotations(&mut self, annotations: Annotations) {
+        self.env.annotations = Some(Rc::new(annotations));
+    }
+
+    // Forgets the context left by a previous evaluation that failed.
+    fn reset_checks(&self) {
+        if self.env.checks {
+            CHECK_CONTEXT.with(|c| c.borrow_mut().clear());
+        }
+    }
+}
+
+impl Default for Eval {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Eval {
+    fn parse_expr(env: &Env, expr: &str) -> Result<Expr, EvalError> {
+        env.parse(expr)
+            .map_err(|e| EvalError::Parse(format!("{:#}", e)))
+    }
+
+    fn try_evaluate_in(&self, env: &Env, expr: Expr) -> Result<Node, EvalError> {
+        self.reset_checks();
+        match env.budget {
+            Some(budget) => budget.run(|| expr.reduce(env).and_then(expr_to_node)).0,
+            None => Eval::reduce_to_node(env, expr),
+        }
+    }
+
+    fn reduce_to_node(env: &Env, expr: Expr) -> Result<Node, EvalError> {
+        #[cfg(feature = "parallel")]
+        if let Some(shared) = env.parallel() {
+            return crate::parallel::reduce(env, &shared, expr, crate::parallel::DEPTH);
+        }
+        expr.reduce(env).and_then(expr_to_node)
     }
 }
 
-impl crate::common::Evaluator for Eval {
+// The infallible methods panic with the message of the EvalError.
+impl crate::eval::Evaluator for Eval {
     fn evaluate(&self, expr: &str) -> Node {
-        let expr = parse_string(&self.env, expr);
-        expr_to_node(expr.reduce(&self.env))
+        self.try_evaluate(expr).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Node {
+        self.try_evaluate_ap2(f, x, y)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn try_evaluate(&self, expr: &str) -> Result<Node, EvalError> {
+        let _span = tracing::debug_span!("eval", expr).entered();
+        crate::eval::retry_after_gc(self, || {
+            self.try_evaluate_in(&self.env, Eval::parse_expr(&self.env, expr)?)
+        })
+    }
+    fn try_evaluate_ap2(&self, f: &str, x: &Node, y: &Node) -> Result<Node, EvalError> {
+        let _span = tracing::debug_span!("eval", symbol = f).entered();
+        crate::eval::retry_after_gc(self, || {
+            let expr = Ap(
+                Ap(
+                    Eval::parse_expr(&self.env, f)?.into(),
+                    node_to_expr(x).into(),
+                )
+                .into(),
+                node_to_expr(y).into(),
+            );
+            self.try_evaluate_in(&self.env, expr)
+        })
+    }
+    fn has_def(&self, name: &str) -> bool {
+        self.env.contains(name)
+    }
+    // Bindings go to a copy of the environment. Definitions are shared by Rc, so the copy is
+    // cheap and keeps the results cached so far; that is also why bound names can't have
+    // definitions, whose cached nodes would keep the bound values.
+    fn evaluate_with(&self, expr: &str, bindings: &[(&str, &str)]) -> Result<Node, EvalError> {
+        crate::eval::check_bindings(self, bindings)?;
+        let mut env = self.env.clone();
+        for (name, value) in bindings {
+            let e = Eval::parse_expr(&self.env, value)?;
+            env.define(name, e);
+        }
+        let expr = Eval::parse_expr(&env, expr)?;
+        self.try_evaluate_in(&env, expr)
     }
     fn add_def(&mut self, line: &str) {
-        let ss = line.split(" = ").collect::<Vec<_>>();
-        let (name, expr) = (ss[0], ss[1]);
-        let e = parse_string(&self.env, expr);
-        self.env.insert(name.to_string(), e);
+        self.env.define_line(line).unwrap();
+    }
+    fn try_add_def(&mut self, name: &str, expr: &str) -> anyhow::Result<()> {
+        self.env.insert_definition(name, expr)?;
+        Ok(())
+    }
+    fn gc(&self) {
+        self.env.gc()
+    }
+    fn collect(&self) {
+        expr::collect_if_grown()
+    }
+    fn heap_nodes(&self) -> Option<usize> {
+        Some(Eval::live_nodes())
+    }
+    fn env(&self) -> Option<&Env> {
+        Some(&self.env)
     }
 }
 
-fn expr_to_node(e: Expr) -> Node {
-    match e {
+pub(crate) fn node_to_expr(n: &Node) -> Expr {
+    match n {
+        Node::Nil => Expr::nil(),
+        Node::Cons(x, y) => Expr::cons(node_to_expr(x).into(), node_to_expr(y).into()),
+        Node::Num(x) => Num(*x),
+        Node::Big(x) => Big(Rc::new(x.clone())),
+    }
+}
+
+pub(crate) fn expr_to_node(e: Expr) -> Result<Node, EvalError> {
+    Ok(match e {
         Op(Primitive::Nil, None, _, _) => Node::Nil,
         Op(Primitive::Cons, Some(x0), Some(x1), None) => Node::Cons(
-            expr_to_node(x0.expr()).into(),
-            expr_to_node(x1.expr()).into(),
+            expr_to_node(x0.expr())?.into(),
+            expr_to_node(x1.expr())?.into(),
         ),
         Num(x) => Node::Num(x),
-        _ => panic!("unconvertible to node: {}", e),
-    }
+        Big(x) => Node::Big((*x).clone()),
+        _ => return Err(EvalError::NotAValue(abbreviate(&e, 80))),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::eval::Evaluator;
     use std::iter::FromIterator;
 
     #[test]
@@ -382,7 +1370,7 @@ mod tests {
             ("ap ap div -5 -3", "1"),
         ] {
             eprintln!("--- testing: {}", tc.0);
-            let env = default_env();
+            let env = Env::galaxy();
 
             let e1 = parse_string(&env, tc.0);
             let e2 = parse_string(&env, tc.1);
@@ -390,8 +1378,8 @@ mod tests {
             eprintln!("e1: {}", e1);
             eprintln!("e2: {}", e2);
 
-            let e1 = e1.reduce(&env);
-            let e2 = e2.reduce(&env);
+            let e1 = e1.reduce(&env).unwrap();
+            let e2 = e2.reduce(&env).unwrap();
 
             eprintln!("e1.eval: {}", e1);
             eprintln!("e2.eval: {}", e2);
@@ -399,4 +1387,670 @@ mod tests {
             assert_eq!(e1, e2);
         }
     }
+
+    #[test]
+    fn test_serde() {
+        let env = Env::galaxy();
+        for s in ["ap ap add 1 ap neg 2", ":1029", "ap ap cons x0 nil"] {
+            let e = parse_string(&env, s);
+            let json = serde_json::to_string(&e).unwrap();
+            assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), e, "{}", json);
+        }
+        assert_eq!(
+            serde_json::to_string(&parse_string(&env, "ap neg 1")).unwrap(),
+            r#"{"ap":[{"op":"neg"},{"num":1}]}"#
+        );
+
+        // Partially applied primitives come back as applications.
+        let e = parse_string(&env, "ap add 1").reduce(&env).unwrap();
+        let back: Expr = serde_json::from_str(&serde_json::to_string(&e).unwrap()).unwrap();
+        assert_eq!(back.to_string(), "ap add 1");
+        assert_eq!(Ap(back.into(), Num(2).into()).reduce(&env).unwrap(), Num(3));
+    }
+
+    #[test]
+    fn test_env() {
+        let mut env = Env::new();
+        assert!(env.is_empty());
+        env.define_line("x0 = ap ap add 1 2").unwrap();
+        env.define_line(":1 = ap ap mul x0 x0").unwrap();
+        assert!(env.define_line("x1").is_err());
+        assert_eq!(
+            format!("{:#}", env.define_line("x1 = undefined").unwrap_err()),
+            "defining x1: token 0: unknown var undefined"
+        );
+        assert_eq!(env.len(), 2);
+        assert_eq!(env.get("x0").unwrap().to_string(), "ap ap add 1 2");
+
+        let v = env.version();
+        let mut other = Env::new();
+        other.define("x0", Num(4));
+        env.merge(other);
+        assert!(env.version() > v);
+        assert_eq!(env.get("x0"), Some(&Num(4)));
+
+        let v = env.version();
+        assert!(env.remove("x2").is_none());
+        assert_eq!(env.version(), v);
+        assert!(env.remove("x0").is_some());
+        assert!(env.version() > v);
+        assert_eq!(env.iter().map(|(k, _)| k).collect::<Vec<_>>(), vec![":1"]);
+
+        let src_env = Env::from_source("pwr2 = ap ap s ap ap c ap eq 0 1 ap ap b ap mul 2 ap ap b pwr2 ap add -1\n\nmain = ap pwr2 n\nn = 10\n").unwrap();
+        assert_eq!(Eval::with_env(src_env).evaluate("main"), Node::Num(1024));
+        assert_eq!(
+            format!(
+                "{:#}",
+                Env::from_source("a = 1\nb = undefined").unwrap_err()
+            ),
+            "line 2: defining b: token 0: unknown var undefined"
+        );
+        assert_eq!(
+            format!("{:#}", Env::from_source("a = 1\nb").unwrap_err()),
+            "line 2: not a definition: \"b\""
+        );
+
+        let mut defs = Env::new();
+        assert!(defs.insert_definition("y", "ap y 1").unwrap().is_none());
+        assert!(defs.insert_definition("z", "ap w 1").is_err());
+        assert!(!defs.contains("z"));
+        assert_eq!(
+            defs.insert_definition("y", "2")
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "ap y 1"
+        );
+
+        let mut eval = Eval::with_env(env);
+        eval.env_mut().define("x0", Num(5));
+        assert_eq!(eval.evaluate(":1"), Node::Num(25));
+
+        let env = Env::from_source(":1 = ap ap add :2 :2\n:2 = ap neg :3\n:3 = 1\n:4 = ap :4 :3")
+            .unwrap();
+        assert_eq!(env.names(), [":1", ":2", ":3", ":4"]);
+        assert_eq!(env.source(":1").unwrap(), "ap ap add :2 :2");
+        assert_eq!(env.source(":5"), None);
+        let eval = Eval::with_env(env);
+        assert_eq!(eval.evaluate(":1"), Node::Num(-2));
+        let env = eval.env();
+        assert_eq!(env.source(":2").unwrap(), "ap neg :3");
+        assert_eq!(
+            env.reference_counts().into_iter().collect::<Vec<_>>(),
+            [
+                (":1".to_string(), 0),
+                (":2".to_string(), 1),
+                (":3".to_string(), 2),
+                (":4".to_string(), 1)
+            ]
+        );
+
+        let mut a = Annotations::new();
+        a.annotate(
+            ":2",
+            crate::annotations::Annotation::new("negate")
+                .description("negates :3")
+                .tag("arith"),
+        );
+        a.annotate(":3", crate::annotations::Annotation::new("one"));
+        assert_eq!(
+            env.describe(":2", &a).unwrap(),
+            ":2 (negate) [arith]\n  negates :3\n= (neg one)\nuses :3 (one)\nused by :1"
+        );
+        assert_eq!(
+            env.describe(":4", &a).unwrap(),
+            ":4\n= (:4 one)\nuses :3 (one), :4\nused by :4"
+        );
+        assert_eq!(env.describe(":5", &a), None);
+    }
+
+    #[test]
+    fn test_simplify() {
+        let mut env = Env::from_source(
+            "a = ap ap add -2 30\nb = ap ap cons ap neg 3 ap ap t x0 :1\nc = ap ap f ap ap div 1 0 ap isnil ap car ap ap cons nil 1\nd = ap ap mul 9223372036854775807 2\n:1 = ap ap div 1 0",
+        )
+        .unwrap();
+        assert_eq!(env.simplify(), 6);
+        let def = |env: &Env, name| env.get(name).unwrap().to_string();
+        assert_eq!(def(&env, "a"), "28");
+        assert_eq!(def(&env, "b"), "ap ap cons -3 x0");
+        assert_eq!(def(&env, "c"), "t");
+        assert_eq!(def(&env, "d"), "ap ap mul 9223372036854775807 2");
+        assert_eq!(def(&env, ":1"), "ap ap div 1 0");
+        assert_eq!(env.simplify(), 0);
+        // gc restores the simplified definitions.
+        let eval = Eval::with_env(env);
+        assert_eq!(eval.evaluate("a"), Node::Num(28));
+        eval.gc();
+        assert_eq!(def(eval.env(), "a"), "28");
+
+        // Clicks of the simplified galaxy take fewer steps to the same result.
+        let click = "ap ap galaxy nil ap ap cons 0 0";
+        let record = |env: Env| Eval::with_env(env).record(click, TraceLimits::default());
+        let plain = record(Env::from_source(include_str!("../galaxy.txt")).unwrap());
+        let simplified = record(Env::galaxy());
+        assert_eq!(simplified.result, plain.result);
+        assert!(simplified.total_steps < plain.total_steps);
+    }
+
+    #[test]
+    fn test_reachable() {
+        let mut env = Env::from_source(
+            "main = ap ap add :1 x0\n:1 = ap neg :2\n:2 = ap :1 :2\n:3 = ap :3 main",
+        )
+        .unwrap();
+        let deps = env.dependencies();
+        assert_eq!(deps["main"], BTreeSet::from_iter(vec![":1".to_string()]));
+        assert_eq!(
+            deps[":2"],
+            BTreeSet::from_iter(vec![":1".to_string(), ":2".to_string()])
+        );
+        assert_eq!(
+            env.reachable_from("main"),
+            BTreeSet::from_iter(vec!["main", ":1", ":2"].into_iter().map(String::from))
+        );
+        assert!(env.reachable_from("x0").is_empty());
+        assert_eq!(env.strip_unreachable("main"), vec![":3"]);
+        assert_eq!(env.len(), 3);
+
+        // Every definition of galaxy.txt is used by galaxy.
+        let mut galaxy = Env::galaxy();
+        assert_eq!(galaxy.reachable_from("galaxy").len(), galaxy.len());
+        galaxy.define_line("unused = ap galaxy nil").unwrap();
+        assert_eq!(galaxy.strip_unreachable("galaxy"), vec!["unused"]);
+        assert_eq!(
+            Eval::with_env(galaxy).evaluate("ap ap galaxy nil ap ap cons 0 0"),
+            Eval::new().evaluate("ap ap galaxy nil ap ap cons 0 0")
+        );
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut eval = Eval::new();
+        eval.add_def(":1 = ap ap add 1 nil");
+        eval.add_def(":2 = ap ap mul 2 :1");
+        for (expr, want) in [
+            ("ap car ap ap cons 1 nil", Ok(Node::Num(1))),
+            ("ap cdr ap ap cons 1 nil", Ok(Node::Nil)),
+            ("ap car 1", Err("car applied to 1")),
+            ("ap isnil ap add 1", Err("isnil applied to ap add 1")),
+            ("ap ap div 1 0", Err("div applied to 0")),
+            (":2", Err("add applied to nil in :2 > :1")),
+            (
+                "ap car",
+                Err("parse error: token 2: unexpected end of input"),
+            ),
+        ] {
+            assert_eq!(
+                eval.check(expr).map_err(|v| v.to_string()),
+                want.map_err(String::from),
+                "{}",
+                expr
+            );
+        }
+
+        let mut a = Annotations::new();
+        a.annotate(":1", crate::annotations::Annotation::new("bad_sum"));
+        eval.set_annotations(a);
+        assert_eq!(
+            eval.check(":2").unwrap_err().to_string(),
+            "add applied to nil in :2 > :1 (bad_sum)"
+        );
+
+        // galaxy itself passes the checks.
+        eval.set_checks(true);
+        let g = crate::protocol::G::new(Box::new(eval));
+        g.galaxy("nil".into(), 0, 0, "").unwrap();
+    }
+
+    #[test]
+    fn test_big() {
+        let eval = Eval::new();
+        for (expr, want) in [
+            ("ap neg -9223372036854775808", "9223372036854775808"),
+            ("ap ap div -9223372036854775808 -1", "9223372036854775808"),
+            ("ap ap add 9223372036854775807 1", "9223372036854775808"),
+            (
+                "ap ap mul 9223372036854775807 9223372036854775807",
+                "85070591730234615847396907784232501249",
+            ),
+            ("ap ap add 9223372036854775808 -1", "9223372036854775807"),
+            ("ap ap div -18446744073709551616 -3", "6148914691236517205"),
+            ("ap ap div -18446744073709551617 3", "-6148914691236517205"),
+            (
+                "ap ap ap ap eq 9223372036854775808 ap neg -9223372036854775808 1 0",
+                "1",
+            ),
+            ("ap ap ap ap lt -9223372036854775809 0 1 0", "1"),
+            ("ap ap ap ap lt 0 -9223372036854775809 1 0", "0"),
+        ] {
+            assert_eq!(
+                eval.try_evaluate(expr).unwrap().to_string(),
+                want,
+                "{}",
+                expr
+            );
+        }
+        assert_eq!(
+            eval.try_evaluate("ap neg -9223372036854775808"),
+            Ok(Node::Big(BigInt::from(i64::MAX) + 1))
+        );
+
+        let e = parse_string(&eval.env, "ap neg 9223372036854775808");
+        let json = serde_json::to_string(&e).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ap":[{"op":"neg"},{"big":"9223372036854775808"}]}"#
+        );
+        assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), e);
+    }
+
+    #[test]
+    fn test_message_primitives() {
+        let eval = Eval::new();
+        for (expr, want) in [
+            ("ap ap ap if0 0 1 2", "1"),
+            ("ap ap ap if0 1 1 2", "2"),
+            // The branch not taken isn't evaluated.
+            ("ap ap ap if0 ap ap add 1 -1 1 x0", "1"),
+            ("ap pwr2 0", "1"),
+            ("ap pwr2 8", "256"),
+            ("ap pwr2 64", "18446744073709551616"),
+            ("ap pwr2 -1", "0"),
+            ("ap ap checkerboard 1 0", "ap ap cons ap ap cons 0 0 nil"),
+            (
+                "ap ap checkerboard 2 0",
+                "ap ap cons ap ap cons 0 0 ap ap cons ap ap cons 1 1 nil",
+            ),
+            ("ap ap checkerboard 0 nil", "nil"),
+        ] {
+            let got = match eval.try_evaluate(expr) {
+                Ok(n) => n.to_string(),
+                Err(e) => e.to_string(),
+            };
+            assert_eq!(got, want, "{}", expr);
+        }
+        assert_eq!(
+            eval.try_evaluate("ap ap checkerboard 7 0")
+                .unwrap()
+                .must_list()
+                .len(),
+            25
+        );
+        assert_eq!(
+            eval.try_evaluate("ap pwr2 1000000000"),
+            Err(EvalError::Overflow(Primitive::Pwr2))
+        );
+        assert_eq!(Primitive::from_name("if0").unwrap().arity(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let eval = Eval::new();
+        for (expr, want) in [
+            ("ap ap", "parse error: token 2: unexpected end of input"),
+            ("foo", "parse error: token 0: unknown var foo"),
+            ("ap neg 1 2", "parse error: token 3: unused token \"2\""),
+            ("ap ( 1 , 2", "parse error: token 1: unclosed \"(\""),
+            (":99999", "undefined: :99999"),
+            ("ap ap add nil 1", "not a num: nil"),
+            ("ap 1 2", "not a function: 1"),
+            ("ap add 1", "unconvertible to node: ap add 1"),
+            ("ap isnil 1", "unconvertible to node: 1"),
+            ("ap ap div 1 0", "division by zero"),
+            ("ap ap div 9223372036854775808 0", "division by zero"),
+        ] {
+            assert_eq!(
+                eval.try_evaluate(expr).map_err(|e| e.to_string()),
+                Err(want.to_string()),
+                "{}",
+                expr
+            );
+        }
+        let g = crate::protocol::G::new(Box::new(eval));
+        assert_eq!(
+            g.interact(":99999", "nil".into(), 0, 0, "")
+                .unwrap_err()
+                .to_string(),
+            "unknown protocol: :99999"
+        );
+        assert_eq!(
+            g.interact("galaxy", "ap".into(), 0, 0, "")
+                .unwrap_err()
+                .to_string(),
+            "invalid state \"ap\": iterator exhausted"
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        use crate::{eval::EvalError, protocol::InteractError};
+        const CLICK: &str = "ap ap galaxy nil ap ap cons 0 0";
+
+        let mut eval = Eval::new();
+        let defined = Eval::live_nodes();
+        let want = eval.evaluate(CLICK);
+        // gc restores the definitions as they were before the evaluation cached results.
+        assert_ne!(Eval::live_nodes(), defined);
+        eval.gc();
+        assert_eq!(Eval::live_nodes(), defined);
+
+        eval.set_memory_limit(Some(defined));
+        assert_eq!(eval.try_evaluate(CLICK), Err(EvalError::MemoryLimit));
+        // The limit counts nodes of all evaluators on the thread, not only this one's.
+        eval.set_memory_limit(Some(defined * 10));
+        assert_eq!(eval.try_evaluate(CLICK), Ok(want));
+
+        eval.set_memory_limit(Some(Eval::live_nodes() / 2));
+        let g = crate::protocol::G::new(Box::new(eval));
+        match g.interact("galaxy", "nil".into(), 0, 0, "") {
+            Err(InteractError::Eval {
+                round: 0,
+                source: EvalError::MemoryLimit,
+            }) => (),
+            res => panic!("unexpected {:?}", res.map(|r| r.state)),
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        use crate::protocol::InteractError;
+        let mut eval = Eval::new();
+        eval.set_budget(Some(EvalBudget::reductions(100)));
+        assert_eq!(eval.try_evaluate("ap ap add 1 2"), Ok(Node::Num(3)));
+        assert_eq!(
+            eval.try_evaluate("ap ap galaxy nil ap ap cons 0 0"),
+            Err(EvalError::BudgetExceeded)
+        );
+        eval.set_budget(Some(EvalBudget::millis(0.0)));
+        assert_eq!(
+            eval.try_evaluate("ap ap galaxy nil ap ap cons 0 0"),
+            Err(EvalError::BudgetExceeded)
+        );
+        eval.set_budget(None);
+
+        let mut g = crate::protocol::G::new(Box::new(eval));
+        g.set_budget(Some(EvalBudget::reductions(1000)));
+        match g.interact("galaxy", "nil".into(), 0, 0, "") {
+            Err(InteractError::BudgetExceeded {
+                round: 0,
+                state,
+                sends,
+            }) => {
+                assert_eq!(state, Node::Nil);
+                assert!(sends.is_empty());
+            }
+            res => panic!("unexpected {:?}", res.map(|r| r.state)),
+        }
+        // Running out of the budget leaves the caches usable.
+        g.set_budget(Some(
+            EvalBudget::reductions(10_000_000).and_millis(60_000.0),
+        ));
+        g.galaxy("nil".into(), 0, 0, "").unwrap();
+    }
+
+    #[test]
+    fn test_cancel() {
+        use crate::{eval::CancellationToken, protocol::InteractError};
+        let eval = Eval::new();
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(
+            token.run(|| eval.try_evaluate("ap ap add 1 2")),
+            Err(EvalError::Cancelled)
+        );
+        assert_eq!(eval.try_evaluate("ap ap add 1 2"), Ok(Node::Num(3)));
+        token.reset();
+        assert_eq!(
+            token.run(|| eval.try_evaluate("ap ap add 1 2")),
+            Ok(Node::Num(3))
+        );
+
+        // Cancelling from another thread stops clicks that would run on.
+        let remote = token.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            remote.cancel();
+        });
+        let res = token.run(|| {
+            (0..).find_map(|i| {
+                eval.try_evaluate(&format!("ap ap galaxy nil ap ap cons {} 0", i))
+                    .err()
+            })
+        });
+        canceller.join().unwrap();
+        assert_eq!(res, Some(EvalError::Cancelled));
+
+        let g = crate::protocol::G::new(Box::new(eval));
+        g.cancel();
+        match g.interact("galaxy", "nil".into(), 0, 0, "") {
+            Err(InteractError::Cancelled { round: 0, .. }) => (),
+            res => panic!("unexpected {:?}", res.map(|r| r.state)),
+        }
+        // The next click runs.
+        assert!(!g.cancellation_token().is_cancelled());
+        g.galaxy("nil".into(), 0, 0, "").unwrap();
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel() {
+        let sequential = Eval::new();
+        let mut parallel = Eval::new();
+        parallel.set_parallel(true);
+        for expr in &[
+            "ap ap cons ap ap add 1 2 ap ap cons ap neg 4 nil",
+            "ap ap galaxy nil ap ap cons 0 0",
+            "ap ap galaxy ap ap cons 0 ap ap cons ap ap cons 0 nil ap ap cons 0 ap ap cons nil nil ap ap cons 8 4",
+            "ap ap cons 1 2",
+            "ap ap cons ap ap div 1 0 nil",
+        ] {
+            assert_eq!(
+                parallel.try_evaluate(expr),
+                sequential.try_evaluate(expr),
+                "{}",
+                expr
+            );
+        }
+
+        // New definitions are seen by the workers.
+        parallel.add_def("x = 5");
+        assert_eq!(
+            parallel.evaluate("ap ap cons x ap ap cons ap neg x nil"),
+            Node::list(vec![Node::Num(5), Node::Num(-5)])
+        );
+    }
+
+    #[test]
+    fn test_tracer() {
+        #[derive(Default)]
+        struct Log(RefCell<Vec<String>>);
+        impl Tracer for Log {
+            fn on_reduce(&self, p: Primitive, args: &[Expr]) {
+                let args = args.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                self.0
+                    .borrow_mut()
+                    .push(format!("{} {}", p, args.join(" ")));
+            }
+            fn on_var_lookup(&self, name: &str) {
+                self.0.borrow_mut().push(name.into());
+            }
+        }
+
+        let mut eval = Eval::new();
+        eval.add_def(":1 = ap ap add 1 2");
+        let log = Rc::new(Log::default());
+        eval.set_tracer(Some(log.clone()));
+        assert_eq!(eval.evaluate("ap neg :1"), Node::Num(-3));
+        assert_eq!(*log.0.borrow(), ["neg :1", ":1", "add 1 2"]);
+
+        eval.set_tracer(None);
+        eval.evaluate("ap neg 1");
+        assert_eq!(log.0.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut eval = Eval::new();
+        eval.add_def(":1 = ap ap add 1 2");
+        let trace = eval.record("ap ap mul :1 :1", TraceLimits::default());
+        assert_eq!(trace.result, Ok(Node::Num(9)));
+        assert_eq!(trace.steps.len(), trace.total_steps);
+        assert_eq!(trace.step(0).unwrap().expr, "ap ap mul :1 :1");
+        assert_eq!(trace.step(0).unwrap().def, None);
+        let in_def = trace
+            .steps_in(":1")
+            .map(|(_, s)| s.expr.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(in_def[0], "ap ap add 1 2");
+        assert!(in_def.contains(&"1") && in_def.contains(&"2"));
+
+        let limits = TraceLimits {
+            max_steps: 2,
+            max_expr_len: 8,
+        };
+        let again = eval.record("ap ap mul :1 :1", limits);
+        assert_eq!(again.total_steps, trace.total_steps);
+        assert_eq!(again.steps.len(), 2);
+        assert_eq!(again.step(0).unwrap().expr, "ap ap...");
+        assert!(again.step(2).is_none());
+
+        let failed = eval.record("ap car 1", TraceLimits::default());
+        assert!(failed.result.is_err());
+
+        let mut a = Annotations::new();
+        a.annotate(":1", crate::annotations::Annotation::new("three"));
+        let lines = trace.render(&a);
+        assert!(lines.starts_with("0 -: ap ap mul :1 :1\n"), "{}", lines);
+        assert!(lines.contains(" :1 (three): ap ap add 1 2\n"), "{}", lines);
+    }
+
+    #[test]
+    fn test_arena() {
+        let ap = |e: Expr| match e {
+            Ap(l, r) => (l, r),
+            e => panic!("{}", e),
+        };
+        let env = Env::new();
+        let (l, y) = ap(env.parse("ap ap add ap neg 1 ap neg 1").unwrap());
+        assert!(ap(l.expr()).1.ptr_eq(&y));
+        // Later expressions share with earlier ones too.
+        let (neg, one) = ap(env.parse("ap neg 1").unwrap());
+        assert!(neg.ptr_eq(&ap(y.expr()).0) && one.ptr_eq(&ap(y.expr()).1));
+
+        // Nodes referring to a redefined name aren't shared with later expressions.
+        let mut eval = Eval::with_env(Env::from_source("a = 1").unwrap());
+        assert_eq!(eval.try_evaluate("ap neg a"), Ok(Node::Num(-1)));
+        eval.env_mut().define_line("a = 2").unwrap();
+        assert_eq!(eval.try_evaluate("ap neg a"), Ok(Node::Num(-2)));
+    }
+
+    #[test]
+    fn test_loop() {
+        use crate::eval::EvalError;
+        let eval = Eval::with_env(
+            Env::from_source(
+                "x = ap neg x\ny = ap ap cons 0 y\nz = ap ap cons 0 ap car z\nw = ap ap t 1 x",
+            )
+            .unwrap(),
+        );
+        for expr in ["x", "y", "ap car ap cdr x"] {
+            // Nodes left by a failed evaluation fail the same way again.
+            for _ in 0..2 {
+                assert!(
+                    matches!(
+                        eval.try_evaluate(expr),
+                        Err(EvalError::DivergenceDetected { .. })
+                    ),
+                    "{}",
+                    expr
+                );
+            }
+        }
+        let definitions = |expr| match eval.try_evaluate(expr) {
+            Err(EvalError::DivergenceDetected { definitions, .. }) => definitions,
+            res => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(definitions("x"), ["x"]);
+        assert_eq!(definitions("y"), ["y"]);
+        assert_eq!(
+            eval.try_evaluate("ap neg x").unwrap_err().to_string(),
+            "infinite loop in x: x"
+        );
+        // Needing the head of a value while reducing it is fine, as is never needing a loop.
+        assert_eq!(
+            eval.try_evaluate("z"),
+            Ok(Node::Cons(Node::Num(0).into(), Node::Num(0).into()))
+        );
+        assert_eq!(eval.try_evaluate("w"), Ok(Node::Num(1)));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        use crate::eval::EvalError;
+        let mut eval = Eval::with_env(
+            Env::from_source(
+                "g = ap ap b g i
+h = ap ap b ap add 1 g
+:3 = ap ap s ap ap c ap eq 0 0 ap ap b ap add 1 ap ap b :3 ap add -1",
+            )
+            .unwrap(),
+        );
+        eval.set_max_depth(Some(1000));
+        match eval.try_evaluate("ap h 1") {
+            Err(EvalError::DivergenceDetected { definitions, .. }) => {
+                assert_eq!(definitions, ["g"]);
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(eval.try_evaluate("ap :3 10"), Ok(Node::Num(10)));
+        assert!(matches!(
+            eval.try_evaluate("ap :3 1000"),
+            Err(EvalError::DivergenceDetected { .. })
+        ));
+        eval.set_max_depth(None);
+        assert_eq!(eval.try_evaluate("ap :3 1000"), Ok(Node::Num(1000)));
+
+        let mut eval = Eval::new();
+        eval.set_max_depth(Some(5000));
+        assert!(eval.try_evaluate("ap ap galaxy nil ap ap cons 0 0").is_ok());
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let before = Eval::live_nodes();
+        let cycle = || {
+            let a = CachedExpr::from(Num(0));
+            let b = CachedExpr::from(Ap(a.clone(), a.clone()));
+            a.reset(Ap(b.clone(), b.clone()));
+            a
+        };
+        let held = cycle();
+        drop(cycle());
+        assert_eq!(Eval::live_nodes(), before + 4);
+        assert_eq!(Eval::collect_cycles(), 2);
+        assert_eq!(Eval::live_nodes(), before + 2);
+        assert_eq!(Eval::collect_cycles(), 0);
+        assert!(matches!(held.expr(), Ap(..)));
+        drop(held);
+        assert_eq!(Eval::collect_cycles(), 2);
+        assert_eq!(Eval::live_nodes(), before);
+
+        // Repeated evaluations of a definition referring to itself don't grow the heap.
+        let eval = Eval::with_env(Env::from_source("ones = ap ap cons 1 ones").unwrap());
+        assert_eq!(
+            eval.try_evaluate("ap car ap cdr ap cdr ones"),
+            Ok(Node::Num(1))
+        );
+        eval.gc();
+        Eval::collect_cycles();
+        let defined = Eval::live_nodes();
+        assert_eq!(
+            eval.try_evaluate("ap car ap cdr ap cdr ones"),
+            Ok(Node::Num(1))
+        );
+        eval.gc();
+        Eval::collect_cycles();
+        assert_eq!(Eval::live_nodes(), defined);
+    }
 }
@@ -0,0 +1,170 @@
+//! Detection of the regions of images that look clickable, for exploring a protocol without
+//! knowing where its buttons are.
+//!
+//! Numbers in alien glyphs are read first. The other cells of each layer are split into
+//! connected components, 8-neighbours included, as shapes in the galaxy are drawn with gaps
+//! between them. A component filling or outlining its bounding box is taken for a button, any
+//! other for a plain shape.
+
+use crate::render::Image;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+
+/// Target is the kind of region a Suggestion points at, from the most to the least likely to
+/// react to clicks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Target {
+    // a filled or outlined rectangle.
+    Rectangle,
+    // a number in alien glyphs. See glyph.
+    Glyph,
+    // any other connected shape.
+    Component,
+}
+
+/// Suggestion is a point worth clicking: a cell of a region, or the center of a rectangle,
+/// with the layer and bounds of the region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub x: i32,
+    pub y: i32,
+    pub kind: Target,
+    pub layer: usize,
+    // bounds of the region, (min_x, min_y, max_x, max_y) inclusive.
+    pub bounds: (i32, i32, i32, i32),
+}
+
+// The suggested points of the layers, by kind, then layer, then from top to bottom and left
+// to right. Each point is suggested once, for the first region having it.
+pub fn suggestions(layers: &[Image]) -> Vec<Suggestion> {
+    let mut res = vec![];
+    for (layer, img) in layers.iter().enumerate() {
+        // Glyphs are taken out first, as their bits needn't touch the rest of them.
+        let mut glyph_cells = HashSet::new();
+        for g in img.numbers() {
+            glyph_cells.extend(
+                (g.x..g.x + g.width).flat_map(|x| (g.y..g.y + g.height).map(move |y| (x, y))),
+            );
+            res.push(Suggestion {
+                x: g.x + g.width / 2,
+                y: g.y + g.height / 2,
+                kind: Target::Glyph,
+                layer,
+                bounds: (g.x, g.y, g.x + g.width - 1, g.y + g.height - 1),
+            });
+        }
+        let rest = (0..img.count())
+            .map(|i| img.point(i))
+            .map(|p| (p.x, p.y))
+            .filter(|p| !glyph_cells.contains(p));
+        res.extend(components(rest).iter().map(|cells| suggest(layer, cells)));
+    }
+    res.sort_by_key(|s| (s.kind, s.layer, s.y, s.x));
+    let mut seen = HashSet::new();
+    res.retain(|s| seen.insert((s.x, s.y)));
+    res
+}
+
+// The 8-connected components of cells, each sorted from top to bottom and left to right.
+pub fn components(cells: impl IntoIterator<Item = (i32, i32)>) -> Vec<Vec<(i32, i32)>> {
+    let mut left = cells
+        .into_iter()
+        .map(|(x, y)| (y, x))
+        .collect::<BTreeSet<_>>();
+    let mut res = vec![];
+    while let Some(&start) = left.iter().next() {
+        left.remove(&start);
+        let mut stack = vec![start];
+        let mut component = vec![];
+        while let Some((y, x)) = stack.pop() {
+            component.push((y, x));
+            for (dy, dx) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dy, dx))) {
+                if left.remove(&(y + dy, x + dx)) {
+                    stack.push((y + dy, x + dx));
+                }
+            }
+        }
+        component.sort_unstable();
+        res.push(component.into_iter().map(|(y, x)| (x, y)).collect());
+    }
+    res
+}
+
+fn suggest(layer: usize, cells: &[(i32, i32)]) -> Suggestion {
+    let xs = cells.iter().map(|c| c.0);
+    let ys = cells.iter().map(|c| c.1);
+    let (min_x, max_x) = (xs.clone().min().unwrap(), xs.max().unwrap());
+    let (min_y, max_y) = (ys.clone().min().unwrap(), ys.max().unwrap());
+    let (width, height) = (max_x - min_x + 1, max_y - min_y + 1);
+    let center = (min_x + width / 2, min_y + height / 2);
+    let set = cells.iter().copied().collect::<HashSet<_>>();
+    let outlined = (0..width)
+        .all(|dx| set.contains(&(min_x + dx, min_y)) && set.contains(&(min_x + dx, max_y)))
+        && (0..height)
+            .all(|dy| set.contains(&(min_x, min_y + dy)) && set.contains(&(max_x, min_y + dy)));
+    let n = cells.len() as i32;
+    let is_rectangle = width >= 2
+        && height >= 2
+        && outlined
+        && (n == 2 * (width + height) - 4 || n == width * height);
+    let (kind, (x, y)) = if is_rectangle {
+        (Target::Rectangle, center)
+    } else {
+        // The cell nearest to the center, as the shape may not cover it.
+        let nearest = *cells
+            .iter()
+            .min_by_key(|c| (c.0 - center.0).pow(2) + (c.1 - center.1).pow(2))
+            .unwrap();
+        (Target::Component, nearest)
+    };
+    Suggestion {
+        x,
+        y,
+        kind,
+        layer,
+        bounds: (min_x, min_y, max_x, max_y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestions() {
+        let mut points = vec![];
+        // A 3x3 outlined button at (0, 0), a filled 2x2 one on the next layer at (10, 0).
+        points.extend(
+            (0..3)
+                .flat_map(|x| (0..3).map(move |y| (x, y)))
+                .filter(|&p| p != (1, 1)),
+        );
+        // 5 in glyphs at (0, 10), with its blank corner.
+        points.extend(vec![(1, 10), (2, 10), (0, 11), (1, 11), (0, 12), (1, 12)]);
+        // An L at (10, 10).
+        points.extend(vec![(10, 10), (10, 11), (10, 12), (11, 12), (12, 12)]);
+        let layers = [
+            Image::from_points(&points),
+            Image::from_points(&[(10, 0), (11, 0), (10, 1), (11, 1)]),
+        ];
+        assert_eq!(
+            components(points.iter().map(|&(x, y)| (x as i32, y as i32))).len(),
+            3
+        );
+        let got = suggestions(&layers)
+            .into_iter()
+            .map(|s| (s.kind, s.layer, s.x, s.y))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                (Target::Rectangle, 0, 1, 1),
+                (Target::Rectangle, 1, 11, 1),
+                (Target::Glyph, 0, 1, 11),
+                (Target::Component, 0, 10, 11),
+            ]
+        );
+        assert!(suggestions(&[]).is_empty());
+    }
+}
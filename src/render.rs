@@ -0,0 +1,363 @@
+//! Decoding of the images protocols draw.
+
+use crate::ast::Node;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    img: Vec<Point>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Image {
+    pub fn count(&self) -> usize {
+        self.img.len()
+    }
+    pub fn point(&self, i: usize) -> Point {
+        self.img[i]
+    }
+}
+
+impl Image {
+    // The points as text for printing frames in terminals. Each character covers scale
+    // columns and 2 * scale rows of cells: ▀ is the upper half, ▄ the lower and █ both.
+    pub fn to_ascii(&self, scale: usize) -> String {
+        let (cells, width, height) = raster(std::slice::from_ref(self), scale);
+        (0..height)
+            .step_by(2)
+            .map(|y| {
+                let line = (0..width)
+                    .map(
+                        |x| match (cells.contains_key(&(x, y)), cells.contains_key(&(x, y + 1))) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                    )
+                    .collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The smallest and largest coordinates of the points, (min_x, min_y, max_x, max_y), all
+    // inclusive, or None if there are no points.
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        common_bounds(std::slice::from_ref(self))
+    }
+
+    // self moved by (dx, dy), e.g. by the negated minimums of bounds to start at (0, 0).
+    pub fn translate(&self, dx: i32, dy: i32) -> Image {
+        Image {
+            img: self
+                .img
+                .iter()
+                .map(|p| Point {
+                    x: p.x + dx,
+                    y: p.y + dy,
+                })
+                .collect(),
+        }
+    }
+
+    // The cells of bounds, rows from min_y down and columns from min_x right, true where a
+    // point is. Empty for an image without points.
+    pub fn to_matrix(&self) -> Result<Vec<Vec<bool>>> {
+        let bitmap = self.to_bitmap()?;
+        Ok((0..bitmap.height)
+            .map(|y| (0..bitmap.width).map(|x| bitmap.get(x, y)).collect())
+            .collect())
+    }
+
+    // to_matrix packed into bits. Fails if bounds has more than MAX_BITMAP_CELLS cells, as
+    // for points far apart.
+    pub fn to_bitmap(&self) -> Result<Bitmap> {
+        let (min_x, min_y, max_x, max_y) = match self.bounds() {
+            Some(b) => b,
+            None => return Ok(Bitmap::default()),
+        };
+        let width = (max_x as i64 - min_x as i64 + 1) as usize;
+        let height = (max_y as i64 - min_y as i64 + 1) as usize;
+        let cells = match width.checked_mul(height) {
+            Some(cells) if cells <= MAX_BITMAP_CELLS => cells,
+            _ => bail!(
+                "image of {}x{} cells is too large for a bitmap",
+                width,
+                height
+            ),
+        };
+        let mut words = vec![0; cells.div_ceil(64)];
+        for p in &self.img {
+            let i = (p.y - min_y) as usize * width + (p.x - min_x) as usize;
+            words[i / 64] |= 1 << (i % 64);
+        }
+        Ok(Bitmap {
+            width,
+            height,
+            words,
+        })
+    }
+
+    // The points of self not in previous, and those of previous not in self, each in the
+    // order of its image.
+    pub fn diff(&self, previous: &Image) -> ImageDiff {
+        let only = |a: &Image, b: &Image| {
+            let b = b.img.iter().map(|p| (p.x, p.y)).collect::<HashSet<_>>();
+            a.img
+                .iter()
+                .filter(|p| !b.contains(&(p.x, p.y)))
+                .copied()
+                .collect()
+        };
+        ImageDiff {
+            added: only(self, previous),
+            removed: only(previous, self),
+        }
+    }
+
+    pub(crate) fn from_points(points: &[(i64, i64)]) -> Image {
+        Image {
+            img: points
+                .iter()
+                .map(|p| Point {
+                    x: p.0 as _,
+                    y: p.1 as _,
+                })
+                .collect(),
+        }
+    }
+}
+
+// The most cells Image::to_bitmap allocates, 128 MiB of words.
+pub const MAX_BITMAP_CELLS: usize = 1 << 30;
+
+/// Bitmap is an image as a width x height grid of bits over its bounds, row-major from the
+/// top-left, 64 cells to a word starting from the least significant bit.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub words: Vec<u64>,
+}
+
+impl Bitmap {
+    // Whether the cell at column x and row y is set. False outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let i = y * self.width + x;
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+}
+
+/// ImageDiff is the change of an image from a previous one, e.g. of a layer between clicks.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageDiff {
+    added: Vec<Point>,
+    removed: Vec<Point>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl ImageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+    // The added points as x0, y0, x1, y1, ..., like InteractResult::image_flat.
+    pub fn added_flat(&self) -> Vec<i32> {
+        flat(&self.added)
+    }
+    pub fn removed_flat(&self) -> Vec<i32> {
+        flat(&self.removed)
+    }
+}
+
+fn flat(points: &[Point]) -> Vec<i32> {
+    let mut res = Vec::with_capacity(2 * points.len());
+    for p in points {
+        res.extend([p.x, p.y]);
+    }
+    res
+}
+
+impl ImageDiff {
+    pub fn added(&self) -> &[Point] {
+        &self.added
+    }
+    pub fn removed(&self) -> &[Point] {
+        &self.removed
+    }
+}
+
+// The bounds of all points of images together, like Image::bounds, so that layers drawn on
+// top of each other are scaled and centered alike.
+pub fn common_bounds(images: &[Image]) -> Option<(i32, i32, i32, i32)> {
+    let mut points = images.iter().flat_map(|img| img.img.iter());
+    let first = points.next()?;
+    Some(points.fold(
+        (first.x, first.y, first.x, first.y),
+        |(min_x, min_y, max_x, max_y), p| {
+            (
+                min_x.min(p.x),
+                min_y.min(p.y),
+                max_x.max(p.x),
+                max_y.max(p.y),
+            )
+        },
+    ))
+}
+
+// Symbols of the images drawn by layers_to_ascii, cycled if there are more images.
+const LAYER_SYMBOLS: [char; 6] = ['#', '*', '+', 'o', '=', '.'];
+
+// The images as text, one character per scale x scale cells, each showing the symbol of the
+// first image having a point there, like the frames a protocol draws on top of each other.
+pub fn layers_to_ascii(images: &[Image], scale: usize) -> String {
+    let (cells, width, height) = raster(images, scale);
+    (0..height)
+        .map(|y| {
+            let line = (0..width)
+                .map(|x| match cells.get(&(x, y)) {
+                    Some(i) => LAYER_SYMBOLS[i % LAYER_SYMBOLS.len()],
+                    None => ' ',
+                })
+                .collect::<String>();
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The points of images shrunk by scale and moved so that the top-left one is at (0, 0),
+// mapped to the index of the first image having them, and the width and height they span.
+fn raster(images: &[Image], scale: usize) -> (HashMap<(usize, usize), usize>, usize, usize) {
+    let scale = scale.max(1) as i64;
+    let (min_x, min_y) = match common_bounds(images) {
+        Some((x, y, _, _)) => (x as i64, y as i64),
+        None => return (HashMap::new(), 0, 0),
+    };
+    let mut cells = HashMap::new();
+    let (mut width, mut height) = (0, 0);
+    for (i, img) in images.iter().enumerate() {
+        for p in &img.img {
+            let x = ((p.x as i64 - min_x) / scale) as usize;
+            let y = ((p.y as i64 - min_y) / scale) as usize;
+            cells.entry((x, y)).or_insert(i);
+            width = width.max(x + 1);
+            height = height.max(y + 1);
+        }
+    }
+    (cells, width, height)
+}
+
+impl Node {
+    pub(crate) fn as_point(&self) -> Option<(i64, i64)> {
+        match self {
+            Node::Cons(x, y) => match (x.as_ref(), y.as_ref()) {
+                (Node::Num(x), Node::Num(y)) => Some((*x, *y)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    // Decodes a list of images, each a list of points.
+    pub(crate) fn as_images(&self) -> Option<Vec<Vec<(i64, i64)>>> {
+        self.as_list()?
+            .into_iter()
+            .map(|l| {
+                let mut img = l
+                    .as_list()?
+                    .into_iter()
+                    .map(Node::as_point)
+                    .collect::<Option<Vec<_>>>()?;
+                img.sort();
+                Some(img)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        let square = Image::from_points(&[(-1, -1), (0, -1), (-1, 0), (0, 0), (1, 1)]);
+        assert_eq!(square.to_ascii(1), "██\n  ▀");
+        assert_eq!(square.to_ascii(2), "▀▄");
+        assert_eq!(Image::from_points(&[]).to_ascii(1), "");
+
+        let dot = Image::from_points(&[(0, 0)]);
+        assert_eq!(layers_to_ascii(&[dot, square.clone()], 1), "**\n*#\n  *");
+        assert_eq!(layers_to_ascii(&[], 1), "");
+    }
+
+    #[test]
+    fn test_bounds() {
+        let img = Image::from_points(&[(3, -1), (-2, 4), (0, 0)]);
+        assert_eq!(img.bounds(), Some((-2, -1, 3, 4)));
+        assert_eq!(Image::from_points(&[]).bounds(), None);
+        let moved = img.translate(2, 1);
+        assert_eq!(moved.bounds(), Some((0, 0, 5, 5)));
+        assert_eq!(moved.point(0), Point { x: 5, y: 0 });
+
+        let dot = Image::from_points(&[(10, -5)]);
+        assert_eq!(common_bounds(&[img, dot]), Some((-2, -5, 10, 4)));
+        assert_eq!(common_bounds(&[]), None);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let img = Image::from_points(&[(-1, 5), (1, 5), (0, 6), (1, 6)]);
+        assert_eq!(
+            img.to_matrix().unwrap(),
+            [vec![true, false, true], vec![false, true, true]]
+        );
+        let bitmap = img.to_bitmap().unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (3, 2));
+        assert_eq!(bitmap.words, [0b110101]);
+        assert!(!bitmap.get(3, 0));
+        assert!(Image::from_points(&[]).to_matrix().unwrap().is_empty());
+
+        // Images wider than a word.
+        let line = Image::from_points(&(0..100).map(|x| (x, 0)).collect::<Vec<_>>());
+        let bitmap = line.to_bitmap().unwrap();
+        assert_eq!(bitmap.words.len(), 2);
+        assert!((0..100).all(|x| bitmap.get(x, 0)));
+
+        let far = Image::from_points(&[
+            (i32::MIN.into(), i32::MIN.into()),
+            (i32::MAX.into(), i32::MAX.into()),
+        ]);
+        let err = far.to_bitmap().unwrap_err();
+        assert!(err.to_string().contains("too large"), "{}", err);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = Image::from_points(&[(0, 0), (1, 0), (2, 0)]);
+        let after = Image::from_points(&[(1, 0), (2, 0), (2, 1), (3, 1)]);
+        let diff = after.diff(&before);
+        assert_eq!(diff.added_flat(), [2, 1, 3, 1]);
+        assert_eq!(diff.removed(), [Point { x: 0, y: 0 }]);
+        assert_eq!(before.diff(&after).removed_flat(), [2, 1, 3, 1]);
+        assert!(after.diff(&after).is_empty());
+    }
+}
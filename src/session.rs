@@ -0,0 +1,666 @@
+use crate::{
+    ast::Node,
+    graph::StateGraph,
+    net::{CachingSender, ReplaySender},
+    protocol::{InteractObserver, InteractResult, G},
+    render::Image,
+};
+
+use anyhow::*;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Session keeps the current state of a protocol and the clicks performed so far.
+pub struct Session {
+    g: G,
+    protocol: String,
+    api_key: String,
+    state: String,
+    clicks: Vec<(i32, i32)>,
+    history: Vec<ClickRecord>,
+    checkpoint: Option<Checkpointer>,
+}
+
+/// ClickRecord is a click performed through a Session: the state it started from, where it
+/// clicked, the exchanges with the aliens, the resulting state and the images it drew.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClickRecord {
+    pub from: String,
+    pub x: i32,
+    pub y: i32,
+    pub sends: Vec<(Node, Node)>,
+    pub to: String,
+    // missing in histories saved before images were recorded.
+    #[serde(default)]
+    pub images: Vec<Vec<(i64, i64)>>,
+}
+
+// Version of the files written by Session::save, bumped on incompatible changes.
+const SAVE_VERSION: u32 = 1;
+
+// The contents of a file written by Session::save, before compression.
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    version: u32,
+    protocol: String,
+    state: String,
+    clicks: Vec<(i32, i32)>,
+    history: Vec<ClickRecord>,
+}
+
+/// History is the clicks of a Session, saved as JSON to reproduce interaction bugs:
+///
+/// ```json
+/// {"protocol": "galaxy",
+///  "clicks": [{"from": "nil", "x": 0, "y": 0, "sends": [], "to": "ap ap cons 0 ..."}]}
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub protocol: String,
+    pub clicks: Vec<ClickRecord>,
+}
+
+/// How History::replay answers the requests of the clicks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReplaySends {
+    /// With the recorded responses, failing if a click sends something else or a different
+    /// number of times.
+    Mock,
+    /// With the sender of the G, e.g. re-issuing them to the aliens.
+    Resend,
+}
+
+impl History {
+    pub fn load(path: &Path) -> Result<Self> {
+        let s =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_json(&s).with_context(|| format!("parsing {}", path.display()))
+    }
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, self.to_json()).with_context(|| format!("writing {}", path.display()))
+    }
+    pub fn from_json(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // The clicks as a StateGraph, e.g. for to_dot. Frames aren't recorded, so every edge has
+    // the frame hash of no images.
+    pub fn graph(&self) -> Result<StateGraph> {
+        let mut res = StateGraph::new();
+        for (i, c) in self.clicks.iter().enumerate() {
+            let parse = |s: &str| -> Result<Node> {
+                s.parse()
+                    .with_context(|| format!("click {}: bad state {:?}", i + 1, s))
+            };
+            res.add(
+                &parse(&c.from)?,
+                (c.x as i64, c.y as i64),
+                &parse(&c.to)?,
+                &[],
+            );
+        }
+        Ok(res)
+    }
+
+    // Performs the clicks again with g, each from its recorded state, and fails on the first
+    // one ending in a different state or drawing different images than recorded. Clicks
+    // recorded without images, as in histories saved before images were, only have their
+    // states checked. With ReplaySends::Mock, g's sender is replaced.
+    pub fn replay(&self, g: G, sends: ReplaySends) -> Result<Session> {
+        let start = self.clicks.first().map_or("nil", |c| c.from.as_str());
+        let mut session = Session::new(g, &self.protocol, start.into());
+        for (i, c) in self.clicks.iter().enumerate() {
+            if sends == ReplaySends::Mock {
+                session
+                    .g
+                    .set_sender(Box::new(ReplaySender::new(c.sends.clone())));
+            }
+            session.state = c.from.clone();
+            let res = session.click(c.x, c.y)?;
+            if sends == ReplaySends::Mock && res.sends.len() != c.sends.len() {
+                bail!(
+                    "click {} at ({}, {}) made {} sends, recorded {}",
+                    i + 1,
+                    c.x,
+                    c.y,
+                    res.sends.len(),
+                    c.sends.len()
+                );
+            }
+            if res.state != c.to {
+                bail!(
+                    "click {} at ({}, {}) diverged: recorded {}, replayed {}",
+                    i + 1,
+                    c.x,
+                    c.y,
+                    c.to,
+                    res.state
+                );
+            }
+            if let Some(diff) = frame_diff(&c.images, &res.images) {
+                bail!(
+                    "click {} at ({}, {}) drew different images: {}",
+                    i + 1,
+                    c.x,
+                    c.y,
+                    diff
+                );
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// Where and how often a session writes checkpoints.
+/// Files are rotated among `keep` slots named `checkpoint.<slot>.txt` in `dir`.
+#[derive(Clone, Debug)]
+pub struct CheckpointConfig {
+    pub dir: PathBuf,
+    pub every_clicks: Option<usize>,
+    pub every: Option<Duration>,
+    pub keep: usize,
+}
+
+impl CheckpointConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        CheckpointConfig {
+            dir: dir.into(),
+            every_clicks: None,
+            every: None,
+            keep: 3,
+        }
+    }
+    pub fn every_clicks(mut self, n: usize) -> Self {
+        self.every_clicks = Some(n);
+        self
+    }
+    pub fn every(mut self, d: Duration) -> Self {
+        self.every = Some(d);
+        self
+    }
+    pub fn keep(mut self, n: usize) -> Self {
+        self.keep = n.max(1);
+        self
+    }
+
+    fn path(&self, slot: usize) -> PathBuf {
+        self.dir.join(format!("checkpoint.{}.txt", slot))
+    }
+
+    // Returns the most recent checkpoint file in dir, if any.
+    pub fn latest(&self) -> Option<PathBuf> {
+        (0..self.keep)
+            .map(|slot| self.path(slot))
+            .filter_map(|p| Some((read_seq(&p).ok()?, p)))
+            .max_by_key(|(seq, _)| *seq)
+            .map(|(_, p)| p)
+    }
+}
+
+/// Preset is a well-known galaxy state a session can jump to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preset {
+    /// The initial state before the countdown.
+    Start,
+    /// The galaxy's main hub, skipping the countdown and all tutorial stages.
+    PostTutorial,
+}
+
+impl Preset {
+    pub fn state(&self) -> &'static str {
+        match self {
+            Preset::Start => "nil",
+            // Stage 1 with the tutorial counter set to 11 is the state galaxy moves to after
+            // the last tutorial stage is cleared. No alien sends are needed from here on.
+            Preset::PostTutorial => {
+                "ap ap cons 1 ap ap cons ap ap cons 11 nil ap ap cons 0 ap ap cons nil nil"
+            }
+        }
+    }
+}
+
+struct Checkpointer {
+    config: CheckpointConfig,
+    seq: usize,
+    clicks_since: usize,
+    last: Instant,
+}
+
+impl Session {
+    pub fn new(g: G, protocol: &str, state: String) -> Self {
+        Session {
+            g,
+            protocol: protocol.into(),
+            api_key: String::new(),
+            state,
+            clicks: vec![],
+            history: vec![],
+            checkpoint: None,
+        }
+    }
+
+    pub fn set_api_key(&mut self, api_key: &str) {
+        self.api_key = api_key.into();
+    }
+
+    pub fn enable_checkpoint(&mut self, config: CheckpointConfig) -> Result<()> {
+        if config.keep == 0 {
+            bail!("checkpoint keep must be at least 1");
+        }
+        std::fs::create_dir_all(&config.dir)
+            .with_context(|| format!("creating {}", config.dir.display()))?;
+        let seq = config
+            .latest()
+            .and_then(|p| read_seq(&p).ok())
+            .map_or(0, |s| s + 1);
+        self.checkpoint = Some(Checkpointer {
+            config,
+            seq,
+            clicks_since: 0,
+            last: Instant::now(),
+        });
+        Ok(())
+    }
+
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+    pub fn clicks(&self) -> &[(i32, i32)] {
+        &self.clicks
+    }
+    // The clicks performed through this session, excluding ones restored from a checkpoint.
+    pub fn history(&self) -> History {
+        History {
+            protocol: self.protocol.clone(),
+            clicks: self.history.clone(),
+        }
+    }
+
+    pub fn click(&mut self, x: i32, y: i32) -> Result<InteractResult> {
+        self.click_with(x, y, &mut ())
+    }
+
+    // Like click, reporting the progress of the click to observer as it happens.
+    pub fn click_with(
+        &mut self,
+        x: i32,
+        y: i32,
+        observer: &mut dyn InteractObserver,
+    ) -> Result<InteractResult> {
+        let res = self
+            .g
+            .interact_with(
+                &self.protocol,
+                self.state.clone(),
+                x,
+                y,
+                &self.api_key,
+                observer,
+            )
+            .with_context(|| format!("click {} at ({}, {})", self.clicks.len() + 1, x, y))?;
+        self.history.push(ClickRecord {
+            from: std::mem::replace(&mut self.state, res.state.clone()),
+            x,
+            y,
+            sends: res.sends.clone(),
+            to: res.state.clone(),
+            images: res.images.clone(),
+        });
+        self.clicks.push((x, y));
+
+        if let Some(c) = self.checkpoint.as_mut() {
+            c.clicks_since += 1;
+            let due = c.config.every_clicks.is_some_and(|n| c.clicks_since >= n)
+                || c.config.every.is_some_and(|d| c.last.elapsed() >= d);
+            if due {
+                self.checkpoint().context("writing a checkpoint")?;
+            }
+        }
+        Ok(res)
+    }
+
+    // Starts the session from the preset state and clicks outside of the screen to render
+    // it. The clicks and the history then lead from the preset, so it has to come before any
+    // click. Only meaningful for the galaxy protocol.
+    pub fn fast_forward(&mut self, preset: Preset) -> Result<InteractResult> {
+        if self.protocol != "galaxy" {
+            bail!(
+                "fast_forward needs the galaxy protocol, got {}",
+                self.protocol
+            );
+        }
+        if !self.clicks.is_empty() {
+            bail!(
+                "fast_forward after {} clicks; it must start the session",
+                self.clicks.len()
+            );
+        }
+        self.state = preset.state().into();
+        self.click(-1000, -1000)
+    }
+
+    // Writes a checkpoint now, regardless of the configured interval.
+    pub fn checkpoint(&mut self) -> Result<PathBuf> {
+        let c = self
+            .checkpoint
+            .as_mut()
+            .ok_or_else(|| anyhow!("checkpoint is not enabled"))?;
+        let path = c.config.path(c.seq % c.config.keep);
+
+        let mut contents = format!(
+            "# checkpoint {}\n{}\n{}\n",
+            c.seq, self.protocol, self.state
+        );
+        for (x, y) in self.clicks.iter() {
+            contents.push_str(&format!("{} {}\n", x, y));
+        }
+        write_atomic(&path, &contents).with_context(|| format!("writing {}", path.display()))?;
+
+        c.seq += 1;
+        c.clicks_since = 0;
+        c.last = Instant::now();
+        Ok(path)
+    }
+
+    // Restores a session from a checkpoint file written by checkpoint().
+    pub fn restore(g: G, path: &Path) -> Result<Self> {
+        let s =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut lines = s.lines();
+        parse_header(lines.next().unwrap_or_default())
+            .with_context(|| format!("{}:1", path.display()))?;
+        let protocol = lines.next().ok_or_else(|| anyhow!("missing protocol"))?;
+        let state = lines.next().ok_or_else(|| anyhow!("missing state"))?;
+        let mut session = Session::new(g, protocol, state.into());
+        // Clicks start at the 4th line, after the header, protocol and state.
+        for (i, line) in lines.enumerate() {
+            let click =
+                parse_click(line).with_context(|| format!("{}:{}", path.display(), i + 4))?;
+            session.clicks.push(click);
+        }
+        Ok(session)
+    }
+
+    // Writes everything needed to resume the session on another machine: the protocol, the
+    // state, the clicks and the history with its images and alien responses, as gzipped
+    // JSON. The API key isn't saved.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, self.to_bytes()).with_context(|| format!("writing {}", path.display()))
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let saved = SavedSession {
+            version: SAVE_VERSION,
+            protocol: self.protocol.clone(),
+            state: self.state.clone(),
+            clicks: self.clicks.clone(),
+            history: self.history.clone(),
+        };
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        serde_json::to_writer(&mut gz, &saved).unwrap();
+        gz.finish().unwrap()
+    }
+
+    // Resumes a session written by save with g. Requests in the history are answered with the
+    // recorded responses instead of going through g's sender.
+    pub fn load(g: G, path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_bytes(g, &bytes).with_context(|| format!("loading {}", path.display()))
+    }
+    pub fn from_bytes(mut g: G, bytes: &[u8]) -> Result<Self> {
+        let mut json = String::new();
+        GzDecoder::new(bytes)
+            .read_to_string(&mut json)
+            .context("decompressing")?;
+        let saved: SavedSession = serde_json::from_str(&json)?;
+        if saved.version != SAVE_VERSION {
+            bail!(
+                "unsupported version {}, want {}",
+                saved.version,
+                SAVE_VERSION
+            );
+        }
+        let sends = saved
+            .history
+            .iter()
+            .flat_map(|c| c.sends.clone())
+            .collect::<Vec<_>>();
+        g.wrap_sender(|inner| Box::new(CachingSender::new(inner).with_responses(sends)));
+        let mut session = Session::new(g, &saved.protocol, saved.state);
+        session.clicks = saved.clicks;
+        session.history = saved.history;
+        Ok(session)
+    }
+}
+
+// How replayed images differ from recorded ones, or None if they don't or none were
+// recorded.
+fn frame_diff(recorded: &[Vec<(i64, i64)>], replayed: &[Vec<(i64, i64)>]) -> Option<String> {
+    if recorded.is_empty() || recorded == replayed {
+        return None;
+    }
+    if recorded.len() != replayed.len() {
+        return Some(format!(
+            "{} layers, recorded {}",
+            replayed.len(),
+            recorded.len()
+        ));
+    }
+    let (i, diff) = recorded
+        .iter()
+        .zip(replayed)
+        .map(|(a, b)| Image::from_points(b).diff(&Image::from_points(a)))
+        .enumerate()
+        .find(|(_, d)| !d.is_empty())?;
+    Some(format!(
+        "layer {} has {} points more and {} less",
+        i,
+        diff.added().len(),
+        diff.removed().len()
+    ))
+}
+
+// Writes to a temporary file first so a crash never leaves a truncated file.
+fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut f = std::fs::File::create(&tmp)?;
+    f.write_all(contents.as_ref())?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn parse_click(line: &str) -> Result<(i32, i32)> {
+    let v = line
+        .split(' ')
+        .map(|s| s.parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("malformed click: {:?}", line))?;
+    if v.len() != 2 {
+        bail!("malformed click: {:?}", line);
+    }
+    Ok((v[0], v[1]))
+}
+
+fn read_seq(path: &Path) -> Result<usize> {
+    let s = std::fs::read_to_string(path)?;
+    parse_header(s.lines().next().unwrap_or_default())
+        .with_context(|| format!("not a checkpoint: {:?}", path))
+}
+
+// Parses the first line of a checkpoint, "# checkpoint <seq>", returning seq.
+fn parse_header(line: &str) -> Result<usize> {
+    line.strip_prefix("# checkpoint ")
+        .and_then(|seq| seq.parse().ok())
+        .ok_or_else(|| anyhow!("malformed checkpoint header: {:?}", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::Evaluator, reduce_evaluator, testutil::STATEFULDRAW};
+
+    fn new_g() -> G {
+        let mut eval = reduce_evaluator::Eval::new();
+        eval.add_def(STATEFULDRAW);
+        G::new(Box::new(eval))
+    }
+
+    #[test]
+    fn test_checkpoint_rotation() {
+        let dir = std::env::temp_dir().join(format!("session_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = CheckpointConfig::new(&dir).every_clicks(2).keep(2);
+
+        let mut session = Session::new(new_g(), ":67108929", "nil".into());
+        session.enable_checkpoint(config.clone()).unwrap();
+
+        assert_eq!(config.latest(), None);
+        for i in 0..5 {
+            session.click(i, 0).unwrap();
+        }
+        // Checkpoints were written after the 2nd and 4th clicks, into slots 0 and 1.
+        let latest = config.latest().unwrap();
+        assert_eq!(latest, config.path(1));
+
+        let restored = Session::restore(new_g(), &latest).unwrap();
+        assert_eq!(restored.clicks(), &[(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(
+            restored.state(),
+            "ap ap cons ap ap cons 3 0 ap ap cons ap ap cons 2 0 ap ap cons ap ap cons 1 0 ap ap cons ap ap cons 0 0 nil"
+        );
+
+        let broken = dir.join("broken.txt");
+        std::fs::write(&broken, "# checkpoint 0\n:67108929\nnil\n0 0\n1\n").unwrap();
+        assert_eq!(
+            format!("{:#}", Session::restore(new_g(), &broken).err().unwrap()),
+            format!("{}:5: malformed click: \"1\"", broken.display())
+        );
+        std::fs::write(&broken, ":67108929\nnil\n0 0\n").unwrap();
+        assert_eq!(
+            format!("{:#}", Session::restore(new_g(), &broken).err().unwrap()),
+            format!(
+                "{}:1: malformed checkpoint header: \":67108929\"",
+                broken.display()
+            )
+        );
+
+        let mut zero = config.clone();
+        zero.keep = 0;
+        assert!(session.enable_checkpoint(zero).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("session_save_{}.gz", std::process::id()));
+        let mut session = Session::new(new_g(), ":67108929", "nil".into());
+        session.click(1, 2).unwrap();
+        session.click(3, 4).unwrap();
+        session.save(&path).unwrap();
+
+        let mut loaded = Session::load(new_g(), &path).unwrap();
+        assert_eq!(loaded.state(), session.state());
+        assert_eq!(loaded.clicks(), session.clicks());
+        assert_eq!(loaded.history(), session.history());
+        assert_eq!(loaded.history().clicks[1].images, [vec![(1, 2), (3, 4)]]);
+        assert_eq!(
+            loaded.click(5, 6).unwrap().state,
+            session.click(5, 6).unwrap().state
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let err = Session::from_bytes(new_g(), b"{}").err().unwrap();
+        assert_eq!(err.to_string(), "decompressing");
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let g = G::new(Box::new(reduce_evaluator::Eval::new()));
+        let mut session = Session::new(g, "galaxy", "nil".into());
+        let res = session.fast_forward(Preset::PostTutorial).unwrap();
+        assert_eq!(
+            session.state(),
+            "ap ap cons 2 ap ap cons ap ap cons 1 ap ap cons -1 nil ap ap cons 0 ap ap cons nil nil"
+        );
+        assert_eq!(res.images.len(), 4);
+        assert!(session.fast_forward(Preset::PostTutorial).is_err());
+
+        let history = session.history();
+        assert_eq!(history.clicks[0].from, Preset::PostTutorial.state());
+        let g = G::new(Box::new(reduce_evaluator::Eval::new()));
+        let replayed = history.replay(g, ReplaySends::Mock).unwrap();
+        assert_eq!(replayed.state(), session.state());
+
+        let mut session = Session::new(new_g(), ":67108929", "nil".into());
+        assert!(session.fast_forward(Preset::PostTutorial).is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut session = Session::new(new_g(), ":67108929", "nil".into());
+        session.click(1, 2).unwrap();
+        session.click(3, 4).unwrap();
+        let history = session.history();
+        assert_eq!(history.clicks.len(), 2);
+        assert_eq!(history.clicks[1].from, history.clicks[0].to);
+        assert_eq!(history.clicks[1].to, session.state());
+
+        let graph = history.graph().unwrap();
+        assert_eq!(
+            graph.edges().map(|e| e.click).collect::<Vec<_>>(),
+            [(1, 2), (3, 4)]
+        );
+        assert!(graph.to_dot().contains("[label=\"(3, 4)\""));
+
+        let json = history.to_json();
+        assert_eq!(History::from_json(&json).unwrap(), history);
+        for sends in [ReplaySends::Mock, ReplaySends::Resend] {
+            let replayed = history.replay(new_g(), sends).unwrap();
+            assert_eq!(replayed.state(), session.state());
+            assert_eq!(replayed.history(), history);
+        }
+
+        let mut tampered = history.clone();
+        tampered.clicks[1].to = "nil".into();
+        assert_eq!(
+            tampered
+                .replay(new_g(), ReplaySends::Mock)
+                .err()
+                .unwrap()
+                .to_string(),
+            format!(
+                "click 2 at (3, 4) diverged: recorded nil, replayed {}",
+                session.state()
+            )
+        );
+        let mut tampered = history.clone();
+        tampered.clicks[1].images[0].push((0, 0));
+        assert_eq!(
+            tampered
+                .replay(new_g(), ReplaySends::Mock)
+                .err()
+                .unwrap()
+                .to_string(),
+            "click 2 at (3, 4) drew different images: layer 0 has 0 points more and 1 less"
+        );
+        let mut tampered = history;
+        tampered.clicks[0].sends.push((Node::Nil, Node::Nil));
+        assert_eq!(
+            tampered
+                .replay(new_g(), ReplaySends::Mock)
+                .err()
+                .unwrap()
+                .to_string(),
+            "click 1 at (1, 2) made 0 sends, recorded 1"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Definitions shared between threads.
+//!
+//! Expression nodes live in a heap per thread, so Env, Eval and G are neither Send nor Sync.
+//! SharedEnv holds definitions in a thread-safe form instead, loaded, parsed and simplified
+//! once, and each thread evaluating them builds its own Env from it:
+//!
+//! ```no_run
+//! use app::shared::SharedEnv;
+//! let galaxy = SharedEnv::galaxy();
+//! let workers = (0..4)
+//!     .map(|x| {
+//!         let galaxy = galaxy.clone();
+//!         std::thread::spawn(move || {
+//!             let g = galaxy.thread_g();
+//!             g.galaxy("nil".into(), x, 0, "").unwrap().state()
+//!         })
+//!     })
+//!     .collect::<Vec<_>>();
+//! ```
+//!
+//! Values cached during evaluation stay with the thread's Env, so a worker thread gets faster
+//! over its clicks as a single-threaded G does.
+
+use crate::{
+    expr::{CachedExpr, Expr, ExprArena, ExprId, Primitive},
+    protocol::G,
+    reduce_evaluator::{Env, Eval},
+};
+
+use num_bigint::BigInt;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Weak},
+};
+
+/// SharedEnv is an Env that can be sent to and shared between threads. Clones share the
+/// definitions.
+#[derive(Clone, Debug)]
+pub struct SharedEnv(Arc<Defs>);
+
+#[derive(Debug)]
+struct Defs {
+    defs: Vec<(String, SharedExpr)>,
+}
+
+// Expr with Arc instead of nodes in the thread's heap. Nodes shared in the Env are shared
+// here too.
+#[derive(Debug)]
+enum SharedExpr {
+    Ap(Arc<SharedExpr>, Arc<SharedExpr>),
+    Op(Primitive, [Option<Arc<SharedExpr>>; 3]),
+    Num(i64),
+    Big(BigInt),
+    Var(String),
+}
+
+thread_local! {
+    // The G of each SharedEnv used on this thread. See SharedEnv::thread_g.
+    static THREAD_GS: RefCell<Vec<(Weak<Defs>, Rc<G>)>> = const { RefCell::new(Vec::new()) };
+    // The Eval of each SharedEnv used on this thread. See SharedEnv::thread_eval.
+    #[cfg(feature = "parallel")]
+    static THREAD_EVALS: RefCell<Vec<(Weak<Defs>, Rc<Eval>)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+impl SharedEnv {
+    // The definitions of env as they were defined, without what evaluation cached.
+    pub fn new(env: &Env) -> Self {
+        let mut memo = HashMap::new();
+        // The copies are trees as parsed, never evaluated, so they have no cycles.
+        let mut defs = env
+            .pristine()
+            .map(|(name, e)| {
+                (
+                    name.to_string(),
+                    share(e, &mut memo, &HashMap::new()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        defs.sort_by(|a, b| a.0.cmp(&b.0));
+        SharedEnv(Arc::new(Defs { defs }))
+    }
+
+    // The definitions of Env::galaxy.
+    pub fn galaxy() -> Self {
+        SharedEnv::new(&Env::galaxy())
+    }
+
+    // A new Env on the current thread with the definitions.
+    pub fn env(&self) -> Env {
+        let mut env = Env::new();
+        let arena = env.arena().clone();
+        let mut memo = HashMap::new();
+        for (name, e) in &self.0.defs {
+            let e = unshare(e, &mut memo, &arena);
+            env.insert(name, e);
+        }
+        env
+    }
+
+    // The Eval of the definitions on the current thread, kept like thread_g's.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn thread_eval(&self) -> Rc<Eval> {
+        THREAD_EVALS.with(|evals| {
+            let mut evals = evals.borrow_mut();
+            evals.retain(|(defs, _)| defs.strong_count() > 0);
+            if let Some((_, eval)) = evals
+                .iter()
+                .find(|(defs, _)| std::ptr::eq(defs.as_ptr(), Arc::as_ptr(&self.0)))
+            {
+                return eval.clone();
+            }
+            let eval = Rc::new(Eval::with_env(self.env()));
+            evals.push((Arc::downgrade(&self.0), eval.clone()));
+            eval
+        })
+    }
+
+    // The G evaluating the definitions on the current thread, with the default sender and
+    // config. It is created on the first call on each thread and kept with what it cached.
+    // The Gs of SharedEnvs whose clones are all dropped are dropped on the next call.
+    pub fn thread_g(&self) -> Rc<G> {
+        THREAD_GS.with(|gs| {
+            let mut gs = gs.borrow_mut();
+            gs.retain(|(defs, _)| defs.strong_count() > 0);
+            if let Some((_, g)) = gs
+                .iter()
+                .find(|(defs, _)| std::ptr::eq(defs.as_ptr(), Arc::as_ptr(&self.0)))
+            {
+                return g.clone();
+            }
+            let g = Rc::new(G::new(Box::new(Eval::with_env(self.env()))));
+            gs.push((Arc::downgrade(&self.0), g.clone()));
+            g
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.defs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.defs.is_empty()
+    }
+}
+
+#[cfg(feature = "parallel")]
+/// Portable is an expression, possibly partly evaluated, copied so that another thread can
+/// evaluate it against a SharedEnv.
+#[derive(Debug)]
+pub(crate) struct Portable(SharedExpr);
+
+#[cfg(feature = "parallel")]
+impl Portable {
+    // A copy of e, with nodes interned in arena, or None if evaluation has tied its nodes into
+    // a cycle. Nodes of names are copied as the names, which breaks the cycles through
+    // recursive definitions.
+    pub(crate) fn new(e: &Expr, arena: &ExprArena) -> Option<Self> {
+        share(e, &mut HashMap::new(), &arena.var_nodes()).map(Portable)
+    }
+    // The expression with nodes interned in arena, on the current thread.
+    pub(crate) fn expr(&self, arena: &ExprArena) -> Expr {
+        unshare(&self.0, &mut HashMap::new(), arena)
+    }
+}
+
+// None marks a node being copied, reached again through a cycle. The nodes in vars are
+// copied as the names they are mapped to, whatever they evaluated to.
+fn share(
+    e: &Expr,
+    memo: &mut HashMap<ExprId, Option<Arc<SharedExpr>>>,
+    vars: &HashMap<ExprId, String>,
+) -> Option<SharedExpr> {
+    let mut node = |c: &CachedExpr| -> Option<Arc<SharedExpr>> {
+        if let Some(s) = memo.get(&c.id()) {
+            return s.clone();
+        }
+        memo.insert(c.id(), None);
+        let s = Arc::new(match vars.get(&c.id()) {
+            Some(name) => SharedExpr::Var(name.clone()),
+            None => share(&c.expr(), memo, vars)?,
+        });
+        memo.insert(c.id(), Some(s.clone()));
+        Some(s)
+    };
+    Some(match e {
+        Expr::Ap(l, r) => SharedExpr::Ap(node(l)?, node(r)?),
+        Expr::Op(p, x, y, z) => {
+            let mut arg = |c: &Option<CachedExpr>| match c {
+                Some(c) => node(c).map(Some),
+                None => Some(None),
+            };
+            SharedExpr::Op(*p, [arg(x)?, arg(y)?, arg(z)?])
+        }
+        Expr::Num(n) => SharedExpr::Num(*n),
+        Expr::Big(n) => SharedExpr::Big((**n).clone()),
+        Expr::Var(name) => SharedExpr::Var(name.clone()),
+    })
+}
+
+fn unshare(
+    e: &SharedExpr,
+    memo: &mut HashMap<*const SharedExpr, CachedExpr>,
+    arena: &ExprArena,
+) -> Expr {
+    let mut node = |s: &Arc<SharedExpr>| -> CachedExpr {
+        if let Some(c) = memo.get(&Arc::as_ptr(s)) {
+            return c.clone();
+        }
+        let c = arena.intern(unshare(s, memo, arena));
+        memo.insert(Arc::as_ptr(s), c.clone());
+        c
+    };
+    match e {
+        SharedExpr::Ap(l, r) => Expr::Ap(node(l), node(r)),
+        SharedExpr::Op(p, [x, y, z]) => Expr::Op(
+            *p,
+            x.as_ref().map(&mut node),
+            y.as_ref().map(&mut node),
+            z.as_ref().map(&mut node),
+        ),
+        SharedExpr::Num(n) => Expr::Num(*n),
+        SharedExpr::Big(n) => Expr::num(n.clone()),
+        SharedExpr::Var(name) => Expr::Var(name.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluator;
+
+    #[test]
+    fn test_threads() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<SharedEnv>();
+
+        let galaxy = SharedEnv::galaxy();
+        assert_eq!(galaxy.len(), Env::galaxy().len());
+        let clicks = [(0, 0), (1, 2), (-3, 4), (8, 4)];
+        let want = clicks
+            .iter()
+            .map(|&(x, y)| {
+                Eval::new().evaluate(&format!("ap ap galaxy nil ap ap cons {} {}", x, y))
+            })
+            .collect::<Vec<_>>();
+        let workers = clicks
+            .iter()
+            .map(|&(x, y)| {
+                let galaxy = galaxy.clone();
+                std::thread::Builder::new()
+                    .stack_size(64 * 1024 * 1024)
+                    .spawn(move || {
+                        let g = galaxy.thread_g();
+                        assert!(Rc::ptr_eq(&g, &galaxy.thread_g()));
+                        let expr = format!("ap ap galaxy nil ap ap cons {} {}", x, y);
+                        (g.evaluator().evaluate(&expr), g.evaluator().evaluate(&expr))
+                    })
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        for (worker, want) in workers.into_iter().zip(want) {
+            assert_eq!(worker.join().unwrap(), (want.clone(), want));
+        }
+
+        // Definitions referring to themselves and shared subexpressions come back as they were.
+        let env = Env::from_source("ones = ap ap cons 1 ones\ntwo = ap ap add 1 1").unwrap();
+        let copy = SharedEnv::new(&env).env();
+        assert_eq!(copy.get("ones"), env.get("ones"));
+        let eval = Eval::with_env(copy);
+        assert_eq!(
+            eval.evaluate("ap car ap cdr ones"),
+            crate::ast::Node::Num(1)
+        );
+        assert_eq!(eval.evaluate("two"), crate::ast::Node::Num(2));
+    }
+}
@@ -0,0 +1,134 @@
+//! Simplification of definitions before they are evaluated.
+//!
+//! Env::simplify folds the applications whose results don't depend on anything but their
+//! literal arguments: arithmetic on numbers, like "ap neg 3" or "ap ap add -2 30", and the
+//! reductions that only select or drop arguments, i, t, f, nil, car and cdr of a cons, and
+//! isnil of nil or a cons. galaxy.txt has a few hundred of them, each otherwise reduced again
+//! on every click.
+//!
+//! Nothing is duplicated, so evaluating a simplified definition takes at most the steps of
+//! the original. Arithmetic that would overflow i64 or divide by zero is left to fail or to
+//! switch to big integers at evaluation, as before.
+
+use crate::expr::{CachedExpr, Expr, ExprArena, ExprId, Primitive};
+
+use std::collections::HashMap;
+
+// Rewrites expressions with nodes interned in arena. Shared nodes are simplified once.
+pub(crate) struct Simplifier<'a> {
+    arena: &'a ExprArena,
+    memo: HashMap<ExprId, CachedExpr>,
+    // Number of applications folded so far.
+    pub(crate) rewrites: usize,
+}
+
+impl<'a> Simplifier<'a> {
+    pub(crate) fn new(arena: &'a ExprArena) -> Self {
+        Simplifier {
+            arena,
+            memo: HashMap::new(),
+            rewrites: 0,
+        }
+    }
+
+    pub(crate) fn expr(&mut self, e: &Expr) -> Expr {
+        let mut node = |c: &Option<CachedExpr>| c.as_ref().map(|c| self.node(c));
+        let e = match e {
+            Expr::Ap(l, r) => Expr::Ap(self.node(l), self.node(r)),
+            Expr::Op(p, x, y, z) => {
+                let (x, y, z) = (node(x), node(y), node(z));
+                Expr::Op(*p, x, y, z)
+            }
+            e => e.clone(),
+        };
+        match fold(&e, self.arena) {
+            Some(folded) => {
+                self.rewrites += 1;
+                self.expr(&folded)
+            }
+            None => e,
+        }
+    }
+
+    fn node(&mut self, c: &CachedExpr) -> CachedExpr {
+        if let Some(res) = self.memo.get(&c.id()) {
+            return res.clone();
+        }
+        let orig = c.expr();
+        let e = self.expr(&orig);
+        let res = if e == orig {
+            c.clone()
+        } else {
+            self.arena.intern(e)
+        };
+        self.memo.insert(c.id(), res.clone());
+        self.memo.insert(res.id(), res.clone());
+        res
+    }
+}
+
+// The primitive at the head of e and its arguments, including those the Op holds.
+fn spine(e: &Expr) -> Option<(Primitive, Vec<CachedExpr>)> {
+    let mut args = vec![];
+    let mut head = e.clone();
+    loop {
+        match head {
+            Expr::Ap(f, x) => {
+                args.push(x);
+                head = f.expr();
+            }
+            Expr::Op(p, x, y, z) => {
+                args.extend(vec![z, y, x].into_iter().flatten());
+                args.reverse();
+                return Some((p, args));
+            }
+            _ => return None,
+        }
+    }
+}
+
+// The result of one reduction at the head of e, if it can be done without evaluating
+// anything.
+fn fold(e: &Expr, arena: &ExprArena) -> Option<Expr> {
+    use Primitive::*;
+    let (p, args) = spine(e)?;
+    if args.len() < p.arity() {
+        return None;
+    }
+    let num = |c: &CachedExpr| match c.expr() {
+        Expr::Num(n) => Some(n),
+        _ => None,
+    };
+    let boolean = |b| Expr::op(if b { T } else { F });
+    let res = match p {
+        I | T => args[0].expr(),
+        F => args[1].expr(),
+        Nil => Expr::op(T),
+        Car | Cdr => match spine(&args[0].expr())? {
+            (Cons, xs) if xs.len() == 2 => xs[if p == Car { 0 } else { 1 }].expr(),
+            _ => return None,
+        },
+        Isnil => match spine(&args[0].expr())? {
+            (Nil, xs) if xs.is_empty() => boolean(true),
+            (Cons, xs) if xs.len() == 2 => boolean(false),
+            _ => return None,
+        },
+        Neg => Expr::Num(num(&args[0])?.checked_neg()?),
+        Add | Mul | Div | Eq | Lt => {
+            let (x, y) = (num(&args[0])?, num(&args[1])?);
+            match p {
+                Add => Expr::Num(x.checked_add(y)?),
+                Mul => Expr::Num(x.checked_mul(y)?),
+                Div => Expr::Num(x.checked_div(y)?),
+                Eq => boolean(x == y),
+                _ => boolean(x < y),
+            }
+        }
+        _ => return None,
+    };
+    Some(
+        args[p.arity()..]
+            .iter()
+            .fold(res, |f, x| Expr::Ap(arena.intern(f), x.clone())),
+    )
+}
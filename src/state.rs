@@ -0,0 +1,202 @@
+use crate::ast::Node;
+
+use anyhow::*;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Formatter, str::FromStr};
+
+/// State is a protocol state, e.g. the one galaxy returns and takes back on the next click.
+///
+/// Fields are addressed by paths of list indices: `[1, 0]` is the first element of the
+/// second element of the top-level list.
+///
+/// States parse from the "ap ap cons ..." text form as well as from the compact tokens of
+/// to_token.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct State(Node);
+
+// Starts the tokens of State::to_token, a character the text form never has.
+const TOKEN_PREFIX: char = '~';
+
+impl State {
+    pub fn new(node: Node) -> Self {
+        State(node)
+    }
+    // Whether s is in the form of to_token rather than the text form.
+    pub fn is_token(s: &str) -> bool {
+        s.starts_with(TOKEN_PREFIX)
+    }
+    // The compact form of the state: "~" and the modulated bits packed into bytes, in URL-safe
+    // base64. Several times shorter than the text form, e.g. for galaxy's states, which grow
+    // to thousands of characters.
+    pub fn to_token(&self) -> String {
+        format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            URL_SAFE_NO_PAD.encode(self.0.modulate_bytes())
+        )
+    }
+    pub fn node(&self) -> &Node {
+        &self.0
+    }
+    pub fn into_node(self) -> Node {
+        self.0
+    }
+
+    pub fn get(&self, path: &[usize]) -> Result<&Node> {
+        let mut node = &self.0;
+        for (depth, &i) in path.iter().enumerate() {
+            node = nth(node, i).with_context(|| format!("at {:?}", &path[..=depth]))?;
+        }
+        Ok(node)
+    }
+
+    pub fn set(&mut self, path: &[usize], value: Node) -> Result<()> {
+        let mut node = &mut self.0;
+        for (depth, &i) in path.iter().enumerate() {
+            node = nth_mut(node, i).with_context(|| format!("at {:?}", &path[..=depth]))?;
+        }
+        *node = value;
+        Ok(())
+    }
+
+    // Checks that the state roughly has the shape galaxy produces:
+    // nil, or a proper list whose first element is a number.
+    pub fn validate_galaxy(&self) -> Result<()> {
+        if self.0 == Node::Nil {
+            return Ok(());
+        }
+        let mut node = &self.0;
+        while let Node::Cons(_, y) = node {
+            node = y;
+        }
+        if node != &Node::Nil {
+            bail!("not a list: {}", self);
+        }
+        match self.get(&[0])? {
+            Node::Num(_) => Ok(()),
+            x => bail!("first element must be a number: {}", x),
+        }
+    }
+}
+
+// Returns the i-th car following the cdr chain of node.
+fn nth(mut node: &Node, i: usize) -> Result<&Node> {
+    for _ in 0..i {
+        node = match node {
+            Node::Cons(_, y) => y,
+            _ => bail!("index {} out of range", i),
+        };
+    }
+    match node {
+        Node::Cons(x, _) => Ok(x),
+        _ => bail!("index {} out of range", i),
+    }
+}
+
+fn nth_mut(mut node: &mut Node, i: usize) -> Result<&mut Node> {
+    for _ in 0..i {
+        node = match node {
+            Node::Cons(_, y) => y,
+            _ => bail!("index {} out of range", i),
+        };
+    }
+    match node {
+        Node::Cons(x, _) => Ok(x),
+        _ => bail!("index {} out of range", i),
+    }
+}
+
+impl FromStr for State {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(TOKEN_PREFIX) {
+            Some(token) => {
+                let bytes = URL_SAFE_NO_PAD
+                    .decode(token)
+                    .context("invalid state token")?;
+                let node = Node::demodulate_bytes(&bytes).context("invalid state token")?;
+                Ok(State(node))
+            }
+            None => Ok(State(s.parse()?)),
+        }
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let mut state: State =
+            "ap ap cons 1 ap ap cons ap ap cons 11 nil ap ap cons 0 ap ap cons nil nil"
+                .parse()
+                .unwrap();
+
+        assert_eq!(state.get(&[]).unwrap(), state.node());
+        assert_eq!(state.get(&[0]).unwrap(), &Node::Num(1));
+        assert_eq!(state.get(&[1, 0]).unwrap(), &Node::Num(11));
+        assert_eq!(state.get(&[3]).unwrap(), &Node::Nil);
+        assert!(state.get(&[4]).is_err());
+        assert!(state.get(&[0, 0]).is_err());
+
+        state.set(&[1, 0], Node::Num(5)).unwrap();
+        state.set(&[2], Node::Num(-1)).unwrap();
+        assert!(state.set(&[1, 1], Node::Num(0)).is_err());
+        assert_eq!(
+            state.to_string(),
+            "ap ap cons 1 ap ap cons ap ap cons 5 nil ap ap cons -1 ap ap cons nil nil"
+        );
+    }
+
+    #[test]
+    fn test_token() {
+        for s in [
+            "nil",
+            "-5",
+            "ap ap cons 1 ap ap cons ap ap cons 11 nil ap ap cons 0 ap ap cons nil nil",
+            "ap ap cons 9223372036854775807 ap ap cons -9223372036854775807 nil",
+        ] {
+            let state: State = s.parse().unwrap();
+            let token = state.to_token();
+            assert!(token.starts_with('~'), "{}", token);
+            assert_eq!(token.parse::<State>().unwrap(), state, "{}", s);
+        }
+        assert_eq!(State::new(Node::Nil).to_token(), "~AA");
+        let long: State = ("ap ap cons 1 ".repeat(100) + "nil").parse().unwrap();
+        assert!(long.to_token().len() * 5 < long.to_string().len());
+
+        for s in ["~", "~AA=", "~/w", "~AAAA"] {
+            assert!(s.parse::<State>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_validate_galaxy() {
+        for (s, ok) in [
+            ("nil", true),
+            (
+                "ap ap cons 0 ap ap cons ap ap cons 0 nil ap ap cons 0 ap ap cons nil nil",
+                true,
+            ),
+            ("ap ap cons 1 nil", true),
+            ("1", false),
+            ("ap ap cons 1 2", false),
+            ("ap ap cons nil nil", false),
+        ] {
+            let state: State = s.parse().unwrap();
+            assert_eq!(state.validate_galaxy().is_ok(), ok, "{}", s);
+        }
+        for s in ["", "ap ap cons 1", "ap ap add 1 2", "nil nil", "x"] {
+            assert!(s.parse::<State>().is_err(), "{}", s);
+        }
+    }
+}
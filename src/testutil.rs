@@ -0,0 +1,233 @@
+//! proptest strategies for galaxy values, expressions and clicks, for property tests of this
+//! crate and of bots built on it, and checkers of the invariants they should keep, which
+//! fuzzers can call as well. Enabled by the `testutil` feature.
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn roundtrip(node in app::testutil::node()) {
+//!         assert_eq!(Node::demodulate(&node.modulate()), node);
+//!     }
+//! }
+//! ```
+
+use crate::{
+    ast::{ExprBuilder as E, Node},
+    expr::{Expr, Primitive},
+    protocol::G,
+    state::State,
+};
+
+use anyhow::{bail, Result};
+use num_bigint::{BigInt, Sign};
+use proptest::{collection::vec, prelude::*};
+
+// The definition of statelessdraw, the simplest protocol that draws: it draws the click and
+// keeps the state nil.
+pub const STATELESSDRAW: &str = "statelessdraw = ap ap c ap ap b b ap ap b ap b ap cons 0 ap ap c ap ap b b cons ap ap c cons nil ap ap c ap ap b cons ap ap c cons nil nil";
+
+// The definition of statefuldraw, :67108929 in galaxy.txt, which draws all the clicks so far
+// and keeps them as its state.
+pub const STATEFULDRAW: &str = ":67108929 = ap ap b ap b ap ap s ap ap b ap b ap cons 0 ap ap c ap ap b b cons ap ap c cons nil ap ap c cons nil ap c cons";
+
+pub fn num() -> impl Strategy<Value = i64> {
+    any::<i64>()
+}
+
+// Numbers of up to 192 bits, mostly outside i64.
+pub fn big() -> impl Strategy<Value = Node> {
+    (any::<bool>(), vec(any::<u32>(), 0..6)).prop_map(|(neg, digits)| {
+        let sign = if neg { Sign::Minus } else { Sign::Plus };
+        Node::from(BigInt::from_slice(sign, &digits))
+    })
+}
+
+// Values made of numbers, nil and cons cells, not necessarily proper lists.
+pub fn node() -> impl Strategy<Value = Node> {
+    let leaf = prop_oneof![Just(Node::Nil), num().prop_map(Node::Num), big()];
+    leaf.prop_recursive(8, 64, 2, |inner| {
+        (inner.clone(), inner).prop_map(|(x, y)| Node::Cons(x.into(), y.into()))
+    })
+}
+
+// Proper lists of up to max_len elements.
+pub fn list(elem: impl Strategy<Value = Node>, max_len: usize) -> impl Strategy<Value = Node> {
+    vec(elem, 0..=max_len).prop_map(from_vec)
+}
+
+// States accepted by State::validate_galaxy.
+pub fn galaxy_state() -> impl Strategy<Value = State> {
+    prop_oneof![
+        Just(Node::Nil),
+        (num(), vec(node(), 0..4)).prop_map(|(n, mut rest)| {
+            rest.insert(0, Node::Num(n));
+            from_vec(rest)
+        }),
+    ]
+    .prop_map(State::new)
+}
+
+// Well-formed modulated strings, as the aliens send them.
+pub fn modulated() -> impl Strategy<Value = String> {
+    node().prop_map(|n| n.modulate())
+}
+
+// The primitives expr applies, all but those of the later messages.
+const PRIMITIVES: &[Primitive] = &[
+    Primitive::Add,
+    Primitive::Mul,
+    Primitive::Div,
+    Primitive::Eq,
+    Primitive::Lt,
+    Primitive::Neg,
+    Primitive::S,
+    Primitive::C,
+    Primitive::B,
+    Primitive::I,
+    Primitive::F,
+    Primitive::T,
+    Primitive::Cons,
+    Primitive::Car,
+    Primitive::Cdr,
+    Primitive::Nil,
+    Primitive::Isnil,
+];
+
+// Closed expressions applying primitives to each other and to small numbers, e.g.
+// "ap ap add 1 ap neg 2". Most aren't values, and some never finish reducing, so evaluate
+// them with an EvalBudget.
+pub fn expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+        (-8i64..8).prop_map(E::num),
+        proptest::sample::select(PRIMITIVES).prop_map(E::op),
+    ];
+    leaf.prop_recursive(6, 32, 2, |inner| {
+        (inner.clone(), inner).prop_map(|(f, x)| f.ap(x))
+    })
+    .prop_map(E::build)
+}
+
+// Sequences of up to max_len clicks, within the area the galaxy draws in.
+pub fn clicks(max_len: usize) -> impl Strategy<Value = Vec<(i32, i32)>> {
+    vec((-64i32..64, -64i32..64), 0..=max_len)
+}
+
+// Checks that node demodulates from its modulated form.
+pub fn check_modulate(node: &Node) -> Result<()> {
+    let s = node.modulate();
+    match Node::try_demodulate(&s) {
+        Ok(n) if n == *node => Ok(()),
+        Ok(n) => bail!("{} modulated to {}, which demodulates to {}", node, s, n),
+        Err(e) => bail!(
+            "{} modulated to {}, which doesn't demodulate: {}",
+            node,
+            s,
+            e
+        ),
+    }
+}
+
+// Checks that s, a well-formed modulated string, modulates back from what it demodulates to.
+pub fn check_demodulate(s: &str) -> Result<()> {
+    let node = Node::try_demodulate(s)?;
+    if node.modulate() != s {
+        bail!(
+            "{} demodulated to {}, which modulates to {}",
+            s,
+            node,
+            node.modulate()
+        );
+    }
+    Ok(())
+}
+
+// Checks that evaluating expr is idempotent in g: expr evaluates to the same value again,
+// and the value evaluates to itself. Expressions that fail to evaluate, e.g. for being
+// functions or exceeding g's budget, pass.
+pub fn check_eval_idempotent(g: &G, expr: &str) -> Result<()> {
+    let value = match g.eval(expr) {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+    match g.eval(expr) {
+        Ok(v) if v == value => (),
+        res => bail!("{} evaluated to {}, then to {:?}", expr, value, res),
+    }
+    match g.eval(&value.to_string()) {
+        Ok(v) if v == value => Ok(()),
+        res => bail!(
+            "{} evaluated to {}, which evaluates to {:?}",
+            expr,
+            value,
+            res
+        ),
+    }
+}
+
+fn from_vec(v: Vec<Node>) -> Node {
+    v.into_iter()
+        .rev()
+        .fold(Node::Nil, |y, x| Node::Cons(x.into(), y.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        eval::EvalBudget,
+        session::{ReplaySends, Session},
+    };
+
+    fn check(res: Result<()>) -> Result<(), TestCaseError> {
+        res.map_err(|e| TestCaseError::fail(format!("{:#}", e)))
+    }
+
+    fn statelessdraw() -> G {
+        G::from_source(STATELESSDRAW).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn test_modulate(node in node()) {
+            check(check_modulate(&node))?;
+        }
+
+        #[test]
+        fn test_demodulate(s in modulated()) {
+            check(check_demodulate(&s))?;
+        }
+
+        #[test]
+        fn test_eval_idempotent(e in expr()) {
+            let mut g = statelessdraw();
+            g.set_budget(Some(EvalBudget::reductions(1000)));
+            check(check_eval_idempotent(&g, &e.to_string()))?;
+        }
+
+        #[test]
+        fn test_clicks(clicks in clicks(8)) {
+            let mut session = Session::new(statelessdraw(), "statelessdraw", "nil".into());
+            for &(x, y) in &clicks {
+                let res = session.click(x, y).unwrap();
+                prop_assert_eq!(res.images, vec![vec![(x as i64, y as i64)]]);
+            }
+            session.history().replay(statelessdraw(), ReplaySends::Mock).unwrap();
+        }
+
+        #[test]
+        fn test_parse(node in node()) {
+            prop_assert_eq!(node.to_string().parse::<Node>().unwrap(), node);
+        }
+
+        #[test]
+        fn test_list(node in list(node(), 5)) {
+            prop_assert!(node.as_list().unwrap().len() <= 5);
+        }
+
+        #[test]
+        fn test_galaxy_state(state in galaxy_state()) {
+            prop_assert!(state.validate_galaxy().is_ok(), "{}", state);
+            prop_assert_eq!(state.to_string().parse::<State>().unwrap(), state);
+        }
+    }
+}
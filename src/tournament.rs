@@ -0,0 +1,455 @@
+//! Bot matches without the contest server: Simulator plays the aliens' game locally, and
+//! Tournament pits two strategies against each other over many matches.
+//!
+//! The simulation follows the contest's rules in simplified form:
+//! - Each tick, every ship is pulled one unit toward the square planet along its dominant
+//!   axes, after applying its commands, and then moves by its velocity.
+//! - Ships die inside the planet (|x| and |y| <= 16) or outside space (|x| or |y| > 128).
+//! - Accelerate costs one fuel. Shots damage enemy ships within 3 of the target by the
+//!   shot's power, capped by the shooter's power, taking fuel, then coolant, then power;
+//!   ships with none left die. Detonations destroy the ship and enemy ships within 4.
+//! - The attacker wins by destroying every defender ship; the defender by surviving 256 ticks.
+//!   Stats costing more than 512 (attacker) or 448 (defender) lose at once.
+
+use crate::{
+    ast::Node,
+    bot::Strategy,
+    game::{Command, GameResponse, GameStage, GameState, Role, ShipState, Stats, Vec2},
+};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+pub const MAX_TICKS: i64 = 256;
+pub const PLANET_RADIUS: i64 = 16;
+pub const SPACE_RADIUS: i64 = 128;
+
+// The cost of stats in points: fuel 1, power 4, coolant 12 and spawns 2 each.
+pub fn stats_cost(stats: &Stats) -> i64 {
+    stats.fuel + 4 * stats.power + 12 * stats.coolant + 2 * stats.spawns
+}
+
+fn max_cost(role: Role) -> i64 {
+    match role {
+        Role::Attacker => 512,
+        Role::Defender => 448,
+    }
+}
+
+/// Simulator is one match in progress.
+pub struct Simulator {
+    tick: i64,
+    ships: Vec<(ShipState, Vec<Command>)>,
+    next_id: i64,
+    // Set when the match is over.
+    winner: Option<Role>,
+}
+
+impl Simulator {
+    // A match whose ships start at opposite points at distance 48 from the planet, chosen by
+    // seed, with the given stats.
+    pub fn new(seed: u64, attacker: Stats, defender: Stats) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let d = rng.gen_range(-48..=48);
+        let start = match rng.gen_range(0..4) {
+            0 => Vec2::new(-48, d),
+            1 => Vec2::new(48, d),
+            2 => Vec2::new(d, -48),
+            _ => Vec2::new(d, 48),
+        };
+        let mut sim = Simulator {
+            tick: 0,
+            ships: vec![],
+            next_id: 0,
+            winner: None,
+        };
+        for (role, stats, position) in [
+            (Role::Attacker, attacker, start),
+            (Role::Defender, defender, Vec2::new(-start.x, -start.y)),
+        ] {
+            if stats_cost(&stats) > max_cost(role) {
+                sim.winner = Some(opponent(role));
+            }
+            sim.spawn(role, position, Vec2::default(), stats);
+        }
+        sim
+    }
+
+    pub fn tick(&self) -> i64 {
+        self.tick
+    }
+    pub fn winner(&self) -> Option<Role> {
+        self.winner
+    }
+
+    // The game response role sees.
+    pub fn response(&self, role: Role) -> GameResponse {
+        let role_num = match role {
+            Role::Attacker => 0,
+            Role::Defender => 1,
+        };
+        let n = |n: i64| Node::Num(n);
+        GameResponse {
+            stage: if self.winner.is_some() {
+                GameStage::Finished
+            } else {
+                GameStage::Started
+            },
+            role,
+            static_info: Node::list(vec![
+                n(MAX_TICKS),
+                n(role_num),
+                Node::list(vec![n(max_cost(role)), n(1), n(64)]),
+                Node::list(vec![n(PLANET_RADIUS), n(SPACE_RADIUS)]),
+                Node::Nil,
+            ]),
+            state: Some(GameState {
+                tick: self.tick,
+                ships: self.ships.clone(),
+            }),
+        }
+    }
+
+    // Advances a tick with each side's commands. Commands for ships of the other side or dead
+    // ships are ignored.
+    pub fn step(&mut self, attacker: &[Command], defender: &[Command]) {
+        if self.winner.is_some() {
+            return;
+        }
+        for (_, applied) in self.ships.iter_mut() {
+            applied.clear();
+        }
+        let mut dead = vec![];
+        for (role, commands) in [(Role::Attacker, attacker), (Role::Defender, defender)] {
+            for command in commands {
+                let i = match self
+                    .ships
+                    .iter()
+                    .position(|(s, _)| s.role == role && s.ship_id == command.ship_id())
+                {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if self.apply(i, command, &mut dead) {
+                    self.ships[i].1.push(command.clone());
+                }
+            }
+        }
+        for (ship, _) in self.ships.iter_mut() {
+            let p = ship.position;
+            let v = &mut ship.velocity;
+            if p.x.abs() >= p.y.abs() {
+                v.x -= p.x.signum();
+            }
+            if p.y.abs() >= p.x.abs() {
+                v.y -= p.y.signum();
+            }
+            ship.position = Vec2::new(p.x + v.x, p.y + v.y);
+            let p = ship.position;
+            if p.x.abs() <= PLANET_RADIUS && p.y.abs() <= PLANET_RADIUS
+                || p.x.abs().max(p.y.abs()) > SPACE_RADIUS
+            {
+                dead.push(ship.ship_id);
+            }
+        }
+        self.ships.retain(|(s, _)| !dead.contains(&s.ship_id));
+        self.tick += 1;
+
+        let alive = |role| self.ships.iter().any(|(s, _)| s.role == role);
+        self.winner = match (alive(Role::Attacker), alive(Role::Defender)) {
+            (_, false) => Some(Role::Attacker),
+            (false, true) => Some(Role::Defender),
+            _ if self.tick >= MAX_TICKS => Some(Role::Defender),
+            _ => None,
+        };
+    }
+
+    // Applies the command to ships[i], returning whether it took effect.
+    fn apply(&mut self, i: usize, command: &Command, dead: &mut Vec<i64>) -> bool {
+        let ship = &self.ships[i].0;
+        let (role, position) = (ship.role, ship.position);
+        match command {
+            Command::Accelerate { vector, .. } => {
+                let ship = &mut self.ships[i].0;
+                if ship.stats.fuel == 0 || (vector.x == 0 && vector.y == 0) {
+                    return false;
+                }
+                let max = ship.max_thrust;
+                let clamp = |n: i64| n.clamp(-max, max);
+                ship.velocity.x -= clamp(vector.x);
+                ship.velocity.y -= clamp(vector.y);
+                ship.stats.fuel -= 1;
+            }
+            Command::Detonate { .. } => {
+                dead.push(ship.ship_id);
+                for (other, _) in &self.ships {
+                    if other.role != role && distance(other.position, position) <= 4 {
+                        dead.push(other.ship_id);
+                    }
+                }
+            }
+            Command::Shoot { target, power, .. } => {
+                let power = (*power).min(ship.stats.power);
+                if power <= 0 {
+                    return false;
+                }
+                for (other, _) in self.ships.iter_mut() {
+                    if other.role != role && distance(other.position, *target) <= 3 {
+                        let s = &mut other.stats;
+                        let mut damage = power;
+                        for stat in [&mut s.fuel, &mut s.coolant, &mut s.power] {
+                            let d = damage.min(*stat);
+                            *stat -= d;
+                            damage -= d;
+                        }
+                        if s.fuel + s.coolant + s.power == 0 {
+                            dead.push(other.ship_id);
+                        }
+                    }
+                }
+            }
+            Command::Split { stats, .. } => {
+                let s = ship.stats;
+                let ok = s.spawns > 1
+                    && stats.spawns >= 1
+                    && stats.fuel <= s.fuel
+                    && stats.power <= s.power
+                    && stats.coolant <= s.coolant
+                    && stats.spawns < s.spawns
+                    && [stats.fuel, stats.power, stats.coolant]
+                        .iter()
+                        .all(|&n| n >= 0);
+                if !ok {
+                    return false;
+                }
+                let velocity = ship.velocity;
+                let parent = &mut self.ships[i].0.stats;
+                parent.fuel -= stats.fuel;
+                parent.power -= stats.power;
+                parent.coolant -= stats.coolant;
+                parent.spawns -= stats.spawns;
+                self.spawn(role, position, velocity, *stats);
+            }
+        }
+        true
+    }
+
+    fn spawn(&mut self, role: Role, position: Vec2, velocity: Vec2, stats: Stats) {
+        self.ships.push((
+            ShipState {
+                role,
+                ship_id: self.next_id,
+                position,
+                velocity,
+                stats,
+                heat: 0,
+                max_heat: 64,
+                max_thrust: 1,
+            },
+            vec![],
+        ));
+        self.next_id += 1;
+    }
+}
+
+fn opponent(role: Role) -> Role {
+    match role {
+        Role::Attacker => Role::Defender,
+        Role::Defender => Role::Attacker,
+    }
+}
+
+fn distance(a: Vec2, b: Vec2) -> i64 {
+    (a.x - b.x).abs().max((a.y - b.y).abs())
+}
+
+// Plays a match, returning the winner and the number of ticks played.
+pub fn play_match(
+    attacker: &mut dyn Strategy,
+    defender: &mut dyn Strategy,
+    seed: u64,
+) -> (Role, i64) {
+    let join = |role| {
+        let mut res = Simulator::new(seed, Stats::default(), Stats::default()).response(role);
+        res.stage = GameStage::NotStarted;
+        res.state = None;
+        res
+    };
+    let mut sim = Simulator::new(
+        seed,
+        attacker.stats(&join(Role::Attacker)),
+        defender.stats(&join(Role::Defender)),
+    );
+    while sim.winner.is_none() {
+        let a = attacker.commands(&sim.response(Role::Attacker));
+        let d = defender.commands(&sim.response(Role::Defender));
+        sim.step(&a, &d);
+    }
+    (sim.winner.unwrap(), sim.tick)
+}
+
+/// Report is the outcome of a tournament between strategies A and B.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    pub matches: usize,
+    // Wins of A and B as the attacker and as the defender.
+    pub a_attacking: usize,
+    pub a_defending: usize,
+    pub b_attacking: usize,
+    pub b_defending: usize,
+    pub ticks: i64,
+}
+
+impl Report {
+    pub fn a_wins(&self) -> usize {
+        self.a_attacking + self.a_defending
+    }
+    pub fn b_wins(&self) -> usize {
+        self.b_attacking + self.b_defending
+    }
+    // Fraction of matches A won.
+    pub fn a_win_rate(&self) -> f64 {
+        self.a_wins() as f64 / self.matches.max(1) as f64
+    }
+    pub fn b_win_rate(&self) -> f64 {
+        self.b_wins() as f64 / self.matches.max(1) as f64
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "A: {:.1}% ({} attacking, {} defending)",
+            100.0 * self.a_win_rate(),
+            self.a_attacking,
+            self.a_defending
+        )?;
+        writeln!(
+            f,
+            "B: {:.1}% ({} attacking, {} defending)",
+            100.0 * self.b_win_rate(),
+            self.b_attacking,
+            self.b_defending
+        )?;
+        write!(
+            f,
+            "{} matches, {:.1} ticks on average",
+            self.matches,
+            self.ticks as f64 / self.matches.max(1) as f64
+        )
+    }
+}
+
+/// Tournament plays strategies A and B against each other, alternating roles. Strategies are
+/// created afresh for every match.
+pub struct Tournament {
+    matches: usize,
+    seed: u64,
+}
+
+impl Tournament {
+    pub fn new(matches: usize) -> Self {
+        Tournament { matches, seed: 0 }
+    }
+    // Seeds the starting positions; the same seed replays the same tournament.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn run(
+        &self,
+        mut a: impl FnMut() -> Box<dyn Strategy>,
+        mut b: impl FnMut() -> Box<dyn Strategy>,
+    ) -> Report {
+        let mut report = Report::default();
+        for i in 0..self.matches {
+            let seed = self.seed.wrapping_add(i as u64 / 2);
+            let a_attacks = i % 2 == 0;
+            let (winner, ticks) = if a_attacks {
+                play_match(a().as_mut(), b().as_mut(), seed)
+            } else {
+                play_match(b().as_mut(), a().as_mut(), seed)
+            };
+            let wins = match (winner, a_attacks) {
+                (Role::Attacker, true) => &mut report.a_attacking,
+                (Role::Defender, false) => &mut report.a_defending,
+                (Role::Attacker, false) => &mut report.b_attacking,
+                (Role::Defender, true) => &mut report.b_defending,
+            };
+            *wins += 1;
+            report.matches += 1;
+            report.ticks += ticks;
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::{OrbitBot, DEFAULT_STATS};
+
+    struct Idle;
+
+    impl Strategy for Idle {
+        fn commands(&mut self, _: &GameResponse) -> Vec<Command> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn test_simulator() {
+        let mut sim = Simulator::new(0, DEFAULT_STATS, DEFAULT_STATS);
+        let res = sim.response(Role::Defender);
+        assert_eq!(res.stage, GameStage::Started);
+        let ship = res.ships(Role::Defender).next().unwrap().clone();
+        assert_eq!(res.ships(Role::Attacker).count(), 1);
+
+        let accelerate = Command::Accelerate {
+            ship_id: ship.ship_id,
+            vector: Vec2::new(5, 0),
+        };
+        let mut coasting = Simulator::new(0, DEFAULT_STATS, DEFAULT_STATS);
+        sim.step(&[], std::slice::from_ref(&accelerate));
+        coasting.step(&[], &[]);
+        let (moved, applied) = &sim.ships[1];
+        assert_eq!(applied, &[accelerate]);
+        assert_eq!(moved.stats.fuel, ship.stats.fuel - 1);
+        // The thrust is clamped to 1 and opposite to the vector.
+        assert_eq!(moved.velocity.x, coasting.ships[1].0.velocity.x - 1);
+        assert_eq!(sim.tick(), 1);
+
+        // Detonating next to the enemy takes it along.
+        let mut sim = Simulator::new(0, DEFAULT_STATS, DEFAULT_STATS);
+        sim.ships[1].0.position = sim.ships[0].0.position;
+        sim.step(&[Command::Detonate { ship_id: 0 }], &[]);
+        assert_eq!(sim.winner(), Some(Role::Attacker));
+
+        let greedy = Stats {
+            fuel: 449,
+            ..Default::default()
+        };
+        assert_eq!(
+            Simulator::new(0, DEFAULT_STATS, greedy).winner(),
+            Some(Role::Attacker)
+        );
+    }
+
+    #[test]
+    fn test_tournament() {
+        let report = Tournament::new(10)
+            .seed(1)
+            .run(|| Box::new(OrbitBot), || Box::new(Idle));
+        assert_eq!(report.matches, 10);
+        // Idle ships fall into the planet; orbiting ones survive.
+        assert_eq!(report.a_wins(), 10, "{}", report);
+        assert_eq!(report.a_win_rate(), 1.0);
+        assert_eq!(
+            report,
+            Tournament::new(10)
+                .seed(1)
+                .run(|| Box::new(OrbitBot), || Box::new(Idle))
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use crate::*;
 
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+// Rc so that the futures of galaxy_async can hold G beyond the call.
 #[wasm_bindgen]
-pub struct GalaxyEvaluator(common::G);
+pub struct GalaxyEvaluator(Rc<common::G>);
 
 #[wasm_bindgen]
 impl GalaxyEvaluator {
@@ -11,9 +13,160 @@ impl GalaxyEvaluator {
     //     Self(common::G::new(Box::new(gen_js::GalaxyEvaluator::new())))
     // }
     pub fn new_reduce_evaluator() -> Self {
-        Self(common::G::new(Box::new(reduce_evaluator::Eval::new())))
+        Self(Rc::new(common::G::new(Box::new(
+            reduce_evaluator::Eval::new(),
+        ))))
     }
-    pub fn galaxy(&self, state: String, x: i32, y: i32, api_key: &str) -> common::InteractResult {
-        self.0.interact("galaxy", state, x, y, api_key)
+    // Fails clicks needing more than max_nodes expression nodes instead of growing the heap
+    // until the tab crashes.
+    pub fn new_reduce_evaluator_with_memory_limit(max_nodes: usize) -> Self {
+        let mut eval = reduce_evaluator::Eval::new();
+        eval.set_memory_limit(Some(max_nodes));
+        Self(Rc::new(common::G::new(Box::new(eval))))
     }
+    // Fails clicks whose reductions nest deeper than max_depth, like non-productive cycles,
+    // with an error naming the definitions involved, instead of overflowing the stack.
+    pub fn new_reduce_evaluator_with_max_depth(max_depth: usize) -> Self {
+        let mut eval = reduce_evaluator::Eval::new();
+        eval.set_max_depth(Some(max_depth));
+        Self(Rc::new(common::G::new(Box::new(eval))))
+    }
+    // Fails clicks whose evaluation takes longer than max_ms with an error naming the round,
+    // instead of freezing the tab.
+    pub fn new_reduce_evaluator_with_budget(max_ms: f64) -> Self {
+        let mut g = common::G::new(Box::new(reduce_evaluator::Eval::new()));
+        g.set_budget(Some(EvalBudget::millis(max_ms)));
+        Self(Rc::new(g))
+    }
+    // Falls back to gen_js's BigInt numbers once a click overflows. The reduce evaluator has big
+    // integers itself, so this only matters for evaluators that overflow.
+    pub fn new_reduce_evaluator_with_bigint() -> Self {
+        let mut g = common::G::new(Box::new(reduce_evaluator::Eval::new()));
+        g.set_bigint_evaluator(Box::new(gen_js::GalaxyEvaluator::new()));
+        Self(Rc::new(g))
+    }
+    // Aborts the click in progress, whose promise rejects with "cancelled in round ...".
+    // Clicks only yield to JS while galaxy_async awaits the aliens, so a click evaluating
+    // on the main thread is cancelled at its next round.
+    pub fn cancel(&self) {
+        self.0.cancel()
+    }
+    pub fn uses_bigint(&self) -> bool {
+        self.0.numeric() == Numeric::BigInt
+    }
+    // Keeps up to limit states for undo, e.g. for the back button.
+    pub fn set_undo_limit(&self, limit: usize) {
+        self.0.set_undo_limit(limit)
+    }
+    // The state before the last click as a string, or undefined.
+    pub fn undo(&self) -> JsValue {
+        self.0
+            .undo()
+            .map_or(JsValue::UNDEFINED, |s| JsValue::from_str(&s))
+    }
+    // The state undo went back from as a string, or undefined.
+    pub fn redo(&self) -> JsValue {
+        self.0
+            .redo()
+            .map_or(JsValue::UNDEFINED, |s| JsValue::from_str(&s))
+    }
+    // Evaluates an expression, reporting errors like "undefined: :1" instead of aborting.
+    pub fn evaluate(&self, expr: &str) -> Result<String, JsValue> {
+        self.0
+            .evaluator()
+            .try_evaluate(expr)
+            .map(|n| n.to_string())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+    // Defines name as source, e.g. a custom protocol to click with interact. Fails if name is
+    // already defined or source doesn't parse, and while a galaxy_async click holds the
+    // evaluator. See G::define.
+    pub fn define(&mut self, name: &str, source: &str) -> Result<(), JsValue> {
+        Rc::get_mut(&mut self.0)
+            .ok_or_else(|| JsValue::from_str("a click is in progress"))?
+            .define(name, source)
+            .map_err(|e| JsValue::from_str(&format!("{:#}", e)))
+    }
+    // Evaluates an expression like "ap car :1" for a console, returning the value in the
+    // structured JSON form of InteractResult::state_json. See G::eval.
+    pub fn eval(&self, expr: &str) -> Result<JsValue, JsValue> {
+        self.0
+            .eval(expr)
+            .map(|v| JsValue::from_str(&common::Node::from(v).to_json()))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+    pub fn galaxy(
+        &self,
+        state: String,
+        x: i32,
+        y: i32,
+        api_key: &str,
+    ) -> Result<common::InteractResult, JsValue> {
+        self.0
+            .galaxy(state, x, y, api_key)
+            .map_err(|e| JsValue::from_str(&format!("{:#}", e)))
+    }
+    // Like galaxy, but returns the result as a JSON string {"state": ..., "images": ...}
+    // using the API key set in the config. See G::galaxy_json.
+    pub fn galaxy_json(&self, state: String, x: i32, y: i32) -> Result<JsValue, JsValue> {
+        self.0
+            .galaxy_json(state, x, y)
+            .map(|json| JsValue::from_str(&json))
+            .map_err(|e| JsValue::from_str(&format!("{:#}", e)))
+    }
+    // Starts a galaxy click to be evaluated by poll a slice at a time, so that a web worker
+    // can post progress to the page between slices. Requests are sent with blocking
+    // XMLHttpRequest, which workers allow.
+    pub fn start_interact(
+        &self,
+        state: String,
+        x: i32,
+        y: i32,
+        api_key: &str,
+    ) -> Result<GalaxyInteraction, JsValue> {
+        self.0
+            .start_interact("galaxy", state, x, y, api_key)
+            .map(GalaxyInteraction)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+    // Like galaxy, but sends with fetch instead of blocking XMLHttpRequest. Returns a Promise
+    // resolving to an InteractResult.
+    #[cfg(target_arch = "wasm32")]
+    pub fn galaxy_async(&self, state: String, x: i32, y: i32, api_key: String) -> js_sys::Promise {
+        let g = self.0.clone();
+        let config = g.config().clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            g.galaxy_async(state, x, y, |req| {
+                crate::net::fetch_send(req, api_key.clone(), config.clone())
+            })
+            .await
+            .map(JsValue::from)
+            .map_err(|e| JsValue::from_str(&format!("{:#}", e)))
+        })
+    }
+}
+
+// A click of GalaxyEvaluator::start_interact.
+#[wasm_bindgen]
+pub struct GalaxyInteraction(Interaction);
+
+#[wasm_bindgen]
+impl GalaxyInteraction {
+    // Sets the reductions of the first slice of each round.
+    pub fn set_slice(&mut self, reductions: u64) {
+        self.0.set_slice(reductions)
+    }
+    // Evaluates a slice, returning the Progress whose result is set once done.
+    pub fn poll(&mut self) -> Result<Progress, JsValue> {
+        self.0.poll().map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+// Converts a state edited in the JSON form of InteractResult::state_json back to the
+// "ap ap cons ..." form taken by galaxy.
+#[wasm_bindgen]
+pub fn state_from_json(json: &str) -> Result<JsValue, JsValue> {
+    common::Node::from_json(json)
+        .map(|n| JsValue::from_str(&n.to_string()))
+        .map_err(|e| JsValue::from_str(&format!("{:#}", e)))
 }
@@ -30,6 +30,7 @@ import('../pkg').then(mod => {
 
     // let g = mod.GalaxyEvaluator.new_gen_js_evaluator();
     let g = mod.GalaxyEvaluator.new_reduce_evaluator();
+    g.set_undo_limit(100);
 
     function toCanvas(p: Point): Point {
         return {
@@ -44,24 +45,27 @@ import('../pkg').then(mod => {
         };
     }
 
-    function step(input: string, vec: Point) {
+    async function step(input: string, vec: Point) {
         ctx.clearRect(0, 0, 800, 800);
 
         let api_key = keyElem.value;
-        let res = g.galaxy(input, vec.x, vec.y, api_key);
-        nextState = res.state();
+        let res = await g.galaxy_async(input, vec.x, vec.y, api_key);
+        nextState = res.state_token();
 
         stateInput.value = nextState;
 
         min = { x: 1000, y: 1000 };
         max = { x: -1000, y: -1000 };
 
+        // x0, y0, x1, y1, ... of all images; image i spans offsets[i] to offsets[i + 1].
+        const flat = res.frames_flat();
+        const offsets = res.frame_offsets();
         let images = [];
-        for (let i = 0; i < res.image_count(); i++) {
+        for (let i = 0; i + 1 < offsets.length; i++) {
             let image = [];
-            for (let j = 0; j < res.image(i).count(); j++) {
-                const p = res.image(i).point(j);
-                image.push({ x: p.x, y: p.y });
+            for (let j = offsets[i]; j < offsets[i + 1]; j += 2) {
+                const p = { x: flat[j], y: flat[j + 1] };
+                image.push(p);
                 min.x = Math.min(min.x, p.x);
                 max.x = Math.max(max.x, p.x + 1);
                 min.y = Math.min(min.y, p.y);
@@ -87,9 +91,20 @@ import('../pkg').then(mod => {
     canvas.addEventListener("click", (e) => {
         let p = fromCanvas({ x: e.offsetX, y: e.offsetY });
         console.log("step", nextState, p);
-        step(nextState, p)
+        step(nextState, p).catch(e => console.log("step failed", e));
     })
 
+    // Renders a state from undo or redo, if any.
+    function jump(state: string | undefined) {
+        if (state !== undefined) {
+            step(state, { x: -1000, y: -1000 });
+        }
+    }
+    document.getElementById("back")!.addEventListener("click", () => jump(g.undo()));
+    document.getElementById("forward")!.addEventListener("click", () => jump(g.redo()));
+    // Aborts a click waiting for the aliens; the rejected step is logged.
+    document.getElementById("stop")!.addEventListener("click", () => g.cancel());
+
     stateInput.addEventListener("keyup", (e) => {
         if (e.keyCode != 13) {
             return
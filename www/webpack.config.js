@@ -36,6 +36,7 @@ module.exports = {
     new WasmPackPlugin({
       crateDirectory: path.resolve(__dirname, ".."),
       outName: "app",
+      extraArgs: "-- --features wasm",
     })
   ],
   devServer: {